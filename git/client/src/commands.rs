@@ -1,25 +1,50 @@
-use super::config_file::config::Config;
+use super::config_file::{config::Config, git_config::Scope};
 use super::plumbing::{
-    checkout::__checkout, clone::__clone, commands::*, commit::__commit, fetch::__fetch,
-    ignore::set_to_be_ignored, log::__log, merge::__merge, push::__push, remote::__remote,
+    am::__am,
+    apply::{__apply, ApplyOptions},
+    bisect::__bisect,
+    blame::{__blame, format_blame, format_blame_porcelain},
+    bundle::__bundle,
+    checkout::__checkout,
+    clone::__clone,
+    commands::*,
+    commit::__commit,
+    config::__config,
+    describe::{__describe, DescribeOptions},
+    fetch::{__fetch, is_up_to_date, prune, tracking_ref},
+    format_patch::__format_patch,
+    grep::{__grep, format_grep, GrepOptions},
+    ignore::set_to_be_ignored,
+    log::{__log, LogCommit, LogOptions},
+    merge::__merge,
+    notes::__notes,
+    push::__push,
+    remote::__remote,
+    signing::{__verify_commit, __verify_tag},
+    sparse::__sparse_checkout,
+    submodule::__submodule,
     tag::__tag,
+    worktree::__worktree,
 };
 use crate::config_file::config_entry::ConfigEntry;
 use crate::plumbing::heads::get_head_name;
-use crate::plumbing::refs::get_local_branches;
-use crate::plumbing::{ignore::files_not_ignored, rebase::__rebase};
+use crate::plumbing::refs::{get_local_branches, rev_parse};
+use crate::plumbing::{ignore::files_not_ignored, merge::get_merge_bases, rebase::__rebase};
 
 use std::{
-    collections::HashSet,
     fs::{self, File},
     io::{self, Write},
 };
-use utils::get_current_refs;
+use utils::index_file::index_entry::IndexEntry;
 use utils::object::object_db::get_object;
 use utils::plumbing::{
+    commit_graph::write_commit_graph,
+    fsck::fsck as __fsck,
+    gc::{gc as __gc, gc_auto as __gc_auto},
     hash_object::__hash_object,
     ls_tree::{__ls_tree, hash_to_str},
 };
+use utils::{common_dir, get_current_refs, get_head_with_offset, read_ref_with_offset};
 
 #[macro_export]
 macro_rules! io_err {
@@ -42,9 +67,54 @@ pub fn clone(url: &str) -> io::Result<()> {
 #[allow(dead_code)]
 pub fn fetch(remote: &str) -> io::Result<()> {
     __fetch(remote)?;
+    update_commit_graph();
     Ok(())
 }
 
+/// Downloads refs and objects from a remote repository, then deletes
+/// the remote-tracking branches of the ones deleted in it. Returns
+/// whether it was up to date and a line for every deleted one.
+#[allow(dead_code)]
+pub fn fetch_prune(remote: &str) -> io::Result<String> {
+    // Fetch first, so nothing is deleted if it fails.
+    let mut output = match fetch(remote) {
+        Ok(()) => String::new(),
+        Err(e) if is_up_to_date(&e) => format!("{e}\n"),
+        Err(e) => return Err(e),
+    };
+
+    for name in prune(remote)? {
+        let name = name.strip_prefix("refs/remotes/").unwrap_or(&name);
+        output.push_str(&format!(" - [deleted] (none) -> {name}\n"));
+    }
+
+    Ok(output)
+}
+
+// Packs the repository if it has too many loose objects. Returns
+// whether it was packed, which rewrites the commit-graph too.
+// Failing to do so doesn't fail the command that triggered it.
+fn gc_auto() -> bool {
+    match __gc_auto() {
+        Ok(packed) => packed,
+        Err(e) => {
+            println!("Auto packing the repository failed: {e}");
+            false
+        }
+    }
+}
+
+// Adds the fetched commits to the commit-graph.
+fn update_commit_graph() {
+    if gc_auto() {
+        return;
+    }
+
+    if let Err(e) = write_commit_graph() {
+        println!("Writing the commit-graph failed: {e}");
+    }
+}
+
 /// Create an empty Git Repository at local directory
 /// If you provide a directory, the command is run inside it.
 /// If this directory does not exist, it will be created.
@@ -80,8 +150,8 @@ pub fn init(directory: &str) -> io::Result<()> {
     let mut desc = File::create(path_local_repo.clone() + "/description")?;
     desc.write_all(b"Unnamed repository; edit this file 'description' to name the repository.")?;
 
+    // The default `cmdlog.path`, where commands are logged.
     let cmdlog_path = "cmdlog.txt";
-    set_userconfig("pepito", "pepito@fi.uba.ar", "cmdlog.txt", "all")?;
     let mut ignore = File::create(".gitignore")?;
     ignore.write_all(format!("{}\n", cmdlog_path).as_bytes())?;
 
@@ -131,6 +201,7 @@ pub enum FileStatus {
     Staged(String),
     Modified(String),
     Deleted(String),
+    ModeChanged(String),
 }
 
 use std::fmt::{self, Display};
@@ -141,6 +212,7 @@ impl Display for FileStatus {
             FileStatus::Staged(name) => ("Staged", name),
             FileStatus::Modified(name) => ("Modified", name),
             FileStatus::Deleted(name) => ("Deleted", name),
+            FileStatus::ModeChanged(name) => ("Mode changed", name),
         };
 
         write!(f, "{prefix}: {name}")
@@ -159,14 +231,30 @@ pub fn status() -> io::Result<Vec<FileStatus>> {
     // they are in the index.
     for path in &paths {
         if let Some(entry) = index.get(path) {
-            let data = fs::read_to_string(path)?;
-            let hash = __hash_object(data.as_bytes(), "blob", false, ".git")?.0;
+            if entry.is_gitlink() {
+                // A submodule is modified if it checked
+                // out a commit other than the recorded one.
+                let head = get_head_with_offset(&format!("{path}/.git"));
+                if head.as_deref() != Some(hash_to_str(entry.get_hash()).as_str()) {
+                    changes.push(FileStatus::Modified(path.to_string()));
+                } else if entry.is_staged() {
+                    changes.push(FileStatus::Staged(path.to_string()));
+                }
+                continue;
+            }
+
+            let data = IndexEntry::read_content(path)?;
+            let hash = __hash_object(&data, "blob", false, &common_dir())?.0;
             // If the hash of the file is different
             // then the file was modified.
             if entry.get_hash() != hash {
                 // If paths are the same but hash is different
                 // then this file was modified.
                 changes.push(FileStatus::Modified(path.to_string()));
+            } else if entry.get_mode() != index.mode_of(path)? {
+                // Same content but the executable bit
+                // was toggled or it became a symlink.
+                changes.push(FileStatus::ModeChanged(path.to_string()));
             } else if entry.is_staged() {
                 changes.push(FileStatus::Staged(path.to_string()));
             }
@@ -180,7 +268,17 @@ pub fn status() -> io::Result<Vec<FileStatus>> {
     // Iter over index entries to check if
     // some of them are missing, indicating
     // that they were deleted.
-    for (path, _) in index.iter() {
+    for (path, entry) in index.iter() {
+        // Submodules that were never initialized are empty directories.
+        if entry.is_gitlink() && fs::metadata(path).is_ok() {
+            continue;
+        }
+
+        // Files left out of the sparse checkout aren't deleted.
+        if entry.is_skip_worktree() {
+            continue;
+        }
+
         if !paths.contains(path) {
             changes.push(FileStatus::Deleted(path.to_string()));
         }
@@ -193,7 +291,31 @@ pub fn status() -> io::Result<Vec<FileStatus>> {
 /// Message should not be '\n' terminated.
 #[allow(dead_code)]
 pub fn commit(msg: &str) -> io::Result<String> {
-    __commit(msg)
+    let hash = __commit(msg, None)?;
+    gc_auto();
+    Ok(hash)
+}
+
+/// Creates a commit object signed with the SSH key at the given path,
+/// as `commit -S`. The path of the public key may be given instead.
+#[allow(dead_code)]
+pub fn commit_signed(msg: &str, key: &str) -> io::Result<String> {
+    let hash = __commit(msg, Some(key))?;
+    gc_auto();
+    Ok(hash)
+}
+
+/// Checks the signature of the commit against the allowed signers file.
+/// Returns the signer when it's good, an error otherwise.
+pub fn verify_commit(rev: &str, allowed_signers: &str) -> io::Result<String> {
+    __verify_commit(&rev_parse(rev)?, allowed_signers)
+}
+
+/// Checks the signature of the annotated tag against the allowed signers
+/// file. Returns the signer when it's good, an error otherwise.
+pub fn verify_tag(name: &str, allowed_signers: &str) -> io::Result<String> {
+    let hash = read_ref_with_offset(&format!("refs/tags/{name}"), &common_dir())?;
+    __verify_tag(&hash, allowed_signers)
 }
 
 /// Returns a String with the data of the given hash's object.
@@ -210,7 +332,7 @@ pub fn branch(name: Option<String>) -> io::Result<Option<Vec<String>>> {
         match get_head() {
             None => Err(io_err!("HEAD is not pointing to any commit")),
             Some(commit) => {
-                let mut branch = File::create(format!(".git/refs/heads/{name}"))?;
+                let mut branch = File::create(format!("{}/refs/heads/{name}", common_dir()))?;
                 branch.write_all(commit.as_bytes())?;
                 branch.write_all(b"\n")?;
                 Ok(None)
@@ -235,23 +357,246 @@ pub fn merge(branch: &str) -> io::Result<()> {
     __merge(branch, "heads")
 }
 
-/// Returns a vector with the history of the current branch.
-/// The vector is ordered from the oldest to the newest commit.
+/// Returns every best common ancestor of the given commits.
+pub fn merge_base(a: &str, b: &str) -> io::Result<Vec<String>> {
+    get_merge_bases(a, b)
+}
+
+/// Options of the blame command.
+#[derive(Debug, Default)]
+pub struct BlameOptions {
+    /// Revision to blame, HEAD when missing.
+    pub rev: Option<String>,
+    /// Lines to blame, as given to `-L`.
+    pub range: Option<String>,
+    /// Outputs git's porcelain format.
+    pub porcelain: bool,
+    /// Follows every parent of merges instead of only the first one.
+    pub all_parents: bool,
+}
+
+/// Shows the commit that last changed every line of the given file.
+pub fn blame(path: &str, opts: BlameOptions) -> io::Result<String> {
+    let rev = rev_parse(opts.rev.as_deref().unwrap_or("HEAD"))?;
+    let lines = __blame(path, &rev, opts.range.as_deref(), opts.all_parents)?;
+
+    match opts.porcelain {
+        true => format_blame_porcelain(&lines, path),
+        false => format_blame(&lines),
+    }
+}
+
+#[allow(dead_code)]
+pub enum BisectCommand {
+    Start {
+        bad: Option<String>,
+        good: Vec<String>,
+    },
+    Good(Option<String>),
+    Bad(Option<String>),
+    Skip(Option<String>),
+    Reset,
+    Log,
+    Run(Vec<String>),
+}
+
+/// Finds the commit that introduced a bug by binary searching the
+/// history between a good and a bad commit. Good, Bad and Skip mark
+/// a commit, HEAD when not given, and check out the next one to test.
+/// Run tests them with a command until the first bad one is found.
+pub fn bisect(cmd: BisectCommand) -> io::Result<String> {
+    __bisect(cmd)
+}
+
+#[allow(dead_code)]
+pub enum SparseCheckoutCommand {
+    Set(Vec<String>),
+    Add(Vec<String>),
+    List,
+    Disable,
+}
+
+/// Checks out only the given directories, in cone mode. Set replaces
+/// them, Add adds to them, List shows them and Disable checks out
+/// every file again. Files left out are kept in the index.
+pub fn sparse_checkout(cmd: SparseCheckoutCommand) -> io::Result<String> {
+    __sparse_checkout(cmd)
+}
+
 #[allow(dead_code)]
-pub fn log() -> io::Result<Vec<String>> {
-    let head = get_head().ok_or(io_err!("HEAD is not pointing to any commit"))?;
-    __log(&head, &mut HashSet::new())
+pub enum WorktreeCommand {
+    Add {
+        path: String,
+        commit: Option<String>,
+        branch: Option<String>,
+    },
+    List,
+    Remove {
+        path: String,
+        force: bool,
+    },
+    Prune,
+}
+
+/// Manages the working trees linked to the repository. Add checks out
+/// a branch or commit in a new directory, a new branch when given,
+/// List shows every worktree, Remove deletes one and Prune forgets the
+/// ones whose directory was deleted.
+pub fn worktree(cmd: WorktreeCommand) -> io::Result<String> {
+    __worktree(cmd)
+}
+
+#[allow(dead_code)]
+pub enum NotesCommand {
+    Add {
+        object: Option<String>,
+        msg: String,
+        force: bool,
+    },
+    Append {
+        object: Option<String>,
+        msg: String,
+    },
+    Show(Option<String>),
+    List(Option<String>),
+    Remove(Option<String>),
+}
+
+/// Attaches notes to objects without changing them, HEAD when no
+/// object is given. Add writes a note, Append adds to it, Show and
+/// List show them and Remove deletes one. Notes are shown by log and
+/// pushed and fetched in `refs/notes/commits`.
+pub fn notes(cmd: NotesCommand) -> io::Result<String> {
+    __notes(cmd)
+}
+
+/// Names the commit after the nearest tag reachable from it, like
+/// `v1.2-14-gabc1234` when it's 14 commits after `v1.2`.
+pub fn describe(opts: &DescribeOptions) -> io::Result<String> {
+    __describe(opts)
+}
+
+/// Writes a mail for every commit in the range, like `a..b` or `a`
+/// for `a..HEAD`, to be applied elsewhere by am. Returns the names of
+/// the files written.
+pub fn format_patch(range: &str) -> io::Result<Vec<String>> {
+    __format_patch(range)
+}
+
+/// Applies a unified diff, like the ones diff or format-patch write,
+/// to the working directory or the index. Hunks that moved are found
+/// nearby. Nothing is changed if a hunk doesn't apply, the rejected
+/// ones are returned in the error.
+pub fn apply(patch: &str, opts: &ApplyOptions) -> io::Result<String> {
+    __apply(patch, opts)
+}
+
+#[allow(dead_code)]
+pub enum BundleCommand {
+    Create { file: String, revs: Vec<String> },
+    Verify(String),
+    ListHeads(String),
+}
+
+/// Moves history without a server. Create writes the references and
+/// objects selected by the revisions to a file, like `master` or
+/// `v1.0..master`, Verify checks it can be fetched here and ListHeads
+/// shows it's references. Clone and fetch read bundle files too.
+pub fn bundle(cmd: BundleCommand) -> io::Result<String> {
+    __bundle(cmd)
+}
+
+#[allow(dead_code)]
+pub enum AmCommand {
+    Apply(String),
+    Continue,
+    Skip,
+    Abort,
+}
+
+/// Applies the patches of a mailbox as new commits, keeping their
+/// authors. When one doesn't apply it stops, Continue commits the
+/// resolved changes, Skip drops the patch and Abort goes back to the
+/// commit it started from.
+pub fn am(cmd: AmCommand) -> io::Result<String> {
+    __am(cmd)
+}
+
+/// Searches the files of the working tree, the index or a revision
+/// for lines matching a regular expression.
+pub fn grep(opts: &GrepOptions) -> io::Result<String> {
+    let files = __grep(opts)?;
+    Ok(format_grep(&files, opts))
+}
+
+/// Returns the commits in the history selected by the options, newest
+/// first. Use `log::format_log` to show them.
+#[allow(dead_code)]
+pub fn log(opts: &LogOptions) -> io::Result<Vec<LogCommit>> {
+    __log(opts)
+}
+
+#[allow(dead_code)]
+pub enum ConfigCommand {
+    Get {
+        key: String,
+        all: bool,
+        scope: Option<Scope>,
+    },
+    Set {
+        key: String,
+        value: String,
+        scope: Scope,
+    },
+    Add {
+        key: String,
+        value: String,
+        scope: Scope,
+    },
+    Unset {
+        key: String,
+        all: bool,
+        scope: Scope,
+    },
+    List {
+        show_origin: bool,
+        scope: Option<Scope>,
+    },
+}
+
+/// Reads and writes git-config files. Get shows the value of a key,
+/// or all of them, from the system, global and repository files, or
+/// only from the given one. Set replaces a value, Add adds another
+/// one, Unset removes it and List shows every value, with the file
+/// it's in if the origin is shown. Keys look like `remote.origin.url`.
+pub fn config(cmd: ConfigCommand) -> io::Result<String> {
+    __config(cmd)
 }
 
 #[allow(dead_code)]
 pub enum RemoteCommand {
-    Add { name: String, url: String },
-    Rem { name: String },
+    Add {
+        name: String,
+        url: String,
+    },
+    Rem {
+        name: String,
+    },
     List,
+    /// Deletes the remote-tracking branches of the branches
+    /// deleted in the remote.
+    Prune {
+        name: String,
+    },
+    /// Shows the remote's branches, which are tracked, new or
+    /// stale, and how the local ones compare to their upstream.
+    Show {
+        name: String,
+    },
 }
 
-/// Manage set of tracked repositories. Adds, removes or lists
-/// remote repositories tracked by git.
+/// Manage set of tracked repositories. Adds, removes, lists, prunes
+/// or shows remote repositories tracked by git.
 pub fn remote(cmd: RemoteCommand) -> io::Result<Option<Vec<String>>> {
     let mut config = Config::read()?;
     match __remote(cmd, &mut config) {
@@ -269,13 +614,30 @@ pub fn remote(cmd: RemoteCommand) -> io::Result<Option<Vec<String>>> {
 pub fn pull(remote: &str) -> io::Result<()> {
     get_head().ok_or(io_err!("HEAD is not pointing to any commit"))?;
     __fetch(remote)?;
+    update_commit_graph();
     let head_name = get_head_name()?;
-    __merge(&head_name, &format!("remotes/{remote}"))
+
+    // refs/remotes/origin/main is merged as origin/main.
+    let tracking = tracking_ref(remote, &head_name)?;
+    let (subfolder, branch) = tracking
+        .strip_prefix("refs/")
+        .and_then(|name| name.split_once('/'))
+        .ok_or(io_err!("Invalid remote-tracking reference"))?;
+    __merge(branch, subfolder)
 }
 
 pub enum PushCommand {
-    SetUpstream { branch: String, remote: String },
+    SetUpstream {
+        branch: String,
+        remote: String,
+    },
     Push,
+    /// Updates the remote references the refspecs say, like
+    /// `feature:main` or `:old-branch` to delete one.
+    Refspecs {
+        remote: String,
+        refspecs: Vec<String>,
+    },
 }
 
 /// Sends changes to the remote repository. If the cmd is
@@ -287,29 +649,26 @@ pub fn push(cmd: PushCommand) -> io::Result<()> {
     use PushCommand::*;
     match cmd {
         SetUpstream { branch, remote } => {
-            match config.get(&branch) {
-                Some(ConfigEntry::Branch { .. }) => {}
-                Some(ConfigEntry::Remote { .. }) => {
-                    return Err(io_err!("Remote name already exists"));
-                }
-                _ => {}
+            if config.remote(&branch).is_some() {
+                return Err(io_err!("Remote name already exists"));
             }
 
-            let entry = ConfigEntry::new_branch(&branch, &remote);
-            config.insert(branch, entry);
+            config.add(ConfigEntry::new_branch(&branch, &remote));
             config.write()?;
 
             // Push the changes.
-            __push(&remote)
+            __push(&remote, &[])
         }
 
         Push => {
             let cur_branch = get_head_name()?;
-            match config.get(&cur_branch) {
-                Some(ConfigEntry::Branch { remote, .. }) => __push(remote),
+            match config.branch(&cur_branch) {
+                Some(ConfigEntry::Branch { remote, .. }) => __push(remote, &[]),
                 _ => Err(io_err!("Current branch has no remote")),
             }
         }
+
+        Refspecs { remote, refspecs } => __push(&remote, &refspecs),
     }
 }
 
@@ -383,6 +742,12 @@ pub enum TagCommand {
         hash: Option<String>,
         msg: Option<String>,
     },
+    AddSigned {
+        name: String,
+        hash: Option<String>,
+        msg: String,
+        key: String,
+    },
     Del {
         name: String,
     },
@@ -392,7 +757,8 @@ pub enum TagCommand {
 /// Creates a new tag with the given name. If the user specified
 /// a hash, then the tag will point to that hash. Otherwise, it
 /// will point to the current HEAD. If the user specified a message,
-/// then the tag will be an annotated tag.
+/// then the tag will be an annotated tag. Signed tags are annotated
+/// tags signed with the SSH key at the given path, as `tag -s`.
 pub fn tag(cmd: TagCommand) -> io::Result<Option<Vec<String>>> {
     __tag(cmd)
 }
@@ -405,3 +771,28 @@ pub fn rebase(branch: &str) -> io::Result<()> {
 
     __rebase(&head_hash, &branch_hash, branch)
 }
+
+pub enum SubmoduleCommand {
+    Init,
+    Update,
+    Status,
+}
+
+/// Manages the repository's submodules. Init registers the ones
+/// declared in .gitmodules, Update clones them and checks out the
+/// recorded commits and Status lists them.
+pub fn submodule(cmd: SubmoduleCommand) -> io::Result<Option<Vec<String>>> {
+    __submodule(cmd)
+}
+
+/// Verifies the integrity and connectivity of the objects in the
+/// repository. Returns a line for every problem found.
+pub fn fsck() -> io::Result<Vec<String>> {
+    __fsck()
+}
+
+/// Packs every reachable object and reference, pruning
+/// unreachable loose objects older than two weeks.
+pub fn gc() -> io::Result<()> {
+    __gc()
+}
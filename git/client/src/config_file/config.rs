@@ -1,116 +1,42 @@
 use super::config_entry::ConfigEntry;
+use super::git_config::{parse_bool, ConfigFile, Scope};
 use std::{
     collections::HashMap,
-    fs::{self, File},
-    io::{self, BufRead, BufReader, Write},
+    fs,
+    io::{self, Write},
     ops::{Deref, DerefMut},
     str::FromStr,
 };
 
+// Entries are keyed by their section and name,
+// a branch and a submodule may share a name.
+type EntryKey = (String, String);
+
+/// The repository's configuration file, with it's remotes, branches
+/// and submodules as entries. Every other key is kept when written.
 #[derive(Debug)]
 pub struct Config {
-    repositoryformatversion: u8,
+    file: ConfigFile,
     filemode: bool,
-    bare: bool,
-    logallrefupdates: bool,
-    entries: HashMap<String, ConfigEntry>,
-}
-
-// Parses a given string to a given type returning an io::Result.
-fn parse<T: FromStr>(val: &str) -> io::Result<T> {
-    val.parse()
-        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Invalid config file"))
+    sparse_checkout: bool,
+    entries: HashMap<EntryKey, ConfigEntry>,
 }
 
 impl FromStr for Config {
     type Err = io::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if !s.starts_with("[core]") {
-            return Err(io::Error::new(io::ErrorKind::Other, "Invalid config file"));
-        }
-
-        let mut reader = BufReader::new(s[1..].as_bytes());
-        let mut buf = vec![];
-
-        let mut repositoryformatversion = 0;
-        let mut filemode = true;
-        let mut bare = false;
-        let mut logallrefupdates = true;
-
-        // Get core section.
-        reader.read_until(b'[', &mut buf)?;
-        for line in buf.lines().flatten() {
-            if let Some((key, val)) = line.split_once(" = ") {
-                match key {
-                    "repositoryformatversion" => repositoryformatversion = parse(val)?,
-                    "filemode" => filemode = parse(val)?,
-                    "bare" => bare = parse(val)?,
-                    "logallrefupdates" => logallrefupdates = parse(val)?,
-                    _ => {}
-                }
-            }
-        }
-
-        // Get entries section.
-        buf.clear();
-        let mut entries = HashMap::new();
-        while let Ok(n) = reader.read_until(b'[', &mut buf) {
-            if n == 0 {
-                break;
-            }
-
-            let s = String::from_utf8_lossy(&buf).to_string();
-            let entry = ConfigEntry::from_str(&s)?;
-            entries.insert(entry.name(), entry);
-            buf.clear();
-        }
-
-        Ok(Self {
-            repositoryformatversion,
-            filemode,
-            bare,
-            logallrefupdates,
-            entries,
-        })
+        Self::from_file(ConfigFile::parse(&Scope::Local.path()?, s)?)
     }
 }
 
 // Writes the config structure to the given output.
-fn __write_to<W: Write>(config: Config, mut out: W) -> io::Result<()> {
-    let mut s = String::from("[core]\n");
-    s.push_str(&format!(
-        "    repositoryformatversion = {}\n",
-        config.repositoryformatversion
-    ));
-
-    s.push_str(&format!(
-        "    filemode = {}\n",
-        if config.filemode { "true" } else { "false" }
-    ));
-
-    s.push_str(&format!(
-        "    bare = {}\n",
-        if config.bare { "true" } else { "false" }
-    ));
-
-    s.push_str(&format!(
-        "    logallrefupdates = {}\n",
-        if config.logallrefupdates {
-            "true"
-        } else {
-            "false"
-        }
-    ));
-
-    for (_, entry) in config.entries.iter() {
-        s.push_str(&entry.to_string());
-    }
-
-    write!(out, "{s}")
+fn __write_to<W: Write>(mut config: Config, mut out: W) -> io::Result<()> {
+    config.sync()?;
+    write!(out, "{}", config.file)
 }
 
 impl Deref for Config {
-    type Target = HashMap<String, ConfigEntry>;
+    type Target = HashMap<EntryKey, ConfigEntry>;
     fn deref(&self) -> &Self::Target {
         &self.entries
     }
@@ -123,18 +49,116 @@ impl DerefMut for Config {
 }
 
 impl Config {
+    fn from_file(file: ConfigFile) -> io::Result<Self> {
+        let get_bool = |key: &str, default: bool| match file.get(key)? {
+            Some(value) => parse_bool(&value),
+            None => Ok(default),
+        };
+
+        let filemode = get_bool("core.filemode", true)?;
+        let sparse_checkout = get_bool("core.sparseCheckout", false)?;
+
+        let mut entries = HashMap::new();
+        for (section, subsection) in file.sections() {
+            let Some(name) = subsection else { continue };
+            if let Some(entry) = ConfigEntry::from_file(&section, &name, &file)? {
+                entries.insert((section, name), entry);
+            }
+        }
+
+        Ok(Self {
+            file,
+            filemode,
+            sparse_checkout,
+            entries,
+        })
+    }
+
     pub fn read() -> io::Result<Config> {
-        let config = fs::read_to_string(".git/config")?;
-        Self::from_str(config.as_str())
+        let path = Scope::Local.path()?;
+        Self::from_file(ConfigFile::parse(&path, &fs::read_to_string(&path)?)?)
     }
 
-    pub fn write(self) -> io::Result<()> {
-        let file = File::create(".git/config")?;
-        __write_to(self, file)
+    // Writes the entries and settings back to the file. Sections of
+    // the entries that were removed are removed too.
+    fn sync(&mut self) -> io::Result<()> {
+        // The keys are left as the user wrote them unless the state changed.
+        let sparse_checkout = match self.file.get("core.sparseCheckout")? {
+            Some(value) => parse_bool(&value)?,
+            None => false,
+        };
+        match self.sparse_checkout {
+            enabled if enabled == sparse_checkout => {}
+            // Only cone mode sparse checkouts are supported.
+            true => {
+                self.file.set("core.sparseCheckout", "true")?;
+                self.file.set("core.sparseCheckoutCone", "true")?;
+            }
+            false => {
+                self.file.unset("core.sparseCheckout", true)?;
+                self.file.unset("core.sparseCheckoutCone", true)?;
+            }
+        }
+
+        for (section, subsection) in self.file.sections() {
+            let Some(name) = subsection else { continue };
+            if ["remote", "branch", "submodule"].contains(&section.as_str())
+                && !self.entries.contains_key(&(section.clone(), name.clone()))
+            {
+                self.file.remove_section(&section, Some(&name));
+            }
+        }
+
+        for entry in self.entries.values() {
+            entry.store(&mut self.file)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write(mut self) -> io::Result<()> {
+        self.sync()?;
+        self.file.write()
     }
 
     pub fn add(&mut self, entry: ConfigEntry) {
-        self.entries.insert(entry.name(), entry);
+        let key = (entry.section().to_string(), entry.name());
+        self.entries.insert(key, entry);
+    }
+
+    /// Returns the entry of the section with the given name.
+    pub fn entry(&self, section: &str, name: &str) -> Option<&ConfigEntry> {
+        self.entries.get(&(section.to_string(), name.to_string()))
+    }
+
+    /// Removes the entry of the section with the given name.
+    pub fn delete(&mut self, section: &str, name: &str) -> Option<ConfigEntry> {
+        self.entries
+            .remove(&(section.to_string(), name.to_string()))
+    }
+
+    /// Returns the remote with the given name.
+    pub fn remote(&self, name: &str) -> Option<&ConfigEntry> {
+        self.entry("remote", name)
+    }
+
+    /// Returns the branch with the given name.
+    pub fn branch(&self, name: &str) -> Option<&ConfigEntry> {
+        self.entry("branch", name)
+    }
+
+    /// Returns the value of core.filemode.
+    pub fn filemode(&self) -> bool {
+        self.filemode
+    }
+
+    /// Returns the value of core.sparseCheckout.
+    pub fn sparse_checkout(&self) -> bool {
+        self.sparse_checkout
+    }
+
+    pub fn set_sparse_checkout(&mut self, enabled: bool) {
+        self.sparse_checkout = enabled;
     }
 }
 
@@ -208,6 +232,61 @@ mod tests {
         let mut file = BufWriter::new(vec![]);
         __write_to(config, &mut file).unwrap();
         let out = String::from_utf8(file.into_inner().unwrap()).unwrap();
-        println!("{out:#?}");
+        assert_eq!(out, s);
+    }
+
+    #[test]
+    fn keeps_unknown_entries() {
+        let s = "[core]\n\tbare = false\n[remote \"origin\"]\n\turl = a\n\ttagOpt = --no-tags\n\
+                 [user]\n\tname = Ada\n[branch \"main\"]\n\tremote = origin\n\tmerge = refs/heads/main\n";
+
+        let mut config = Config::from_str(s).unwrap();
+        config.delete("branch", "main");
+        let origin = ("remote".to_string(), "origin".to_string());
+        if let Some(ConfigEntry::Remote { url, .. }) = config.get_mut(&origin) {
+            *url = "b".to_string();
+        }
+
+        let mut out = vec![];
+        __write_to(config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[core]\n\tbare = false\n[remote \"origin\"]\n\turl = b\n\ttagOpt = --no-tags\n[user]\n\tname = Ada\n"
+        );
+    }
+
+    #[test]
+    fn entries_of_different_sections_share_names() {
+        let s = "[branch \"lib\"]\n\tremote = origin\n\tmerge = refs/heads/lib\n\
+                 [submodule \"lib\"]\n\tpath = lib\n\turl = ../lib\n";
+
+        let mut config = Config::from_str(s).unwrap();
+        assert!(config.branch("lib").is_some());
+        assert!(config.entry("submodule", "lib").is_some());
+
+        config.delete("submodule", "lib");
+        let mut out = vec![];
+        __write_to(config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[branch \"lib\"]\n\tremote = origin\n\tmerge = refs/heads/lib\n"
+        );
+    }
+
+    #[test]
+    fn sparse_checkout_is_kept_unless_changed() {
+        let s = "[core]\n\tsparseCheckout = false\n\tsparseCheckoutCone = false\n";
+        let mut out = vec![];
+        __write_to(Config::from_str(s).unwrap(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), s);
+
+        let mut config = Config::from_str(s).unwrap();
+        config.set_sparse_checkout(true);
+        let mut out = vec![];
+        __write_to(config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[core]\n\tsparsecheckout = true\n\tsparsecheckoutcone = true\n"
+        );
     }
 }
@@ -1,144 +1,113 @@
-use crate::io_err;
-use std::{
-    fmt::{self, Display},
-    io,
-    str::{FromStr, Lines},
-};
+use super::git_config::ConfigFile;
+use std::io;
+
+// Sets the key to the value, removing it when the value is empty.
+fn set_or_unset(file: &mut ConfigFile, key: &str, value: &str) -> io::Result<()> {
+    match value.is_empty() {
+        true => file.unset(key, true).map(|_| ()),
+        false => file.set(key, value),
+    }
+}
 
 #[derive(Debug)]
 pub enum ConfigEntry {
     Remote {
         name: String,
         url: String,
-        fetch: String,
+        fetch: Vec<String>,
+        push: Vec<String>,
     },
     Branch {
         name: String,
         remote: String,
         merge: String,
     },
+    Submodule {
+        name: String,
+        path: String,
+        url: String,
+    },
 }
 
-impl Display for ConfigEntry {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut s = String::new();
-
-        match self {
-            ConfigEntry::Remote { name, url, fetch } => {
-                s.push_str(&format!("[remote \"{name}\"]\n"));
-                s.push_str(&format!("    url = {url}\n"));
-                s.push_str(&format!("    fetch = {fetch}\n"));
-            }
-            ConfigEntry::Branch {
-                name,
-                remote,
-                merge,
-            } => {
-                s.push_str(&format!("[branch \"{}\"]\n", name));
-                s.push_str(&format!("    remote = {}\n", remote));
-                s.push_str(&format!("    merge = {}\n", merge));
-            }
-        }
-
-        write!(f, "{s}")
-    }
-}
-
-fn branch_entry(name: String, lines: Lines) -> io::Result<ConfigEntry> {
-    let mut remote = String::new();
-    let mut merge = String::new();
-
-    // Helper closure to return an error.
-    let err = || io_err!("Invalid config file");
-
-    for line in lines.filter(|l| !l.is_empty()) {
-        if line.starts_with('[') || line.is_empty() {
-            continue;
-        }
+impl ConfigEntry {
+    /// Reads the entry of the section with the given name from the
+    /// file, None for sections that aren't remotes, branches or
+    /// submodules. Other keys in the section are left in the file.
+    pub fn from_file(section: &str, name: &str, file: &ConfigFile) -> io::Result<Option<Self>> {
+        let get = |key: &str| -> io::Result<String> {
+            Ok(file
+                .get(&format!("{section}.{name}.{key}"))?
+                .unwrap_or_default())
+        };
 
-        let line = line.split(" = ").collect::<Vec<&str>>();
-        let key = line[0].trim();
-        let val = line[1].trim();
+        let entry = match section {
+            "remote" => ConfigEntry::Remote {
+                name: name.to_string(),
+                url: get("url")?,
+                // Remotes may have many refspecs.
+                fetch: file.get_all(&format!("remote.{name}.fetch"))?,
+                push: file.get_all(&format!("remote.{name}.push"))?,
+            },
+            "branch" => ConfigEntry::Branch {
+                name: name.to_string(),
+                remote: get("remote")?,
+                merge: get("merge")?,
+            },
+            "submodule" => ConfigEntry::Submodule {
+                name: name.to_string(),
+                path: get("path")?,
+                url: get("url")?,
+            },
+            _ => return Ok(None),
+        };
 
-        match key {
-            "remote" => remote = val.to_string(),
-            "merge" => merge = val.to_string(),
-            _ => return Err(err()),
-        }
+        Ok(Some(entry))
     }
 
-    Ok(ConfigEntry::Branch {
-        name,
-        remote,
-        merge,
-    })
-}
-
-fn remote_entry(name: String, lines: Lines) -> io::Result<ConfigEntry> {
-    let mut url = String::new();
-    let mut fetch = String::new();
-
-    // Helper closure to return an error.
-    let err = || io_err!("Invalid config file");
-
-    for line in lines.filter(|l| !l.is_empty()) {
-        if line.starts_with('[') {
-            continue;
-        }
-
-        let line = line.split(" = ").collect::<Vec<&str>>();
-        let key = line[0].trim();
-        let val = line[1].trim();
+    /// Writes the entry to it's section of the file.
+    pub fn store(&self, file: &mut ConfigFile) -> io::Result<()> {
+        let key = |name: &str| format!("{}.{}.{name}", self.section(), self.name());
 
-        match key {
-            "url" => url = val.to_string(),
-            "fetch" => fetch = val.to_string(),
-            _ => return Err(err()),
+        match self {
+            ConfigEntry::Remote {
+                url, fetch, push, ..
+            } => {
+                set_or_unset(file, &key("url"), url)?;
+                file.set_all(&key("fetch"), fetch)?;
+                file.set_all(&key("push"), push)
+            }
+            ConfigEntry::Branch { remote, merge, .. } => {
+                set_or_unset(file, &key("remote"), remote)?;
+                set_or_unset(file, &key("merge"), merge)
+            }
+            ConfigEntry::Submodule { path, url, .. } => {
+                set_or_unset(file, &key("path"), path)?;
+                set_or_unset(file, &key("url"), url)
+            }
         }
     }
 
-    Ok(ConfigEntry::Remote { name, url, fetch })
-}
-
-impl FromStr for ConfigEntry {
-    type Err = io::Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut lines = s.lines();
-
-        // Helper closure to return an error.
-        let err = || io_err!("Invalid config file");
-
-        let (etype, name) = match lines.next() {
-            None => return Err(err()),
-            Some(header) => {
-                let header = header.split(' ').collect::<Vec<&str>>();
-                let etype = header[0];
-                let name = header[1].replace(['"', ']'], "");
-
-                (etype, name)
-            }
-        };
-
-        match etype {
-            "branch" => branch_entry(name, lines),
-            "remote" => remote_entry(name, lines),
-            _ => Err(err()),
+    /// Returns the section of the configuration the entry is in.
+    pub fn section(&self) -> &str {
+        match self {
+            ConfigEntry::Remote { .. } => "remote",
+            ConfigEntry::Branch { .. } => "branch",
+            ConfigEntry::Submodule { .. } => "submodule",
         }
     }
-}
 
-impl ConfigEntry {
     pub fn name(&self) -> String {
         match self {
             ConfigEntry::Remote { name, .. } => name.to_owned(),
             ConfigEntry::Branch { name, .. } => name.to_owned(),
+            ConfigEntry::Submodule { name, .. } => name.to_owned(),
         }
     }
 
     pub fn is_remote(&self) -> bool {
         match self {
             ConfigEntry::Remote { .. } => true,
-            ConfigEntry::Branch { .. } => false,
+            ConfigEntry::Branch { .. } | ConfigEntry::Submodule { .. } => false,
         }
     }
 
@@ -0,0 +1,937 @@
+use crate::io_err;
+use std::{
+    env,
+    fmt::{self, Display},
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+use utils::common_dir;
+
+// Includes nested deeper than this are taken as an include loop.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// The configuration files git reads, from the least to the most
+/// specific one. Values of the later ones override the earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    System,
+    Global,
+    Local,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::System => write!(f, "system"),
+            Scope::Global => write!(f, "global"),
+            Scope::Local => write!(f, "local"),
+        }
+    }
+}
+
+fn home() -> io::Result<String> {
+    env::var("HOME").map_err(|_| io_err!("HOME is not set"))
+}
+
+impl Scope {
+    /// Returns the path of the file the scope's values are written to.
+    pub fn path(&self) -> io::Result<String> {
+        match self {
+            Scope::System => Ok(env::var("GIT_CONFIG_SYSTEM").unwrap_or("/etc/gitconfig".into())),
+            Scope::Global => match env::var("GIT_CONFIG_GLOBAL") {
+                Ok(path) => Ok(path),
+                Err(_) => Ok(format!("{}/.gitconfig", home()?)),
+            },
+            Scope::Local => Ok(format!("{}/config", common_dir())),
+        }
+    }
+
+    // Returns the files the scope's values are read from. The global
+    // scope reads `$XDG_CONFIG_HOME/git/config` before `~/.gitconfig`.
+    fn paths(&self) -> Vec<String> {
+        match self {
+            Scope::System if env::var_os("GIT_CONFIG_NOSYSTEM").is_some() => vec![],
+            Scope::Global if env::var_os("GIT_CONFIG_GLOBAL").is_none() => {
+                let xdg = match env::var("XDG_CONFIG_HOME") {
+                    Ok(dir) => Ok(format!("{dir}/git/config")),
+                    Err(_) => home().map(|home| format!("{home}/.config/git/config")),
+                };
+                xdg.into_iter().chain(self.path()).collect()
+            }
+            _ => self.path().into_iter().collect(),
+        }
+    }
+}
+
+// A line of a configuration file. Lines keep the text they were read
+// from, so writing the file back only changes the edited ones.
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    // `[section "subsection"]`, the section in lowercase.
+    Section {
+        section: String,
+        subsection: Option<String>,
+        raw: String,
+    },
+    // `key = value`, the key in lowercase. Keys without a value are
+    // booleans set to true.
+    Entry {
+        key: String,
+        value: Option<String>,
+        raw: String,
+    },
+    // Blank lines and comments.
+    Other(String),
+}
+
+impl Line {
+    fn raw(&self) -> &str {
+        match self {
+            Line::Section { raw, .. } | Line::Entry { raw, .. } | Line::Other(raw) => raw,
+        }
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-'
+}
+
+// Parses `[section]`, `[section "subsection"]` or the deprecated
+// `[section.subsection]`, whose subsection is case insensitive.
+// Returns the names and what follows the header in the line.
+fn parse_section(line: &str) -> io::Result<(String, Option<String>, &str)> {
+    let err = || invalid(format!("bad config section header '{line}'"));
+    let inner = line.strip_prefix('[').ok_or_else(err)?;
+    let len = inner
+        .find(|c: char| !(is_key_char(c) || c == '.'))
+        .unwrap_or(inner.len());
+    let (name, rest) = inner.split_at(len);
+    if name.is_empty() {
+        return Err(err());
+    }
+
+    if let Some(rest) = rest.strip_prefix(']') {
+        let name = name.to_lowercase();
+        return Ok(match name.split_once('.') {
+            Some((section, subsection)) => (section.into(), Some(subsection.into()), rest),
+            None => (name, None, rest),
+        });
+    }
+
+    let rest = rest.trim_start_matches([' ', '\t']);
+    let rest = rest.strip_prefix('"').filter(|_| !name.contains('.'));
+    let rest = rest.ok_or_else(err)?;
+
+    // Only `\` and `"` are escaped in subsections.
+    let mut subsection = String::new();
+    let mut chars = rest.char_indices();
+    let end = loop {
+        match chars.next() {
+            Some((_, '\\')) => subsection.push(chars.next().ok_or_else(err)?.1),
+            Some((i, '"')) => break i + 1,
+            Some((_, c)) => subsection.push(c),
+            None => return Err(err()),
+        }
+    };
+
+    let rest = rest[end..].strip_prefix(']').ok_or_else(err)?;
+    Ok((name.to_lowercase(), Some(subsection), rest))
+}
+
+// Parses the value of an entry: quotes keep whitespace and comment
+// characters, backslashes escape, and a backslash ending the line
+// continues the value in the next one, which is appended to raw.
+fn parse_value<'a>(
+    mut text: &'a str,
+    raw: &mut String,
+    next: &mut impl Iterator<Item = &'a str>,
+) -> io::Result<String> {
+    let err = |raw: &str| invalid(format!("bad config line '{raw}'"));
+    let mut value = String::new();
+    let mut space = String::new();
+    let mut quoted = false;
+
+    loop {
+        let mut chars = text.chars();
+        let mut continued = false;
+        while let Some(c) = chars.next() {
+            // Whitespace outside quotes is kept only between words.
+            if c.is_whitespace() && !quoted {
+                space.push(c);
+                continue;
+            }
+            if matches!(c, '#' | ';') && !quoted {
+                break;
+            }
+            if !value.is_empty() {
+                value.push_str(&space);
+            }
+            space.clear();
+
+            match c {
+                '"' => quoted = !quoted,
+                '\\' => match chars.next() {
+                    None => continued = true,
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => value.push('\x08'),
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    Some(_) => return Err(err(raw)),
+                },
+                c => value.push(c),
+            }
+        }
+
+        if !continued {
+            break;
+        }
+
+        text = next.next().unwrap_or_default();
+        raw.push('\n');
+        raw.push_str(text);
+    }
+
+    match quoted {
+        true => Err(err(raw)),
+        false => Ok(value),
+    }
+}
+
+// Parses a `key = value` line, and the next ones it continues in.
+fn parse_entry<'a>(line: &'a str, next: &mut impl Iterator<Item = &'a str>) -> io::Result<Line> {
+    let mut raw = line.to_string();
+    let text = line.trim_start();
+    let len = text.find(|c| !is_key_char(c)).unwrap_or(text.len());
+    let (key, rest) = text.split_at(len);
+    let rest = rest.trim_start();
+
+    if !key.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(invalid(format!("bad config line '{line}'")));
+    }
+
+    let value = match rest.strip_prefix('=') {
+        Some(text) => Some(parse_value(text, &mut raw, next)?),
+        None if rest.is_empty() || rest.starts_with(['#', ';']) => None,
+        None => return Err(invalid(format!("bad config line '{line}'"))),
+    };
+
+    Ok(Line::Entry {
+        key: key.to_lowercase(),
+        value,
+        raw,
+    })
+}
+
+// Quotes the value if it would read differently otherwise.
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+
+    match needs_quotes {
+        true => format!("\"{escaped}\""),
+        false => escaped,
+    }
+}
+
+/// Splits a key like `remote.origin.url` in it's section, subsection
+/// and name. Sections and names are case insensitive, so they are
+/// returned in lowercase, subsections are kept as they are.
+pub fn split_key(key: &str) -> io::Result<(String, Option<String>, String)> {
+    let err = || invalid(format!("invalid key: {key}"));
+    let (section, rest) = key.split_once('.').ok_or_else(err)?;
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection.to_string()), name),
+        None => (None, rest),
+    };
+
+    let valid_section = !section.is_empty() && section.chars().all(is_key_char);
+    let valid_name =
+        name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(is_key_char);
+    match valid_section && valid_name {
+        true => Ok((section.to_lowercase(), subsection, name.to_lowercase())),
+        false => Err(err()),
+    }
+}
+
+// Returns the key with it's section and name in lowercase.
+fn normalize_key(key: &str) -> io::Result<String> {
+    let (section, subsection, name) = split_key(key)?;
+    Ok(full_key(&section, subsection.as_deref(), &name))
+}
+
+fn full_key(section: &str, subsection: Option<&str>, name: &str) -> String {
+    match subsection {
+        Some(subsection) => format!("{section}.{subsection}.{name}"),
+        None => format!("{section}.{name}"),
+    }
+}
+
+/// Parses a boolean value: `true`, `yes`, `on` and `1` are true,
+/// `false`, `no`, `off`, `0` and an empty value are false.
+pub fn parse_bool(value: &str) -> io::Result<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => Err(invalid(format!("bad boolean config value '{value}'"))),
+    }
+}
+
+/// A file in the git-config format. Keeps every line it was read
+/// from, unknown sections and comments included, when written back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile {
+    /// Where the file is read from and written to.
+    pub path: String,
+    lines: Vec<Line>,
+}
+
+impl Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line.raw())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ConfigFile {
+    /// Parses the text of the configuration file at the given path.
+    pub fn parse(path: &str, text: &str) -> io::Result<Self> {
+        let mut lines = vec![];
+        let mut next = text.lines();
+        let total = text.lines().count();
+        let mut in_section = false;
+
+        while let Some(line) = next.next() {
+            let text = line.trim_start();
+            if text.is_empty() || text.starts_with(['#', ';']) {
+                lines.push(Line::Other(line.to_string()));
+                continue;
+            }
+
+            // Entries must be in a section.
+            if !text.starts_with('[') && !in_section {
+                let number = total - next.clone().count();
+                return Err(invalid(format!("bad config line {number} in file {path}")));
+            }
+
+            if !text.starts_with('[') {
+                lines.push(parse_entry(line, &mut next)?);
+                continue;
+            }
+
+            // An entry may follow the header in the same line.
+            let (section, subsection, rest) = parse_section(text)?;
+            in_section = true;
+            lines.push(Line::Section {
+                section,
+                subsection,
+                raw: line[..line.len() - rest.len()].to_string(),
+            });
+
+            let rest = rest.trim_start();
+            if !rest.is_empty() && !rest.starts_with(['#', ';']) {
+                lines.push(parse_entry(rest, &mut next)?);
+            }
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            lines,
+        })
+    }
+
+    /// Reads the configuration file at the given path, empty if
+    /// there is no file there.
+    pub fn read(path: &str) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(path, &text),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self {
+                path: path.to_string(),
+                lines: vec![],
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the file back to it's path.
+    pub fn write(&self) -> io::Result<()> {
+        if let Some(dir) = Path::new(&self.path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(&self.path, self.to_string())
+    }
+
+    // Returns the entries with their line, as (line, key, value).
+    fn entries(&self) -> Vec<(usize, String, Option<String>)> {
+        let mut entries = vec![];
+        let mut current = None;
+
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Section {
+                    section,
+                    subsection,
+                    ..
+                } => current = Some((section, subsection.as_deref())),
+                Line::Entry { key, value, .. } => {
+                    // Parsing rejects entries before any section.
+                    if let Some((section, subsection)) = current {
+                        entries.push((i, full_key(section, subsection, key), value.clone()));
+                    }
+                }
+                Line::Other(_) => {}
+            }
+        }
+
+        entries
+    }
+
+    /// Returns every value in the file as (key, value), in order. The
+    /// value is None for keys that are set to true by being there.
+    pub fn values(&self) -> Vec<(String, Option<String>)> {
+        self.entries()
+            .into_iter()
+            .map(|(_, key, value)| (key, value))
+            .collect()
+    }
+
+    /// Returns every value of the key, in order.
+    pub fn get_all(&self, key: &str) -> io::Result<Vec<String>> {
+        let key = normalize_key(key)?;
+        Ok(self
+            .entries()
+            .into_iter()
+            .filter(|(_, k, _)| *k == key)
+            .map(|(_, _, value)| value.unwrap_or("true".to_string()))
+            .collect())
+    }
+
+    /// Returns the last value of the key, the one that's in effect.
+    pub fn get(&self, key: &str) -> io::Result<Option<String>> {
+        Ok(self.get_all(key)?.pop())
+    }
+
+    /// Returns the sections in the file as (section, subsection),
+    /// once each, in the order they first appear.
+    pub fn sections(&self) -> Vec<(String, Option<String>)> {
+        let mut sections = vec![];
+        for line in &self.lines {
+            if let Line::Section {
+                section,
+                subsection,
+                ..
+            } = line
+            {
+                let section = (section.clone(), subsection.clone());
+                if !sections.contains(&section) {
+                    sections.push(section);
+                }
+            }
+        }
+
+        sections
+    }
+
+    // Adds the value after the last line of the key's section,
+    // adding the section at the end of the file if it's missing.
+    fn insert(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let entry = Line::Entry {
+            key: name.clone(),
+            value: Some(value.to_string()),
+            raw: format!("\t{name} = {}", quote(value)),
+        };
+
+        let mut last = None;
+        let mut inside = false;
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Section {
+                    section: s,
+                    subsection: sub,
+                    ..
+                } => {
+                    inside = *s == section && *sub == subsection;
+                    if inside {
+                        last = Some(i);
+                    }
+                }
+                Line::Entry { .. } if inside => last = Some(i),
+                _ => {}
+            }
+        }
+
+        if let Some(i) = last {
+            self.lines.insert(i + 1, entry);
+            return Ok(());
+        }
+
+        let raw = match &subsection {
+            Some(sub) => format!(
+                "[{section} \"{}\"]",
+                sub.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            None => format!("[{section}]"),
+        };
+
+        self.lines.push(Line::Section {
+            section,
+            subsection,
+            raw,
+        });
+        self.lines.push(entry);
+        Ok(())
+    }
+
+    /// Sets the key to the value, replacing it's last value or adding
+    /// it if it isn't in the file. Lines are kept if it doesn't change.
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let key = normalize_key(key)?;
+        let last = self.entries().into_iter().rfind(|(_, k, _)| *k == key);
+
+        match last {
+            Some((_, _, Some(old))) if old == value => Ok(()),
+            Some((i, ..)) => {
+                let (_, _, name) = split_key(&key)?;
+                self.lines[i] = Line::Entry {
+                    raw: format!("\t{name} = {}", quote(value)),
+                    key: name,
+                    value: Some(value.to_string()),
+                };
+                Ok(())
+            }
+            None => self.insert(&key, value),
+        }
+    }
+
+    /// Adds a value to the key, keeping the ones it has.
+    pub fn add(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.insert(&normalize_key(key)?, value)
+    }
+
+    /// Removes the values of the key. Fails if it has many values and
+    /// not all of them are removed. Returns how many were removed.
+    pub fn unset(&mut self, key: &str, all: bool) -> io::Result<usize> {
+        let key = normalize_key(key)?;
+        let lines: Vec<_> = self
+            .entries()
+            .into_iter()
+            .filter(|(_, k, _)| *k == key)
+            .map(|(i, ..)| i)
+            .collect();
+
+        if lines.len() > 1 && !all {
+            return Err(invalid(format!("{key} has multiple values")));
+        }
+
+        for i in lines.iter().rev() {
+            self.lines.remove(*i);
+        }
+
+        Ok(lines.len())
+    }
+
+    /// Replaces every value of the key with the given ones, keeping
+    /// the lines if they don't change.
+    pub fn set_all(&mut self, key: &str, values: &[String]) -> io::Result<()> {
+        if self.get_all(key)? == values {
+            return Ok(());
+        }
+
+        self.unset(key, true)?;
+        for value in values {
+            self.add(key, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the section and every line in it.
+    pub fn remove_section(&mut self, section: &str, subsection: Option<&str>) {
+        let mut inside = false;
+        self.lines.retain(|line| {
+            if let Line::Section {
+                section: s,
+                subsection: sub,
+                ..
+            } = line
+            {
+                inside = s == section && sub.as_deref() == subsection;
+            }
+
+            !inside
+        });
+    }
+}
+
+/// A value read from the configuration, with where it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigValue {
+    pub scope: Scope,
+    /// The path of the file it's in.
+    pub origin: String,
+    pub key: String,
+    /// None for keys that are set to true by being there.
+    pub value: Option<String>,
+}
+
+/// The configuration files of every scope, in the order git reads them.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSet {
+    files: Vec<(Scope, ConfigFile)>,
+}
+
+// Returns the path an include refers to: `~/` is the home directory
+// and relative paths are relative to the including file.
+fn include_path(path: &str, from: &str) -> io::Result<String> {
+    if let Some(path) = path.strip_prefix("~/") {
+        return Ok(format!("{}/{path}", home()?));
+    }
+
+    match Path::new(from).parent() {
+        Some(dir) if Path::new(path).is_relative() => Ok(dir.join(path).to_string_lossy().into()),
+        _ => Ok(path.to_string()),
+    }
+}
+
+// Appends the values of the file to values, and the ones of the files
+// it includes where the `include.path` keys are.
+fn read_values(
+    scope: Scope,
+    file: &ConfigFile,
+    depth: usize,
+    values: &mut Vec<ConfigValue>,
+) -> io::Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(invalid(format!(
+            "exceeded maximum include depth in {}",
+            file.path
+        )));
+    }
+
+    for (key, value) in file.values() {
+        let include = match (key.as_str(), &value) {
+            ("include.path", Some(path)) => Some(include_path(path, &file.path)?),
+            _ => None,
+        };
+
+        values.push(ConfigValue {
+            scope,
+            origin: file.path.clone(),
+            key,
+            value,
+        });
+
+        // Missing included files are ignored.
+        if let Some(path) = include {
+            let included = ConfigFile::read(&path)?;
+            read_values(scope, &included, depth + 1, values)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl ConfigSet {
+    /// Reads the configuration files of the scope, or of every scope.
+    /// The repository's is only read inside one.
+    pub fn read(scope: Option<Scope>) -> io::Result<Self> {
+        let scopes = match scope {
+            Some(scope) => vec![scope],
+            None => vec![Scope::System, Scope::Global, Scope::Local],
+        };
+
+        let mut files = vec![];
+        for scope in scopes {
+            if scope == Scope::Local && !Path::new(&common_dir()).is_dir() {
+                continue;
+            }
+
+            for path in scope.paths() {
+                files.push((scope, ConfigFile::read(&path)?));
+            }
+        }
+
+        Ok(Self { files })
+    }
+
+    /// Returns every value, following the includes where they are.
+    pub fn values(&self) -> io::Result<Vec<ConfigValue>> {
+        let mut values = vec![];
+        for (scope, file) in &self.files {
+            read_values(*scope, file, 0, &mut values)?;
+        }
+
+        Ok(values)
+    }
+
+    /// Returns every value of the key, from every file.
+    pub fn get_all(&self, key: &str) -> io::Result<Vec<String>> {
+        let key = normalize_key(key)?;
+        Ok(self
+            .values()?
+            .into_iter()
+            .filter(|value| value.key == key)
+            .map(|value| value.value.unwrap_or("true".to_string()))
+            .collect())
+    }
+
+    /// Returns the value of the key that's in effect, the last one.
+    pub fn get(&self, key: &str) -> io::Result<Option<String>> {
+        Ok(self.get_all(key)?.pop())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# Written by hand.
+[core]
+	bare = false
+	autocrlf
+[remote "origin"]
+	url = git://127.0.0.1/repo.git
+	fetch = +refs/heads/*:refs/remotes/origin/*
+	fetch = +refs/tags/*:refs/tags/*   ; tags too
+	tagOpt = --no-tags
+[alias]
+	lg = "log --oneline ; # not a comment"
+	st = status \
+--short
+[Branch.Main]
+	merge = refs/heads/main
+"#;
+
+    #[test]
+    fn parse() {
+        let file = ConfigFile::parse("config", CONFIG).unwrap();
+        assert_eq!(file.get("core.autocrlf").unwrap(), Some("true".to_string()));
+        assert_eq!(
+            file.get("remote.origin.tagopt").unwrap(),
+            Some("--no-tags".to_string())
+        );
+        assert_eq!(file.get_all("remote.origin.fetch").unwrap().len(), 2);
+        assert_eq!(
+            file.get("remote.origin.fetch").unwrap(),
+            Some("+refs/tags/*:refs/tags/*".to_string())
+        );
+        assert_eq!(
+            file.get("alias.lg").unwrap(),
+            Some("log --oneline ; # not a comment".to_string())
+        );
+        assert_eq!(
+            file.get("alias.st").unwrap(),
+            Some("status --short".to_string())
+        );
+        assert_eq!(
+            file.get("branch.main.merge").unwrap(),
+            Some("refs/heads/main".to_string())
+        );
+
+        assert_eq!(file.to_string(), CONFIG);
+        assert!(ConfigFile::parse("config", "[core\n").is_err());
+        assert!(ConfigFile::parse("config", "[a]\nkey = \"open\n").is_err());
+
+        let err = ConfigFile::parse("config", "# top\n\nkey = value\n[a]\n").unwrap_err();
+        assert_eq!(err.to_string(), "bad config line 3 in file config");
+    }
+
+    #[test]
+    fn edit() {
+        let mut file = ConfigFile::parse("config", CONFIG).unwrap();
+        file.set("core.bare", "true").unwrap();
+        file.set("user.name", " Ada ").unwrap();
+        file.add("remote.origin.push", "refs/heads/main").unwrap();
+        assert!(file.unset("remote.origin.fetch", false).is_err());
+        assert_eq!(file.unset("alias.st", false).unwrap(), 1);
+
+        let text = file.to_string();
+        assert!(text.contains("\tbare = true\n\tautocrlf\n"));
+        assert!(text.contains("\ttagOpt = --no-tags\n\tpush = refs/heads/main\n"));
+        assert!(text.ends_with("[user]\n\tname = \" Ada \"\n"));
+        assert!(!text.contains("status"));
+
+        let file = ConfigFile::parse("config", &text).unwrap();
+        assert_eq!(file.get("user.name").unwrap(), Some(" Ada ".to_string()));
+
+        let mut file = file;
+        file.remove_section("remote", Some("origin"));
+        assert_eq!(file.get("remote.origin.url").unwrap(), None);
+        assert!(file.get("alias.lg").unwrap().is_some());
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(
+            split_key("Branch.Feature.x.Merge").unwrap(),
+            ("branch".into(), Some("Feature.x".into()), "merge".into())
+        );
+        assert!(split_key("core").is_err());
+        assert!(split_key("core.1st").is_err());
+        assert!(parse_bool("yes").unwrap());
+        assert!(!parse_bool("Off").unwrap());
+    }
+
+    // Writes the files to a temporary directory, returning it.
+    fn config_dir(name: &str, files: &[(&str, &str)]) -> String {
+        let dir = format!(
+            "{}/git-config-{name}-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, text) in files {
+            fs::write(format!("{dir}/{file}"), text).unwrap();
+        }
+
+        dir
+    }
+
+    fn config_set(files: &[(Scope, String)]) -> ConfigSet {
+        let files = files
+            .iter()
+            .map(|(scope, path)| (*scope, ConfigFile::read(path).unwrap()))
+            .collect();
+        ConfigSet { files }
+    }
+
+    #[test]
+    fn includes() {
+        let dir = config_dir(
+            "include",
+            &[
+                (
+                    "config",
+                    "[user]\n\tname = before\n[include]\n\tpath = user\n\tpath = missing\n",
+                ),
+                (
+                    "user",
+                    "[user]\n\tname = included\n\temail = ada@fi.uba.ar\n",
+                ),
+            ],
+        );
+
+        let set = config_set(&[(Scope::Local, format!("{dir}/config"))]);
+        assert_eq!(set.get("user.name").unwrap(), Some("included".to_string()));
+        assert_eq!(
+            set.get_all("user.name").unwrap(),
+            ["before".to_string(), "included".to_string()]
+        );
+
+        // Included values are where the include is.
+        let email = set
+            .values()
+            .unwrap()
+            .into_iter()
+            .rfind(|v| v.key == "user.email");
+        assert_eq!(email.unwrap().origin, format!("{dir}/user"));
+
+        fs::write(
+            format!("{dir}/config"),
+            "[include]\n\tpath = user\n[user]\n\tname = after\n",
+        )
+        .unwrap();
+        let set = config_set(&[(Scope::Local, format!("{dir}/config"))]);
+        assert_eq!(set.get("user.name").unwrap(), Some("after".to_string()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_depth() {
+        // Every file includes the next one, the last one itself.
+        let files: Vec<_> = (0..=MAX_INCLUDE_DEPTH)
+            .map(|i| (i.to_string(), format!("[include]\n\tpath = {}\n", i + 1)))
+            .chain([(
+                (MAX_INCLUDE_DEPTH + 1).to_string(),
+                "[core]\n\tbare = false\n".to_string(),
+            )])
+            .collect();
+        let files: Vec<_> = files
+            .iter()
+            .map(|(n, t)| (n.as_str(), t.as_str()))
+            .collect();
+        let dir = config_dir("depth", &files);
+
+        // The deepest file is as deep as it can be.
+        let set = config_set(&[(Scope::Local, format!("{dir}/1"))]);
+        assert_eq!(set.get("core.bare").unwrap(), Some("false".to_string()));
+
+        let set = config_set(&[(Scope::Local, format!("{dir}/0"))]);
+        assert!(set.get("core.bare").is_err());
+
+        fs::write(format!("{dir}/0"), "[include]\n\tpath = 0\n").unwrap();
+        let set = config_set(&[(Scope::Local, format!("{dir}/0"))]);
+        assert!(set.values().is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn scope_precedence() {
+        let dir = config_dir(
+            "scopes",
+            &[
+                (
+                    "system",
+                    "[user]\n\tname = system\n[core]\n\tbare = false\n",
+                ),
+                (
+                    "global",
+                    "[user]\n\tname = global\n\temail = ada@fi.uba.ar\n",
+                ),
+                ("local", "[user]\n\tname = local\n"),
+            ],
+        );
+
+        let scopes = [Scope::System, Scope::Global, Scope::Local];
+        let files: Vec<_> = scopes
+            .iter()
+            .map(|scope| (*scope, format!("{dir}/{scope}")))
+            .collect();
+
+        let set = config_set(&files);
+        assert_eq!(set.get("user.name").unwrap(), Some("local".to_string()));
+        assert_eq!(
+            set.get("user.email").unwrap(),
+            Some("ada@fi.uba.ar".to_string())
+        );
+        assert_eq!(set.get("core.bare").unwrap(), Some("false".to_string()));
+        assert_eq!(
+            set.get_all("user.name").unwrap(),
+            ["system", "global", "local"].map(String::from)
+        );
+
+        let name_scopes: Vec<_> = set
+            .values()
+            .unwrap()
+            .into_iter()
+            .filter(|value| value.key == "user.name")
+            .map(|value| value.scope)
+            .collect();
+        assert_eq!(name_scopes, scopes);
+
+        // Without the repository's file the global value is in effect.
+        let set = config_set(&files[..2]);
+        assert_eq!(set.get("user.name").unwrap(), Some("global".to_string()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,2 +1,3 @@
 pub mod config;
 pub mod config_entry;
+pub mod git_config;
@@ -1,4 +1,4 @@
-use crate::plumbing::commands::get_userconfig;
+use crate::config_file::git_config::ConfigSet;
 use std::{
     fs::OpenOptions,
     io::{self, Write},
@@ -9,13 +9,29 @@ pub enum LogMsgStatus {
     CorrectExecution,
 }
 
+// Where the commands are logged and whether all of them are or only
+// the failures, `cmdlog.path` and `cmdlog.mode`. The defaults are used
+// if the configuration can't be read.
+fn log_settings() -> (String, String) {
+    let config = ConfigSet::read(None).and_then(|config| config.values());
+    let setting = |key: &str, default: &str| match &config {
+        Ok(values) => values
+            .iter()
+            .rfind(|value| value.key == key)
+            .and_then(|value| value.value.clone())
+            .unwrap_or(default.to_string()),
+        Err(_) => default.to_string(),
+    };
+
+    (
+        setting("cmdlog.path", "cmdlog.txt"),
+        setting("cmdlog.mode", "all"),
+    )
+}
+
 #[allow(dead_code)]
-fn register_log(log_msg: String) -> io::Result<()> {
-    let usr_config = get_userconfig()?;
-    let mut file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(usr_config.get_log_path())?;
+fn register_log(path: &str, log_msg: String) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
 
     file.write_all(log_msg.as_bytes())?;
 
@@ -23,8 +39,8 @@ fn register_log(log_msg: String) -> io::Result<()> {
 }
 
 pub fn log_command(command: String, execution_status: LogMsgStatus) -> io::Result<()> {
-    let usr_config = get_userconfig()?;
-    let log_mode = usr_config.get_log_mode();
+    // `cmdlog.mode` is `all`, or `errors` to log only the failures.
+    let (log_path, log_mode) = log_settings();
     let time = chrono::Local::now().format("%d-%m-%Y %H:%M:%S");
 
     let log_msg = match execution_status {
@@ -42,6 +58,6 @@ pub fn log_command(command: String, execution_status: LogMsgStatus) -> io::Resul
         }
     };
 
-    register_log(log_msg)?;
+    register_log(&log_path, log_msg)?;
     Ok(())
 }
@@ -32,10 +32,15 @@ impl GitObject for Blob {
 }
 
 impl Blob {
-    // Returns true if this file has execute permissions.
+    // Returns the git mode of this file, symlinks
+    // are not followed and get mode 120000.
     fn get_git_mode(path: &PathBuf) -> io::Result<&'static str> {
-        let mode = fs::metadata(path)?.permissions().mode();
-        if mode & 0o111 != 0 {
+        let metadata = fs::symlink_metadata(path)?;
+        let mode = metadata.permissions().mode();
+        if metadata.file_type().is_symlink() {
+            // Symbolic link.
+            Ok("120000")
+        } else if mode & 0o111 != 0 {
             // Executable file.
             Ok("100755")
         } else {
@@ -48,7 +53,12 @@ impl Blob {
     /// a text file's path, saving it's content,
     /// sha1 hash and file mode.
     pub fn new(path: &PathBuf) -> io::Result<Self> {
-        let content = fs::read_to_string(path)?;
+        // Symlinks store their target as content.
+        let content = match fs::read_link(path) {
+            Ok(target) => target.to_string_lossy().to_string(),
+            Err(_) => fs::read_to_string(path)?,
+        };
+
         let content = format!("blob {}\0{}", content.len(), content);
 
         // Compute this blob's hash and mode.
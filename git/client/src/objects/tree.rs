@@ -66,7 +66,7 @@ impl Tree {
                 Some(name) => String::from(name),
             };
 
-            files.push(if path.is_dir() {
+            files.push(if path.is_dir() && !path.is_symlink() {
                 (name, Box::new(Self::new(&path)?))
             } else {
                 (name, Box::new(Blob::new(&path)?))
@@ -0,0 +1,355 @@
+use super::apply::apply_to_work_dir;
+use super::checkout::refactor_root_dir;
+use super::commands::{get_head, read_index, read_tree, update_head, write_index, write_tree};
+use super::commit::commit_with_author;
+use super::diff::diff_tree::diff_tree;
+use super::log::LogCommit;
+use super::patch::parse_patch;
+use crate::commands::{ls_tree, AmCommand};
+use crate::io_err;
+use chrono::DateTime;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+use utils::git_dir;
+use utils::plumbing::ls_tree::hash_to_str;
+
+const AM_DIR: &str = "rebase-apply";
+const NEXT: &str = "next";
+const LAST: &str = "last";
+const ORIG_HEAD: &str = "orig-head";
+
+const RESOLVE_HINTS: &str = "\
+When you have resolved this problem, run \"git am --continue\".
+If you prefer to skip this patch, run \"git am --skip\" instead.
+To restore the original branch and stop patching, run \"git am --abort\".";
+
+/// A patch sent by mail.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mail {
+    /// Author as `Name <mail>`.
+    pub author: String,
+    /// Date as a unix time and offset, like commits have it.
+    pub date: String,
+    /// Subject without the `[PATCH]` prefix.
+    pub subject: String,
+    pub message: String,
+    /// Everything after the message, the diff included.
+    pub patch: String,
+}
+
+// Returns the path of a file of the session, they are kept
+// with the HEAD of the worktree.
+fn state_file(name: &str) -> String {
+    format!("{}/{AM_DIR}/{name}", git_dir())
+}
+
+fn in_progress() -> bool {
+    Path::new(&state_file("")).exists()
+}
+
+fn read_number(name: &str) -> io::Result<usize> {
+    fs::read_to_string(state_file(name))?
+        .trim()
+        .parse()
+        .map_err(|_| io_err!("Invalid am state"))
+}
+
+/// Splits a mailbox into it's mails. Every mail starts with a
+/// `From ` line followed by the headers.
+pub fn split_mbox(text: &str) -> Vec<String> {
+    let lines: Vec<_> = text.split_inclusive('\n').collect();
+    let mut mails: Vec<String> = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let starts_mail = line.starts_with("From ")
+            && lines
+                .get(i + 1)
+                .is_some_and(|next| next.split_once(':').is_some_and(|(h, _)| !h.contains(' ')));
+
+        match mails.last_mut() {
+            Some(mail) if !starts_mail => mail.push_str(line),
+            _ if starts_mail => mails.push(String::new()),
+            // Text before the first mail.
+            _ => {}
+        }
+    }
+
+    mails
+}
+
+// Removes the `[PATCH n/m]` and `Re:` prefixes of a subject.
+fn strip_subject(mut subject: &str) -> &str {
+    loop {
+        subject = subject.trim_start();
+        if let Some((_, rest)) = subject
+            .strip_prefix('[')
+            .and_then(|subject| subject.split_once(']'))
+        {
+            subject = rest;
+        } else if let Some(rest) = subject.strip_prefix("Re:").or(subject.strip_prefix("re:")) {
+            subject = rest;
+        } else {
+            return subject.trim_end();
+        }
+    }
+}
+
+/// Parses a mail, the `From ` line already removed.
+pub fn parse_mail(mail: &str) -> io::Result<Mail> {
+    let (headers, body) = mail.split_once("\n\n").unwrap_or((mail, ""));
+
+    // Long headers continue in lines starting with whitespace.
+    let mut unfolded: Vec<String> = vec![];
+    for line in headers.lines() {
+        match unfolded.last_mut() {
+            Some(last) if line.starts_with([' ', '\t']) => last.push_str(line),
+            _ => unfolded.push(line.to_string()),
+        }
+    }
+
+    let mut ret = Mail::default();
+    for header in &unfolded {
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "from" => ret.author = value.to_string(),
+            "subject" => ret.subject = strip_subject(value).to_string(),
+            "date" => {
+                let date = DateTime::parse_from_rfc2822(value).map_err(|_| {
+                    io::Error::new(ErrorKind::InvalidData, format!("invalid date: {value}"))
+                })?;
+                ret.date = date.format("%s %z").to_string();
+            }
+            _ => {}
+        }
+    }
+
+    if ret.author.is_empty() || ret.date.is_empty() {
+        return Err(io_err!("Patch does not have a valid e-mail address."));
+    }
+
+    // The message ends where the diff, or the stat before it, starts.
+    let end = body
+        .split_inclusive('\n')
+        .scan(0, |pos, line| {
+            let start = *pos;
+            *pos += line.len();
+            Some((start, line))
+        })
+        .find(|(_, line)| line.trim_end() == "---" || line.starts_with("diff -"))
+        .map_or(body.len(), |(start, _)| start);
+
+    let message = body[..end].trim();
+    ret.message = match message.is_empty() {
+        true => ret.subject.clone(),
+        false => format!("{}\n\n{message}", ret.subject),
+    };
+    ret.patch = body[end..].to_string();
+
+    Ok(ret)
+}
+
+// Commits the index as the mail, moving HEAD to it.
+fn commit_mail(mail: &Mail) -> io::Result<()> {
+    let hash = commit_with_author(
+        &mail.author,
+        &mail.date,
+        &mail.message,
+        get_head().as_deref(),
+    )?;
+    update_head(&hash)?;
+
+    let mut index = read_index().unwrap_or_default();
+    index.unstage_all();
+    write_index(index)
+}
+
+// Applies the mails from the next one to the last, stopping at
+// the first that doesn't apply.
+fn run() -> io::Result<String> {
+    let last = read_number(LAST)?;
+    let mut output = String::new();
+
+    for n in read_number(NEXT)?..=last {
+        fs::write(state_file(NEXT), format!("{n}\n"))?;
+        let mail = parse_mail(&fs::read_to_string(state_file(&format!("{n:04}")))?)?;
+        output.push_str(&format!("Applying: {}\n", mail.subject));
+
+        let patches = parse_patch(&mail.patch)?;
+        if patches.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{output}Patch is empty.\n{RESOLVE_HINTS}"),
+            ));
+        }
+
+        let mut index = read_index().unwrap_or_default();
+        if let Err(e) = apply_to_work_dir(&patches, &mut index) {
+            let msg = format!(
+                "{output}{e}\nPatch failed at {n:04} {}\n{RESOLVE_HINTS}",
+                mail.subject
+            );
+            return Err(io::Error::new(ErrorKind::InvalidData, msg));
+        }
+
+        write_index(index)?;
+        commit_mail(&mail)?;
+    }
+
+    fs::remove_dir_all(state_file(""))?;
+    Ok(output)
+}
+
+// Starts applying the mails of the mailbox.
+fn start(path: &str) -> io::Result<String> {
+    if in_progress() {
+        return Err(io_err!(
+            "previous rebase directory .git/rebase-apply still exists but mbox given."
+        ));
+    }
+
+    let mails = split_mbox(&fs::read_to_string(path)?);
+    if mails.is_empty() {
+        return Err(io_err!("Patch format detection failed."));
+    }
+
+    fs::create_dir_all(state_file(""))?;
+    for (i, mail) in mails.iter().enumerate() {
+        fs::write(state_file(&format!("{:04}", i + 1)), mail)?;
+    }
+
+    fs::write(state_file(NEXT), "1\n")?;
+    fs::write(state_file(LAST), format!("{}\n", mails.len()))?;
+    fs::write(state_file(ORIG_HEAD), get_head().unwrap_or_default())?;
+    run()
+}
+
+// Commits the changes staged for the current mail and goes on.
+fn resume() -> io::Result<String> {
+    let n = read_number(NEXT)?;
+    let mail = parse_mail(&fs::read_to_string(state_file(&format!("{n:04}")))?)?;
+
+    let tree = hash_to_str(&write_tree()?);
+    if get_head().is_some_and(|head| LogCommit::read(&head).is_ok_and(|c| c.tree == tree)) {
+        let msg = format!(
+            "Applying: {}\nNo changes - did you forget to use 'git add'?\n{RESOLVE_HINTS}",
+            mail.subject
+        );
+        return Err(io::Error::new(ErrorKind::InvalidData, msg));
+    }
+
+    commit_mail(&mail)?;
+    fs::write(state_file(NEXT), format!("{}\n", n + 1))?;
+    Ok(format!("Applying: {}\n{}", mail.subject, run()?))
+}
+
+// Moves HEAD, the index and the working directory back to the
+// commit they were at before the session.
+fn abort() -> io::Result<String> {
+    let orig = fs::read_to_string(state_file(ORIG_HEAD))?;
+    let orig = orig.trim();
+
+    if let Some(head) = get_head() {
+        let head_tree = ls_tree(&LogCommit::read(&head)?.tree)?;
+        let (orig_root, orig_tree) = match orig {
+            "" => (None, String::new()),
+            orig => {
+                let root = LogCommit::read(orig)?.tree;
+                let tree = ls_tree(&root)?;
+                (Some(root), tree)
+            }
+        };
+
+        refactor_root_dir(diff_tree(&head_tree, &orig_tree).collect(), ".")?;
+        match orig_root {
+            Some(root) => {
+                write_index(read_tree(&root, "")?)?;
+                update_head(orig)?;
+            }
+            None => write_index(Default::default())?,
+        }
+    }
+
+    fs::remove_dir_all(state_file(""))?;
+    Ok(String::new())
+}
+
+/// Underlying implementation of `git am`. Applies the patches of a
+/// mailbox as commits, keeping the author, date and message of every
+/// mail. The session is kept in `.git/rebase-apply` while a patch
+/// doesn't apply.
+pub fn __am(cmd: AmCommand) -> io::Result<String> {
+    if !matches!(cmd, AmCommand::Apply(_)) && !in_progress() {
+        return Err(io_err!(
+            "Resolve operation not in progress, we are not resuming."
+        ));
+    }
+
+    match cmd {
+        AmCommand::Apply(path) => start(&path),
+        AmCommand::Continue => resume(),
+        AmCommand::Skip => {
+            // Patches that fail leave the working directory untouched.
+            let n = read_number(NEXT)?;
+            fs::write(state_file(NEXT), format!("{}\n", n + 1))?;
+            run()
+        }
+        AmCommand::Abort => abort(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MBOX: &str = "\
+From 1a2b3c Mon Sep 17 00:00:00 2001
+From: Pepito <pepito@fi.uba.ar>
+Date: Fri, 3 Nov 2023 13:13:41 -0300
+Subject: [PATCH 1/2] Fix the
+ parser
+
+It failed on empty lines.
+---
+diff --git a/a b/a
+index 8baef1b..7898192 100644
+--- a/a
++++ b/a
+@@ -1 +1 @@
+-abc
++a
+--
+2.39.2
+
+From 4d5e6f Mon Sep 17 00:00:00 2001
+From: Pepito <pepito@fi.uba.ar>
+Date: Fri, 3 Nov 2023 13:14:00 -0300
+Subject: [PATCH 2/2] Add b
+
+diff --git a/b b/b
+new file mode 100644
+";
+
+    #[test]
+    fn mails() {
+        let mails = split_mbox(MBOX);
+        assert_eq!(mails.len(), 2);
+
+        let mail = parse_mail(&mails[0]).unwrap();
+        assert_eq!(mail.author, "Pepito <pepito@fi.uba.ar>");
+        assert_eq!(mail.date, "1699028021 -0300");
+        assert_eq!(mail.subject, "Fix the parser");
+        assert_eq!(mail.message, "Fix the parser\n\nIt failed on empty lines.");
+        assert!(mail.patch.starts_with("---\ndiff --git a/a b/a\n"));
+
+        let mail = parse_mail(&mails[1]).unwrap();
+        assert_eq!(mail.message, "Add b");
+        assert!(mail.patch.starts_with("diff --git a/b b/b\n"));
+    }
+}
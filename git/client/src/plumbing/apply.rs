@@ -0,0 +1,449 @@
+use super::commands::{read_index, write_index};
+use super::patch::{parse_patch, FilePatch, Hunk, HunkLine};
+use crate::io_err;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+use utils::common_dir;
+use utils::index_file::index::Index;
+use utils::object::object_db::get_object;
+use utils::plumbing::ls_tree::hash_to_str;
+
+const DEFAULT_MODE: &str = "100644";
+
+// Most context lines ignored around a hunk that doesn't match.
+const MAX_FUZZ: usize = 2;
+
+/// Options of the apply command.
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    /// Only checks that the patch applies.
+    pub check: bool,
+    /// Applies the patch to the index, not the working directory.
+    pub cached: bool,
+    /// Undoes the patch.
+    pub reverse: bool,
+}
+
+/// Where a hunk was applied: the line it starts at, how many lines
+/// away from where it said and how many context lines it ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub line: usize,
+    pub offset: isize,
+    pub fuzz: usize,
+}
+
+// Returns the number of context lines before and after the changes.
+fn context_len(hunk: &Hunk) -> (usize, usize) {
+    let is_context = |line: &&HunkLine| matches!(line, HunkLine::Context(_));
+    let before = hunk.lines.iter().take_while(is_context).count();
+    let after = hunk.lines.iter().rev().take_while(is_context).count();
+    (before, after.min(hunk.lines.len() - before))
+}
+
+// Finds where the lines match the file, from the given position on,
+// nearest to where they were expected. Hunks at the start or the end
+// of a file are tried there first.
+fn find_lines(
+    lines: &[&str],
+    old: &[&str],
+    pos: usize,
+    expected: usize,
+    anchor: Option<usize>,
+) -> Option<usize> {
+    let matches = |at: usize| at >= pos && lines.get(at..at + old.len()) == Some(old);
+    if let Some(at) = anchor.filter(|&at| matches(at)) {
+        return Some(at);
+    }
+
+    let last = lines.len().checked_sub(old.len())?;
+    if pos > last {
+        return None;
+    }
+
+    let expected = expected.clamp(pos, last);
+    let max = (expected - pos).max(last - expected);
+    (0..=max)
+        .flat_map(|d| [expected.checked_add(d), expected.checked_sub(d)])
+        .flatten()
+        .find(|&at| matches(at))
+}
+
+/// Applies the hunks to the content. Hunks that aren't where they say
+/// are searched for nearby, and if they still don't match up to
+/// `MAX_FUZZ` context lines around them are ignored. Returns the new
+/// content and where every hunk was applied, None if it was rejected.
+pub fn apply_hunks(content: &str, hunks: &[Hunk]) -> (String, Vec<Option<Placement>>) {
+    let lines: Vec<_> = content.split_inclusive('\n').collect();
+    let mut ret = String::new();
+    let mut placements = vec![];
+    let mut pos = 0;
+
+    for hunk in hunks {
+        let (old, new) = (hunk.old_lines(), hunk.new_lines());
+        let (before, after) = context_len(hunk);
+
+        // Hunks that only add lines start after the line they name.
+        let start = match hunk.old_len {
+            0 => hunk.old_start,
+            _ => hunk.old_start.saturating_sub(1),
+        };
+
+        let mut placement = None;
+        for fuzz in 0..=MAX_FUZZ.min(before.max(after)) {
+            let (skip, trim) = (fuzz.min(before), fuzz.min(after));
+            let anchor = match (start, after) {
+                _ if fuzz > 0 => None,
+                (0, _) => Some(0),
+                (_, 0) => lines.len().checked_sub(old.len()),
+                _ => None,
+            };
+
+            // The context lines that are ignored must still fit in the file.
+            let old = &old[skip..old.len() - trim];
+            if let Some(at) = find_lines(&lines, old, pos.max(skip), start + skip, anchor) {
+                ret.extend(lines[pos..at].iter().copied());
+                ret.extend(new[skip..new.len() - trim].iter().copied());
+                pos = at + old.len();
+
+                let at = at - skip;
+                placement = Some(Placement {
+                    line: at + 1,
+                    offset: at as isize - start as isize,
+                    fuzz,
+                });
+                break;
+            }
+        }
+
+        placements.push(placement);
+    }
+
+    ret.extend(lines[pos..].iter().copied());
+    (ret, placements)
+}
+
+/// The result of applying a patch to a file: the path it's
+/// removed from and the path, mode and content it's written to.
+#[derive(Debug)]
+pub struct Applied {
+    pub removed: Option<String>,
+    pub written: Option<(String, String, String)>,
+}
+
+// Returns the content of the file in the index.
+fn read_from_index(index: &Index, path: &str) -> io::Result<String> {
+    let entry = index.get(path).ok_or(io::Error::new(
+        ErrorKind::NotFound,
+        format!("error: {path}: does not exist in index"),
+    ))?;
+
+    let (_, _, data) = get_object(&hash_to_str(entry.get_hash()))?;
+    Ok(String::from_utf8_lossy(&data).to_string())
+}
+
+// Returns the content of the file in the working directory.
+fn read_from_work_dir(path: &str) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("error: {path}: does not exist in working directory"),
+        )),
+        content => content,
+    }
+}
+
+// Applies the patch to the file in the working directory, or in the
+// index if cached. Returns what to write and the hunks that didn't
+// apply where they said.
+fn apply_file(patch: &FilePatch, index: &Index, cached: bool) -> io::Result<(Applied, String)> {
+    let old = match &patch.old_path {
+        Some(path) if cached => read_from_index(index, path)?,
+        Some(path) => read_from_work_dir(path)?,
+        None => String::new(),
+    };
+
+    if let (None, Some(path)) = (&patch.old_path, &patch.new_path) {
+        if cached && index.contains_key(path) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("error: {path}: already exists in index"),
+            ));
+        }
+        if !cached && Path::new(path).exists() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("error: {path}: already exists in working directory"),
+            ));
+        }
+    }
+
+    let (new, placements) = apply_hunks(&old, &patch.hunks);
+    let path = patch.path();
+
+    let mut rejected = String::new();
+    let mut notes = String::new();
+    for (i, (hunk, placement)) in patch.hunks.iter().zip(placements).enumerate() {
+        match placement {
+            None => rejected.push_str(&format!("error: patch failed: {path}:{}\n", hunk.old_start)),
+            Some(Placement { line, offset, fuzz }) if offset != 0 || fuzz != 0 => {
+                notes.push_str(&format!("Hunk #{} succeeded at {line}", i + 1));
+                if fuzz != 0 {
+                    notes.push_str(&format!(" with fuzz {fuzz}"));
+                }
+                match offset {
+                    0 => notes.push_str(".\n"),
+                    1 | -1 => notes.push_str(&format!(" (offset {offset} line).\n")),
+                    _ => notes.push_str(&format!(" (offset {offset} lines).\n")),
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    if patch.new_path.is_none() && rejected.is_empty() && !new.is_empty() {
+        rejected.push_str(&format!(
+            "error: {path}: removal patch leaves file contents\n"
+        ));
+    }
+
+    if !rejected.is_empty() {
+        rejected.push_str(&format!("error: {path}: patch does not apply"));
+        return Err(io::Error::new(ErrorKind::InvalidData, rejected));
+    }
+
+    let old_mode = patch
+        .old_path
+        .as_ref()
+        .and_then(|path| index.get(path))
+        .map(|entry| entry.get_mode());
+
+    let written = patch.new_path.as_ref().map(|path| {
+        let mode = patch
+            .new_mode
+            .as_deref()
+            .or(old_mode)
+            .unwrap_or(DEFAULT_MODE);
+        (path.clone(), mode.to_string(), new)
+    });
+
+    let removed = match (&patch.old_path, &patch.new_path) {
+        (Some(old), new) if new.as_ref() != Some(old) => Some(old.clone()),
+        _ => None,
+    };
+
+    Ok((Applied { removed, written }, notes))
+}
+
+/// Applies every patch, without writing them. Returns the results
+/// and the hunks that didn't apply where they said. Fails with all
+/// the rejected hunks if any of them doesn't apply.
+pub fn apply_all(
+    patches: &[FilePatch],
+    index: &Index,
+    cached: bool,
+) -> io::Result<(Vec<Applied>, String)> {
+    let mut applied = vec![];
+    let mut notes = String::new();
+    let mut errors = vec![];
+
+    for patch in patches {
+        match apply_file(patch, index, cached) {
+            Ok((file, file_notes)) => {
+                applied.push(file);
+                notes.push_str(&file_notes);
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidData, errors.join("\n")));
+    }
+
+    Ok((applied, notes))
+}
+
+// Writes the file to the working directory with the given mode.
+fn write_file(path: &str, mode: &str, content: &str) -> io::Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, content)?;
+    let perm = if mode == "100755" { 0o755 } else { 0o644 };
+    fs::set_permissions(path, fs::Permissions::from_mode(perm))
+}
+
+/// Writes the results to the working directory, the index or both.
+pub fn write_applied(
+    applied: &[Applied],
+    mut index: Option<&mut Index>,
+    work_dir: bool,
+) -> io::Result<()> {
+    for applied in applied {
+        if let Some(path) = &applied.removed {
+            if work_dir {
+                match fs::remove_file(path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            if let Some(index) = index.as_deref_mut() {
+                index.remove(path);
+            }
+        }
+
+        if let Some((path, mode, content)) = &applied.written {
+            if work_dir {
+                write_file(path, mode, content)?;
+            }
+
+            match index.as_deref_mut() {
+                Some(index) if work_dir => index.add(path.clone(), false, true)?,
+                Some(index) => {
+                    let data = content.as_bytes().to_vec();
+                    index.add_from_repo(path.clone(), &common_dir(), mode, data, true)?;
+                }
+                None => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the patches to the working directory and the index.
+/// Nothing is changed unless every one of them applies.
+pub fn apply_to_work_dir(patches: &[FilePatch], index: &mut Index) -> io::Result<String> {
+    let (applied, notes) = apply_all(patches, index, false)?;
+    write_applied(&applied, Some(index), true)?;
+    Ok(notes)
+}
+
+/// Underlying implementation of `git apply`. Applies the patch in the
+/// file to the working directory, or to the index if cached. Returns
+/// the hunks that didn't apply where they said.
+pub fn __apply(path: &str, opts: &ApplyOptions) -> io::Result<String> {
+    let mut patches = parse_patch(&fs::read_to_string(path)?)?;
+    if patches.is_empty() {
+        return Err(io_err!("No valid patches in input"));
+    }
+
+    if opts.reverse {
+        patches.iter_mut().for_each(FilePatch::reverse);
+    }
+
+    let mut index = read_index().unwrap_or_default();
+    let (applied, notes) = apply_all(&patches, &index, opts.cached)?;
+    if opts.check {
+        return Ok(notes);
+    }
+
+    match opts.cached {
+        true => {
+            write_applied(&applied, Some(&mut index), false)?;
+            write_index(index)?;
+        }
+        false => write_applied(&applied, None, true)?,
+    }
+
+    Ok(notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "\
+diff --git a/src/main.rs b/src/main.rs
+index 3b18e51..a5c1966 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,3 @@
+ fn main() {
+-    println!(\"hello\");
++    println!(\"hello world\");
+ }
+\\ No newline at end of file
+diff --git a/run.sh b/run.sh
+new file mode 100755
+index 0000000..e69de29
+--- /dev/null
++++ b/run.sh
+@@ -0,0 +1 @@
++cargo run
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+index 8baef1b..0000000
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-abc
+";
+
+    #[test]
+    fn apply() {
+        let patches = parse_patch(PATCH).unwrap();
+        let apply = |content: &str, patch: &FilePatch| {
+            let (new, placements) = apply_hunks(content, &patch.hunks);
+            placements.iter().all(Option::is_some).then_some(new)
+        };
+
+        let old = "fn main() {\n    println!(\"hello\");\n}";
+        let new = apply(old, &patches[0]).unwrap();
+        assert_eq!(new, "fn main() {\n    println!(\"hello world\");\n}");
+
+        assert_eq!(apply("", &patches[1]).unwrap(), "cargo run\n");
+        assert_eq!(apply("abc\n", &patches[2]).unwrap(), "");
+        assert_eq!(apply("fn main() {}\n", &patches[0]), None);
+
+        let mut reversed = patches[0].clone();
+        reversed.reverse();
+        assert_eq!(apply(&new, &reversed).unwrap(), old);
+    }
+
+    #[test]
+    fn offset_and_fuzz() {
+        let patch = "\
+--- a/f
++++ b/f
+@@ -2,7 +2,7 @@
+ b
+ c
+ d
+-e
++E
+ f
+ g
+ h
+";
+        let hunks = &parse_patch(patch).unwrap()[0].hunks;
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+
+        let (_, placements) = apply_hunks(old, hunks);
+        assert_eq!(placements[0].unwrap().offset, 0);
+
+        let (new, placements) = apply_hunks(&format!("x\ny\n{old}"), hunks);
+        assert_eq!(new, format!("x\ny\n{}", old.replace("e\n", "E\n")));
+        assert_eq!(placements[0].unwrap().offset, 2);
+
+        let (new, placements) = apply_hunks(&old.replace("b\n", "B\n"), hunks);
+        assert_eq!(new, "a\nB\nc\nd\nE\nf\ng\nh\ni\n");
+        assert_eq!(placements[0].unwrap().fuzz, 1);
+
+        let (_, placements) = apply_hunks(&old.replace("d\n", "D\n"), hunks);
+        assert_eq!(placements[0], None);
+
+        // Ignoring context can't place a hunk before the file starts.
+        let patch = "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n b\n-c\n+C\n";
+        let hunks = &parse_patch(patch).unwrap()[0].hunks;
+        let (new, placements) = apply_hunks("b\nc\n", hunks);
+        assert_eq!(placements[0], None);
+        assert_eq!(new, "b\nc\n");
+    }
+}
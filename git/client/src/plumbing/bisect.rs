@@ -0,0 +1,541 @@
+use super::checkout::__checkout;
+use super::commands::get_head;
+use super::heads::get_head_name;
+use super::log::{format_commit, LogCommit, LogFormat};
+use super::refs::rev_parse;
+use crate::commands::BisectCommand;
+use crate::io_err;
+use std::{
+    collections::{BinaryHeap, HashSet},
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    process::Command,
+};
+use utils::plumbing::commit_graph::History;
+use utils::{common_dir, git_dir};
+
+const BISECT_START: &str = "BISECT_START";
+const BISECT_BAD: &str = "BISECT_BAD";
+const BISECT_GOOD: &str = "BISECT_GOOD";
+const BISECT_SKIP: &str = "BISECT_SKIP";
+const BISECT_LOG: &str = "BISECT_LOG";
+
+// Exit code of `bisect run` commands that can't test a commit.
+const RUN_SKIP: i32 = 125;
+
+/// Result of marking a commit.
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// A good and a bad commit are needed to start.
+    Waiting,
+    /// The given commit is checked out to be tested, with the number
+    /// of commits left to test after it and the estimated steps.
+    Testing(String, usize, usize),
+    /// The first bad commit was found.
+    Found(String),
+    /// Only skipped commits are left, any of them may be the first bad one.
+    OnlySkipped(Vec<String>),
+}
+
+// Returns the path of a file of the search, they are kept
+// with the HEAD of the worktree.
+fn state_file(git_dir: &str, name: &str) -> String {
+    format!("{git_dir}/{name}")
+}
+
+fn is_bisecting(git_dir: &str) -> bool {
+    fs::metadata(state_file(git_dir, BISECT_START)).is_ok()
+}
+
+// Reads a file with a hash per line, a missing file has none.
+fn read_hashes(path: &str) -> io::Result<Vec<String>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(data.lines().map(String::from).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+fn append(path: &str, line: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{line}")
+}
+
+// Marks a commit as good, bad or skipped. The state is kept in
+// the git directory and the commits are read from the offset.
+fn mark(git_dir: &str, offset: &str, term: &str, hash: &str) -> io::Result<()> {
+    if !is_bisecting(git_dir) {
+        return Err(io_err!("You need to start by \"git bisect start\""));
+    }
+
+    match term {
+        "bad" => fs::write(state_file(git_dir, BISECT_BAD), format!("{hash}\n"))?,
+        "good" => append(&state_file(git_dir, BISECT_GOOD), hash)?,
+        _ => append(&state_file(git_dir, BISECT_SKIP), hash)?,
+    }
+
+    let subject = LogCommit::read_with_offset(hash, offset)?
+        .subject()
+        .to_string();
+    append(
+        &state_file(git_dir, BISECT_LOG),
+        &format!("# {term}: [{hash}] {subject}"),
+    )?;
+    append(
+        &state_file(git_dir, BISECT_LOG),
+        &format!("git bisect {term} {hash}"),
+    )
+}
+
+// Returns the commits that may be the first bad one: the bad commit
+// and it's ancestors that aren't ancestors of a good commit. They
+// are ordered from the newest.
+fn candidates(history: &History, bad: &str, good: &[String]) -> io::Result<Vec<String>> {
+    let mut excluded = HashSet::new();
+    let mut stack = good.to_vec();
+    while let Some(hash) = stack.pop() {
+        if excluded.insert(hash.clone()) {
+            stack.extend(history.commit(&hash)?.parents);
+        }
+    }
+
+    let mut candidates = vec![];
+    let mut seen = HashSet::from([bad.to_string()]);
+    let mut queue = BinaryHeap::from([(history.commit(bad)?.date, bad.to_string())]);
+
+    while let Some((_, hash)) = queue.pop() {
+        if excluded.contains(&hash) {
+            continue;
+        }
+
+        for parent in history.commit(&hash)?.parents {
+            if seen.insert(parent.clone()) {
+                queue.push((history.commit(&parent)?.date, parent));
+            }
+        }
+
+        candidates.push(hash);
+    }
+
+    Ok(candidates)
+}
+
+// Estimates the number of steps needed to bisect the given number
+// of commits, the same way git does.
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+
+    let n = all.ilog2() as usize;
+    let e = 1 << n;
+    match e < 3 * (all - e) {
+        true => n,
+        false => n - 1,
+    }
+}
+
+// Picks the commit to test, the one whose ancestors among the
+// candidates are closest to half of them. Returns it along with
+// the number of it's ancestors among the candidates.
+fn best_midpoint(
+    history: &History,
+    candidates: &[String],
+    skipped: &HashSet<String>,
+) -> io::Result<Option<(String, usize)>> {
+    let set: HashSet<_> = candidates.iter().collect();
+    let mut best: Option<(String, usize)> = None;
+    let mut best_distance = 0;
+
+    // The bad commit, the first one, is never tested.
+    for hash in candidates.iter().skip(1).filter(|h| !skipped.contains(*h)) {
+        let mut reach = HashSet::new();
+        let mut stack = vec![hash.clone()];
+        while let Some(hash) = stack.pop() {
+            if set.contains(&hash) && reach.insert(hash.clone()) {
+                stack.extend(history.commit(&hash)?.parents);
+            }
+        }
+
+        let distance = reach.len().min(candidates.len() - reach.len());
+        if best.is_none() || distance > best_distance {
+            best = Some((hash.clone(), reach.len()));
+            best_distance = distance;
+        }
+    }
+
+    Ok(best)
+}
+
+// Returns the next step of the search, the commit to test if there is one.
+fn next_step(git_dir: &str, offset: &str) -> io::Result<Step> {
+    let bad = read_hashes(&state_file(git_dir, BISECT_BAD))?
+        .into_iter()
+        .next();
+    let good = read_hashes(&state_file(git_dir, BISECT_GOOD))?;
+    let bad = match bad {
+        Some(bad) if !good.is_empty() => bad,
+        _ => return Ok(Step::Waiting),
+    };
+
+    let history = History::with_offset(offset);
+    for good in &good {
+        if !history.is_ancestor(good, &bad)? {
+            return Err(io_err!("A good commit isn't an ancestor of the bad one"));
+        }
+    }
+
+    let skipped: HashSet<_> = read_hashes(&state_file(git_dir, BISECT_SKIP))?
+        .into_iter()
+        .collect();
+    let candidates = candidates(&history, &bad, &good)?;
+
+    if candidates.len() == 1 {
+        let subject = LogCommit::read_with_offset(&bad, offset)?
+            .subject()
+            .to_string();
+        append(
+            &state_file(git_dir, BISECT_LOG),
+            &format!("# first bad commit: [{bad}] {subject}"),
+        )?;
+        return Ok(Step::Found(bad));
+    }
+
+    match best_midpoint(&history, &candidates, &skipped)? {
+        Some((hash, reach)) => {
+            // Testing it leaves either it's ancestors or the rest.
+            let all = candidates.len();
+            let left = (reach - 1).max(all - reach - 1);
+            Ok(Step::Testing(hash, left, estimate_steps(all)))
+        }
+        None => Ok(Step::OnlySkipped(
+            candidates
+                .into_iter()
+                .filter(|hash| *hash == bad || skipped.contains(hash))
+                .collect(),
+        )),
+    }
+}
+
+// Describes a step like git does.
+fn describe(git_dir: &str, offset: &str, step: &Step) -> io::Result<String> {
+    let ret = match step {
+        Step::Waiting => {
+            let bad = read_hashes(&state_file(git_dir, BISECT_BAD))?.is_empty();
+            let good = read_hashes(&state_file(git_dir, BISECT_GOOD))?.is_empty();
+            match (bad, good) {
+                (true, true) => "status: waiting for both good and bad commits\n".to_string(),
+                (true, false) => "status: waiting for bad commit, good commit known\n".to_string(),
+                _ => "status: waiting for good commit(s), bad commit known\n".to_string(),
+            }
+        }
+        Step::Testing(hash, left, steps) => {
+            let subject = LogCommit::read_with_offset(hash, offset)?
+                .subject()
+                .to_string();
+            format!(
+                "Bisecting: {left} revisions left to test after this (roughly {steps} steps)\n\
+                 [{hash}] {subject}\n"
+            )
+        }
+        Step::Found(hash) => {
+            let commit = LogCommit::read_with_offset(hash, offset)?;
+            let commit = format_commit(&commit, &LogFormat::Medium);
+            format!("{hash} is the first bad commit\n{commit}")
+        }
+        Step::OnlySkipped(hashes) => {
+            let mut ret = "There are only 'skip'ped commits left to test.\n\
+                           The first bad commit could be any of:\n"
+                .to_string();
+            for hash in hashes {
+                ret.push_str(&format!("{hash}\n"));
+            }
+            ret + "We cannot bisect more!\n"
+        }
+    };
+
+    Ok(ret)
+}
+
+// Checks out the commit to test, if there is one, and describes the step.
+fn step(step: Step) -> io::Result<String> {
+    if let Step::Testing(hash, ..) = &step {
+        __checkout(hash)?;
+    }
+
+    describe(&git_dir(), &common_dir(), &step)
+}
+
+// Runs the command on the checked out commits until the search ends.
+// Exiting with 0 marks the commit as good, 125 skips it and
+// 1 to 127 mark it as bad. Other codes abort the search.
+fn run(cmd: &[String]) -> io::Result<String> {
+    let (git_dir, offset) = (git_dir(), common_dir());
+    let (program, args) = cmd
+        .split_first()
+        .ok_or(io_err!("bisect run needs a command"))?;
+    if read_hashes(&state_file(&git_dir, BISECT_BAD))?.is_empty()
+        || read_hashes(&state_file(&git_dir, BISECT_GOOD))?.is_empty()
+    {
+        return Err(io_err!("bisect run needs a good and a bad commit"));
+    }
+
+    let mut ret = String::new();
+    loop {
+        ret.push_str(&format!("running {}\n", cmd.join(" ")));
+        let status = Command::new(program).args(args).status()?;
+        let term = match status.code() {
+            Some(0) => "good",
+            Some(RUN_SKIP) => "skip",
+            Some(1..=127) => "bad",
+            _ => return Err(io_err!("bisect run failed, the command was aborted")),
+        };
+
+        mark(&git_dir, &offset, term, &rev_parse("HEAD")?)?;
+        let next = next_step(&git_dir, &offset)?;
+        let found = matches!(next, Step::Found(_));
+        let testing = matches!(next, Step::Testing(..));
+        ret.push_str(&step(next)?);
+
+        if found {
+            return Ok(ret + "bisect found first bad commit\n");
+        } else if !testing {
+            return Ok(ret);
+        }
+    }
+}
+
+// Starts a search from the given branch or commit, marking
+// the bad and good commits if they are given.
+fn start(
+    git_dir: &str,
+    offset: &str,
+    head: &str,
+    bad: Option<String>,
+    good: Vec<String>,
+) -> io::Result<Step> {
+    if is_bisecting(git_dir) {
+        return Err(io_err!("Already bisecting, use reset first"));
+    }
+
+    fs::write(state_file(git_dir, BISECT_START), format!("{head}\n"))?;
+    append(&state_file(git_dir, BISECT_LOG), "git bisect start")?;
+
+    if let Some(bad) = bad {
+        mark(git_dir, offset, "bad", &bad)?;
+    }
+
+    for good in good {
+        mark(git_dir, offset, "good", &good)?;
+    }
+
+    next_step(git_dir, offset)
+}
+
+// Ends the search, removing it's state. Returns the
+// branch or commit it started from, if there was one.
+fn finish(git_dir: &str) -> io::Result<Option<String>> {
+    if !is_bisecting(git_dir) {
+        return Ok(None);
+    }
+
+    let start = fs::read_to_string(state_file(git_dir, BISECT_START))?;
+    for name in [
+        BISECT_START,
+        BISECT_BAD,
+        BISECT_GOOD,
+        BISECT_SKIP,
+        BISECT_LOG,
+    ] {
+        match fs::remove_file(state_file(git_dir, name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    Ok(Some(start.trim().to_string()))
+}
+
+// Ends the search, checking out the branch or commit it started from.
+fn reset() -> io::Result<String> {
+    let start = match finish(&git_dir())? {
+        Some(start) => start,
+        None => return Ok("We are not bisecting.\n".to_string()),
+    };
+
+    __checkout(&start)?;
+    Ok(format!("Previous HEAD position was restored to {start}\n"))
+}
+
+/// Underlying implementation of `git bisect`. Binary searches the
+/// history between a good and a bad commit for the first bad one,
+/// checking out the commits to test. The search is stored in
+/// `.git/BISECT_*` files until it's reset.
+pub fn __bisect(cmd: BisectCommand) -> io::Result<String> {
+    let (git_dir, offset) = (git_dir(), common_dir());
+    let resolve = |rev: Option<String>| rev_parse(rev.as_deref().unwrap_or("HEAD"));
+
+    match cmd {
+        BisectCommand::Start { bad, good } => {
+            let head = match get_head_name() {
+                Ok(branch) => branch,
+                Err(_) => get_head().ok_or(io_err!("HEAD is not pointing to any commit"))?,
+            };
+
+            let bad = bad.map(|bad| rev_parse(&bad)).transpose()?;
+            let good = good
+                .iter()
+                .map(|good| rev_parse(good))
+                .collect::<io::Result<_>>()?;
+            step(start(&git_dir, &offset, &head, bad, good)?)
+        }
+        BisectCommand::Good(rev) => {
+            mark(&git_dir, &offset, "good", &resolve(rev)?)?;
+            step(next_step(&git_dir, &offset)?)
+        }
+        BisectCommand::Bad(rev) => {
+            mark(&git_dir, &offset, "bad", &resolve(rev)?)?;
+            step(next_step(&git_dir, &offset)?)
+        }
+        BisectCommand::Skip(rev) => {
+            mark(&git_dir, &offset, "skip", &resolve(rev)?)?;
+            step(next_step(&git_dir, &offset)?)
+        }
+        BisectCommand::Reset => reset(),
+        BisectCommand::Log => {
+            if !is_bisecting(&git_dir) {
+                return Err(io_err!("We are not bisecting."));
+            }
+
+            fs::read_to_string(state_file(&git_dir, BISECT_LOG))
+        }
+        BisectCommand::Run(cmd) => run(&cmd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::plumbing::hash_object::__hash_object;
+
+    // Creates a repository with a linear history,
+    // returning it's commits from the oldest.
+    fn history(name: &str, len: usize) -> (String, Vec<String>) {
+        let offset = format!(
+            "{}/bisect-{name}-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let _ = fs::remove_dir_all(&offset);
+        fs::create_dir_all(format!("{offset}/objects")).unwrap();
+
+        let tree = __hash_object(b"", "tree", true, &offset).unwrap().1;
+        let mut commits: Vec<String> = vec![];
+        for i in 0..len {
+            let parent = match commits.last() {
+                Some(parent) => format!("parent {parent}\n"),
+                None => String::new(),
+            };
+            let time = 1700000000 + i;
+            let commit = format!(
+                "tree {tree}\n{parent}author pepito <pepito@fi.uba.ar> {time} -0300\n\
+                 committer pepito <pepito@fi.uba.ar> {time} -0300\n\ncommit {i}\n"
+            );
+            commits.push(
+                __hash_object(commit.as_bytes(), "commit", true, &offset)
+                    .unwrap()
+                    .1,
+            );
+        }
+
+        (offset, commits)
+    }
+
+    #[test]
+    fn start_good_bad_reset() {
+        let (offset, commits) = history("search", 8);
+        let dir = offset.as_str();
+
+        assert!(mark(dir, dir, "bad", &commits[7]).is_err());
+        assert_eq!(
+            start(dir, dir, "master", None, vec![]).unwrap(),
+            Step::Waiting
+        );
+        assert!(start(dir, dir, "master", None, vec![]).is_err());
+        assert_eq!(
+            describe(dir, dir, &Step::Waiting).unwrap(),
+            "status: waiting for both good and bad commits\n"
+        );
+
+        mark(dir, dir, "bad", &commits[7]).unwrap();
+        assert_eq!(next_step(dir, dir).unwrap(), Step::Waiting);
+        mark(dir, dir, "good", &commits[0]).unwrap();
+
+        // Commit 5 is the first bad one.
+        let mut step = next_step(dir, dir).unwrap();
+        assert!(matches!(step, Step::Testing(_, 3, 2)));
+
+        let mut tested = 0;
+        while let Step::Testing(hash, ..) = &step {
+            let i = commits.iter().position(|commit| commit == hash).unwrap();
+            let term = if i >= 5 { "bad" } else { "good" };
+            mark(dir, dir, term, hash).unwrap();
+
+            tested += 1;
+            step = next_step(dir, dir).unwrap();
+        }
+        assert_eq!(step, Step::Found(commits[5].clone()));
+        assert!(tested <= 3);
+
+        let log = fs::read_to_string(state_file(dir, BISECT_LOG)).unwrap();
+        assert!(log.starts_with("git bisect start\n"));
+        assert!(log.ends_with(&format!("# first bad commit: [{}] commit 5\n", commits[5])));
+
+        assert_eq!(finish(dir).unwrap(), Some("master".to_string()));
+        assert!(!is_bisecting(dir));
+        assert!(fs::metadata(state_file(dir, BISECT_GOOD)).is_err());
+        assert_eq!(finish(dir).unwrap(), None);
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+
+    #[test]
+    fn start_with_bad_and_good() {
+        let (offset, commits) = history("start", 3);
+        let dir = offset.as_str();
+
+        let step = start(
+            dir,
+            dir,
+            &commits[2],
+            Some(commits[2].clone()),
+            vec![commits[0].clone()],
+        )
+        .unwrap();
+        assert_eq!(step, Step::Testing(commits[1].clone(), 0, 0));
+
+        mark(dir, dir, "skip", &commits[1]).unwrap();
+        assert_eq!(
+            next_step(dir, dir).unwrap(),
+            Step::OnlySkipped(vec![commits[2].clone(), commits[1].clone()])
+        );
+
+        // Good commits must be ancestors of the bad one.
+        mark(dir, dir, "bad", &commits[0]).unwrap();
+        mark(dir, dir, "good", &commits[2]).unwrap();
+        assert!(next_step(dir, dir).is_err());
+
+        assert_eq!(finish(dir).unwrap(), Some(commits[2].clone()));
+        fs::remove_dir_all(offset).unwrap();
+    }
+
+    #[test]
+    fn steps() {
+        assert_eq!(estimate_steps(1), 0);
+        assert_eq!(estimate_steps(2), 0);
+        assert_eq!(estimate_steps(3), 1);
+        assert_eq!(estimate_steps(8), 2);
+        assert_eq!(estimate_steps(12), 3);
+        assert_eq!(estimate_steps(1024), 9);
+    }
+}
@@ -0,0 +1,336 @@
+use super::commit::{get_author_and_time, get_committer_and_time, get_parent_commits};
+use super::diff::lcs::{diff, FileDiff};
+use crate::io_err;
+use chrono::DateTime;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io;
+use utils::common_dir;
+use utils::object::object_db::get_object;
+use utils::plumbing::commit_graph::History;
+use utils::plumbing::ls_tree::get_entry_with_offset;
+
+/// A line of a file along with the commit that last changed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlameLine {
+    pub hash: String,
+    /// Line number in the commit that introduced it.
+    pub orig_line: usize,
+    /// Line number in the blamed revision.
+    pub line: usize,
+    pub content: String,
+}
+
+// Returns the content of the blob at path in the given tree, if any.
+fn file_at(tree: &str, path: &str) -> io::Result<Option<String>> {
+    match get_entry_with_offset(tree, path, &common_dir())? {
+        Some((otype, hash)) if otype == "blob" => {
+            let (_, _, data) = get_object(&hash)?;
+            Ok(Some(String::from_utf8_lossy(&data).to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Maps every line of new to the line of old it comes from, if any.
+fn line_map(old: &str, new: &str) -> Vec<Option<usize>> {
+    let mut map = vec![];
+    let mut i = 0;
+
+    for line in diff(old, new) {
+        match line {
+            FileDiff::Same(_) => {
+                map.push(Some(i));
+                i += 1;
+            }
+            FileDiff::Added(_) => map.push(None),
+            FileDiff::Removed(_) => i += 1,
+        }
+    }
+
+    map
+}
+
+/// Parses a `-L` line range of a file with len lines, returning the
+/// 1-based inclusive range. It is `<start>,<end>` or `<start>,+<count>`,
+/// a missing end blames until the end of the file.
+pub fn parse_line_range(range: &str, len: usize) -> io::Result<(usize, usize)> {
+    let (start, end) = range.split_once(',').unwrap_or((range, ""));
+    let start: usize = start.parse().map_err(|_| io_err!("Invalid line range"))?;
+
+    let end = match end {
+        "" => len,
+        end => match end.strip_prefix('+') {
+            Some(count) => match count.parse::<usize>() {
+                Ok(count) if count > 0 => start + count - 1,
+                _ => return Err(io_err!("Invalid line range")),
+            },
+            None => end.parse().map_err(|_| io_err!("Invalid line range"))?,
+        },
+    };
+
+    if start == 0 || start > end || end > len {
+        return Err(io_err!("Line range is outside the file"));
+    }
+
+    Ok((start, end))
+}
+
+/// Underlying implementation of `git blame`. Finds the commit that last
+/// changed every line of the file at path in the given commit, following
+/// only the first parent of merges unless all_parents is set.
+pub fn __blame(
+    path: &str,
+    rev: &str,
+    range: Option<&str>,
+    all_parents: bool,
+) -> io::Result<Vec<BlameLine>> {
+    let history = History::with_offset(&common_dir());
+    let commit = history.commit(rev)?;
+    let content = file_at(&commit.tree, path)?.ok_or(io_err!("No such path in revision"))?;
+    let lines: Vec<_> = content.lines().collect();
+
+    let (start, end) = match range {
+        Some(range) => parse_line_range(range, lines.len())?,
+        None => (1, lines.len()),
+    };
+
+    // Commits are visited children first, each carrying the lines
+    // still unblamed as (line in the revision, line in the commit).
+    let mut queue = BinaryHeap::from([(commit.generation, commit.date, rev.to_string())]);
+    let mut pending: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    pending.insert(rev.to_string(), (start - 1..end).map(|i| (i, i)).collect());
+
+    let mut blamed = HashMap::new();
+    while let Some((_, _, hash)) = queue.pop() {
+        let mut unblamed = match pending.remove(&hash) {
+            Some(lines) => lines,
+            None => continue,
+        };
+
+        let commit = history.commit(&hash)?;
+        let content = file_at(&commit.tree, path)?.unwrap_or_default();
+        let parents = match all_parents {
+            true => commit.parents,
+            false => commit.parents.into_iter().take(1).collect(),
+        };
+
+        // Lines that are in a parent are passed to it.
+        for parent in parents {
+            if unblamed.is_empty() {
+                break;
+            }
+
+            let parent_commit = history.commit(&parent)?;
+            let parent_content = match file_at(&parent_commit.tree, path)? {
+                Some(content) => content,
+                None => continue,
+            };
+
+            let map = line_map(&parent_content, &content);
+            let (passed, kept): (Vec<_>, Vec<_>) = unblamed
+                .into_iter()
+                .partition(|(_, line)| map.get(*line).copied().flatten().is_some());
+            unblamed = kept;
+
+            if !passed.is_empty() {
+                let lines = pending.entry(parent.clone()).or_default();
+                lines.extend(passed.into_iter().filter_map(|(l, i)| Some((l, map[i]?))));
+                queue.push((parent_commit.generation, parent_commit.date, parent));
+            }
+        }
+
+        // The rest were introduced by this commit.
+        for (line, orig_line) in unblamed {
+            blamed.insert(line, (hash.clone(), orig_line));
+        }
+    }
+
+    let mut result = vec![];
+    for (i, content) in lines.iter().enumerate().take(end).skip(start - 1) {
+        let (hash, orig_line) = blamed.remove(&i).ok_or(io_err!("Unblamed line"))?;
+        result.push(BlameLine {
+            hash,
+            orig_line: orig_line + 1,
+            line: i + 1,
+            content: content.to_string(),
+        });
+    }
+
+    Ok(result)
+}
+
+// Returns (name, mail, time, tz) of an author or committer line.
+fn split_identity(identity: Option<(String, String)>) -> (String, String, String, String) {
+    let (who, time) = identity.unwrap_or_default();
+    let (name, mail) = match who.split_once(" <") {
+        Some((name, mail)) => (name.to_string(), format!("<{mail}")),
+        None => (who, String::new()),
+    };
+
+    let (time, tz) = time.split_once(' ').unwrap_or((&time, ""));
+    (name, mail, time.to_string(), tz.to_string())
+}
+
+// Returns the first line of the commit message.
+fn get_summary(data: &[u8]) -> String {
+    let data = String::from_utf8_lossy(data);
+    let msg = data.split_once("\n\n").map(|(_, msg)| msg).unwrap_or("");
+    msg.lines().next().unwrap_or("").to_string()
+}
+
+// Groups consecutive lines coming from consecutive lines of the same
+// commit, returning the index of their first line and their length.
+fn groups(lines: &[BlameLine]) -> Vec<(usize, usize)> {
+    let mut groups: Vec<(usize, usize)> = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        match groups.last_mut() {
+            Some((start, len))
+                if lines[*start].hash == line.hash
+                    && lines[*start].orig_line + *len == line.orig_line =>
+            {
+                *len += 1
+            }
+            _ => groups.push((i, 1)),
+        }
+    }
+
+    groups
+}
+
+/// Formats blamed lines like git does by default:
+/// `<hash> (<author> <date> <line>) <content>`.
+/// Root commits are marked with a `^`.
+pub fn format_blame(lines: &[BlameLine]) -> io::Result<String> {
+    let width = lines.last().map(|l| l.line.to_string().len()).unwrap_or(1);
+    let mut commits = HashMap::new();
+    let mut ret = String::new();
+
+    for line in lines {
+        if !commits.contains_key(&line.hash) {
+            let (_, _, data) = get_object(&line.hash)?;
+            let (name, _, time, tz) = split_identity(get_author_and_time(&data));
+            let date = DateTime::parse_from_str(&format!("{time} {tz}"), "%s %z")
+                .map(|date| date.format("%Y-%m-%d %H:%M:%S %z").to_string())
+                .unwrap_or_default();
+            let hash = match get_parent_commits(&data) {
+                Some(_) => line.hash[..8].to_string(),
+                None => format!("^{}", &line.hash[..7]),
+            };
+
+            commits.insert(line.hash.clone(), format!("{hash} ({name} {date}"));
+        }
+
+        ret.push_str(&format!(
+            "{} {:>width$}) {}\n",
+            commits[&line.hash], line.line, line.content
+        ));
+    }
+
+    Ok(ret)
+}
+
+// Returns the first parent of the commit that has the file.
+fn previous(history: &History, hash: &str, path: &str) -> io::Result<Option<String>> {
+    for parent in history.commit(hash)?.parents {
+        if file_at(&history.commit(&parent)?.tree, path)?.is_some() {
+            return Ok(Some(parent));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Formats blamed lines in git's porcelain format, meant for tools.
+/// The first line of every group has it's length and the first line
+/// of every commit is followed by it's information.
+pub fn format_blame_porcelain(lines: &[BlameLine], path: &str) -> io::Result<String> {
+    let history = History::with_offset(&common_dir());
+    let mut seen = HashSet::new();
+    let mut ret = String::new();
+
+    for (start, len) in groups(lines) {
+        for (i, line) in lines[start..start + len].iter().enumerate() {
+            let BlameLine {
+                hash,
+                orig_line,
+                line,
+                content,
+            } = line;
+
+            if i > 0 {
+                ret.push_str(&format!("{hash} {orig_line} {line}\n\t{content}\n"));
+                continue;
+            }
+
+            ret.push_str(&format!("{hash} {orig_line} {line} {len}\n"));
+            if seen.insert(hash.clone()) {
+                let (_, _, data) = get_object(hash)?;
+                for (role, identity) in [
+                    ("author", get_author_and_time(&data)),
+                    ("committer", get_committer_and_time(&data)),
+                ] {
+                    let (name, mail, time, tz) = split_identity(identity);
+                    ret.push_str(&format!("{role} {name}\n{role}-mail {mail}\n"));
+                    ret.push_str(&format!("{role}-time {time}\n{role}-tz {tz}\n"));
+                }
+
+                ret.push_str(&format!("summary {}\n", get_summary(&data)));
+                match previous(&history, hash, path)? {
+                    Some(parent) => ret.push_str(&format!("previous {parent} {path}\n")),
+                    None if get_parent_commits(&data).is_none() => ret.push_str("boundary\n"),
+                    None => {}
+                }
+
+                ret.push_str(&format!("filename {path}\n"));
+            }
+
+            ret.push_str(&format!("\t{content}\n"));
+        }
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_lines() {
+        let old = "a\nb\nc\n";
+        let new = "a\nx\nc\nd\n";
+
+        assert_eq!(line_map(old, new), vec![Some(0), None, Some(2), None]);
+    }
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(parse_line_range("2,4", 10).unwrap(), (2, 4));
+        assert_eq!(parse_line_range("3,+2", 10).unwrap(), (3, 4));
+        assert_eq!(parse_line_range("7", 10).unwrap(), (7, 10));
+        assert!(parse_line_range("0,2", 10).is_err());
+        assert!(parse_line_range("4,2", 10).is_err());
+        assert!(parse_line_range("2,11", 10).is_err());
+    }
+
+    #[test]
+    fn porcelain_groups() {
+        let line = |hash: &str, orig_line, line| BlameLine {
+            hash: hash.to_string(),
+            orig_line,
+            line,
+            content: String::new(),
+        };
+
+        let lines = [
+            line("a", 1, 1),
+            line("a", 2, 2),
+            line("b", 1, 3),
+            line("a", 5, 4),
+            line("a", 6, 5),
+        ];
+
+        assert_eq!(groups(&lines), vec![(0, 2), (2, 1), (3, 2)]);
+    }
+}
@@ -0,0 +1,372 @@
+use super::commands::get_head;
+use super::log::{__log, LogCommit, LogOptions};
+use crate::commands::BundleCommand;
+use crate::io_err;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufReader, ErrorKind, Read},
+    path::Path,
+};
+use utils::object::object_db::get_object;
+use utils::package::{pack::Pack, packentry::PackEntry};
+use utils::plumbing::ls_tree::{ls_tree, parse_ls_tree_entry};
+use utils::{common_dir, get_current_refs, parse_tag};
+
+const BUNDLE_SIGNATURE: &str = "# v2 git bundle\n";
+
+/// A bundle file: the references it has, the commits it needs the
+/// receiver to have and a pack with the objects.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bundle {
+    /// Commits missing from the pack, as (hash, subject).
+    pub prerequisites: Vec<(String, String)>,
+    /// References as (hash, name).
+    pub refs: Vec<(String, String)>,
+    pub pack: Vec<u8>,
+}
+
+impl Bundle {
+    /// Parses the bytes of a bundle file.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let data = data
+            .strip_prefix(BUNDLE_SIGNATURE.as_bytes())
+            .ok_or(io_err!("Not a v2 bundle file"))?;
+
+        let mut bundle = Self::default();
+        let mut pos = 0;
+        loop {
+            let len = data[pos..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .ok_or(io_err!("Invalid bundle header"))?;
+            let line = String::from_utf8_lossy(&data[pos..pos + len]).to_string();
+            pos += len + 1;
+
+            // The header ends with an empty line.
+            if line.is_empty() {
+                break;
+            }
+
+            match line.strip_prefix('-') {
+                Some(line) => {
+                    let (hash, subject) = line.split_once(' ').unwrap_or((line, ""));
+                    bundle
+                        .prerequisites
+                        .push((hash.to_string(), subject.to_string()));
+                }
+                None => {
+                    let (hash, name) = line
+                        .split_once(' ')
+                        .ok_or(io_err!("Invalid bundle reference"))?;
+                    bundle.refs.push((hash.to_string(), name.to_string()));
+                }
+            }
+        }
+
+        bundle.pack = data[pos..].to_vec();
+        Ok(bundle)
+    }
+
+    /// Reads the bundle file at the given path.
+    pub fn read(path: &str) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Returns the bytes of the bundle file.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut data = BUNDLE_SIGNATURE.as_bytes().to_vec();
+        for (hash, subject) in &self.prerequisites {
+            data.extend(format!("-{hash} {subject}\n").as_bytes());
+        }
+        for (hash, name) in &self.refs {
+            data.extend(format!("{hash} {name}\n").as_bytes());
+        }
+
+        data.push(b'\n');
+        data.extend(&self.pack);
+        data
+    }
+
+    // Fails with the prerequisites missing from the repository.
+    fn check_prerequisites(&self) -> io::Result<()> {
+        let missing: Vec<_> = self
+            .prerequisites
+            .iter()
+            .filter(|(hash, _)| get_object(hash).is_err())
+            .map(|(hash, subject)| format!("error: {hash} {subject}"))
+            .collect();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let msg = format!(
+            "error: Repository lacks these prerequisite commits:\n{}",
+            missing.join("\n")
+        );
+        Err(io::Error::new(ErrorKind::NotFound, msg))
+    }
+
+    fn pack_reader(&self) -> BufReader<&[u8]> {
+        BufReader::with_capacity(self.pack.len(), self.pack.as_slice())
+    }
+}
+
+/// Returns true if the file at the given path is a bundle.
+pub fn is_bundle(path: &str) -> bool {
+    let mut signature = [0; BUNDLE_SIGNATURE.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|_| signature == BUNDLE_SIGNATURE.as_bytes())
+}
+
+/// Returns the name of the repository cloned from a bundle,
+/// `repo` for `path/to/repo.bundle`.
+pub fn bundle_repo_name(path: &str) -> io::Result<String> {
+    Path::new(path)
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or(io_err!("Invalid bundle name"))
+}
+
+/// Stores the objects of the bundle in the repository, returning it's
+/// references. Fails if the repository lacks any prerequisite.
+pub fn fetch_bundle(path: &str) -> io::Result<Vec<(String, String)>> {
+    let bundle = Bundle::read(path)?;
+    bundle.check_prerequisites()?;
+    Pack::unpack(bundle.pack_reader())?;
+    Ok(bundle.refs)
+}
+
+// Resolves a revision to the full name of the reference it is,
+// None if it isn't one.
+fn full_ref_name(rev: &str) -> io::Result<Option<String>> {
+    if rev == "HEAD" {
+        return Ok(Some(rev.to_string()));
+    }
+
+    let refs = get_current_refs()?;
+    Ok([
+        rev.to_string(),
+        format!("refs/{rev}"),
+        format!("refs/tags/{rev}"),
+        format!("refs/heads/{rev}"),
+        format!("refs/remotes/{rev}"),
+    ]
+    .into_iter()
+    .find(|name| name.starts_with("refs/") && refs.contains_key(name)))
+}
+
+// Returns the references named by the revisions, with the hash of
+// the object they point to. `--all` names every reference and HEAD.
+fn bundle_refs(revs: &[String]) -> io::Result<Vec<(String, String)>> {
+    let all = get_current_refs()?;
+    let mut refs = vec![];
+
+    for rev in revs {
+        if rev == "--all" {
+            let mut names: Vec<_> = all.iter().map(|(n, h)| (h.clone(), n.clone())).collect();
+            names.sort_by(|a, b| a.1.cmp(&b.1));
+            refs.extend(names);
+
+            if let Some(head) = get_head() {
+                refs.push((head, "HEAD".to_string()));
+            }
+            continue;
+        }
+
+        // The positive side of a range is listed too.
+        let rev = match rev.split_once("..") {
+            Some((_, "")) => "HEAD",
+            Some((_, rev)) => rev,
+            None if rev.starts_with('^') => continue,
+            None => rev,
+        };
+
+        if let Some(name) = full_ref_name(rev)? {
+            let hash = match all.get(&name) {
+                Some(hash) => hash.clone(),
+                None => get_head().ok_or(io_err!("HEAD is not pointing to any commit"))?,
+            };
+
+            if !refs.iter().any(|(_, n)| *n == name) {
+                refs.push((hash, name));
+            }
+        }
+    }
+
+    Ok(refs)
+}
+
+// Collects the hashes of the trees and blobs reachable from the tree.
+fn tree_objects(tree: &str, objects: &mut HashSet<String>) -> io::Result<()> {
+    if !objects.insert(tree.to_string()) {
+        return Ok(());
+    }
+
+    for line in ls_tree(tree)?.lines() {
+        let (_, otype, hash, _) = parse_ls_tree_entry(line);
+        match otype {
+            "tree" => tree_objects(hash, objects)?,
+            // Gitlinks point to commits of other repositories.
+            "blob" => {
+                objects.insert(hash.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// Adds the object to the entries unless it was added before.
+fn add_entry(
+    hash: &str,
+    seen: &mut HashSet<String>,
+    entries: &mut Vec<PackEntry>,
+) -> io::Result<()> {
+    if seen.insert(hash.to_string()) {
+        let (otype, _, data) = get_object(hash)?;
+        entries.push(PackEntry::new(&otype, data));
+    }
+
+    Ok(())
+}
+
+// Writes a bundle with the given references and the objects reachable
+// from the revisions, but not from the excluded ones.
+fn create(path: &str, revs: &[String]) -> io::Result<String> {
+    let refs = bundle_refs(revs)?;
+    if refs.is_empty() {
+        return Err(io_err!("Refusing to create empty bundle."));
+    }
+
+    let revs = revs
+        .iter()
+        .flat_map(|rev| match rev.as_str() {
+            "--all" => refs.iter().map(|(hash, _)| hash.clone()).collect(),
+            _ => vec![rev.clone()],
+        })
+        .collect();
+
+    let commits = __log(&LogOptions {
+        revs,
+        ..Default::default()
+    })?;
+    let listed: HashSet<_> = commits.iter().map(|commit| commit.hash.as_str()).collect();
+
+    // Parents left out are needed by the receiver, and so
+    // are all the objects they have.
+    let mut prerequisites: Vec<(String, String)> = vec![];
+    let mut seen = HashSet::new();
+    for commit in &commits {
+        for parent in &commit.parents {
+            if !listed.contains(parent.as_str()) && !prerequisites.iter().any(|(p, _)| p == parent)
+            {
+                let parent = LogCommit::read(parent)?;
+                tree_objects(&parent.tree, &mut seen)?;
+                prerequisites.push((parent.hash.clone(), parent.subject().to_string()));
+            }
+        }
+    }
+
+    let mut entries = vec![];
+    for commit in &commits {
+        add_entry(&commit.hash, &mut seen, &mut entries)?;
+
+        let mut objects = HashSet::new();
+        tree_objects(&commit.tree, &mut objects)?;
+        for object in objects {
+            add_entry(&object, &mut seen, &mut entries)?;
+        }
+    }
+
+    // Annotated tags are sent with the objects they point to.
+    for (hash, _) in &refs {
+        let mut hash = hash.clone();
+        loop {
+            let (otype, _, data) = get_object(&hash)?;
+            if otype != "tag" {
+                break;
+            }
+
+            add_entry(&hash, &mut seen, &mut entries)?;
+            hash = parse_tag(&data).map_err(|_| io_err!("Invalid tag object"))?;
+        }
+    }
+
+    let bundle = Bundle {
+        prerequisites,
+        refs,
+        pack: Pack::new(entries).as_bytes()?,
+    };
+    fs::write(path, bundle.as_bytes())?;
+    Ok(String::new())
+}
+
+// Lists the references, with a header when more than one is shown.
+fn list_refs(refs: &[(String, String)], what: &str) -> String {
+    let mut ret = match refs.len() {
+        1 => format!("The bundle {what} this ref:\n"),
+        n => format!("The bundle {what} these {n} refs:\n"),
+    };
+
+    for (hash, name) in refs {
+        ret.push_str(&format!("{hash} {name}\n"));
+    }
+
+    ret
+}
+
+// Checks that the bundle can be fetched into this repository.
+fn verify(path: &str) -> io::Result<String> {
+    let bundle = Bundle::read(path)?;
+    bundle.check_prerequisites()?;
+    Pack::read_with_offset(bundle.pack_reader(), &common_dir())?;
+
+    let mut ret = list_refs(&bundle.refs, "contains");
+    match bundle.prerequisites.len() {
+        0 => ret.push_str("The bundle records a complete history.\n"),
+        _ => ret.push_str(&list_refs(&bundle.prerequisites, "requires")),
+    }
+
+    ret.push_str(&format!("{path} is okay\n"));
+    Ok(ret)
+}
+
+/// Underlying implementation of `git bundle`. Bundles hold references
+/// and their objects in a single file, to move them without a server.
+pub fn __bundle(cmd: BundleCommand) -> io::Result<String> {
+    match cmd {
+        BundleCommand::Create { file, revs } => create(&file, &revs),
+        BundleCommand::Verify(file) => verify(&file),
+        BundleCommand::ListHeads(file) => Ok(Bundle::read(&file)?
+            .refs
+            .iter()
+            .map(|(hash, name)| format!("{hash} {name}\n"))
+            .collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_bytes() {
+        let bundle = Bundle {
+            prerequisites: vec![("a".repeat(40), "Fix the parser".to_string())],
+            refs: vec![
+                ("b".repeat(40), "refs/heads/master".to_string()),
+                ("b".repeat(40), "HEAD".to_string()),
+            ],
+            pack: b"PACK\0\0\0\x02".to_vec(),
+        };
+
+        let bytes = bundle.as_bytes();
+        assert!(bytes.starts_with(b"# v2 git bundle\n-aaaa"));
+        assert_eq!(Bundle::from_bytes(&bytes).unwrap(), bundle);
+        assert!(Bundle::from_bytes(b"# v3 git bundle\n\n").is_err());
+    }
+}
@@ -1,42 +1,75 @@
 use super::{
     super::commands::ls_tree,
+    super::config_file::{config::Config, config_entry::ConfigEntry},
     super::plumbing::{
-        commands::*, diff::diff_tree::diff_tree, diff::diff_type::*, work_dir::directify_tree,
+        commands::*,
+        diff::diff_tree::diff_tree,
+        diff::diff_type::*,
+        work_dir::{__directify_tree, write_blob},
     },
-    heads::update_head_reference,
+    fetch::add_to_refs,
+    heads::{detach_head, update_head_reference},
+    refs::rev_parse,
+    sparse::SparseCheckout,
 };
 use crate::io_err;
-use std::{
-    fs::{self, File},
-    io::{self, Write},
-};
+use std::{fs, io};
 use utils::object::object_db::get_object;
 use utils::plumbing::{commit::get_commit_root, ls_tree::parse_ls_tree_entry};
+use utils::{common_dir, read_ref_with_offset};
 
-/// Applies the given diffs to the working directory.
+/// Applies the given diffs to the working directory, only
+/// to the files in the sparse checkout if there is one.
 pub fn refactor_root_dir(diffs: Vec<Diff>, path: &str) -> io::Result<()> {
+    __refactor_root_dir(diffs, path, SparseCheckout::read()?.as_ref())
+}
+
+// Underlying implementation of refactor_root_dir.
+fn __refactor_root_dir(
+    diffs: Vec<Diff>,
+    path: &str,
+    sparse: Option<&SparseCheckout>,
+) -> io::Result<()> {
     for diff in diffs {
-        let (_, otype, hash, name) = parse_ls_tree_entry(&diff.line);
+        let (mode, otype, hash, name) = parse_ls_tree_entry(&diff.line);
         let path = path.to_string() + "/" + &name;
 
+        // Files left out of the sparse checkout aren't there.
+        let excluded = match otype {
+            "tree" => sparse.is_some_and(|sparse| !sparse.includes_dir(&path)),
+            _ => sparse.is_some_and(|sparse| !sparse.includes(&path)),
+        };
+
         use DiffType::*;
         match (diff.tag, otype) {
             (Unchanged, "blob" | "tree") => {}
+            (_, "blob" | "tree") if excluded => {}
+
+            // Submodule checkouts are only moved by `submodule update`,
+            // an initialized submodule is never removed.
+            (Unchanged | Modified(_), "commit") => {}
+            (Added, "commit") => fs::create_dir_all(&path)?,
+            (Removed, "commit") => match fs::read_dir(&path) {
+                Ok(mut entries) => {
+                    if entries.next().is_none() {
+                        fs::remove_dir(&path)?;
+                    }
+                }
+
+                // The submodule was never checked out.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            },
+
             (Removed, "blob") => fs::remove_file(&path)?,
             (Removed, "tree") => fs::remove_dir_all(&path)?,
 
             (Modified(line), "blob") => {
-                let (_, _, new_hash, _) = parse_ls_tree_entry(&line);
-                let mut file = File::create(path)?;
-                let (_, _, data) = get_object(new_hash)?;
-                file.write_all(&data)?;
+                let (new_mode, _, new_hash, _) = parse_ls_tree_entry(&line);
+                write_blob(&path, new_mode, new_hash)?;
             }
 
-            (Added, "blob") => {
-                let mut file = File::create(path)?;
-                let (_, _, data) = get_object(hash)?;
-                file.write_all(&data)?;
-            }
+            (Added, "blob") => write_blob(&path, mode, hash)?,
 
             (Modified(line), "tree") => {
                 let (_, _, other_hash, _) = parse_ls_tree_entry(&line);
@@ -48,12 +81,12 @@ pub fn refactor_root_dir(diffs: Vec<Diff>, path: &str) -> io::Result<()> {
                 // Calculate differences between
                 // trees and apply them to work dir.
                 let diffs = diff_tree(&cur_tree, &other_tree).collect();
-                refactor_root_dir(diffs, &path)?;
+                __refactor_root_dir(diffs, &path, sparse)?;
             }
 
             (Added, "tree") => {
                 fs::create_dir(&path)?;
-                directify_tree(hash, &path)?;
+                __directify_tree(hash, &path, sparse)?;
             }
 
             _ => return Err(io_err!("invalid object type")),
@@ -63,6 +96,28 @@ pub fn refactor_root_dir(diffs: Vec<Diff>, path: &str) -> io::Result<()> {
     Ok(())
 }
 
+// Creates the branch from the remote-tracking one of the remote that
+// has it, if there isn't one, setting it to merge from that remote.
+fn track_remote_branch(branch: &str) -> io::Result<()> {
+    if read_ref_with_offset(&format!("refs/heads/{branch}"), &common_dir()).is_ok() {
+        return Ok(());
+    }
+
+    let mut config = Config::read()?;
+    if config.branch(branch).is_some() {
+        return Ok(());
+    }
+
+    if let Some((remote, hash)) = get_remote_branch(branch, &config) {
+        add_to_refs(&format!("refs/heads/{branch}"), &hash)?;
+        config.add(ConfigEntry::new_branch(branch, &remote));
+        config.write()?;
+    }
+
+    Ok(())
+}
+
+/// Checks out a branch, or any other revision detaching HEAD.
 pub fn __checkout(branch: &str) -> io::Result<()> {
     // Get HEAD commit object.
     let head = get_head().ok_or(io_err!("HEAD is not pointing to any commit"))?;
@@ -70,7 +125,12 @@ pub fn __checkout(branch: &str) -> io::Result<()> {
 
     // Get commit object for
     // the branch to checkout.
-    let hash = get_branch(branch).ok_or(io_err!("branch does not exist"))?;
+    track_remote_branch(branch)?;
+    let branch_hash = get_branch(branch);
+    let hash = match &branch_hash {
+        Some(hash) => hash.clone(),
+        None => rev_parse(branch).map_err(|_| io_err!("branch does not exist"))?,
+    };
     let (otype, _, refs_commit) = get_object(&hash)?;
 
     // Validate object is a commit.
@@ -96,6 +156,43 @@ pub fn __checkout(branch: &str) -> io::Result<()> {
     write_index(index)?;
 
     // update HEAD to point to branch
-    update_head_reference(branch)?;
+    match branch_hash {
+        Some(_) => update_head_reference(branch)?,
+        None => detach_head(&hash)?,
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn removed_gitlinks() {
+        let dir = format!(
+            "{}/checkout-gitlink-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let removed = |name: &str| {
+            let line = format!("160000 commit {} {name}\0", "1".repeat(40));
+            vec![Diff::removed(line)]
+        };
+
+        // Submodules that were never checked out are skipped.
+        __refactor_root_dir(removed("missing"), &dir, None).unwrap();
+
+        // Empty directories are removed, checked out ones are kept.
+        fs::create_dir_all(format!("{dir}/empty")).unwrap();
+        fs::create_dir_all(format!("{dir}/full")).unwrap();
+        fs::write(format!("{dir}/full/a.txt"), "a").unwrap();
+        __refactor_root_dir(removed("empty"), &dir, None).unwrap();
+        __refactor_root_dir(removed("full"), &dir, None).unwrap();
+        assert!(!Path::new(&format!("{dir}/empty")).exists());
+        assert!(Path::new(&format!("{dir}/full/a.txt")).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
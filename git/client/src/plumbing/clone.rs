@@ -1,14 +1,17 @@
 use super::super::commands::{init, remote, RemoteCommand};
 use super::super::plumbing::commands::update_head;
+use super::bundle::{bundle_repo_name, is_bundle};
 use super::commands::read_tree;
 use super::commands::write_index;
 use super::fetch::__fetch;
 use super::heads::move_head;
+use super::local::local_git_dir;
 use super::work_dir::directify_tree;
 use crate::commands::branch;
+use crate::config_file::{config::Config, config_entry::ConfigEntry};
 use crate::io_err;
-use crate::protocol::parse_url;
-use std::{env, fs, io};
+use crate::protocol::{ext_command, parse_url};
+use std::{env, fs, io, path::Path};
 use utils::object::object_db::get_object;
 use utils::plumbing::commit::get_commit_root;
 
@@ -31,9 +34,26 @@ fn get_repo_name(repo: &str) -> io::Result<String> {
     }
 }
 
+// /path/to/repo, /path/to/repo/.git or /path/to/repo.git -> repo
+fn local_repo_name(git_dir: &str) -> io::Result<String> {
+    let path = git_dir.strip_suffix("/.git").unwrap_or(git_dir);
+    let name = Path::new(path)
+        .file_name()
+        .ok_or(io_err!("Invalid repo name"))?
+        .to_string_lossy();
+    Ok(name.strip_suffix(".git").unwrap_or(&name).to_string())
+}
+
 pub fn __clone(url: &str) -> io::Result<()> {
-    let (_, repo) = parse_url(url)?;
-    let repo = get_repo_name(&repo)?;
+    let repo = match local_git_dir(url) {
+        _ if is_bundle(url) => bundle_repo_name(url)?,
+        Some(git_dir) => local_repo_name(&git_dir)?,
+        // The repository is the last argument of the command.
+        None => match ext_command(url) {
+            Some(command) => local_repo_name(command.split_whitespace().last().unwrap_or(""))?,
+            None => get_repo_name(&parse_url(url)?.1)?,
+        },
+    };
 
     // Check if the directory exists
     if fs::metadata(&repo).is_ok() {
@@ -42,10 +62,22 @@ pub fn __clone(url: &str) -> io::Result<()> {
 
     // Create the directory if it doesn't exist.
     fs::create_dir(&repo)?;
+    __clone_into(url, &repo)
+}
+
+/// Clones the repository at url into the existing directory dir,
+/// leaving it as the current working directory.
+pub fn __clone_into(url: &str, dir: &str) -> io::Result<()> {
+    // Bundles and local repositories are found from the new one too.
+    let url = match local_git_dir(url) {
+        _ if is_bundle(url) => fs::canonicalize(url)?.to_string_lossy().to_string(),
+        Some(git_dir) => git_dir,
+        None => url.to_string(),
+    };
 
     // Move cwd.
     let mut cwd = env::current_dir()?;
-    cwd.push(&repo);
+    cwd.push(dir);
     env::set_current_dir(cwd)?;
 
     // Initialize a git repo.
@@ -53,7 +85,6 @@ pub fn __clone(url: &str) -> io::Result<()> {
 
     // Add the new remote.
     let name = "origin".to_string();
-    let url = url.to_string();
     remote(RemoteCommand::Add { name, url })?;
 
     // Bring objects and references.
@@ -76,6 +107,13 @@ pub fn __clone(url: &str) -> io::Result<()> {
     let head = head.0.ok_or(io_err!("No head found"))?;
     update_head(&head)?;
     branch(Some(reference.to_string()))?;
+
+    // The checked out branch merges from the remote one.
+    if reference != "HEAD" {
+        let mut config = Config::read()?;
+        config.add(ConfigEntry::new_branch(reference, "origin"));
+        config.write()?;
+    }
     println!("head: {head}");
 
     // Get tree hash from commit.
@@ -1,48 +1,60 @@
 use super::{
-    super::config_file::{config::Config, config_entry::ConfigEntry},
+    super::config_file::{config::Config, config_entry::ConfigEntry, git_config::ConfigSet},
     fetch::add_to_refs,
     heads::*,
+    identity::{__get_identity, Identity, Role},
     read_tree::__read_tree,
-    userconfig::*,
+    sparse::SparseCheckout,
     write_tree::__write_tree,
 };
 use std::{
     collections::HashMap,
+    env,
     fs::{self, File},
     io,
 };
 use utils::index_file::commands::{__read_index, __write_index};
 use utils::index_file::index::Index;
 use utils::plumbing::hash_object::__hash_object;
+use utils::{common_dir, git_dir, read_ref_with_offset};
 
 /// Hashes a string following the git object format. Returns a vector of bytes.
 pub fn hash_object(data: &[u8], otype: &str, write: bool) -> io::Result<String> {
-    Ok(__hash_object(data, otype, write, ".git")?.1)
+    Ok(__hash_object(data, otype, write, &common_dir())?.1)
 }
 
-/// Returns a vector of the entries in the .git/index file.
+/// Returns a vector of the entries in the index file of the worktree.
 pub fn read_index() -> io::Result<Index> {
-    let entries = __read_index(File::open(".git/index")?)?;
+    let entries = __read_index(File::open(format!("{}/index", git_dir()))?)?;
     let mut map = HashMap::with_capacity(entries.len());
     for entry in entries {
         map.insert(entry.get_path().to_string(), entry);
     }
 
-    Ok(Index::with(map))
+    // Honour core.filemode when comparing against the working directory.
+    let mut index = Index::with(map);
+    index.set_filemode(Config::read().map_or(true, |config| config.filemode()));
+    Ok(index)
 }
 
-/// Writes the given entries to the .git/index file.
+/// Writes the given entries to the index file of the worktree.
 pub fn write_index(index: Index) -> io::Result<()> {
     let mut entries = index.get_entries();
     entries.sort_by_key(|e| e.get_path().to_string());
-    __write_index(entries, File::create(".git/index")?)
+    __write_index(entries, File::create(format!("{}/index", git_dir()))?)
 }
 
 /// Given a Tree object's hash, reads it's content and
 /// generates an Index object from it. Returning it.
+/// Files left out of the sparse checkout are marked skip-worktree.
 pub fn read_tree(root: &str, path: &str) -> io::Result<Index> {
     let mut entries = vec![];
-    __read_tree(root, &mut entries, String::from(path))?;
+    __read_tree(
+        root,
+        &mut entries,
+        String::from(path),
+        SparseCheckout::read()?.as_ref(),
+    )?;
     let map = HashMap::from_iter(entries.into_iter().map(|e| (e.get_path().to_string(), e)));
     Ok(Index::with(map))
 }
@@ -57,14 +69,38 @@ pub fn write_tree() -> io::Result<Vec<u8>> {
 
 /// Returns the hash of the commit object pointed to by HEAD.
 pub fn get_head() -> Option<String> {
-    let file = File::open(".git/HEAD").ok()?;
+    let file = File::open(format!("{}/HEAD", git_dir())).ok()?;
     __get_head_commit(file).ok()
 }
 
 /// Returns the hash of the commit object pointed to by the given branch.
 fn get_branch_hash(branch: &str) -> Option<String> {
-    let file = File::open(format!(".git/refs/{branch}")).ok()?;
-    __get_branch(file).ok()
+    read_ref_with_offset(&format!("refs/{branch}"), &common_dir()).ok()
+}
+
+/// Returns the remote a branch with the given name is taken from, the
+/// branch's one or else the only one that has it, and the hash of
+/// it's remote-tracking branch.
+pub fn get_remote_branch(branch: &str, config: &Config) -> Option<(String, String)> {
+    let remote = match config.branch(branch) {
+        Some(ConfigEntry::Branch { remote, .. }) => remote.clone(),
+        _ => {
+            let remotes: Vec<_> = config
+                .values()
+                .filter(|entry| entry.is_remote())
+                .map(|entry| entry.name())
+                .filter(|remote| get_branch_hash(&format!("remotes/{remote}/{branch}")).is_some())
+                .collect();
+
+            match remotes.as_slice() {
+                [remote] => remote.clone(),
+                _ => return None,
+            }
+        }
+    };
+
+    let hash = get_branch_hash(&format!("remotes/{remote}/{branch}"))?;
+    Some((remote, hash))
 }
 
 pub fn get_branch(branch: &str) -> Option<String> {
@@ -72,20 +108,12 @@ pub fn get_branch(branch: &str) -> Option<String> {
         return Some(hash);
     }
 
-    // Get remote.
     let config = Config::read().ok()?;
-    let remote = match config.get(branch) {
-        Some(ConfigEntry::Branch { remote, .. }) => remote,
-        _ => return None,
-    };
+    let (_, hash) = get_remote_branch(branch, &config)?;
 
-    if let Some(hash) = get_branch_hash(&format!("remotes/{remote}/{branch}")) {
-        // Add the branch to the local refs.
-        add_to_refs(&format!("refs/heads/{branch}"), &hash).ok()?;
-        return Some(hash);
-    }
-
-    None
+    // Add the branch to the local refs.
+    add_to_refs(&format!("refs/heads/{branch}"), &hash).ok()?;
+    Some(hash)
 }
 
 /// Updates the HEAD file to point to the given branch.
@@ -94,30 +122,17 @@ pub fn update_head(hash_commit: &str) -> io::Result<()> {
     Ok(())
 }
 
-/// Returns a Config struct containing the current user's
-/// configuration for commit purposes.
-pub fn get_userconfig() -> io::Result<UserConfig> {
-    let file = match File::open(".git/.gitconfig") {
-        Ok(file) => file,
-        Err(_) => {
-            set_userconfig("pepito", "default@fi.uba.ar", "cmdlog.txt", "all")?;
-            File::open(".git/.gitconfig")?
-        }
-    };
-
-    __get_userconfig(file)
-}
-
-/// Writes the given user and mail to the .git/.gitconfig file.
-pub fn set_userconfig(user: &str, mail: &str, log_path: &str, log_mode: &str) -> io::Result<()> {
-    let file = File::create(".git/.gitconfig")?;
-    __set_userconfig(user, mail, log_path, log_mode, file)
+/// Returns the identity of the author or committer of the changes
+/// made now, from the environment or the configuration.
+pub fn get_identity(role: Role) -> io::Result<Identity> {
+    let config = ConfigSet::read(None)?;
+    __get_identity(role, |var| env::var(var).ok(), |key| config.get(key))
 }
 
 /// Returns the name of the branch pointed to by HEAD.
 /// If HEAD is detached, returns the hash of the commit.
 pub fn get_cur_branch() -> io::Result<String> {
-    let head = fs::read_to_string(".git/HEAD")?;
+    let head = fs::read_to_string(format!("{}/HEAD", git_dir()))?;
 
     if let Some(stripped) = head.strip_prefix("ref: ") {
         Ok(stripped.trim().to_string())
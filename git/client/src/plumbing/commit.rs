@@ -1,6 +1,9 @@
 use super::commands::*;
+use super::identity::Role;
+use super::signing::{read_signing_key, sign_commit};
 use chrono::{DateTime, Local};
 use std::io::{self, BufRead, Write};
+use utils::plumbing::ls_tree::hash_to_str;
 
 /// Commits can have multiple parents. Because of Merge Commits.
 pub fn get_parent_commits(data: &[u8]) -> Option<Vec<String>> {
@@ -63,9 +66,11 @@ pub fn get_committer_and_time(data: &[u8]) -> Option<(String, String)> {
     Some((committer, time))
 }
 
-/// Returns the message of the commit.
+/// Returns the message of the commit, without the last newline.
 pub fn get_commit_msg(data: &[u8]) -> Option<String> {
-    data.lines().flatten().last()
+    let data = String::from_utf8_lossy(data);
+    let (_, msg) = data.split_once("\n\n")?;
+    Some(msg.strip_suffix('\n').unwrap_or(msg).to_string())
 }
 
 // Returns date formated for commit purposes.
@@ -76,7 +81,42 @@ pub fn get_time_fmt(date: DateTime<Local>) -> String {
     format!("{stamp} {offset}")
 }
 
-pub fn __commit(msg: &str) -> io::Result<String> {
+/// Commits the index on top of the parent, keeping the given author
+/// and time. The committer is the user. Returns the commit's hash.
+pub fn commit_with_author(
+    author: &str,
+    time: &str,
+    msg: &str,
+    parent: Option<&str>,
+) -> io::Result<String> {
+    let root = hash_to_str(&write_tree()?);
+
+    // Write root tree.
+    let mut commit = vec![];
+    commit.write_all(format!("tree {root}\n").as_bytes())?;
+
+    // Append parent commit.
+    if let Some(parent) = parent {
+        commit.write_all(format!("parent {parent}\n").as_bytes())?;
+    }
+
+    // Append author and committer.
+    commit.write_all(format!("author {author} {time}\n").as_bytes())?;
+
+    let committer = get_identity(Role::Committer)?;
+    let time = get_time_fmt(Local::now());
+    commit.write_all(format!("committer {committer} {time}\n").as_bytes())?;
+
+    // Append commit message.
+    commit.write_all(format!("\n{msg}\n").as_bytes())?;
+
+    hash_object(&commit, "commit", true)
+}
+
+/// Underlying implementation of `git commit`. When a key is given,
+/// the commit is signed with it, as `commit -S`.
+pub fn __commit(msg: &str, key: Option<&str>) -> io::Result<String> {
+    let key = key.map(read_signing_key).transpose()?;
     let root = write_tree()?;
 
     let root = root.iter().fold(String::new(), |mut acc, byte| {
@@ -96,13 +136,19 @@ pub fn __commit(msg: &str) -> io::Result<String> {
     }
 
     // Append author and committer.
-    let author = get_userconfig()?.to_string();
+    let author = get_identity(Role::Author)?;
+    let committer = get_identity(Role::Committer)?;
     let time = get_time_fmt(Local::now());
-    commit.write_all(format!("author {author} {time}\ncommitter {author} {time}\n").as_bytes())?;
+    commit
+        .write_all(format!("author {author} {time}\ncommitter {committer} {time}\n").as_bytes())?;
 
     // Append commit message.
     commit.write_all(format!("\n{msg}\n").as_bytes())?;
 
+    if let Some(key) = key {
+        commit = sign_commit(&commit, &key);
+    }
+
     // Hash commit object and update HEAD.
     let hash = hash_object(&commit, "commit", true)?;
     update_head(&hash)?;
@@ -146,6 +192,10 @@ committer Pepito <pepito@fi.uba.ar> 1699028021 -0300
 clippy, siempre es clippy ;(
 "#;
         let msg = get_commit_msg(commit.as_bytes()).unwrap();
-        println!("{msg}");
+        assert_eq!(msg, "clippy, siempre es clippy ;(");
+
+        let commit = commit.replace(";(\n", ";(\n\nbody\n");
+        let msg = get_commit_msg(commit.as_bytes()).unwrap();
+        assert_eq!(msg, "clippy, siempre es clippy ;(\n\nbody");
     }
 }
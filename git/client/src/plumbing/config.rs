@@ -0,0 +1,81 @@
+use crate::commands::ConfigCommand;
+use crate::config_file::git_config::{ConfigFile, ConfigSet, Scope};
+use crate::io_err;
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+};
+use utils::common_dir;
+
+// Reads the file the scope writes to. The repository's one can only
+// be written inside a repository.
+fn read_scope(scope: Scope) -> io::Result<ConfigFile> {
+    if scope == Scope::Local && !Path::new(&common_dir()).is_dir() {
+        return Err(io_err!("not in a git directory"));
+    }
+
+    ConfigFile::read(&scope.path()?)
+}
+
+fn not_set(key: &str) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("{key} is not set"))
+}
+
+// Lists the values as `key=value`, after the file they are in if
+// the origin is shown. Keys set to true by being there show no value.
+fn list(show_origin: bool, scope: Option<Scope>) -> io::Result<String> {
+    let mut ret = String::new();
+    for value in ConfigSet::read(scope)?.values()? {
+        if show_origin {
+            ret.push_str(&format!("file:{}\t", value.origin));
+        }
+
+        match value.value {
+            Some(v) => ret.push_str(&format!("{}={v}\n", value.key)),
+            None => ret.push_str(&format!("{}\n", value.key)),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Underlying implementation of `git config`.
+pub fn __config(cmd: ConfigCommand) -> io::Result<String> {
+    use ConfigCommand::*;
+    match cmd {
+        Get { key, all, scope } => {
+            let config = ConfigSet::read(scope)?;
+            let values = match all {
+                true => config.get_all(&key)?,
+                false => config.get(&key)?.into_iter().collect(),
+            };
+
+            match values.is_empty() {
+                true => Err(not_set(&key)),
+                false => Ok(values.iter().map(|value| format!("{value}\n")).collect()),
+            }
+        }
+
+        Set { key, value, scope } => {
+            let mut file = read_scope(scope)?;
+            file.set(&key, &value)?;
+            file.write().map(|_| String::new())
+        }
+
+        Add { key, value, scope } => {
+            let mut file = read_scope(scope)?;
+            file.add(&key, &value)?;
+            file.write().map(|_| String::new())
+        }
+
+        Unset { key, all, scope } => {
+            let mut file = read_scope(scope)?;
+            if file.unset(&key, all)? == 0 {
+                return Err(not_set(&key));
+            }
+            file.write().map(|_| String::new())
+        }
+
+        List { show_origin, scope } => list(show_origin, scope),
+    }
+}
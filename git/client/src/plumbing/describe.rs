@@ -0,0 +1,239 @@
+use super::tag::get_tags;
+use crate::commands::{status, FileStatus};
+use crate::io_err;
+use crate::plumbing::refs::rev_parse;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, ErrorKind},
+};
+use utils::object::object_db::get_object;
+use utils::plumbing::commit_graph::History;
+use utils::{common_dir, parse_tag};
+
+// The walk stops after finding this many tagged commits,
+// the nearest one is usually among the first.
+const MAX_CANDIDATES: usize = 10;
+
+/// Options of the describe command.
+#[derive(Debug, Clone, Default)]
+pub struct DescribeOptions {
+    /// Commit to describe, HEAD when missing.
+    pub rev: Option<String>,
+    /// Uses lightweight tags too, not only annotated ones.
+    pub tags: bool,
+    /// Always shows the commits since the tag and the hash.
+    pub long: bool,
+    /// Appends `-dirty` if the working tree has changes.
+    pub dirty: bool,
+    /// Only uses tags whose name matches this glob.
+    pub pattern: Option<String>,
+}
+
+// A tag pointing to a commit.
+#[derive(Debug, Clone)]
+struct Name {
+    tag: String,
+    annotated: bool,
+}
+
+/// Returns true if the text matches the glob, where `*` matches
+/// any sequence of characters and `?` any single one.
+pub fn glob_matches(glob: &str, text: &str) -> bool {
+    let (glob, text): (Vec<_>, Vec<_>) = (glob.chars().collect(), text.chars().collect());
+    let (mut g, mut t) = (0, 0);
+    // Where the last `*` was and the text it was trying to match.
+    let mut star = None;
+
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sg, st)) => {
+                    star = Some((sg, st + 1));
+                    g = sg + 1;
+                    t = st + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+// Maps every tagged commit to it's best tag. Annotated tags are
+// peeled to the commit, nested ones too.
+fn tagged_commits(opts: &DescribeOptions) -> io::Result<(HashMap<String, Name>, bool)> {
+    let mut names: HashMap<String, Name> = HashMap::new();
+    let mut unannotated = false;
+
+    for (hash, tag) in get_tags()? {
+        if opts
+            .pattern
+            .as_ref()
+            .is_some_and(|pattern| !glob_matches(pattern, &tag))
+        {
+            continue;
+        }
+
+        let mut commit = hash;
+        let mut annotated = false;
+        loop {
+            match get_object(&commit)? {
+                (otype, _, _) if otype == "commit" => break,
+                (otype, _, data) if otype == "tag" => {
+                    commit = parse_tag(&data).map_err(|_| io_err!("Invalid tag object"))?;
+                    annotated = true;
+                }
+                _ => break,
+            }
+        }
+
+        if !annotated && !opts.tags {
+            unannotated = true;
+            continue;
+        }
+
+        // Annotated tags are preferred over lightweight
+        // ones, then the first one by name.
+        let name = Name { tag, annotated };
+        match names.get(&commit) {
+            Some(best) if best.annotated || !name.annotated => {}
+            _ => {
+                names.insert(commit, name);
+            }
+        }
+    }
+
+    Ok((names, unannotated))
+}
+
+// Counts the commits reachable from the target but not from the tagged one.
+fn depth(history: &History, target: &str, tagged: &str) -> io::Result<usize> {
+    let mut excluded = HashSet::new();
+    let mut stack = vec![tagged.to_string()];
+    while let Some(hash) = stack.pop() {
+        if excluded.insert(hash.clone()) {
+            stack.extend(history.commit(&hash)?.parents);
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut stack = vec![target.to_string()];
+    while let Some(hash) = stack.pop() {
+        if !excluded.contains(&hash) && visited.insert(hash.clone()) {
+            stack.extend(history.commit(&hash)?.parents);
+        }
+    }
+
+    Ok(visited.len())
+}
+
+// Walks the history breadth first from the commit, returning the
+// nearest tagged commit and the commits since it.
+fn nearest_tag(
+    history: &History,
+    commit: &str,
+    names: &HashMap<String, Name>,
+) -> io::Result<Option<(String, usize)>> {
+    let mut candidates = vec![];
+    let mut visited = HashSet::from([commit.to_string()]);
+    let mut queue = VecDeque::from([commit.to_string()]);
+
+    while let Some(hash) = queue.pop_front() {
+        if names.contains_key(&hash) {
+            candidates.push(hash.clone());
+            if candidates.len() == MAX_CANDIDATES {
+                break;
+            }
+        }
+
+        for parent in history.commit(&hash)?.parents {
+            if visited.insert(parent.clone()) {
+                queue.push_back(parent);
+            }
+        }
+    }
+
+    // Ties go to the candidate found first.
+    let mut best: Option<(String, usize)> = None;
+    for candidate in candidates {
+        let depth = depth(history, commit, &candidate)?;
+        if best.as_ref().is_none_or(|(_, best)| depth < *best) {
+            best = Some((candidate, depth));
+        }
+    }
+
+    Ok(best)
+}
+
+/// Underlying implementation of `git describe`. Names the commit after
+/// the nearest tag reachable from it, as `<tag>-<commits since>-g<hash>`.
+pub fn __describe(opts: &DescribeOptions) -> io::Result<String> {
+    if opts.dirty && opts.rev.is_some() {
+        return Err(io_err!("--dirty is incompatible with commit-ishes"));
+    }
+
+    let commit = rev_parse(opts.rev.as_deref().unwrap_or("HEAD"))?;
+    let (names, unannotated) = tagged_commits(opts)?;
+    let history = History::with_offset(&common_dir());
+
+    let (tagged, depth) = match nearest_tag(&history, &commit, &names)? {
+        Some(nearest) => nearest,
+        None if names.is_empty() && unannotated => {
+            let msg = format!(
+                "No annotated tags can describe '{commit}'.\n\
+                 However, there were unannotated tags: try --tags."
+            );
+            return Err(io::Error::new(ErrorKind::NotFound, msg));
+        }
+        None if names.is_empty() => {
+            return Err(io_err!("No names found, cannot describe anything."))
+        }
+        None => {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("No tags can describe '{commit}'."),
+            ))
+        }
+    };
+
+    let mut ret = names[&tagged].tag.clone();
+    if depth > 0 || opts.long {
+        ret.push_str(&format!("-{depth}-g{}", &commit[..7]));
+    }
+
+    if opts.dirty
+        && status()?
+            .iter()
+            .any(|status| !matches!(status, FileStatus::New(_)))
+    {
+        ret.push_str("-dirty");
+    }
+
+    ret.push('\n');
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("v*", "v1.2"));
+        assert!(glob_matches("v?.*", "v1.2"));
+        assert!(glob_matches("*rc*", "v1.2-rc1"));
+        assert!(glob_matches("v1.2", "v1.2"));
+        assert!(!glob_matches("v?", "v1.2"));
+        assert!(!glob_matches("release-*", "v1.2"));
+        assert!(glob_matches("*", ""));
+    }
+}
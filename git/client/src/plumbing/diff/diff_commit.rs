@@ -15,19 +15,27 @@ use utils::plumbing::ls_tree::parse_ls_tree_entry;
 /// The patch is represented as a vector of
 /// FileDiff (difference between the lines of the file).
 /// The patch also contains the path of the file,
-/// the old and new hash of the file.
+/// the old and new hash and mode of the file.
 
 #[derive(Debug)]
 pub struct Patch {
     pub path: String,
     pub old: Option<String>,
     pub new: Option<String>,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
     pub difftype: DiffType,
     pub diff: VecDeque<FileDiff>,
 }
 
 pub fn differences_beetween_files(patch: &Patch) -> String {
     let mut s = String::new();
+    if let (Some(old), Some(new)) = (&patch.old_mode, &patch.new_mode) {
+        if old != new {
+            s.push_str(&format!("old mode {old}\nnew mode {new}\n"));
+        }
+    }
+
     for line in &patch.diff {
         s.push_str(&format!("{}\n", line));
     }
@@ -37,7 +45,7 @@ pub fn differences_beetween_files(patch: &Patch) -> String {
 pub fn get_patch_of_tree_diffs(diffs: &[Diff], path: &str) -> io::Result<Vec<Patch>> {
     let mut patches = vec![];
     for difference in diffs {
-        let (_, otype, hash, name) = parse_ls_tree_entry(&difference.line);
+        let (mode, otype, hash, name) = parse_ls_tree_entry(&difference.line);
         let path = if path.is_empty() {
             name
         } else {
@@ -54,6 +62,8 @@ pub fn get_patch_of_tree_diffs(diffs: &[Diff], path: &str) -> io::Result<Vec<Pat
                     path,
                     old: Some(hash.to_string()),
                     new: Some(hash.to_string()),
+                    old_mode: Some(mode.to_string()),
+                    new_mode: Some(mode.to_string()),
                     difftype: Unchanged,
                     diff,
                 });
@@ -67,6 +77,8 @@ pub fn get_patch_of_tree_diffs(diffs: &[Diff], path: &str) -> io::Result<Vec<Pat
                     path,
                     old: None,
                     new: Some(hash.to_string()),
+                    old_mode: None,
+                    new_mode: Some(mode.to_string()),
                     difftype: Added,
                     diff,
                 })
@@ -80,13 +92,15 @@ pub fn get_patch_of_tree_diffs(diffs: &[Diff], path: &str) -> io::Result<Vec<Pat
                     path,
                     old: Some(hash.to_string()),
                     new: None,
+                    old_mode: Some(mode.to_string()),
+                    new_mode: None,
                     difftype: Removed,
                     diff,
                 })
             }
 
             (Modified(line), "blob") => {
-                let (_, _, new_hash, _) = parse_ls_tree_entry(line);
+                let (new_mode, _, new_hash, _) = parse_ls_tree_entry(line);
                 let (_, _, old_content) = cat_file(hash)?;
                 let (_, _, new_content) = cat_file(new_hash)?;
 
@@ -97,11 +111,40 @@ pub fn get_patch_of_tree_diffs(diffs: &[Diff], path: &str) -> io::Result<Vec<Pat
                     path,
                     old: Some(hash.to_string()),
                     new: Some(new_hash.to_string()),
+                    old_mode: Some(mode.to_string()),
+                    new_mode: Some(new_mode.to_string()),
                     difftype: Modified(line.to_string()),
                     diff,
                 })
             }
 
+            // Gitlinks have no content in this repository,
+            // like git show the commit they point to.
+            (tag, "commit") => {
+                let (old, new) = match tag {
+                    Added => (None, Some(hash)),
+                    Removed => (Some(hash), None),
+                    Modified(line) => (Some(hash), Some(parse_ls_tree_entry(line).2)),
+                    Unchanged => (Some(hash), Some(hash)),
+                };
+
+                let content = |hash: Option<&str>| {
+                    hash.map(|hash| format!("Subproject commit {hash}"))
+                        .unwrap_or_default()
+                };
+                let diff = diff(&content(old), &content(new));
+
+                patches.push(Patch {
+                    path,
+                    old: old.map(str::to_string),
+                    new: new.map(str::to_string),
+                    old_mode: old.map(|_| mode.to_string()),
+                    new_mode: new.map(|_| mode.to_string()),
+                    difftype: tag.clone(),
+                    diff,
+                })
+            }
+
             (Unchanged, "tree") => {
                 let tree = ls_tree(hash)?;
                 let diffs: Vec<_> = diff_tree(&tree, &tree).collect();
@@ -37,11 +37,10 @@ pub fn diff_tree(a: &str, b: &str) -> impl Iterator<Item = Diff> {
             }
             Some(data) => {
                 let (mode2, otype2, hash2) = data;
-                // If the mode or object type changed
-                // this means the file was renamed or
-                // changed from a file to a directory
-                // or viceversa.
-                if mode != mode2 || otype != otype2 {
+                // If the object type changed this
+                // means the file was changed from a
+                // file to a directory or viceversa.
+                if otype != otype2 {
                     // Remove the old entry.
                     let diff = Diff::removed(line);
                     diffs.push(diff);
@@ -50,11 +49,13 @@ pub fn diff_tree(a: &str, b: &str) -> impl Iterator<Item = Diff> {
                     let line = format!("{mode2} {otype2} {hash2} {name}\0");
                     let diff = Diff::added(line);
                     diffs.push(diff);
-                } else if hash != hash2 {
-                    // If only the hash changed this means
-                    // the file content was modified or in
-                    // the case of a directory it now
-                    // contains different files.
+                } else if hash != hash2 || mode != mode2 {
+                    // If the hash changed this means the
+                    // file content was modified or in the
+                    // case of a directory it now contains
+                    // different files. A different mode
+                    // means the executable bit was toggled
+                    // or the file became a symlink.
                     let other = format!("{mode2} {otype2} {hash2} {name}\0");
                     let diff = Diff::modified(line, other);
                     diffs.push(diff);
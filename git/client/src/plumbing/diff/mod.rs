@@ -3,3 +3,4 @@ pub mod diff_tree;
 pub mod diff_type;
 pub mod lcs;
 mod mat;
+pub mod unified;
@@ -0,0 +1,207 @@
+use super::diff_commit::Patch;
+use super::diff_type::DiffType;
+use super::lcs::FileDiff;
+use crate::commands::cat_file;
+use std::io;
+
+// Lines of context around the changes of a hunk.
+const CONTEXT: usize = 3;
+const NO_NEWLINE: &str = "\\ No newline at end of file\n";
+const GITLINK: &str = "160000";
+
+// Returns the content of the blob, gitlinks have none.
+fn content(hash: Option<&str>, mode: Option<&str>) -> io::Result<String> {
+    match (hash, mode) {
+        (Some(hash), Some(mode)) if mode != GITLINK => Ok(cat_file(hash)?.2),
+        _ => Ok(String::new()),
+    }
+}
+
+// Returns the lines of the patch as (prefix, line). A line without
+// newline at the end of one file differs from the same text with one
+// in the other.
+fn diff_lines(patch: &Patch, old_eol: bool, new_eol: bool) -> Vec<(char, &str)> {
+    let mut lines: Vec<_> = patch
+        .diff
+        .iter()
+        .map(|line| match line {
+            FileDiff::Same(line) => (' ', line.as_str()),
+            FileDiff::Added(line) => ('+', line.as_str()),
+            FileDiff::Removed(line) => ('-', line.as_str()),
+        })
+        .collect();
+
+    let old_last = lines.iter().rposition(|(c, _)| *c != '+');
+    let new_last = lines.iter().rposition(|(c, _)| *c != '-');
+
+    // Whether the old and the new line at i end with a newline.
+    let eols = |i| {
+        let old = old_last != Some(i) || old_eol;
+        let new = new_last != Some(i) || new_eol;
+        (old, new)
+    };
+
+    let mut split: Vec<_> = [old_last, new_last].into_iter().flatten().collect();
+    split.sort();
+    split.dedup();
+    for i in split.into_iter().rev() {
+        let (old, new) = eols(i);
+        if lines[i].0 == ' ' && old != new {
+            let line = lines[i].1;
+            lines[i] = ('-', line);
+            lines.insert(i + 1, ('+', line));
+        }
+    }
+
+    lines
+}
+
+// Formats a hunk range, the length is left out when it's 1.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        1 => format!("{start}"),
+        _ => format!("{start},{len}"),
+    }
+}
+
+// Formats the hunks of the patch, with `CONTEXT` lines around
+// every change.
+fn hunks(lines: &[(char, &str)], old_eol: bool, new_eol: bool) -> String {
+    let old_total = lines.iter().filter(|(c, _)| *c != '+').count();
+    let new_total = lines.iter().filter(|(c, _)| *c != '-').count();
+    let changes: Vec<_> = (0..lines.len()).filter(|&i| lines[i].0 != ' ').collect();
+
+    // Changes closer than twice the context share a hunk.
+    let mut groups: Vec<(usize, usize)> = vec![];
+    for &i in &changes {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last <= 2 * CONTEXT + 1 => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    let mut ret = String::new();
+    for (first, last) in groups {
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(lines.len());
+
+        let old_before = lines[..start].iter().filter(|(c, _)| *c != '+').count();
+        let new_before = lines[..start].iter().filter(|(c, _)| *c != '-').count();
+        let old_len = lines[start..end].iter().filter(|(c, _)| *c != '+').count();
+        let new_len = lines[start..end].iter().filter(|(c, _)| *c != '-').count();
+
+        // Empty ranges start at the line before them.
+        let old_start = old_before + usize::from(old_len > 0);
+        let new_start = new_before + usize::from(new_len > 0);
+        ret.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        ));
+
+        let (mut old_line, mut new_line) = (old_before, new_before);
+        for (c, line) in &lines[start..end] {
+            ret.push_str(&format!("{c}{line}\n"));
+            if *c != '+' {
+                old_line += 1;
+            }
+            if *c != '-' {
+                new_line += 1;
+            }
+
+            let old_last = *c != '+' && old_line == old_total && !old_eol;
+            let new_last = *c != '-' && new_line == new_total && !new_eol;
+            if old_last || new_last {
+                ret.push_str(NO_NEWLINE);
+            }
+        }
+    }
+
+    ret
+}
+
+/// Formats the patch as a unified diff, with the headers git writes.
+/// Unchanged files have no diff.
+pub fn unified_diff(patch: &Patch) -> io::Result<String> {
+    let path = &patch.path;
+    let old_mode = patch.old_mode.as_deref();
+    let new_mode = patch.new_mode.as_deref();
+    let abbrev = |hash: &Option<String>| {
+        hash.as_deref()
+            .map_or("0000000".to_string(), |hash| hash[..7].to_string())
+    };
+
+    if patch.difftype == DiffType::Unchanged {
+        return Ok(String::new());
+    }
+
+    let old = content(patch.old.as_deref(), old_mode)?;
+    let new = content(patch.new.as_deref(), new_mode)?;
+    let old_eol = old.is_empty() || old.ends_with('\n');
+    let new_eol = new.is_empty() || new.ends_with('\n');
+
+    let lines = diff_lines(patch, old_eol, new_eol);
+    let hunks = hunks(&lines, old_eol, new_eol);
+
+    let mut ret = format!("diff --git a/{path} b/{path}\n");
+    let index = format!("index {}..{}", abbrev(&patch.old), abbrev(&patch.new));
+    match (old_mode, new_mode) {
+        (None, Some(mode)) => ret.push_str(&format!("new file mode {mode}\n{index}\n")),
+        (Some(mode), None) => ret.push_str(&format!("deleted file mode {mode}\n{index}\n")),
+        (Some(old_mode), Some(new_mode)) if old_mode != new_mode => {
+            ret.push_str(&format!("old mode {old_mode}\nnew mode {new_mode}\n"));
+            if patch.old != patch.new {
+                ret.push_str(&format!("{index}\n"));
+            }
+        }
+        (_, mode) => ret.push_str(&format!("{index} {}\n", mode.unwrap_or_default())),
+    }
+
+    if !hunks.is_empty() {
+        let old_path = old_mode.map_or("/dev/null".to_string(), |_| format!("a/{path}"));
+        let new_path = new_mode.map_or("/dev/null".to_string(), |_| format!("b/{path}"));
+        ret.push_str(&format!("--- {old_path}\n+++ {new_path}\n{hunks}"));
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lcs::diff;
+    use super::*;
+
+    #[test]
+    fn hunks_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+        let new = old
+            .replace("\n2\n", "\ntwo\n")
+            .replace("15\n", "15\n15.5\n");
+        let patch = Patch {
+            path: "f".to_string(),
+            old: None,
+            new: None,
+            old_mode: None,
+            new_mode: None,
+            difftype: DiffType::Modified(String::new()),
+            diff: diff(old, &new),
+        };
+
+        let lines = diff_lines(&patch, true, true);
+        assert_eq!(
+            hunks(&lines, true, true),
+            "@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+             @@ -13,4 +13,5 @@\n 13\n 14\n 15\n+15.5\n 16\n"
+        );
+
+        let patch = Patch {
+            diff: diff("a\nb", "a\nb\n"),
+            ..patch
+        };
+        let lines = diff_lines(&patch, false, true);
+        assert_eq!(
+            hunks(&lines, false, true),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+    }
+}
@@ -1,14 +1,33 @@
 use super::super::config_file::config::Config;
+use super::bundle::{fetch_bundle, is_bundle, Bundle};
+use super::local::{copy_objects, local_git_dir, local_head, local_refs, update_local_ref};
+use super::merge::is_ancestor;
+use super::notes::{get_notes_commit, NOTES_REF};
+use super::refspec::{map_ref, parse_refspecs, Refspec};
 use crate::config_file::config_entry::ConfigEntry;
 use crate::io_err;
 use crate::protocol::*;
 use std::{
     fs::{self, File},
-    io::{self, Write},
+    io::{self, ErrorKind, Write},
 };
+use utils::{common_dir, get_refs_from_with_prefix, read_ref_with_offset};
 
 const ZERO_ID: &str = "0000000000000000000000000000000000000000";
 
+// References asked for to servers that speak protocol v2.
+const FETCHED_PREFIXES: [&str; 4] = ["HEAD", "refs/heads/", "refs/tags/", "refs/notes/"];
+
+// What fetching fails with when no objects were wanted.
+const UP_TO_DATE: &str = "Already up to date";
+const NO_NEW_COMMITS: &str = "No new commits";
+
+/// Returns true if fetching failed because the remote
+/// had no commits the repository didn't have.
+pub fn is_up_to_date(error: &io::Error) -> bool {
+    error.kind() == ErrorKind::Other && [UP_TO_DATE, NO_NEW_COMMITS].contains(&&*error.to_string())
+}
+
 // Creates the files for the tags that the client doesn't have.
 fn create_unexisting_tags<'a>(
     tags: impl Iterator<Item = &'a (String, String)>,
@@ -23,7 +42,7 @@ fn create_unexisting_tags<'a>(
         }
 
         println!();
-        let path = ".git/".to_string() + ref_path;
+        let path = format!("{}/{ref_path}", common_dir());
         if fs::metadata(&path).is_err() {
             let mut file = File::create(&path)?;
             file.write_all(hash.as_bytes())?;
@@ -38,39 +57,89 @@ pub fn add_to_refs(path: &str, hash: &str) -> io::Result<()> {
     // Create path till file if it doesn't exist yet.
     let path_split = path.split('/').collect::<Vec<&str>>();
     let path_till_file = path_split[..path_split.len() - 1].join("/");
-    fs::create_dir_all(format!(".git/{path_till_file}"))?;
+    let common_dir = common_dir();
+    fs::create_dir_all(format!("{common_dir}/{path_till_file}"))?;
 
     // Write to file.
-    let mut file = File::create(format!(".git/{path}"))?;
+    let mut file = File::create(format!("{common_dir}/{path}"))?;
     let hash = hash.to_string() + "\n";
     file.write_all(hash.as_bytes())
 }
 
+/// Returns the fetch refspecs of the remote.
+pub fn fetch_refspecs(config: &Config, remote: &str) -> io::Result<Vec<Refspec>> {
+    match config.remote(remote) {
+        Some(ConfigEntry::Remote { fetch, .. }) => parse_refspecs(fetch),
+        _ => Err(io_err!("Remote not found")),
+    }
+}
+
+// Returns true if the reference is fetched: tags and notes always
+// are, others when a refspec stores them.
+fn is_fetched(specs: &[Refspec], name: &str) -> bool {
+    name.starts_with("refs/tags/") || name == NOTES_REF || map_ref(specs, name).is_some()
+}
+
+/// Returns the remote-tracking reference the branch merges from,
+/// the one the fetch refspecs store its upstream branch in.
+pub fn tracking_ref(remote: &str, branch: &str) -> io::Result<String> {
+    let config = Config::read()?;
+    let upstream = match config.branch(branch) {
+        Some(ConfigEntry::Branch {
+            remote: branch_remote,
+            merge,
+            ..
+        }) if branch_remote == remote => merge.clone(),
+        _ => format!("refs/heads/{branch}"),
+    };
+
+    map_ref(&fetch_refspecs(&config, remote)?, &upstream)
+        .map(|(tracking, _)| tracking)
+        .ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No remote-tracking reference for {upstream} of {remote}"),
+        ))
+}
+
 #[allow(unreachable_code)]
 pub fn __fetch(remote: &str) -> io::Result<(Option<String>, Option<String>)> {
     // Get the remote's url.
     let config = Config::read()?;
-    let url = match config.get(remote) {
+    let url = match config.remote(remote) {
         Some(ConfigEntry::Remote { url, .. }) => url,
         _ => return Err(io_err!("Remote not found")),
     };
 
-    // Generate TCP with own IP Address.
-    let (link, repo) = parse_url(url)?;
-    let mut transmiter = connect_to_server("git-upload-pack", &link, &repo)?;
+    if is_bundle(url) {
+        return fetch_from_bundle(remote, url);
+    }
+
+    if let Some(git_dir) = local_git_dir(url) {
+        return fetch_local(remote, &git_dir);
+    }
+
+    // Connect to the server asking for protocol v2, or spawn it for ext:: urls.
+    let mut transmiter = connect("git-upload-pack", url, 2)?;
 
-    // Read the references from Git Daemon
-    let refs = get_response(&mut transmiter);
+    // Read the references from the server, the ones that speak
+    // protocol v2 list them only when asked for.
+    let advertisement = get_response(&mut transmiter);
+    let v2 = is_v2(&advertisement);
 
-    println!("refs: {}", String::from_utf8_lossy(&refs));
+    println!("refs: {}", String::from_utf8_lossy(&advertisement));
 
     // Process references received.
-    let mut refs = parse_references(refs)?;
+    let mut refs = match v2 {
+        true => ls_refs(&mut transmiter, &FETCHED_PREFIXES)?,
+        false => parse_references(advertisement)?,
+    };
 
     println!("{:?}", refs);
 
-    if refs[0].0 == ZERO_ID {
-        transmiter.write_all(b"0000")?;
+    if refs.first().is_none_or(|(hash, _)| hash == ZERO_ID) {
+        if !v2 {
+            transmiter.write_all(b"0000")?;
+        }
         return Err(io_err!("No refs received"));
     }
 
@@ -93,36 +162,38 @@ pub fn __fetch(remote: &str) -> io::Result<(Option<String>, Option<String>)> {
         refs.insert(0, head);
     }
 
+    // Only the references the refspecs take are wanted.
+    let specs = fetch_refspecs(&config, remote)?;
+    refs.retain(|(_, name)| is_fetched(&specs, name));
+
     // Create the tags the client doesn't have.
     let tags_created =
         create_unexisting_tags(refs.iter().filter(|(_, path)| path.contains("refs/tags")))?;
 
-    // Send want lines.
-    let sent = send_want_lines(&refs, &transmiter)?;
-    transmiter.write_all(b"0000")?;
-
-    // If no want lines were sent, then the client is up to date.
-    if sent == 0 && tags_created == 0 {
-        return Err(io_err!("Already up to date"));
-    } else if sent == 0 {
-        return Err(io_err!("No new commits"));
-    }
-
-    // Send have lines and done.
-    send_have_lines(&transmiter)?;
-    let done_line = to_pkt_line_format("done", "");
-    transmiter.write_all(done_line.as_bytes())?;
+    // Receive pack-file from server, if any object is missing.
+    let pack_file = match v2 {
+        true => fetch_pack(&mut transmiter, &refs)?,
+        false => fetch_pack_v0(&mut transmiter, &refs)?,
+    };
 
-    // Process ack lines.
-    process_ack_line(&mut transmiter)?;
+    // If no objects were wanted, then the client is up to date.
+    let pack_file = match pack_file {
+        Some(pack_file) => pack_file,
+        None if tags_created == 0 => return Err(io::Error::other(UP_TO_DATE)),
+        None => return Err(io::Error::other(NO_NEW_COMMITS)),
+    };
 
-    // Receive pack-file from server and
-    // write the objects to the objects db.
-    let pack_file = get_response(&mut transmiter);
+    // Write the objects to the objects db.
     process_pack_file(pack_file)?;
 
-    // Add refs to .git directory.
-    let mut config = Config::read()?;
+    save_refs(remote, refs)?;
+    Ok(ret)
+}
+
+// Writes the fetched references where the refspecs of the remote
+// say. Updates that aren't fast-forwards need a forcing refspec.
+fn save_refs(remote: &str, refs: Vec<(String, String)>) -> io::Result<()> {
+    let specs = fetch_refspecs(&Config::read()?, remote)?;
     for (hash, path) in refs {
         // refs/heads/branch_name
 
@@ -130,17 +201,233 @@ pub fn __fetch(remote: &str) -> io::Result<(Option<String>, Option<String>)> {
             continue;
         }
 
-        // Add to remotes.
-        let path = path.replace("heads", &format!("remotes/{remote}"));
+        // Notes are kept in the same ref, unless the local
+        // notes have changes the remote doesn't have.
+        if path == NOTES_REF {
+            match get_notes_commit() {
+                Some(local) if !is_ancestor(&local, &hash)? => {
+                    println!("notes: {path} (not fast-forward, skipped)")
+                }
+                _ => add_to_refs(&path, &hash)?,
+            }
+            continue;
+        }
+
+        let (path, force) = match map_ref(&specs, &path) {
+            Some(mapped) => mapped,
+            None => continue,
+        };
+
+        if let Ok(old) = read_ref_with_offset(&path, &common_dir()) {
+            if !force && old != hash && !is_ancestor(&old, &hash)? {
+                println!("{path} (non-fast-forward, rejected)");
+                continue;
+            }
+        }
+
         add_to_refs(&path, &hash)?;
+    }
+
+    Ok(())
+}
+
+// Fetches the references and objects of a bundle file. HEAD is the
+// branch it points to, or the first one if the bundle doesn't have it.
+fn fetch_from_bundle(remote: &str, path: &str) -> io::Result<(Option<String>, Option<String>)> {
+    let mut refs = fetch_bundle(path)?;
+
+    let head = match refs.iter().position(|(_, name)| name == "HEAD") {
+        Some(i) => Some(refs.remove(i).0),
+        None => refs
+            .iter()
+            .find(|(_, name)| name.starts_with("refs/heads/"))
+            .map(|(hash, _)| hash.clone()),
+    };
+
+    let head_ref = head.as_ref().map(|head| {
+        refs.iter()
+            .find(|(hash, name)| hash == head && name.starts_with("refs/heads/"))
+            .map_or("HEAD".to_string(), |(_, name)| name.clone())
+    });
 
-        // Add it to config.
-        let path_split = path.split('/').collect::<Vec<&str>>();
-        if let Some(branch_name) = path_split.last() {
-            config.add(ConfigEntry::new_branch(branch_name, remote));
+    create_unexisting_tags(refs.iter().filter(|(_, path)| path.contains("refs/tags")))?;
+    save_refs(remote, refs)?;
+    Ok((head, head_ref))
+}
+
+// Fetches the references of a repository in this machine, copying
+// the objects it has that are missing here.
+fn fetch_local(remote: &str, git_dir: &str) -> io::Result<(Option<String>, Option<String>)> {
+    copy_objects(git_dir, &common_dir())?;
+    let refs = local_refs(git_dir)?;
+    let (head, head_ref) = local_head(git_dir).unzip();
+
+    create_unexisting_tags(refs.iter().filter(|(_, path)| path.contains("refs/tags")))?;
+    save_refs(remote, refs)?;
+    Ok((head, head_ref))
+}
+
+/// Returns the branches, tags and notes of the remote as (hash, name).
+/// The branch HEAD points to is listed as `HEAD:<branch>`, if known.
+pub fn ls_remote(remote: &str) -> io::Result<Vec<(String, String)>> {
+    let config = Config::read()?;
+    let url = match config.remote(remote) {
+        Some(ConfigEntry::Remote { url, .. }) => url,
+        _ => return Err(io_err!("Remote not found")),
+    };
+
+    if is_bundle(url) {
+        let mut refs = Bundle::read(url)?.refs;
+        if let Some(i) = refs.iter().position(|(_, name)| name == "HEAD") {
+            let head = refs.remove(i).0;
+            let branch = refs
+                .iter()
+                .find(|(hash, name)| *hash == head && name.starts_with("refs/heads/"))
+                .map(|(_, name)| format!("HEAD:{name}"));
+            refs.extend(branch.map(|branch| (head, branch)));
         }
+        return Ok(refs);
     }
 
-    config.write()?;
-    Ok(ret)
+    if let Some(git_dir) = local_git_dir(url) {
+        let mut refs = local_refs(&git_dir)?;
+        if let Some((hash, name)) = local_head(&git_dir) {
+            refs.push((hash, format!("HEAD:{name}")));
+        }
+        return Ok(refs);
+    }
+
+    let mut transmiter = connect("git-upload-pack", url, 2)?;
+    let advertisement = get_response(&mut transmiter);
+    let refs = match is_v2(&advertisement) {
+        true => ls_refs(&mut transmiter, &FETCHED_PREFIXES)?,
+        false => {
+            // Nothing is wanted.
+            transmiter.write_all(b"0000")?;
+            parse_references(advertisement)?
+        }
+    };
+
+    // Empty repositories advertise their capabilities with a zero id.
+    Ok(refs
+        .into_iter()
+        .map(|(hash, name)| (hash, name.replace('\0', "")))
+        .filter(|(hash, name)| hash != ZERO_ID && name != "HEAD" && !name.ends_with("^{}"))
+        .collect())
+}
+
+/// Returns the remote-tracking references of the repository at the
+/// given offset that the refspecs store, whose branches aren't in the
+/// remote's references.
+pub fn stale_refs_with_offset(
+    specs: &[Refspec],
+    remote_refs: &[(String, String)],
+    offset: &str,
+) -> io::Result<Vec<String>> {
+    let refs = get_refs_from_with_prefix(&format!("{offset}/refs"), &format!("{offset}/"))?;
+    let mut stale: Vec<_> = refs
+        .into_keys()
+        .filter(|name| {
+            // Sources may be short names, so the remote's references
+            // are mapped instead of the local one being unmapped.
+            specs
+                .iter()
+                .filter(|spec| !spec.negative)
+                .find(|spec| spec.unmap(name).is_some())
+                .is_some_and(|spec| {
+                    !remote_refs
+                        .iter()
+                        .any(|(_, src)| spec.map(src).as_deref() == Some(name))
+                })
+        })
+        .collect();
+
+    stale.sort();
+    Ok(stale)
+}
+
+/// Deletes the stale remote-tracking references of the remote,
+/// returning their names.
+pub fn prune(remote: &str) -> io::Result<Vec<String>> {
+    let specs = fetch_refspecs(&Config::read()?, remote)?;
+    prune_with_offset(&specs, &ls_remote(remote)?, &common_dir())
+}
+
+/// Deletes the stale references of the repository at the
+/// given offset, returning their names.
+pub fn prune_with_offset(
+    specs: &[Refspec],
+    remote_refs: &[(String, String)],
+    offset: &str,
+) -> io::Result<Vec<String>> {
+    let stale = stale_refs_with_offset(specs, remote_refs, offset)?;
+    for name in &stale {
+        update_local_ref(offset, name, ZERO_ID)?;
+    }
+
+    Ok(stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(name: &str) -> String {
+        let offset = format!(
+            "{}/fetch-{name}-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let _ = fs::remove_dir_all(&offset);
+        for name in ["feature", "gone", "kept"] {
+            update_local_ref(&offset, &format!("refs/remotes/origin/{name}"), "a1").unwrap();
+        }
+        update_local_ref(&offset, "refs/heads/master", "a1").unwrap();
+        offset
+    }
+
+    fn remote_refs(names: &[&str]) -> Vec<(String, String)> {
+        names
+            .iter()
+            .map(|name| ("a1".to_string(), format!("refs/heads/{name}")))
+            .collect()
+    }
+
+    #[test]
+    fn stale_refs() {
+        let offset = repo("stale");
+        let specs = parse_refspecs(&["+refs/heads/*:refs/remotes/origin/*".to_string()]).unwrap();
+        assert_eq!(
+            stale_refs_with_offset(&specs, &remote_refs(&["kept"]), &offset).unwrap(),
+            ["refs/remotes/origin/feature", "refs/remotes/origin/gone"]
+        );
+
+        // Short sources name the remote's full references.
+        let specs = parse_refspecs(&["feature:refs/remotes/origin/feature".to_string()]).unwrap();
+        assert!(
+            stale_refs_with_offset(&specs, &remote_refs(&["feature"]), &offset)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            stale_refs_with_offset(&specs, &remote_refs(&["kept"]), &offset).unwrap(),
+            ["refs/remotes/origin/feature"]
+        );
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+
+    #[test]
+    fn prune() {
+        let offset = repo("prune");
+        let specs = parse_refspecs(&["+refs/heads/*:refs/remotes/origin/*".to_string()]).unwrap();
+        let pruned = prune_with_offset(&specs, &remote_refs(&["feature", "kept"]), &offset);
+
+        assert_eq!(pruned.unwrap(), ["refs/remotes/origin/gone"]);
+        assert!(read_ref_with_offset("refs/remotes/origin/gone", &offset).is_err());
+        assert!(read_ref_with_offset("refs/remotes/origin/kept", &offset).is_ok());
+        assert!(read_ref_with_offset("refs/heads/master", &offset).is_ok());
+
+        fs::remove_dir_all(offset).unwrap();
+    }
 }
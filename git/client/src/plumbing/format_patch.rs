@@ -0,0 +1,126 @@
+use super::diff::diff_commit::{get_patch_of_tree_diffs, Patch};
+use super::diff::diff_tree::diff_tree;
+use super::diff::unified::unified_diff;
+use super::log::{__log, LogCommit, LogOptions};
+use crate::commands::ls_tree;
+use std::{fs, io};
+
+// Longest subject kept in the name of a patch file.
+const MAX_NAME_LEN: usize = 64;
+
+// Date of the `From` line starting every mail, it's the same for all
+// of them so they can be told apart from other mailboxes.
+const MBOX_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+/// Returns the name of the file of the n-th patch, like
+/// `0001-Fix-the-parser.patch`.
+pub fn patch_file_name(n: usize, subject: &str) -> String {
+    let mut slug = String::new();
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.truncate(MAX_NAME_LEN);
+    let slug = slug.trim_end_matches(['-', '.']);
+    format!("{n:04}-{slug}.patch")
+}
+
+// Returns the changes of the commit against it's first parent, a
+// commit without parents adds all of it's files.
+fn commit_patches(commit: &LogCommit) -> io::Result<Vec<Patch>> {
+    let old_tree = match commit.parents.first() {
+        Some(parent) => ls_tree(&LogCommit::read(parent)?.tree)?,
+        None => String::new(),
+    };
+    let new_tree = ls_tree(&commit.tree)?;
+
+    let diffs: Vec<_> = diff_tree(&old_tree, &new_tree).collect();
+    get_patch_of_tree_diffs(&diffs, "")
+}
+
+/// Formats the commit as a mail, the n-th of a series of total.
+pub fn format_mail(commit: &LogCommit, n: usize, total: usize) -> io::Result<String> {
+    let prefix = match total {
+        1 => "[PATCH]".to_string(),
+        _ => format!("[PATCH {n}/{total}]"),
+    };
+
+    // The first paragraph of the message is the subject.
+    let message = commit.message.trim_end();
+    let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    let subject = subject.lines().collect::<Vec<_>>().join(" ");
+
+    let mut mail = format!(
+        "From {} {MBOX_DATE}\nFrom: {}\nDate: {}\nSubject: {prefix} {subject}\n\n",
+        commit.hash,
+        commit.author,
+        commit.author_date.to_rfc2822(),
+    );
+
+    if !body.is_empty() {
+        mail.push_str(&format!("{body}\n"));
+    }
+    mail.push_str("---\n");
+
+    for patch in commit_patches(commit)? {
+        mail.push_str(&unified_diff(&patch)?);
+    }
+
+    mail.push_str(&format!("-- \n{}\n\n", env!("CARGO_PKG_VERSION")));
+    Ok(mail)
+}
+
+/// Underlying implementation of `git format-patch`. Writes a file
+/// for every commit in the range, oldest first, returning their
+/// names. A single revision is the range from it to HEAD.
+pub fn __format_patch(range: &str) -> io::Result<Vec<String>> {
+    let revs = match range.contains("..") {
+        true => vec![range.to_string()],
+        false => vec![format!("{range}..HEAD")],
+    };
+
+    let opts = LogOptions {
+        revs,
+        ..Default::default()
+    };
+
+    // Merges have no single diff to send.
+    let mut commits: Vec<_> = __log(&opts)?
+        .into_iter()
+        .filter(|commit| commit.parents.len() < 2)
+        .collect();
+    commits.reverse();
+
+    let total = commits.len();
+    let mut files = vec![];
+    for (i, commit) in commits.iter().enumerate() {
+        let name = patch_file_name(i + 1, commit.subject());
+        fs::write(&name, format_mail(commit, i + 1, total)?)?;
+        files.push(name);
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            patch_file_name(1, "Fix the parser"),
+            "0001-Fix-the-parser.patch"
+        );
+        assert_eq!(
+            patch_file_name(12, "Add `am` (mailbox) support..."),
+            "0012-Add-am-mailbox-support.patch"
+        );
+        assert_eq!(patch_file_name(3, "v1.2: bump"), "0003-v1.2-bump.patch");
+        assert_eq!(patch_file_name(1, &"a".repeat(100)).len(), 5 + 64 + 6);
+    }
+}
@@ -0,0 +1,320 @@
+use super::commands::read_index;
+use super::refs::rev_parse;
+use regex::{Regex, RegexBuilder};
+use std::{
+    io::{self, ErrorKind},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+use utils::common_dir;
+use utils::index_file::index_entry::IndexEntry;
+use utils::object::object_db::{expand_hash_with_offset, get_object};
+use utils::plumbing::commit::get_commit_root;
+use utils::plumbing::ls_tree::{hash_to_str, ls_tree, parse_ls_tree_entry};
+
+/// Where grep looks for files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum GrepSource {
+    /// The working tree's version of the files in the index.
+    #[default]
+    WorkTree,
+    /// The files in the index.
+    Index,
+    /// The files of a commit or tree.
+    Tree(String),
+}
+
+/// Options of the grep command.
+#[derive(Debug, Clone, Default)]
+pub struct GrepOptions {
+    /// Regular expression matched against every line.
+    pub pattern: String,
+    pub source: GrepSource,
+    pub ignore_case: bool,
+    /// Shows the number of every matching line.
+    pub line_number: bool,
+    /// Only shows the names of the matching files.
+    pub files_with_matches: bool,
+    /// Only searches files under these paths.
+    pub paths: Vec<String>,
+    /// Number of files searched at the same time,
+    /// the number of CPUs when 0.
+    pub threads: usize,
+}
+
+/// A file with lines matching the pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrepFile {
+    pub path: String,
+    /// Matches in binary files have no lines.
+    pub binary: bool,
+    /// The number and content of every matching line.
+    pub lines: Vec<(usize, String)>,
+}
+
+// Returns true if the path is under any of the paths, or there are none.
+fn in_paths(path: &str, paths: &[String]) -> bool {
+    paths.is_empty()
+        || paths.iter().any(|filter| {
+            let filter = filter.trim_end_matches('/');
+            filter.is_empty()
+                || filter == "."
+                || path == filter
+                || path.starts_with(&format!("{filter}/"))
+        })
+}
+
+// Resolves a commit or tree to the hash of it's tree.
+fn get_tree(rev: &str) -> io::Result<String> {
+    let err = match rev_parse(rev) {
+        Ok(commit) => {
+            let (_, _, data) = get_object(&commit)?;
+            return get_commit_root(&data);
+        }
+        Err(err) => err,
+    };
+
+    let hash = expand_hash_with_offset(rev, &common_dir()).map_err(|_| err)?;
+    match get_object(&hash)? {
+        (otype, _, _) if otype == "tree" => Ok(hash),
+        _ => Err(io::Error::new(ErrorKind::InvalidInput, "Not a tree-ish")),
+    }
+}
+
+// Lists the blobs of a tree as (path, hash).
+fn tree_files(tree: &str) -> io::Result<Vec<(String, String)>> {
+    let mut files = vec![];
+    let mut stack = vec![(String::new(), tree.to_string())];
+
+    while let Some((dir, tree)) = stack.pop() {
+        for entry in ls_tree(&tree)?.lines() {
+            let (_, otype, hash, name) = parse_ls_tree_entry(entry);
+            let path = match dir.as_str() {
+                "" => name,
+                dir => format!("{dir}/{name}"),
+            };
+
+            // Submodules' commits aren't in this repository.
+            match otype {
+                "tree" => stack.push((path, hash.to_string())),
+                "blob" => files.push((path, hash.to_string())),
+                _ => {}
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+// Lists the files to search as (path, hash of it's blob), files
+// of the working tree have no hash, they are read from disk.
+fn list_files(source: &GrepSource) -> io::Result<Vec<(String, Option<String>)>> {
+    let files = match source {
+        GrepSource::Tree(rev) => tree_files(&get_tree(rev)?)?
+            .into_iter()
+            .map(|(path, hash)| (path, Some(hash)))
+            .collect(),
+        source => {
+            let mut entries: Vec<IndexEntry> = read_index()?.get_entries();
+            entries.retain(|entry| !entry.is_gitlink());
+            entries.sort_by(|a, b| a.get_path().cmp(b.get_path()));
+
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let hash = match source {
+                        GrepSource::Index => Some(hash_to_str(entry.get_hash())),
+                        _ => None,
+                    };
+                    (entry.get_path().to_string(), hash)
+                })
+                .collect()
+        }
+    };
+
+    Ok(files)
+}
+
+// Searches the content of a file, returning it if it matches.
+fn grep_file(path: &str, data: &[u8], regex: &Regex) -> Option<GrepFile> {
+    let text = String::from_utf8_lossy(data);
+
+    // Like git, files with a NUL byte are binary.
+    if data.contains(&0) {
+        return regex.is_match(&text).then(|| GrepFile {
+            path: path.to_string(),
+            binary: true,
+            lines: vec![],
+        });
+    }
+
+    let lines: Vec<_> = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(i, line)| (i + 1, line.to_string()))
+        .collect();
+
+    (!lines.is_empty()).then(|| GrepFile {
+        path: path.to_string(),
+        binary: false,
+        lines,
+    })
+}
+
+/// Underlying implementation of `git grep`. Searches the files of the
+/// source for lines matching the pattern with multiple threads,
+/// returning the matching files sorted by path.
+pub fn __grep(opts: &GrepOptions) -> io::Result<Vec<GrepFile>> {
+    let regex = RegexBuilder::new(&opts.pattern)
+        .case_insensitive(opts.ignore_case)
+        .build()
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+    let mut files = list_files(&opts.source)?;
+    files.retain(|(path, _)| in_paths(path, &opts.paths));
+
+    let threads = match opts.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+
+    // Workers take the next file to search until there are none left.
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![]);
+    thread::scope(|scope| {
+        for _ in 0..threads.min(files.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let (path, hash) = match files.get(i) {
+                    Some(file) => file,
+                    None => break,
+                };
+
+                let data = match hash {
+                    Some(hash) => get_object(hash).map(|(_, _, data)| Some(data)),
+                    // Files removed from the working tree are skipped.
+                    None => match IndexEntry::read_content(path) {
+                        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                        data => data.map(Some),
+                    },
+                };
+
+                let result = data.map(|data| data.and_then(|data| grep_file(path, &data, &regex)));
+                let mut results = results.lock().unwrap_or_else(|e| e.into_inner());
+                results.push((i, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut matches = vec![];
+    for (_, result) in results {
+        matches.extend(result?);
+    }
+
+    Ok(matches)
+}
+
+/// Formats the matches like git does: `<path>:<line>`, with the line's
+/// number when asked for and prefixed by the revision they were found in.
+pub fn format_grep(files: &[GrepFile], opts: &GrepOptions) -> String {
+    let prefix = match &opts.source {
+        GrepSource::Tree(rev) => format!("{rev}:"),
+        _ => String::new(),
+    };
+
+    let mut ret = String::new();
+    for GrepFile {
+        path,
+        binary,
+        lines,
+    } in files
+    {
+        if opts.files_with_matches {
+            ret.push_str(&format!("{prefix}{path}\n"));
+        } else if *binary {
+            ret.push_str(&format!("Binary file {prefix}{path} matches\n"));
+        } else {
+            for (n, line) in lines {
+                match opts.line_number {
+                    true => ret.push_str(&format!("{prefix}{path}:{n}:{line}\n")),
+                    false => ret.push_str(&format!("{prefix}{path}:{line}\n")),
+                }
+            }
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_filters() {
+        let paths = ["src/".to_string(), "README.md".to_string()];
+
+        assert!(in_paths("src/main.rs", &paths));
+        assert!(in_paths("README.md", &paths));
+        assert!(!in_paths("srcs/main.rs", &paths));
+        assert!(in_paths("anything", &[]));
+        assert!(in_paths("anything", &[".".to_string()]));
+    }
+
+    #[test]
+    fn matching_lines() {
+        let regex = RegexBuilder::new("^fn [a-z]+")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let data = b"fn main() {}\nstruct A;\nFN other() {}\n";
+
+        let file = grep_file("a.rs", data, &regex).unwrap();
+        assert_eq!(
+            file.lines,
+            vec![
+                (1, "fn main() {}".to_string()),
+                (3, "FN other() {}".to_string())
+            ]
+        );
+        assert!(grep_file("a.rs", b"struct A;\n", &regex).is_none());
+        assert!(grep_file("a.bin", b"fn x\0", &regex).unwrap().binary);
+    }
+
+    #[test]
+    fn format() {
+        let files = [
+            GrepFile {
+                path: "a.rs".to_string(),
+                binary: false,
+                lines: vec![(3, "fn main() {}".to_string())],
+            },
+            GrepFile {
+                path: "b.bin".to_string(),
+                binary: true,
+                lines: vec![],
+            },
+        ];
+
+        let mut opts = GrepOptions {
+            source: GrepSource::Tree("v1.0".to_string()),
+            line_number: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_grep(&files, &opts),
+            "v1.0:a.rs:3:fn main() {}\nBinary file v1.0:b.bin matches\n"
+        );
+
+        opts.files_with_matches = true;
+        assert_eq!(format_grep(&files, &opts), "v1.0:a.rs\nv1.0:b.bin\n");
+    }
+}
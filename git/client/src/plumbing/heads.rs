@@ -4,9 +4,15 @@ use std::{
 };
 
 use crate::io_err;
+use utils::{common_dir, git_dir, read_ref_with_offset};
 
 use super::commands::get_branch;
 
+// Returns the path of the HEAD file of the worktree.
+fn head_path() -> String {
+    format!("{}/HEAD", git_dir())
+}
+
 enum Head<E> {
     Detached(String),
     Refered(String),
@@ -25,7 +31,7 @@ fn cur_branch_file_path<R: Read>(mut head: R) -> Head<io::Error> {
     let branch = branch.replace('\n', "");
     if let Some(stripped) = branch.strip_prefix("ref: ") {
         // HEAD is checked out.
-        Head::Refered(".git/".to_string() + stripped)
+        Head::Refered(format!("{}/{stripped}", common_dir()))
     } else {
         // HEAD is detached.
         let hash = branch;
@@ -37,8 +43,8 @@ fn cur_branch_file_path<R: Read>(mut head: R) -> Head<io::Error> {
 pub fn __get_head_commit<R: Read>(head: R) -> io::Result<String> {
     match cur_branch_file_path(head) {
         Head::Refered(branch) => {
-            let s = fs::read_to_string(branch)?;
-            Ok(s.replace('\n', ""))
+            let common_dir = common_dir();
+            read_ref_with_offset(&branch[common_dir.len() + 1..], &common_dir)
         }
         Head::Detached(hash) => Ok(hash),
         Head::Err(e) => Err(e),
@@ -51,7 +57,8 @@ pub fn __get_head_commit<R: Read>(head: R) -> io::Result<String> {
 /// It doesn't change the current branch, it just changes the commit.
 pub fn __update_head_commit(hash_commit: &str) -> io::Result<()> {
     // update HEAD file to point to branch
-    let head = File::open(".git/HEAD")?;
+    let head_path = head_path();
+    let head = File::open(&head_path)?;
 
     match cur_branch_file_path(head) {
         Head::Refered(branch) => {
@@ -61,9 +68,9 @@ pub fn __update_head_commit(hash_commit: &str) -> io::Result<()> {
             Ok(())
         }
 
-        Head::Detached(hash) => {
-            let mut head = File::create(".git/HEAD")?;
-            head.write_all(hash.as_bytes())?;
+        Head::Detached(_) => {
+            let mut head = File::create(head_path)?;
+            head.write_all(hash_commit.as_bytes())?;
             head.write_all(b"\n")?;
             Ok(())
         }
@@ -78,11 +85,19 @@ pub fn update_head_reference(reference: &str) -> io::Result<()> {
     get_branch(reference).ok_or(io_err!("Invalid reference"))?;
 
     // Point to that reference
-    let mut head = File::create(".git/HEAD")?;
+    let mut head = File::create(head_path())?;
     head.write_all(format!("ref: refs/heads/{reference}\n").as_bytes())?;
     Ok(())
 }
 
+/// Detaches HEAD, making it point directly to the given commit.
+pub fn detach_head(hash: &str) -> io::Result<()> {
+    let mut head = File::create(head_path())?;
+    head.write_all(hash.as_bytes())?;
+    head.write_all(b"\n")?;
+    Ok(())
+}
+
 /// Underlying implementation of get_branch.
 pub fn __get_branch<R: Read>(mut branch: R) -> io::Result<String> {
     let mut hash = String::new();
@@ -93,9 +108,9 @@ pub fn __get_branch<R: Read>(mut branch: R) -> io::Result<String> {
 /// Returns the name of the current branch.
 /// If HEAD is detached, returns the hash of the commit.
 pub fn get_head_name() -> io::Result<String> {
-    match cur_branch_file_path(File::open(".git/HEAD")?) {
+    match cur_branch_file_path(File::open(head_path())?) {
         Head::Refered(_) => {
-            let branch = fs::read_to_string(".git/HEAD")?;
+            let branch = fs::read_to_string(head_path())?;
             // get the name of the branch
             let branch = branch.replace('\n', "");
             let branch = branch
@@ -112,7 +127,7 @@ pub fn get_head_name() -> io::Result<String> {
 /// Writes the content in reference to HEAD file
 /// in the format: `ref: <reference>\n`.
 pub fn move_head(reference: &str) -> io::Result<()> {
-    let mut file = File::create(".git/HEAD")?;
+    let mut file = File::create(head_path())?;
     file.write_all(b"ref: ")?;
     file.write_all(reference.as_bytes())?;
     file.write_all(b"\n")?;
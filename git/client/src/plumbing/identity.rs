@@ -0,0 +1,162 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, ErrorKind},
+};
+
+/// Who made a change: the author or committer of a commit, or the
+/// tagger of a tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} <{}>", self.name, self.email)
+    }
+}
+
+/// Whose identity is wanted. Taggers are committers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    // Returns the environment variables that override the
+    // configuration, as (name, email).
+    fn env_vars(&self) -> (&str, &str) {
+        match self {
+            Role::Author => ("GIT_AUTHOR_NAME", "GIT_AUTHOR_EMAIL"),
+            Role::Committer => ("GIT_COMMITTER_NAME", "GIT_COMMITTER_EMAIL"),
+        }
+    }
+}
+
+// Fails telling the user how to set who they are.
+fn unknown(role: Role) -> io::Error {
+    let role = match role {
+        Role::Author => "Author",
+        Role::Committer => "Committer",
+    };
+
+    let msg = format!(
+        "{role} identity unknown\n\n\
+         *** Please tell me who you are.\n\n\
+         Run\n\n  \
+         git config --global user.email \"you@example.com\"\n  \
+         git config --global user.name \"Your Name\"\n\n\
+         to set your account's default identity."
+    );
+    io::Error::new(ErrorKind::NotFound, msg)
+}
+
+// Drops what can't be in an identity as git does: `<`, `>` and newlines
+// anywhere, which would end it early or split the header it's in, and
+// the punctuation and whitespace around it.
+fn without_crud(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| !matches!(c, '<' | '>' | '\n'))
+        .collect();
+    value
+        .trim_matches(|c: char| c.is_whitespace() || ".,:;\"\\'".contains(c))
+        .to_string()
+}
+
+/// Underlying implementation of get_identity. The environment variables
+/// of the role override `user.name` and `user.email`, and `EMAIL` is
+/// used if there is no email. Fails if the name or email is missing.
+pub fn __get_identity<E, C>(role: Role, env: E, config: C) -> io::Result<Identity>
+where
+    E: Fn(&str) -> Option<String>,
+    C: Fn(&str) -> io::Result<Option<String>>,
+{
+    let (name_var, email_var) = role.env_vars();
+    let name = match env(name_var) {
+        Some(name) => Some(name),
+        None => config("user.name")?,
+    };
+    let email = match env(email_var) {
+        Some(email) => Some(email),
+        None => config("user.email")?.or_else(|| env("EMAIL")),
+    };
+
+    let (name, email) = match (name, email) {
+        (Some(name), Some(email)) if !name.trim().is_empty() && !email.trim().is_empty() => {
+            (name, email)
+        }
+        _ => return Err(unknown(role)),
+    };
+
+    let identity = Identity {
+        name: without_crud(&name),
+        email: without_crud(&email),
+    };
+    if identity.name.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("name consists only of disallowed characters: {name}"),
+        ));
+    }
+    if identity.email.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("email consists only of disallowed characters: {email}"),
+        ));
+    }
+
+    Ok(identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(key: &str) -> io::Result<Option<String>> {
+        Ok(match key {
+            "user.name" => Some("Ada Lovelace".to_string()),
+            "user.email" => Some("ada@example.com".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn identity() {
+        let author = __get_identity(Role::Author, |_| None, config).unwrap();
+        assert_eq!(author.to_string(), "Ada Lovelace <ada@example.com>");
+
+        let env = |var: &str| (var == "GIT_COMMITTER_NAME").then(|| "CI".to_string());
+        let committer = __get_identity(Role::Committer, env, config).unwrap();
+        assert_eq!(committer.to_string(), "CI <ada@example.com>");
+        assert_eq!(__get_identity(Role::Author, env, config).unwrap(), author);
+
+        let err = __get_identity(Role::Author, |_| None, |_| Ok(None)).unwrap_err();
+        assert!(err.to_string().starts_with("Author identity unknown"));
+
+        let env = |var: &str| (var == "EMAIL").then(|| "ada@home".to_string());
+        let no_email = |key: &str| config(key).map(|v| v.filter(|_| key == "user.name"));
+        let author = __get_identity(Role::Author, env, no_email).unwrap();
+        assert_eq!(author.email, "ada@home");
+    }
+
+    #[test]
+    fn strips_crud() {
+        let env = |var: &str| match var {
+            "GIT_AUTHOR_NAME" => Some(" Ada <Lovelace>\nparent 1234 ".to_string()),
+            "GIT_AUTHOR_EMAIL" => Some("<ada@example.com>.".to_string()),
+            _ => None,
+        };
+        let author = __get_identity(Role::Author, env, config).unwrap();
+        assert_eq!(
+            author.to_string(),
+            "Ada Lovelaceparent 1234 <ada@example.com>"
+        );
+
+        let env = |var: &str| (var == "GIT_AUTHOR_NAME").then(|| "<>".to_string());
+        let err = __get_identity(Role::Author, env, config).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::io_err;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+use utils::{delete_packed_ref_with_offset, get_refs_from_with_prefix, read_ref_with_offset};
+
+const FILE_SCHEME: &str = "file://";
+const ZERO_ID: &str = "0000000000000000000000000000000000000000";
+
+// Directories of the references sent to other repositories.
+const REF_DIRS: [&str; 3] = ["refs/heads", "refs/tags", "refs/notes"];
+
+/// Returns the path of a local url, a plain path or a `file://`
+/// one. None for urls of other transports, like `git://` or `ext::`.
+pub fn local_path(url: &str) -> Option<&str> {
+    match url.strip_prefix(FILE_SCHEME) {
+        Some(path) => Some(path),
+        None if url.contains("://") || url.contains("::") => None,
+        None => Some(url),
+    }
+}
+
+/// Returns the git directory of the repository at a local url: it's
+/// `.git` directory, or itself if it's bare. None if the url isn't
+/// local or there is no repository there.
+pub fn local_git_dir(url: &str) -> Option<String> {
+    let path = Path::new(local_path(url)?);
+    let git_dir = match path.join(".git") {
+        dir if dir.is_dir() => dir,
+        _ if path.join("objects").is_dir() && path.join("HEAD").is_file() => path.to_path_buf(),
+        _ => return None,
+    };
+
+    let git_dir = fs::canonicalize(&git_dir).unwrap_or(git_dir);
+    Some(git_dir.to_string_lossy().to_string())
+}
+
+/// Returns the commit HEAD points to in the repository and the
+/// branch it's on, None if it has no commits.
+pub fn local_head(git_dir: &str) -> Option<(String, String)> {
+    let head = fs::read_to_string(format!("{git_dir}/HEAD")).ok()?;
+    match head.strip_prefix("ref: ") {
+        Some(name) => {
+            let name = name.trim();
+            let hash = read_ref_with_offset(name, git_dir).ok()?;
+            Some((hash, name.to_string()))
+        }
+        None => Some((head.trim().to_string(), "HEAD".to_string())),
+    }
+}
+
+/// Returns the branches, tags and notes of the repository
+/// as (hash, name), sorted by name.
+pub fn local_refs(git_dir: &str) -> io::Result<Vec<(String, String)>> {
+    let mut refs = vec![];
+    for dir in REF_DIRS {
+        match get_refs_from_with_prefix(&format!("{git_dir}/{dir}"), &format!("{git_dir}/")) {
+            Ok(dir_refs) => refs.extend(dir_refs.into_iter().map(|(name, hash)| (hash, name))),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    refs.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(refs)
+}
+
+// Hard links the file, copying it if it's in another file system.
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::hard_link(from, to).is_err() {
+        fs::copy(from, to)?;
+    }
+
+    Ok(())
+}
+
+/// Copies the loose objects and packs of a repository to another one,
+/// skipping the ones it already has. Returns how many were copied.
+pub fn copy_objects(from: &str, to: &str) -> io::Result<usize> {
+    let mut copied = 0;
+    for dir in fs::read_dir(format!("{from}/objects"))? {
+        let dir = dir?;
+        let name = dir.file_name().to_string_lossy().to_string();
+
+        // Object directories are named by the first two hex
+        // digits of their objects, packs are kept in `pack`.
+        let is_objects = name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit());
+        if !dir.file_type()?.is_dir() || !(is_objects || name == "pack") {
+            continue;
+        }
+
+        let target = Path::new(to).join("objects").join(&name);
+        fs::create_dir_all(&target)?;
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let target = target.join(file.file_name());
+            if !target.exists() {
+                link_or_copy(&file.path(), &target)?;
+                copied += 1;
+            }
+        }
+    }
+
+    Ok(copied)
+}
+
+/// Points the reference of the repository to the given hash,
+/// deleting it if the hash is all zeros.
+pub fn update_local_ref(git_dir: &str, name: &str, hash: &str) -> io::Result<()> {
+    let path = Path::new(git_dir).join(name);
+    if hash == ZERO_ID {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => delete_packed_ref_with_offset(name, git_dir)?,
+        };
+        return Ok(());
+    }
+
+    let dir = path.parent().ok_or(io_err!("Invalid reference"))?;
+    fs::create_dir_all(dir)?;
+    fs::write(path, format!("{hash}\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_urls() {
+        assert_eq!(local_path("file:///srv/repo.git"), Some("/srv/repo.git"));
+        assert_eq!(local_path("../repo"), Some("../repo"));
+        assert_eq!(local_path("git://127.0.0.1/repo.git"), None);
+    }
+}
@@ -1,96 +1,685 @@
-use super::commit::{
-    get_author_and_time, get_commit_msg, get_committer_and_time, get_parent_commits,
+use super::commit::{get_author_and_time, get_committer_and_time, get_parent_commits};
+use super::notes::{get_note, read_notes};
+use super::refs::rev_parse;
+use super::signing::signature_status;
+use crate::io_err;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    io,
 };
-use chrono::{DateTime, FixedOffset};
-use std::{collections::HashSet, io};
-use utils::object::object_db::get_object;
-
-fn get_log(
-    hash: &str,
-    visited: &mut HashSet<String>,
-) -> io::Result<Vec<(String, Option<DateTime<FixedOffset>>)>> {
-    visited.insert(hash.to_string());
+use utils::common_dir;
+use utils::object::object_db::get_object_with_offset;
+use utils::plumbing::commit_graph::History;
+use utils::plumbing::ls_tree::get_entry_with_offset;
+
+/// A commit as listed by log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogCommit {
+    pub hash: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    /// Parents as drawn by the graph. Ancestors that aren't
+    /// listed are replaced by the listed ones they lead to.
+    pub edges: Vec<String>,
+    /// Author as `Name <mail>`.
+    pub author: String,
+    pub author_date: DateTime<FixedOffset>,
+    /// Committer as `Name <mail>`.
+    pub committer: String,
+    pub committer_date: DateTime<FixedOffset>,
+    pub message: String,
+}
+
+// Splits an author or committer line into it's identity and date.
+fn identity(identity: Option<(String, String)>) -> (String, DateTime<FixedOffset>) {
+    let (who, time) = identity.unwrap_or_default();
+    let date = DateTime::parse_from_str(&time, "%s %z").unwrap_or_default();
+    (who, date)
+}
+
+impl LogCommit {
+    /// Reads the commit with the given hash.
+    pub fn read(hash: &str) -> io::Result<Self> {
+        Self::read_with_offset(hash, &common_dir())
+    }
+
+    /// Reads the commit with the given hash from the database at the offset.
+    pub fn read_with_offset(hash: &str, offset: &str) -> io::Result<Self> {
+        let (otype, _, data) = get_object_with_offset(hash, offset)?;
+        if otype != "commit" {
+            return Err(io_err!("Object is not a commit"));
+        }
+
+        let text = String::from_utf8_lossy(&data);
+        let (header, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+        let tree = header
+            .lines()
+            .find_map(|line| line.strip_prefix("tree "))
+            .ok_or(io_err!("Commit without tree"))?;
+
+        let parents = get_parent_commits(&data).unwrap_or_default();
+        let (author, author_date) = identity(get_author_and_time(&data));
+        let (committer, committer_date) = identity(get_committer_and_time(&data));
+
+        Ok(Self {
+            hash: hash.to_string(),
+            tree: tree.to_string(),
+            edges: parents.clone(),
+            parents,
+            author,
+            author_date,
+            committer,
+            committer_date,
+            message: message.to_string(),
+        })
+    }
+
+    /// Returns the first line of the message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+
+    /// Returns the message without it's subject.
+    pub fn body(&self) -> &str {
+        self.message.split_once("\n\n").map_or("", |(_, body)| body)
+    }
+}
+
+/// How log shows every commit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum LogFormat {
+    /// The hash, author, date and message of the commit.
+    #[default]
+    Medium,
+    /// The abbreviated hash and the subject.
+    Oneline,
+    /// A `--format` string, see `expand_format`.
+    Custom(String),
+}
+
+/// Options of the log command.
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Revisions to list the history of, HEAD when empty. Revisions
+    /// prefixed with `^` exclude their ancestors, `a..b` is `^a b`.
+    pub revs: Vec<String>,
+    /// Only lists commits that change these paths.
+    pub paths: Vec<String>,
+    /// Only lists commits whose author contains this.
+    pub author: Option<String>,
+    /// Only lists commits whose message contains this.
+    pub grep: Option<String>,
+    /// Only lists commits committed after this unix time.
+    pub since: Option<i64>,
+    /// Only lists commits committed before this unix time.
+    pub until: Option<i64>,
+    /// Maximum number of commits listed.
+    pub max_count: Option<usize>,
+    /// Follows only the first parent of merges.
+    pub first_parent: bool,
+    pub format: LogFormat,
+    /// Draws the history as an ASCII graph.
+    pub graph: bool,
+    /// Checks the signature of signed commits against this allowed
+    /// signers file, as `--show-signature`.
+    pub show_signature: Option<String>,
+    /// Hides the notes the default format shows after the message.
+    pub no_notes: bool,
+}
+
+impl LogOptions {
+    // Returns whether the commit passes the filters.
+    fn matches(&self, commit: &LogCommit) -> bool {
+        let time = commit.committer_date.timestamp();
+
+        self.author
+            .as_ref()
+            .is_none_or(|author| commit.author.contains(author.as_str()))
+            && self
+                .grep
+                .as_ref()
+                .is_none_or(|grep| commit.message.contains(grep.as_str()))
+            && self.since.is_none_or(|since| time >= since)
+            && self.until.is_none_or(|until| time <= until)
+    }
+}
+
+/// Parses a `--since` or `--until` date into a unix time. Dates are
+/// `@<unix time>`, `<unix time> <tz>`, `YYYY-MM-DD` or
+/// `YYYY-MM-DD HH:MM:SS`, the last two in local time.
+pub fn parse_date(date: &str) -> io::Result<i64> {
+    let date = date.trim();
+
+    if let Some(time) = date.strip_prefix('@') {
+        return time.parse().map_err(|_| io_err!("Invalid date"));
+    }
+
+    if let Ok(date) = DateTime::parse_from_str(date, "%s %z") {
+        return Ok(date.timestamp());
+    }
+
+    let naive = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").or_else(|_| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+    });
+
+    match naive.map(|naive| Local.from_local_datetime(&naive).earliest()) {
+        Ok(Some(date)) => Ok(date.timestamp()),
+        _ => Err(io_err!("Invalid date")),
+    }
+}
+
+// Splits revisions into the ones to list and the
+// ones whose ancestors are excluded.
+fn parse_revs(revs: &[String]) -> (Vec<String>, Vec<String>) {
+    let or_head = |rev: &str| match rev {
+        "" => "HEAD".to_string(),
+        rev => rev.to_string(),
+    };
+
+    let mut include = vec![];
+    let mut exclude = vec![];
+    for rev in revs {
+        if let Some((a, b)) = rev.split_once("..") {
+            exclude.push(or_head(a));
+            include.push(or_head(b));
+        } else if let Some(rev) = rev.strip_prefix('^') {
+            exclude.push(rev.to_string());
+        } else {
+            include.push(rev.to_string());
+        }
+    }
+
+    if include.is_empty() {
+        include.push("HEAD".to_string());
+    }
+
+    (include, exclude)
+}
+
+// Returns the hash of every path in the tree.
+fn path_entries(tree: &str, paths: &[String]) -> io::Result<Vec<Option<String>>> {
+    paths
+        .iter()
+        .map(|path| Ok(get_entry_with_offset(tree, path, &common_dir())?.map(|(_, hash)| hash)))
+        .collect()
+}
+
+// History simplification. A commit that changes the paths from every
+// parent is listed, otherwise only the first parent with the same
+// paths is followed. Returns whether it's listed and the parents to follow.
+fn simplify(
+    history: &History,
+    tree: &str,
+    parents: Vec<String>,
+    paths: &[String],
+) -> io::Result<(bool, Vec<String>)> {
+    let entries = path_entries(tree, paths)?;
+    if parents.is_empty() {
+        return Ok((entries.iter().any(Option::is_some), parents));
+    }
+
+    for parent in &parents {
+        let commit = history.commit(parent)?;
+        if path_entries(&commit.tree, paths)? == entries {
+            return Ok((false, vec![parent.clone()]));
+        }
+    }
+
+    Ok((true, parents))
+}
+
+// Replaces the parents that aren't listed with the listed
+// commits the walk reached through them.
+fn resolve_edges(
+    parents: &[String],
+    skipped: &HashMap<String, Vec<String>>,
+    listed: &HashSet<String>,
+) -> Vec<String> {
+    let mut edges = vec![];
+    let mut visited = HashSet::new();
+    let mut stack: Vec<_> = parents.iter().rev().cloned().collect();
+
+    while let Some(hash) = stack.pop() {
+        if !visited.insert(hash.clone()) {
+            continue;
+        }
+
+        if listed.contains(&hash) {
+            edges.push(hash);
+        } else if let Some(parents) = skipped.get(&hash) {
+            stack.extend(parents.iter().rev().cloned());
+        }
+    }
+
+    edges
+}
+
+/// Walks the history selected by the options, newest commits first,
+/// returning the commits that pass the filters. Parents are never
+/// listed before their children.
+pub fn __log(opts: &LogOptions) -> io::Result<Vec<LogCommit>> {
+    let history = History::with_offset(&common_dir());
+    let (include, exclude) = parse_revs(&opts.revs);
+
+    // Ancestors of excluded revisions are never listed.
+    let mut excluded = HashSet::new();
+    let mut stack = exclude
+        .iter()
+        .map(|rev| rev_parse(rev))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    while let Some(hash) = stack.pop() {
+        if excluded.insert(hash.clone()) {
+            stack.extend(history.commit(&hash)?.parents);
+        }
+    }
+
+    let mut queue = BinaryHeap::new();
+    let mut seen = HashSet::new();
+    for rev in include {
+        let hash = rev_parse(&rev)?;
+        if seen.insert(hash.clone()) {
+            let commit = history.commit(&hash)?;
+            queue.push((commit.date, commit.generation, hash));
+        }
+    }
+
+    // Walked commits that aren't listed, with the parents followed.
+    let mut skipped = HashMap::new();
     let mut commits = vec![];
 
-    let mut new_msg = String::new();
-    let (_, _, data) = get_object(hash)?;
+    while let Some((_, _, hash)) = queue.pop() {
+        if opts.max_count.is_some_and(|max| commits.len() >= max) {
+            break;
+        }
 
-    // Append commit's hash.
-    new_msg.push_str(&format!("commit {}\n", hash));
+        if excluded.contains(&hash) {
+            continue;
+        }
 
-    // If this is a merge commit, append the parent commits.
-    if let Some(parents) = get_parent_commits(&data) {
-        if parents.len() > 1 {
-            new_msg.push_str(&format!(
-                "Merge: {}\n",
-                parents
-                    .iter()
-                    .map(|p| &p[..7])
-                    .collect::<Vec<&str>>()
-                    .join(" ")
-            ));
-        }
-    }
-
-    let mut commit_time = None;
-
-    // Append author and time.
-    if let Some((author, _)) = get_author_and_time(&data) {
-        new_msg.push_str(&format!("Author: {author}\n"));
-
-        // Get committer's time.
-        if let Some((_, time)) = get_committer_and_time(&data) {
-            // Format time.
-            if let Ok(time) = DateTime::parse_from_str(&time, "%s %z") {
-                new_msg.push_str(&format!("Date:   {time}\n"));
-                commit_time = Some(time);
+        let commit = history.commit(&hash)?;
+        let mut parents = commit.parents;
+        if opts.first_parent {
+            parents.truncate(1);
+        }
+
+        let mut listed = true;
+        if !opts.paths.is_empty() {
+            (listed, parents) = simplify(&history, &commit.tree, parents, &opts.paths)?;
+        }
+
+        for parent in &parents {
+            if seen.insert(parent.clone()) {
+                let commit = history.commit(parent)?;
+                queue.push((commit.date, commit.generation, parent.clone()));
+            }
+        }
+
+        if listed {
+            let mut commit = LogCommit::read(&hash)?;
+            if opts.matches(&commit) {
+                commit.edges = parents;
+                commits.push(commit);
+                continue;
             }
         }
+
+        skipped.insert(hash, parents);
+    }
+
+    let listed: HashSet<_> = commits.iter().map(|commit| commit.hash.clone()).collect();
+    for commit in &mut commits {
+        commit.edges = resolve_edges(&commit.edges, &skipped, &listed);
     }
 
-    // Append commit message.
-    if let Some(msg) = get_commit_msg(&data) {
-        new_msg.push_str(&format!("\n\t{}\n", msg));
+    Ok(topo_order(commits))
+}
+
+// Reorders the commits so none is listed before it's children,
+// otherwise keeping their order. Commit dates can't be trusted
+// for that, they may be equal or skewed.
+fn topo_order(commits: Vec<LogCommit>) -> Vec<LogCommit> {
+    let index: HashMap<_, _> = commits
+        .iter()
+        .enumerate()
+        .map(|(i, commit)| (commit.hash.clone(), i))
+        .collect();
+
+    let mut children = vec![0; commits.len()];
+    for edge in commits.iter().flat_map(|commit| &commit.edges) {
+        children[index[edge]] += 1;
     }
 
-    // Add it to the commit history.
-    commits.push((new_msg, commit_time));
+    let mut ready: BinaryHeap<_> = (0..commits.len())
+        .filter(|i| children[*i] == 0)
+        .map(Reverse)
+        .collect();
+
+    let mut order = vec![];
+    while let Some(Reverse(i)) = ready.pop() {
+        order.push(i);
+        for edge in &commits[i].edges {
+            children[index[edge]] -= 1;
+            if children[index[edge]] == 0 {
+                ready.push(Reverse(index[edge]));
+            }
+        }
+    }
 
-    // Iter through parents.
-    let parents = get_parent_commits(&data)
-        .unwrap_or_default()
+    let mut commits: Vec<_> = commits.into_iter().map(Some).collect();
+    order
         .into_iter()
-        .rev();
+        .filter_map(|i| commits[i].take())
+        .collect()
+}
 
-    for parent in parents {
-        if visited.contains(&parent) {
-            continue;
+// Splits `Name <mail>` into it's name and mail.
+fn split_identity(identity: &str) -> (&str, &str) {
+    match identity.split_once(" <") {
+        Some((name, mail)) => (name, mail.trim_end_matches('>')),
+        None => (identity, ""),
+    }
+}
+
+// Formats a date like git does by default.
+fn git_date(date: &DateTime<FixedOffset>) -> String {
+    date.format("%a %b %-d %H:%M:%S %Y %z").to_string()
+}
+
+// Expands the placeholder at the start of spec, returning
+// it's value and length. Unknown placeholders are kept.
+fn placeholder(commit: &LogCommit, spec: &str) -> Option<(String, usize)> {
+    const PLACEHOLDERS: [&str; 19] = [
+        "H", "h", "T", "t", "P", "p", "an", "ae", "ad", "at", "cn", "ce", "cd", "ct", "s", "b",
+        "B", "n", "%",
+    ];
+
+    let abbrev = |hashes: &[String]| {
+        let hashes: Vec<_> = hashes.iter().map(|hash| &hash[..7]).collect();
+        hashes.join(" ")
+    };
+
+    // Hex escapes, `%x09` is a tab.
+    if let Some(byte) = spec.strip_prefix('x').and_then(|hex| hex.get(..2)) {
+        if let Ok(byte) = u8::from_str_radix(byte, 16) {
+            return Some(((byte as char).to_string(), 3));
+        }
+    }
+
+    let key = PLACEHOLDERS.iter().find(|key| spec.starts_with(**key))?;
+    let (author, author_mail) = split_identity(&commit.author);
+    let (committer, committer_mail) = split_identity(&commit.committer);
+
+    let value = match *key {
+        "H" => commit.hash.clone(),
+        "h" => commit.hash[..7].to_string(),
+        "T" => commit.tree.clone(),
+        "t" => commit.tree[..7].to_string(),
+        "P" => commit.parents.join(" "),
+        "p" => abbrev(&commit.parents),
+        "an" => author.to_string(),
+        "ae" => author_mail.to_string(),
+        "ad" => git_date(&commit.author_date),
+        "at" => commit.author_date.timestamp().to_string(),
+        "cn" => committer.to_string(),
+        "ce" => committer_mail.to_string(),
+        "cd" => git_date(&commit.committer_date),
+        "ct" => commit.committer_date.timestamp().to_string(),
+        "s" => commit.subject().to_string(),
+        "b" => commit.body().to_string(),
+        "B" => commit.message.clone(),
+        "n" => "\n".to_string(),
+        _ => "%".to_string(),
+    };
+
+    Some((value, key.len()))
+}
+
+/// Expands the placeholders of a `--format` string: `%H` and `%h` for
+/// the hash, `%T` and `%t` for the tree, `%P` and `%p` for the parents,
+/// `%an`, `%ae`, `%ad` and `%at` for the author's name, mail, date and
+/// unix time, `%cn`, `%ce`, `%cd` and `%ct` for the committer's, `%s`
+/// for the subject, `%b` for the body, `%B` for the whole message,
+/// `%n` for a newline, `%xNN` for the byte NN in hex and `%%` for a `%`.
+pub fn expand_format(commit: &LogCommit, format: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = format;
+
+    while let Some(i) = rest.find('%') {
+        ret.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        match placeholder(commit, rest) {
+            Some((value, len)) => {
+                ret.push_str(&value);
+                rest = &rest[len..];
+            }
+            None => ret.push('%'),
+        }
+    }
+
+    ret.push_str(rest);
+    ret
+}
+
+// Builds the default log entry of a commit.
+fn medium(commit: &LogCommit) -> String {
+    let mut ret = format!("commit {}\n", commit.hash);
+
+    // If this is a merge commit, append the parent commits.
+    if commit.parents.len() > 1 {
+        let parents: Vec<_> = commit.parents.iter().map(|p| &p[..7]).collect();
+        ret.push_str(&format!("Merge: {}\n", parents.join(" ")));
+    }
+
+    ret.push_str(&format!("Author: {}\n", commit.author));
+    ret.push_str(&format!("Date:   {}\n\n", commit.committer_date));
+
+    for line in commit.message.lines() {
+        ret.push_str(&format!("\t{line}\n"));
+    }
+
+    ret
+}
+
+/// Formats a commit as a log entry.
+pub fn format_commit(commit: &LogCommit, format: &LogFormat) -> String {
+    match format {
+        LogFormat::Medium => medium(commit),
+        LogFormat::Oneline => format!("{} {}\n", &commit.hash[..7], commit.subject()),
+        LogFormat::Custom(format) => format!("{}\n", expand_format(commit, format)),
+    }
+}
+
+// Draws a row of lanes, `*` marks the commit's lane.
+fn draw_lanes(len: usize, commit: Option<usize>) -> String {
+    let lanes: Vec<_> = (0..len)
+        .map(|i| if Some(i) == commit { "*" } else { "|" })
+        .collect();
+    lanes.join(" ")
+}
+
+// Draws how lanes move between two rows, given as (old lane, new lane).
+// Returns None if every lane goes straight down.
+fn draw_moves(moves: &[(usize, usize)]) -> Option<String> {
+    if moves.iter().all(|(a, b)| a == b) {
+        return None;
+    }
+
+    let width = moves.iter().map(|(a, b)| 2 * a.max(b) + 2).max()?;
+    let mut line = vec![' '; width];
+
+    for &(a, b) in moves {
+        let (from, to) = match a.cmp(&b) {
+            Ordering::Equal => {
+                line[2 * a] = '|';
+                continue;
+            }
+            Ordering::Greater => {
+                line[2 * b + 1] = '/';
+                (2 * b + 2, 2 * a)
+            }
+            Ordering::Less => {
+                line[2 * a + 1] = '\\';
+                (2 * a + 2, 2 * b)
+            }
+        };
+
+        // Lanes moving more than one column are drawn with underscores.
+        for c in line[from..to].iter_mut().filter(|c| **c == ' ') {
+            *c = '_';
+        }
+    }
+
+    Some(line.into_iter().collect::<String>().trim_end().to_string())
+}
+
+/// Draws the history as an ASCII graph to the left of the log entries,
+/// one entry for every commit. Every commit has a lane, which is
+/// continued by the lanes of it's edges.
+pub fn draw_graph(commits: &[LogCommit], entries: &[String]) -> String {
+    let mut lanes: Vec<String> = vec![];
+    let mut ret = String::new();
+
+    for (commit, entry) in commits.iter().zip(entries) {
+        let idx = match lanes.iter().position(|hash| *hash == commit.hash) {
+            Some(idx) => idx,
+            None => {
+                lanes.push(commit.hash.clone());
+                lanes.len() - 1
+            }
+        };
+
+        // The commit's lane is replaced by the lanes of the edges
+        // that don't have one yet, the rest are joined to theirs.
+        let mut next = lanes[..idx].to_vec();
+        let mut moves: Vec<_> = (0..idx).map(|i| (i, i)).collect();
+
+        for edge in commit.edges.iter().filter(|edge| !lanes.contains(edge)) {
+            moves.push((idx, next.len()));
+            next.push(edge.clone());
+        }
+
+        for (i, hash) in lanes.iter().enumerate().skip(idx + 1) {
+            moves.push((i, next.len()));
+            next.push(hash.clone());
+        }
+
+        for edge in commit.edges.iter().filter(|edge| lanes.contains(edge)) {
+            if let Some(i) = next.iter().position(|hash| hash == edge) {
+                moves.push((idx, i));
+            }
+        }
+
+        let width = 2 * lanes.len().max(next.len());
+        let mut prefixes = vec![draw_lanes(lanes.len(), Some(idx))];
+        prefixes.extend(draw_moves(&moves));
+
+        let mut text = entry.lines();
+        for prefix in prefixes {
+            match text.next() {
+                Some(line) if !line.is_empty() => ret.push_str(&format!("{prefix:width$}{line}\n")),
+                _ => ret.push_str(&format!("{prefix}\n")),
+            }
+        }
+
+        for line in text {
+            let prefix = draw_lanes(next.len(), None);
+            match line {
+                "" => ret.push_str(&format!("{prefix}\n")),
+                line => ret.push_str(&format!("{prefix:width$}{line}\n")),
+            }
         }
 
-        commits.append(&mut get_log(&parent, visited).unwrap_or_default());
+        lanes = next;
     }
 
-    Ok(commits)
+    ret
 }
 
-/// Travels through the commit history of the given commit recompiling a readable
-/// version of the commit to show a history log to the user.
-pub fn __log(hash: &str, visited: &mut HashSet<String>) -> io::Result<Vec<String>> {
-    let mut log = get_log(hash, visited)?;
-    log.sort_by_key(|(_, time)| time.unwrap_or_default());
-    log.reverse();
-    Ok(log.into_iter().map(|(msg, _)| msg).collect())
+/// Formats the commits listed by log as the options say.
+pub fn format_log(commits: &[LogCommit], opts: &LogOptions) -> String {
+    let mut entries: Vec<_> = commits
+        .iter()
+        .map(|commit| format_commit(commit, &opts.format))
+        .collect();
+
+    // Signatures go after the first line of the default format,
+    // before the entry otherwise.
+    if let Some(allowed_signers) = &opts.show_signature {
+        for (commit, entry) in commits.iter().zip(entries.iter_mut()) {
+            let Some(status) = signature_status(&commit.hash, allowed_signers) else {
+                continue;
+            };
+
+            let at = match opts.format {
+                LogFormat::Medium => entry.find('\n').map_or(entry.len(), |i| i + 1),
+                _ => 0,
+            };
+            entry.insert_str(at, &status);
+        }
+    }
+
+    // Notes go after the message of the default format.
+    if opts.format == LogFormat::Medium && !opts.no_notes {
+        let notes = read_notes().unwrap_or_default();
+        for (commit, entry) in commits.iter().zip(entries.iter_mut()) {
+            if let Ok(Some(note)) = get_note(&notes, &commit.hash) {
+                entry.push_str("\nNotes:\n");
+                for line in note.lines() {
+                    entry.push_str(&format!("\t{line}\n"));
+                }
+            }
+        }
+    }
+
+    // Entries of the default format are separated by an empty line.
+    if opts.format == LogFormat::Medium {
+        let len = entries.len();
+        for entry in entries.iter_mut().take(len.saturating_sub(1)) {
+            entry.push('\n');
+        }
+    }
+
+    match opts.graph {
+        true => draw_graph(commits, &entries),
+        false => entries.concat(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn commit(hash: &str, edges: &[&str]) -> LogCommit {
+        let date = DateTime::parse_from_str("1700000000 -0300", "%s %z").unwrap();
+        let edges: Vec<_> = edges.iter().map(|edge| edge.to_string()).collect();
+
+        LogCommit {
+            hash: hash.repeat(40),
+            tree: "t".repeat(40),
+            parents: edges.iter().map(|edge| edge.repeat(40)).collect(),
+            edges: edges.iter().map(|edge| edge.repeat(40)).collect(),
+            author: "Pepito <pepito@fi.uba.ar>".to_string(),
+            author_date: date,
+            committer: "Pepito <pepito@fi.uba.ar>".to_string(),
+            committer_date: date,
+            message: "subject\n\nbody\n".to_string(),
+        }
+    }
+
     #[test]
     #[ignore]
     fn test() {
-        let commit = "378edd0df9db2008a0a7ec90e14770b22ca81b2b";
-        let log = __log(commit, &mut HashSet::new()).unwrap();
+        let opts = LogOptions {
+            revs: vec!["378edd0df9db2008a0a7ec90e14770b22ca81b2b".to_string()],
+            ..Default::default()
+        };
+
+        let log = __log(&opts).unwrap();
         println!("{log:#?}");
     }
 
@@ -102,4 +691,73 @@ mod tests {
 
         println!("{time}");
     }
+
+    #[test]
+    fn revision_ranges() {
+        let revs = ["a..b", "^c", "d", "..e"].map(String::from);
+        let (include, exclude) = parse_revs(&revs);
+
+        assert_eq!(include, vec!["b", "d", "e"]);
+        assert_eq!(exclude, vec!["a", "c", "HEAD"]);
+        assert_eq!(parse_revs(&[]).0, vec!["HEAD"]);
+    }
+
+    #[test]
+    fn format_placeholders() {
+        let commit = commit("a", &["b", "c"]);
+        let format = "%h %p %an <%ae> %at%n%s|%b|%x%%%x41";
+
+        assert_eq!(
+            expand_format(&commit, format),
+            "aaaaaaa bbbbbbb ccccccc Pepito <pepito@fi.uba.ar> 1700000000\nsubject|body\n|%x%A"
+        );
+        assert_eq!(
+            expand_format(&commit, "%ad"),
+            "Tue Nov 14 19:13:20 2023 -0300"
+        );
+    }
+
+    #[test]
+    fn edges_skip_unlisted_commits() {
+        let skipped = HashMap::from([
+            ("b".to_string(), vec!["c".to_string()]),
+            ("c".to_string(), vec!["d".to_string(), "e".to_string()]),
+        ]);
+        let listed = HashSet::from(["d".to_string(), "e".to_string(), "f".to_string()]);
+        let parents = ["b", "f", "x"].map(String::from);
+
+        assert_eq!(
+            resolve_edges(&parents, &skipped, &listed),
+            vec!["d", "e", "f"]
+        );
+    }
+
+    #[test]
+    fn graph() {
+        let commits = [
+            commit("m", &["t", "s"]),
+            commit("t", &["b"]),
+            commit("s", &["b"]),
+            commit("b", &["a"]),
+            commit("a", &[]),
+        ];
+        let entries: Vec<_> = ["merge", "third", "side", "second", "first"]
+            .iter()
+            .map(|entry| format!("{entry}\n"))
+            .collect();
+
+        assert_eq!(
+            draw_graph(&commits, &entries),
+            "*   merge\n|\\\n* | third\n| * side\n|/\n* second\n* first\n"
+        );
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("@1700000000").unwrap(), 1700000000);
+        assert_eq!(parse_date("1700000000 -0300").unwrap(), 1700000000);
+        assert!(parse_date("2023-11-14").is_ok());
+        assert!(parse_date("2023-11-14 19:13:20").is_ok());
+        assert!(parse_date("yesterday").is_err());
+    }
 }
@@ -1,7 +1,8 @@
 use super::{
     super::plumbing::{
-        checkout::refactor_root_dir, commands::*, commit::get_parent_commits,
-        work_dir::directify_tree,
+        checkout::refactor_root_dir,
+        commands::*,
+        work_dir::{directify_tree, write_blob},
     },
     commit::*,
     diff::{
@@ -9,20 +10,25 @@ use super::{
         diff_type::*,
         lcs::{diff, FileDiff},
     },
+    identity::Role,
     refs::get_ref,
+    sparse::SparseCheckout,
 };
 use crate::ui::keep_or_remove_conflict::keep_or_remove_window::{
     KeepOrRemoveResult, KeepRemoveWindow,
 };
 use crate::{io_err, ui::conflicts_window::GtkConflict};
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{HashMap, VecDeque},
     fs::{self, File},
     io::{self, Write},
+    path::Path,
 };
+use utils::common_dir;
 use utils::index_file::index::Index;
 use utils::object::object_db::get_object;
 use utils::plumbing::commit::get_commit_root;
+use utils::plumbing::commit_graph::History;
 use utils::plumbing::ls_tree::{hash_to_str, ls_tree, parse_ls_tree_entry};
 
 #[macro_export]
@@ -43,86 +49,22 @@ macro_rules! diff_2_map {
 //
 // commits: [] <- ... <- [a] <- ... <- [b] <- ... <- []
 //
-fn __is_ancestor(a: &str, b: &str, steps: &mut HashSet<String>) -> io::Result<bool> {
-    // Check if we have already visited b.
-    if steps.contains(b) {
-        return Ok(false);
-    }
-
-    // Added b to steps.
-    steps.insert(b.to_string());
-
-    // Check if a is b.
-    if a == b {
-        return Ok(true);
-    }
-
-    // Get b's data.
-    let (_, _, data) = get_object(b)?;
-    match get_parent_commits(&data) {
-        Some(parents) => {
-            for parent in parents {
-                if __is_ancestor(a, &parent, steps)? {
-                    return Ok(true);
-                }
-            }
-
-            Ok(false)
-        }
-        None => Ok(false),
-    }
-}
-
-// Returns true if a is ancestor of b.
 pub fn is_ancestor(a: &str, b: &str) -> io::Result<bool> {
-    __is_ancestor(a, b, &mut HashSet::new())
+    History::with_offset(&common_dir()).is_ancestor(a, b)
 }
 
-/// Gets the common ancestor of two commits.
-fn __get_ancestor(
-    mut a: Vec<u8>,
-    mut b: Vec<u8>,
-    steps1: &mut HashSet<String>,
-    steps2: &mut HashSet<String>,
-) -> io::Result<String> {
-    loop {
-        let parents1 = get_parent_commits(&a);
-        let parents2 = get_parent_commits(&b);
-
-        if let Some(parents) = parents1 {
-            for parent in parents {
-                if steps2.contains(&parent) {
-                    return Ok(parent);
-                }
-
-                steps1.insert(parent.clone());
-                let (_, _, data) = get_object(&parent)?;
-                a = data;
-            }
-        }
-
-        if let Some(parents) = parents2 {
-            for parent in parents {
-                if steps1.contains(&parent) {
-                    return Ok(parent);
-                }
-
-                steps2.insert(parent.clone());
-                let (_, _, data) = get_object(&parent)?;
-                b = data;
-            }
-        }
-    }
+/// Returns every best common ancestor of two commits.
+pub fn get_merge_bases(a: &str, b: &str) -> io::Result<Vec<String>> {
+    History::with_offset(&common_dir()).merge_bases(a, b)
 }
 
-/// Returns the common ancestor of two commits.
-pub fn get_ancestor(a: &[u8], b: &[u8]) -> io::Result<String> {
-    __get_ancestor(
-        a.to_vec(),
-        b.to_vec(),
-        &mut HashSet::new(),
-        &mut HashSet::new(),
-    )
+/// Returns the common ancestor of two commits. When
+/// there are many merge bases the first one is returned.
+pub fn get_ancestor(a: &str, b: &str) -> io::Result<String> {
+    get_merge_bases(a, b)?
+        .into_iter()
+        .next()
+        .ok_or(io_err!("No common ancestor"))
 }
 
 // Creates a merge commit in the database and returns it's hash.
@@ -137,9 +79,11 @@ fn merge_commit(parent1: &str, parent2: &str, msg: &str) -> io::Result<String> {
     commit.write_all(format!("parent {parent1}\n").as_bytes())?;
     commit.write_all(format!("parent {parent2}\n").as_bytes())?;
 
-    let author = get_userconfig()?.to_string();
+    let author = get_identity(Role::Author)?;
+    let committer = get_identity(Role::Committer)?;
     let time = get_time_fmt(chrono::Local::now());
-    commit.write_all(format!("author {author} {time}\ncommitter {author} {time}\n").as_bytes())?;
+    commit
+        .write_all(format!("author {author} {time}\ncommitter {committer} {time}\n").as_bytes())?;
 
     commit.write_all(format!("\n{msg}\n").as_bytes())?;
 
@@ -435,6 +379,73 @@ fn resolve_merge(
     Ok(())
 }
 
+// Error for a gitlink moved to different commits in both branches.
+fn submodule_conflict(path: &str) -> io::Error {
+    io::Error::other(format!("CONFLICT (submodule): Merge conflict in {path}"))
+}
+
+// Whether both branches moved the gitlink, or one of
+// them moved it and the other one removed it.
+fn gitlink_conflict(diff1: &Diff, diff2: &Diff) -> bool {
+    let hash = |line: &str| parse_ls_tree_entry(line).2.to_string();
+
+    use DiffType::*;
+    match (&diff1.tag, &diff2.tag) {
+        (Modified(line1), Modified(line2)) => hash(line1) != hash(line2),
+        (Added, Added) => hash(&diff1.line) != hash(&diff2.line),
+        (Modified(_), Removed) | (Removed, Modified(_)) => true,
+        _ => false,
+    }
+}
+
+// Looks for submodule conflicts before the working directory
+// is touched, gitlinks can't be left with conflict markers.
+fn check_submodule_conflicts(
+    diffs1: &HashMap<String, Diff>,
+    diffs2: &HashMap<String, Diff>,
+    dir: &str,
+) -> io::Result<()> {
+    for (name, diff1) in diffs1 {
+        let path = if dir.is_empty() {
+            name.clone()
+        } else {
+            format!("{dir}/{name}")
+        };
+
+        if let Some(diff2) = diffs2.get(name) {
+            let (_, _, hash1, _) = parse_ls_tree_entry(&diff1.line);
+            let (_, otype, hash2, _) = parse_ls_tree_entry(&diff2.line);
+
+            use DiffType::*;
+            match (&diff1.tag, &diff2.tag, otype) {
+                (_, _, "commit") if gitlink_conflict(diff1, diff2) => {
+                    return Err(submodule_conflict(&path));
+                }
+
+                (Modified(line1), Modified(line2), "tree") => {
+                    let ancestor_tree = ls_tree(hash1)?;
+                    let head_tree = ls_tree(parse_ls_tree_entry(line1).2)?;
+                    let other_tree = ls_tree(parse_ls_tree_entry(line2).2)?;
+
+                    let diffs1 = diff_2_map!(diff_tree(&ancestor_tree, &head_tree));
+                    let diffs2 = diff_2_map!(diff_tree(&ancestor_tree, &other_tree));
+                    check_submodule_conflicts(&diffs1, &diffs2, &path)?;
+                }
+
+                (Added, Added, "tree") => {
+                    let diffs1 = diff_2_map!(diff_tree("", &ls_tree(hash1)?));
+                    let diffs2 = diff_2_map!(diff_tree("", &ls_tree(hash2)?));
+                    check_submodule_conflicts(&diffs1, &diffs2, &path)?;
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Applies the given diffs to the working directory.
 ///
 /// Unchanged  ->  (Unchanged | Removed | Modified) & !Added
@@ -455,6 +466,7 @@ pub fn refactor_dir(
     dir: String,
     other_branch_commit: &str,
     index: &mut Index,
+    sparse: Option<&SparseCheckout>,
 ) -> io::Result<()> {
     for (name, diff1) in diffs1 {
         // Build the current
@@ -465,6 +477,10 @@ pub fn refactor_dir(
             dir.clone() + "/" + &name
         };
 
+        // Files left out of the sparse checkout are only updated
+        // in the index, unless they have conflicts.
+        let excluded = sparse.is_some_and(|sparse| !sparse.includes(&path));
+
         // Get the diff from the same file
         // from the other branch.
         if let Some(diff2) = diffs2.remove(&name) {
@@ -472,33 +488,35 @@ pub fn refactor_dir(
             // the object didn't change in bewteen branches.
             let (_, _, hash1, _) = parse_ls_tree_entry(&diff1.line);
             let (_, otype, hash2, _) = parse_ls_tree_entry(&diff2.line);
+            let conflict = otype == "commit" && gitlink_conflict(&diff1, &diff2);
 
             use DiffType::*;
             match (diff1.tag, diff2.tag, otype) {
                 (Unchanged, Removed, "blob") => {
-                    fs::remove_file(&path)?;
+                    if !excluded {
+                        fs::remove_file(&path)?;
+                    }
                     index.remove(&path);
                 }
 
                 // If the file was modified in the other branch but
                 // not in this one then bring the file from the other branch.
                 (Unchanged, Modified(line), "blob") => {
-                    let (_, _, hash, _) = parse_ls_tree_entry(&line);
-                    let (_, _, data) = get_object(hash)?;
-                    let mut file = File::create(&path)?;
-                    file.write_all(&data)?;
-                    index.add(path, false, true)?;
+                    let (mode, _, hash, _) = parse_ls_tree_entry(&line);
+                    if excluded {
+                        index.add_skip_worktree(path, mode, hash)?;
+                    } else {
+                        write_blob(&path, mode, hash)?;
+                        index.add(path, false, true)?;
+                    }
                 }
 
                 (Removed, Modified(line), "blob") => match solve_keep_or_remove_conflict(&path)? {
                     KeepOrRemoveResult::Delete => { /* File doesn't exist in workspace */ }
                     KeepOrRemoveResult::Keep => {
-                        let (_, _, hash, _) = parse_ls_tree_entry(&line);
-                        let (_, _, data) = get_object(hash)?;
-
                         // Write the file.
-                        let mut file = File::create(&path)?;
-                        file.write_all(&data)?;
+                        let (mode, _, hash, _) = parse_ls_tree_entry(&line);
+                        write_blob(&path, mode, hash)?;
                         index.add(path, false, true)?;
                     }
 
@@ -527,10 +545,20 @@ pub fn refactor_dir(
 
                     // If the data is the same then
                     // just write it to the file.
-                    if data1 == data2 {
+                    if data1 == data2 && excluded {
+                        let (mode, _, _, _) = parse_ls_tree_entry(&diff2.line);
+                        index.add_skip_worktree(path, mode, hash2)?;
+                        continue;
+                    } else if data1 == data2 {
                         let mut file = File::create(&path)?;
                         file.write_all(&data1)?;
                     } else {
+                        // Conflicts are checked out even if they
+                        // are left out of the sparse checkout.
+                        if let Some(dir) = Path::new(&path).parent().filter(|_| excluded) {
+                            fs::create_dir_all(dir)?;
+                        }
+
                         // Check for conflict and
                         // resolve it (if any).
 
@@ -555,10 +583,20 @@ pub fn refactor_dir(
 
                     // If the data is the same then
                     // just write it to the file.
-                    if data_head_blob == data_other_blob {
+                    if data_head_blob == data_other_blob && excluded {
+                        let (mode, _, _, _) = parse_ls_tree_entry(&line2);
+                        index.add_skip_worktree(path, mode, hash_other_blob)?;
+                        continue;
+                    } else if data_head_blob == data_other_blob {
                         let mut file = File::create(&path)?;
                         file.write_all(&data_head_blob)?;
                     } else {
+                        // Conflicts are checked out even if they
+                        // are left out of the sparse checkout.
+                        if let Some(dir) = Path::new(&path).parent().filter(|_| excluded) {
+                            fs::create_dir_all(dir)?;
+                        }
+
                         // Check for conflict and
                         // resolve it (if any).
                         let ancestor_file_hash = hash1;
@@ -576,6 +614,22 @@ pub fn refactor_dir(
                     index.add(path, false, true)?;
                 }
 
+                // Gitlinks can't be merged, they follow the branch
+                // that moved them. Ours is already in the index.
+                (Unchanged, Unchanged, "commit") | (Modified(_), Unchanged, "commit") => {}
+                (Removed, Unchanged | Removed, "commit") => {}
+
+                (Unchanged, Modified(line), "commit") => {
+                    let (_, _, hash, _) = parse_ls_tree_entry(&line);
+                    fs::create_dir_all(&path)?;
+                    index.add_gitlink(path, hash, false)?;
+                }
+
+                (Unchanged, Removed, "commit") => index.remove(&path),
+
+                (_, _, "commit") if conflict => return Err(submodule_conflict(&path)),
+                (Modified(_), Modified(_), "commit") | (Added, Added, "commit") => {}
+
                 (Unchanged, Removed, "tree") => {
                     let _ = fs::remove_dir_all(&path);
                     index.remove(&path);
@@ -633,7 +687,7 @@ pub fn refactor_dir(
                     let diffs2 = diff_2_map!(diff_tree("", &other_tree));
 
                     // Recurse.
-                    refactor_dir(diffs1, diffs2, path, other_branch_commit, index)?;
+                    refactor_dir(diffs1, diffs2, path, other_branch_commit, index, sparse)?;
                 }
 
                 // If both were modified then recurse.
@@ -652,7 +706,7 @@ pub fn refactor_dir(
                     let diffs2 = diff_2_map!(diff_tree(&ancestor_tree, &other_tree));
 
                     // Recurse.
-                    refactor_dir(diffs1, diffs2, path, other_branch_commit, index)?;
+                    refactor_dir(diffs1, diffs2, path, other_branch_commit, index, sparse)?;
                 }
 
                 _ => {}
@@ -662,7 +716,7 @@ pub fn refactor_dir(
 
     // Add the files that were added in the other branch.
     for (_, diff2) in diffs2 {
-        let (_, otype, hash, name) = parse_ls_tree_entry(&diff2.line);
+        let (mode, otype, hash, name) = parse_ls_tree_entry(&diff2.line);
         let path = if dir.is_empty() {
             name.clone()
         } else {
@@ -670,11 +724,13 @@ pub fn refactor_dir(
         };
 
         match otype {
+            "blob" if sparse.is_some_and(|sparse| !sparse.includes(&path)) => {
+                index.add_skip_worktree(path, mode, hash)?;
+            }
+
             "blob" => {
-                let (_, _, data) = get_object(hash)?;
-                let mut file = File::create(&path)?;
-                file.write_all(&data)?;
-                index.add(name, false, true)?;
+                write_blob(&path, mode, hash)?;
+                index.add(path, false, true)?;
             }
 
             "tree" => {
@@ -685,6 +741,11 @@ pub fn refactor_dir(
                 index2.merge(index);
             }
 
+            "commit" => {
+                fs::create_dir_all(&path)?;
+                index.add_gitlink(path, hash, false)?;
+            }
+
             _ => return Err(io_err!("invalid object type")),
         }
     }
@@ -730,7 +791,7 @@ pub fn __merge(branch: &str, subfolder: &str) -> io::Result<()> {
     } else {
         println!("3-Way-Merge");
         // Get the common ancestor tree.
-        let ancestor = get_ancestor(&head_data, &refs_data)?;
+        let ancestor = get_ancestor(&head, &refs)?;
         let (_, _, ancestor_data) = get_object(&ancestor)?;
         let ancestor_tree_root = get_commit_root(&ancestor_data)?;
 
@@ -741,9 +802,19 @@ pub fn __merge(branch: &str, subfolder: &str) -> io::Result<()> {
         let diffs1 = diff_2_map!(diff_tree(&ancestor_tree, &head_tree));
         let diffs2 = diff_2_map!(diff_tree(&ancestor_tree, &refs_tree));
 
+        check_submodule_conflicts(&diffs1, &diffs2, "")?;
+
         // Apply the changes to the working directory and update index.
         let mut index = read_index().unwrap_or_default();
-        refactor_dir(diffs1, diffs2, "".to_string(), &refs, &mut index)?;
+        let sparse = SparseCheckout::read()?;
+        refactor_dir(
+            diffs1,
+            diffs2,
+            "".to_string(),
+            &refs,
+            &mut index,
+            sparse.as_ref(),
+        )?;
         write_index(index)?;
 
         // Create merge commit.
@@ -761,6 +832,84 @@ pub fn __merge(branch: &str, subfolder: &str) -> io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use utils::plumbing::ls_tree::hash_to_str;
+
+    const A: &str = "1111111111111111111111111111111111111111";
+    const B: &str = "2222222222222222222222222222222222222222";
+    const C: &str = "3333333333333333333333333333333333333333";
+
+    fn gitlinks(hash: Option<&str>) -> String {
+        hash.map(|hash| format!("160000 commit {hash}\tsub\n"))
+            .unwrap_or_default()
+    }
+
+    // Merges a tree with a single gitlink, starting from
+    // an index with the gitlink at HEAD.
+    fn merge_gitlink(
+        ancestor: Option<&str>,
+        head: Option<&str>,
+        other: Option<&str>,
+    ) -> io::Result<Vec<(String, String)>> {
+        let dir = format!(
+            "{}/merge-gitlink-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let diffs1 = diff_2_map!(diff_tree(&gitlinks(ancestor), &gitlinks(head)));
+        let diffs2 = diff_2_map!(diff_tree(&gitlinks(ancestor), &gitlinks(other)));
+
+        let mut index = Index::new();
+        if let Some(hash) = head {
+            index.add_gitlink(format!("{dir}/sub"), hash, false)?;
+        }
+
+        check_submodule_conflicts(&diffs1, &diffs2, &dir)?;
+        refactor_dir(diffs1, diffs2, dir.clone(), "other", &mut index, None)?;
+        let _ = fs::remove_dir_all(&dir);
+
+        Ok(index
+            .get_entries()
+            .iter()
+            .map(|entry| {
+                let path = entry.get_path().trim_start_matches(&dir).to_string();
+                (path, hash_to_str(entry.get_hash()))
+            })
+            .collect())
+    }
+
+    #[test]
+    fn gitlinks_follow_the_moved_side() {
+        let sub = |hash: &str| vec![("/sub".to_string(), hash.to_string())];
+
+        assert_eq!(merge_gitlink(Some(A), Some(A), Some(A)).unwrap(), sub(A));
+        assert_eq!(merge_gitlink(Some(A), Some(B), Some(A)).unwrap(), sub(B));
+        assert_eq!(merge_gitlink(Some(A), Some(A), Some(B)).unwrap(), sub(B));
+        assert_eq!(merge_gitlink(Some(A), Some(B), Some(B)).unwrap(), sub(B));
+        assert_eq!(merge_gitlink(None, Some(B), Some(B)).unwrap(), sub(B));
+        assert_eq!(merge_gitlink(None, Some(B), None).unwrap(), sub(B));
+        assert_eq!(merge_gitlink(None, None, Some(B)).unwrap(), sub(B));
+
+        assert_eq!(merge_gitlink(Some(A), None, Some(A)).unwrap(), vec![]);
+        assert_eq!(merge_gitlink(Some(A), Some(A), None).unwrap(), vec![]);
+        assert_eq!(merge_gitlink(Some(A), None, None).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn divergent_gitlinks_conflict() {
+        let conflict = |ancestor, head, other| {
+            let diffs1 = diff_2_map!(diff_tree(&gitlinks(ancestor), &gitlinks(head)));
+            let diffs2 = diff_2_map!(diff_tree(&gitlinks(ancestor), &gitlinks(other)));
+            check_submodule_conflicts(&diffs1, &diffs2, "")
+                .unwrap_err()
+                .to_string()
+        };
+
+        let msg = "CONFLICT (submodule): Merge conflict in sub";
+        assert_eq!(conflict(Some(A), Some(B), Some(C)), msg);
+        assert_eq!(conflict(None, Some(B), Some(C)), msg);
+        assert_eq!(conflict(Some(A), Some(B), None), msg);
+        assert_eq!(conflict(Some(A), None, Some(C)), msg);
+    }
 
     fn merge_and_show_patch(o: &str, a: &str, b: &str) {
         let diff1 = diff(o, a);
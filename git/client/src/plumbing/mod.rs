@@ -1,19 +1,36 @@
+pub mod am;
+pub mod apply;
+pub mod bisect;
+pub mod blame;
+pub mod bundle;
 pub mod checkout;
 pub mod clone;
 pub mod commands;
 pub mod commit;
+pub mod config;
+pub mod describe;
 pub mod diff;
 pub mod fetch;
+pub mod format_patch;
+pub mod grep;
 pub mod heads;
+pub mod identity;
 pub mod ignore;
+pub mod local;
 pub mod log;
 pub mod merge;
+pub mod notes;
+pub mod patch;
 pub mod push;
 pub mod read_tree;
 pub mod rebase;
 pub mod refs;
+pub mod refspec;
 pub mod remote;
+pub mod signing;
+pub mod sparse;
+pub mod submodule;
 pub mod tag;
-pub mod userconfig;
 pub mod work_dir;
+pub mod worktree;
 pub mod write_tree;
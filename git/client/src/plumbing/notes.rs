@@ -0,0 +1,256 @@
+use super::commands::{get_identity, hash_object};
+use super::commit::get_time_fmt;
+use super::fetch::add_to_refs;
+use super::identity::Role;
+use super::refs::rev_parse;
+use crate::commands::NotesCommand;
+use crate::io_err;
+use chrono::Local;
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind},
+};
+use utils::object::object_db::get_object;
+use utils::plumbing::ls_tree::{ls_tree, parse_ls_tree_entry};
+use utils::{common_dir, read_ref_with_offset};
+
+pub const NOTES_REF: &str = "refs/notes/commits";
+
+// Notes trees with more notes than this are split in subtrees
+// named by the first two hex digits of the objects, and so on.
+const FANOUT_THRESHOLD: usize = 256;
+
+/// Returns the hash of the latest notes commit, None if there are no notes.
+pub fn get_notes_commit() -> Option<String> {
+    read_ref_with_offset(NOTES_REF, &common_dir()).ok()
+}
+
+// Reads the notes of the tree, the path of every note is the hash of
+// it's object, split in as many subtrees as the fan-out needs.
+fn read_notes_tree(
+    tree: &str,
+    prefix: &str,
+    notes: &mut BTreeMap<String, String>,
+) -> io::Result<()> {
+    for line in ls_tree(tree)?.lines() {
+        let (_, otype, hash, name) = parse_ls_tree_entry(line);
+        let object = format!("{prefix}{name}");
+
+        match otype {
+            "tree" if object.len() < 40 => read_notes_tree(hash, &object, notes)?,
+            "blob" if object.len() == 40 => {
+                notes.insert(object, hash.to_string());
+            }
+            // Other files can live in a notes tree, they aren't notes.
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the notes as a map from the object to the hash of it's note.
+pub fn read_notes() -> io::Result<BTreeMap<String, String>> {
+    let mut notes = BTreeMap::new();
+    let Some(commit) = get_notes_commit() else {
+        return Ok(notes);
+    };
+
+    let (_, _, data) = get_object(&commit)?;
+    let tree = String::from_utf8_lossy(&data)
+        .lines()
+        .find_map(|line| line.strip_prefix("tree ").map(str::to_string))
+        .ok_or(io_err!("Notes commit without tree"))?;
+
+    read_notes_tree(&tree, "", &mut notes)?;
+    Ok(notes)
+}
+
+/// Returns the content of the note of the object, if it has one.
+pub fn get_note(notes: &BTreeMap<String, String>, object: &str) -> io::Result<Option<String>> {
+    match notes.get(object) {
+        Some(hash) => {
+            let (_, _, data) = get_object(hash)?;
+            Ok(Some(String::from_utf8_lossy(&data).to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+// Returns how many levels of subtrees hold the given number of notes.
+fn fanout(mut len: usize) -> usize {
+    let mut levels = 0;
+    while len > FANOUT_THRESHOLD && levels < 19 {
+        len /= FANOUT_THRESHOLD;
+        levels += 1;
+    }
+
+    levels
+}
+
+// Writes the tree of the notes, relative to the prefix already taken
+// from their objects. Returns it's hash.
+fn write_notes_tree(
+    notes: &[(&str, &str)],
+    prefix_len: usize,
+    levels: usize,
+) -> io::Result<String> {
+    let mut tree = vec![];
+
+    if levels == 0 {
+        for (object, note) in notes {
+            tree.extend(format!("100644 {}\0", &object[prefix_len..]).as_bytes());
+            tree.extend(hex_to_bytes(note)?);
+        }
+    } else {
+        let mut i = 0;
+        while i < notes.len() {
+            let dir = &notes[i].0[prefix_len..prefix_len + 2];
+            let len = notes[i..]
+                .iter()
+                .take_while(|(object, _)| &object[prefix_len..prefix_len + 2] == dir)
+                .count();
+
+            let subtree = write_notes_tree(&notes[i..i + len], prefix_len + 2, levels - 1)?;
+            tree.extend(format!("40000 {dir}\0").as_bytes());
+            tree.extend(hex_to_bytes(&subtree)?);
+            i += len;
+        }
+    }
+
+    hash_object(&tree, "tree", true)
+}
+
+fn hex_to_bytes(hash: &str) -> io::Result<Vec<u8>> {
+    (0..hash.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hash[i..i + 2], 16)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+        })
+        .collect()
+}
+
+// Writes a notes commit with the given notes on top of the last one.
+fn write_notes(notes: &BTreeMap<String, String>, msg: &str) -> io::Result<()> {
+    let entries: Vec<_> = notes
+        .iter()
+        .map(|(o, n)| (o.as_str(), n.as_str()))
+        .collect();
+    let tree = write_notes_tree(&entries, 0, fanout(entries.len()))?;
+
+    let mut commit = format!("tree {tree}\n");
+    if let Some(parent) = get_notes_commit() {
+        commit.push_str(&format!("parent {parent}\n"));
+    }
+
+    let author = get_identity(Role::Author)?;
+    let committer = get_identity(Role::Committer)?;
+    let time = get_time_fmt(Local::now());
+    commit.push_str(&format!(
+        "author {author} {time}\ncommitter {committer} {time}\n\n{msg}\n"
+    ));
+
+    let hash = hash_object(commit.as_bytes(), "commit", true)?;
+    add_to_refs(NOTES_REF, &hash)
+}
+
+// Saves the message as the note of the object.
+fn set_note(
+    notes: &mut BTreeMap<String, String>,
+    object: &str,
+    msg: &str,
+    cmd: &str,
+) -> io::Result<String> {
+    let note = hash_object(format!("{}\n", msg.trim_end()).as_bytes(), "blob", true)?;
+    notes.insert(object.to_string(), note);
+    write_notes(notes, &format!("Notes added by 'git notes {cmd}'"))?;
+    Ok(String::new())
+}
+
+/// Underlying implementation of `git notes`, the notes are kept
+/// in `refs/notes/commits`.
+pub fn __notes(cmd: NotesCommand) -> io::Result<String> {
+    let mut notes = read_notes()?;
+    let object = |rev: Option<String>| rev_parse(rev.as_deref().unwrap_or("HEAD"));
+
+    match cmd {
+        NotesCommand::Add {
+            object: rev,
+            msg,
+            force,
+        } => {
+            let object = object(rev)?;
+            if notes.contains_key(&object) && !force {
+                let msg = format!(
+                    "Cannot add notes. Found existing notes for object {object}. \
+                     Use '-f' to overwrite existing notes"
+                );
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            }
+
+            set_note(&mut notes, &object, &msg, "add")
+        }
+
+        NotesCommand::Append { object: rev, msg } => {
+            let object = object(rev)?;
+            let msg = match get_note(&notes, &object)? {
+                Some(note) => format!("{note}\n{msg}"),
+                None => msg,
+            };
+
+            set_note(&mut notes, &object, &msg, "append")
+        }
+
+        NotesCommand::Show(rev) => {
+            let object = object(rev)?;
+            get_note(&notes, &object)?.ok_or(io::Error::new(
+                ErrorKind::NotFound,
+                format!("no note found for object {object}."),
+            ))
+        }
+
+        NotesCommand::List(None) => Ok(notes
+            .iter()
+            .map(|(object, note)| format!("{note} {object}\n"))
+            .collect()),
+
+        NotesCommand::List(Some(rev)) => {
+            let object = object(Some(rev))?;
+            match notes.get(&object) {
+                Some(note) => Ok(format!("{note}\n")),
+                None => Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("no note found for object {object}."),
+                )),
+            }
+        }
+
+        NotesCommand::Remove(rev) => {
+            let object = object(rev)?;
+            if notes.remove(&object).is_none() {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("Object {object} has no note"),
+                ));
+            }
+
+            write_notes(&notes, "Notes removed by 'git notes remove'")?;
+            Ok(format!("Removing note for object {object}\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fanout_levels() {
+        assert_eq!(fanout(0), 0);
+        assert_eq!(fanout(256), 0);
+        assert_eq!(fanout(257), 1);
+        assert_eq!(fanout(256 * 256), 1);
+        assert_eq!(fanout(256 * 256 + 256), 2);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::io_err;
+use std::io;
+
+const DEV_NULL: &str = "/dev/null";
+
+/// A line of a hunk. Lines keep their newline, the last
+/// line of a file may not have one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HunkLine {
+    Context(String),
+    Added(String),
+    Removed(String),
+}
+
+/// A change to a range of lines of a file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+impl Hunk {
+    /// Returns the lines the hunk expects in the file.
+    pub fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(line) | HunkLine::Removed(line) => Some(line.as_str()),
+                HunkLine::Added(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the lines the hunk leaves in the file.
+    pub fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(line) | HunkLine::Added(line) => Some(line.as_str()),
+                HunkLine::Removed(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// The changes to a single file. New files have no old path
+/// and deleted ones no new path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    /// Returns the path of the file, the new one if it was renamed.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Turns the patch into the one undoing it.
+    pub fn reverse(&mut self) {
+        std::mem::swap(&mut self.old_path, &mut self.new_path);
+        std::mem::swap(&mut self.old_mode, &mut self.new_mode);
+
+        for hunk in &mut self.hunks {
+            std::mem::swap(&mut hunk.old_start, &mut hunk.new_start);
+            std::mem::swap(&mut hunk.old_len, &mut hunk.new_len);
+            for line in &mut hunk.lines {
+                *line = match std::mem::replace(line, HunkLine::Context(String::new())) {
+                    HunkLine::Added(line) => HunkLine::Removed(line),
+                    HunkLine::Removed(line) => HunkLine::Added(line),
+                    context => context,
+                };
+            }
+        }
+    }
+}
+
+// Strips the `a/` or `b/` prefix and the timestamp of a path in a
+// `---` or `+++` line. Returns None for /dev/null.
+fn parse_path(path: &str) -> Option<String> {
+    let path = path.split('\t').next().unwrap_or_default().trim_end();
+    if path == DEV_NULL {
+        return None;
+    }
+
+    let path = path
+        .strip_prefix("a/")
+        .or(path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+// Parses a hunk range like `12,3`, where the length defaults to 1.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+// Parses the hunk whose header is the first line. Returns it and the
+// number of lines it takes.
+fn parse_hunk(lines: &[&str]) -> io::Result<(Hunk, usize)> {
+    let err = || io_err!("Invalid hunk header");
+    let mut header = lines[0].split_whitespace().skip(1);
+    let old = header
+        .next()
+        .and_then(|r| r.strip_prefix('-'))
+        .ok_or_else(err)?;
+    let new = header
+        .next()
+        .and_then(|r| r.strip_prefix('+'))
+        .ok_or_else(err)?;
+    let (old_start, old_len) = parse_range(old).ok_or_else(err)?;
+    let (new_start, new_len) = parse_range(new).ok_or_else(err)?;
+
+    let mut hunk = Hunk {
+        old_start,
+        old_len,
+        new_start,
+        new_len,
+        lines: vec![],
+    };
+
+    let (mut old, mut new) = (0, 0);
+    let mut i = 1;
+    while i < lines.len() && (old < old_len || new < new_len || lines[i].starts_with('\\')) {
+        let line = lines[i];
+        let (prefix, text) = match line.chars().next() {
+            // Editors may strip the space of empty context lines.
+            Some('\n') => (' ', line),
+            Some(c) => (c, &line[1..]),
+            None => break,
+        };
+
+        match prefix {
+            ' ' => {
+                hunk.lines.push(HunkLine::Context(text.to_string()));
+                old += 1;
+                new += 1;
+            }
+            '-' => {
+                hunk.lines.push(HunkLine::Removed(text.to_string()));
+                old += 1;
+            }
+            '+' => {
+                hunk.lines.push(HunkLine::Added(text.to_string()));
+                new += 1;
+            }
+            // The line before has no newline at the end of the file.
+            '\\' => match hunk.lines.last_mut() {
+                Some(HunkLine::Context(line) | HunkLine::Added(line) | HunkLine::Removed(line)) => {
+                    line.pop();
+                }
+                None => return Err(err()),
+            },
+            _ => break,
+        }
+        i += 1;
+    }
+
+    if old != old_len || new != new_len {
+        return Err(io_err!("Corrupt patch, hunk is truncated"));
+    }
+
+    Ok((hunk, i))
+}
+
+// Parses the `---` and `+++` lines and the hunks that follow them,
+// returning the number of lines they take.
+fn parse_hunks(lines: &[&str], patch: &mut FilePatch) -> io::Result<usize> {
+    let mut i = 0;
+    if let (Some(old), Some(new)) = (
+        lines.first().and_then(|line| line.strip_prefix("--- ")),
+        lines.get(1).and_then(|line| line.strip_prefix("+++ ")),
+    ) {
+        patch.old_path = parse_path(old);
+        patch.new_path = parse_path(new);
+        i = 2;
+    }
+
+    while lines.get(i).is_some_and(|line| line.starts_with("@@ ")) {
+        let (hunk, len) = parse_hunk(&lines[i..])?;
+        patch.hunks.push(hunk);
+        i += len;
+    }
+
+    Ok(i)
+}
+
+// Parses a `diff --git` header and the extended headers after it.
+fn parse_git_header(lines: &[&str]) -> (FilePatch, usize) {
+    let paths = lines[0].trim_end().trim_start_matches("diff --git ");
+    let (old, new) = paths.rsplit_once(" b/").unwrap_or((paths, paths));
+    let old = old.strip_prefix("a/").unwrap_or(old).to_string();
+
+    let mut patch = FilePatch {
+        old_path: Some(old),
+        new_path: Some(new.to_string()),
+        ..Default::default()
+    };
+
+    let mut i = 1;
+    while let Some(line) = lines.get(i).map(|line| line.trim_end()) {
+        if let Some(mode) = line.strip_prefix("new file mode ") {
+            patch.old_path = None;
+            patch.new_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+            patch.new_path = None;
+            patch.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            patch.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("new mode ") {
+            patch.new_mode = Some(mode.to_string());
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            patch.old_path = Some(path.to_string());
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            patch.new_path = Some(path.to_string());
+        } else if let Some(index) = line.strip_prefix("index ") {
+            // The mode is only there if it didn't change.
+            if let Some((_, mode)) = index.split_once(' ') {
+                patch.old_mode = Some(mode.to_string());
+                patch.new_mode = Some(mode.to_string());
+            }
+        } else if line.starts_with("--- ") || line.starts_with("@@ ") || line.starts_with("diff ") {
+            break;
+        }
+        i += 1;
+    }
+
+    (patch, i)
+}
+
+/// Parses a unified diff, as written by `git diff` or `diff -u`.
+/// Lines outside of the file patches are ignored.
+pub fn parse_patch(text: &str) -> io::Result<Vec<FilePatch>> {
+    let lines: Vec<_> = text.split_inclusive('\n').collect();
+    let mut patches = vec![];
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.starts_with("diff --git ") {
+            let (mut patch, len) = parse_git_header(&lines[i..]);
+            i += len;
+
+            // The paths of the header are kept for mode only changes.
+            let (old, new) = (patch.old_path.clone(), patch.new_path.clone());
+            i += parse_hunks(&lines[i..], &mut patch)?;
+            if patch.hunks.is_empty() {
+                (patch.old_path, patch.new_path) = (old, new);
+            }
+
+            patches.push(patch);
+        } else if line.starts_with("--- ")
+            && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "))
+        {
+            let mut patch = FilePatch::default();
+            i += parse_hunks(&lines[i..], &mut patch)?;
+
+            // Without git headers both names are the same file,
+            // like `file.orig` and `file`.
+            if patch.old_path.is_some() && patch.new_path.is_some() {
+                patch.old_path = patch.new_path.clone();
+            }
+            patches.push(patch);
+        } else {
+            i += 1;
+        }
+    }
+
+    Ok(patches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "\
+diff --git a/src/main.rs b/src/main.rs
+index 3b18e51..a5c1966 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,3 @@
+ fn main() {
+-    println!(\"hello\");
++    println!(\"hello world\");
+ }
+\\ No newline at end of file
+diff --git a/run.sh b/run.sh
+new file mode 100755
+index 0000000..e69de29
+--- /dev/null
++++ b/run.sh
+@@ -0,0 +1 @@
++cargo run
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+index 8baef1b..0000000
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-abc
+";
+
+    #[test]
+    fn parse() {
+        let patches = parse_patch(PATCH).unwrap();
+        assert_eq!(patches.len(), 3);
+
+        let main = &patches[0];
+        assert_eq!(main.path(), "src/main.rs");
+        assert_eq!(main.new_mode.as_deref(), Some("100644"));
+        assert_eq!(main.hunks[0].old_lines().last(), Some(&"}"));
+
+        assert_eq!(patches[1].old_path, None);
+        assert_eq!(patches[1].new_mode.as_deref(), Some("100755"));
+        assert_eq!(patches[2].new_path, None);
+        assert_eq!(patches[2].old_path.as_deref(), Some("old.txt"));
+    }
+}
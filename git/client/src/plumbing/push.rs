@@ -1,13 +1,20 @@
 use crate::{
     config_file::{config::Config, config_entry::ConfigEntry},
     io_err,
-    protocol::{connect_to_server, parse_url},
+    protocol::connect,
+};
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Write},
+    path::Path,
 };
-use std::io::{self, Write};
 use utils::package::pack::Pack;
 use utils::*;
 
+use super::local::{copy_objects, local_git_dir, local_head, local_refs, update_local_ref};
 use super::merge::is_ancestor;
+use super::notes::{get_notes_commit, NOTES_REF};
+use super::refspec::{is_excluded, parse_refspecs, Refspec};
 
 const ZERO_ID: &str = "0000000000000000000000000000000000000000";
 
@@ -42,6 +49,9 @@ fn get_references_to_send(
     // get a hashmap of current branches and its respectives commits (k: branch_path, v: obj_id)
     let mut current_refs = get_local_refs()?;
     current_refs.extend(get_tags()?);
+    if let Some(notes) = get_notes_commit() {
+        current_refs.insert(NOTES_REF.to_string(), notes);
+    }
 
     for (remote_obj_id, remote_branch_path) in remote_references {
         // If the hashes of the local branch and the remote branch are not equal, we have to update/remove the remote branch
@@ -120,16 +130,139 @@ pub fn parse_references(refs: Vec<u8>) -> io::Result<Vec<(String, String)>> {
     Ok(want_refs)
 }
 
-pub fn __push(remote: &str) -> io::Result<()> {
+// Expands a short destination to the remote reference with that
+// name, or else to one in the same directory as the source.
+fn remote_ref_name(dst: &str, src: Option<&str>, remote: &HashMap<String, String>) -> String {
+    if dst.starts_with("refs/") {
+        return dst.to_string();
+    }
+
+    let names = [format!("refs/heads/{dst}"), format!("refs/tags/{dst}")];
+    if let Some(name) = names.iter().find(|name| remote.contains_key(*name)) {
+        return name.clone();
+    }
+
+    match src {
+        Some(src) if src.starts_with("refs/tags/") => format!("refs/tags/{dst}"),
+        _ => format!("refs/heads/{dst}"),
+    }
+}
+
+// Returns the updates of the remote references the refspecs make,
+// and the ones rejected because they aren't fast-forwards.
+fn refspec_updates(
+    remote_references: &[(String, String)],
+    specs: &[Refspec],
+) -> io::Result<(Vec<SendEntry>, Vec<String>)> {
+    let mut local = get_local_refs()?;
+    local.extend(get_tags()?);
+    if let Some(notes) = get_notes_commit() {
+        local.insert(NOTES_REF.to_string(), notes);
+    }
+
+    let mut local_names: Vec<_> = local.keys().cloned().collect();
+    local_names.sort();
+
+    let remote: HashMap<_, _> = remote_references
+        .iter()
+        .map(|(hash, name)| (name.replace('\0', ""), hash.clone()))
+        .collect();
+
+    // Pairs every source with it's destination, as (new, dst, force).
+    let mut pushes = vec![];
+    for spec in specs.iter().filter(|spec| !spec.negative) {
+        if spec.src.is_empty() {
+            let dst = remote_ref_name(&spec.dst, None, &remote);
+            pushes.push((ZERO_ID.to_string(), dst, spec.force));
+            continue;
+        }
+
+        let sources: Vec<_> = local_names
+            .iter()
+            .filter(|name| spec.matches(name) && !is_excluded(specs, name))
+            .collect();
+        if sources.is_empty() && !spec.is_glob() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("error: src refspec {} does not match any", spec.src),
+            ));
+        }
+
+        // Without a destination the source's name is used.
+        for name in sources {
+            let dst = spec.map(name).unwrap_or(name.clone());
+            let dst = remote_ref_name(&dst, Some(name), &remote);
+            pushes.push((local[name].clone(), dst, spec.force));
+        }
+    }
+
+    let mut updates = vec![];
+    let mut rejected = vec![];
+    for (new, dst, force) in pushes {
+        let old = remote.get(&dst).cloned().unwrap_or(ZERO_ID.to_string());
+        if old == ZERO_ID && new == ZERO_ID {
+            rejected.push(format!(
+                "error: unable to delete '{dst}': remote ref does not exist"
+            ));
+        } else if old != ZERO_ID
+            && new != ZERO_ID
+            && !force
+            && !is_ancestor(&old, &new).unwrap_or(false)
+        {
+            rejected.push(format!(" ! [rejected] {dst} (non-fast-forward)"));
+        } else if old != new {
+            updates.push(SendEntry::new(old, new, dst));
+        }
+    }
+
+    Ok((updates, rejected))
+}
+
+// Returns the updates to send and the rejected ones. Without refspecs
+// every local branch, tag and notes the remote is behind on is sent.
+fn references_to_send(
+    remote_references: &mut Vec<(String, String)>,
+    specs: &[Refspec],
+) -> io::Result<(Vec<SendEntry>, Vec<String>)> {
+    match specs.is_empty() {
+        true => Ok((get_references_to_send(remote_references)?, vec![])),
+        false => refspec_updates(remote_references, specs),
+    }
+}
+
+// Fails with the rejected updates, if any.
+fn check_rejected(rejected: Vec<String>) -> io::Result<()> {
+    match rejected.is_empty() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "{}\nerror: failed to push some refs",
+            rejected.join("\n")
+        ))),
+    }
+}
+
+/// Underlying implementation of `git push`. Updates the references
+/// of the remote the refspecs say, the ones on the command line or
+/// else the remote's `push` ones. Without any, the local branches,
+/// tags and notes the remote is behind on are pushed.
+pub fn __push(remote: &str, refspecs: &[String]) -> io::Result<()> {
     // Get the remote's url.
     let config = Config::read()?;
-    let url = match config.get(remote) {
-        Some(ConfigEntry::Remote { url, .. }) => url,
+    let (url, push) = match config.remote(remote) {
+        Some(ConfigEntry::Remote { url, push, .. }) => (url, push),
         _ => return Err(io_err!("Remote not found")),
     };
 
-    let (link, repo) = parse_url(url)?;
-    let mut transmiter = connect_to_server("git-receive-pack", &link, &repo)?;
+    let specs = match refspecs.is_empty() {
+        true => parse_refspecs(push)?,
+        false => parse_refspecs(refspecs)?,
+    };
+
+    if let Some(git_dir) = local_git_dir(url) {
+        return push_local(&git_dir, &specs);
+    }
+
+    let mut transmiter = connect("git-receive-pack", url, 0)?;
 
     // Read the references from server and parse them.
     let references = get_response(&mut transmiter);
@@ -138,7 +271,7 @@ pub fn __push(remote: &str) -> io::Result<()> {
     println!("refs: {:?}", refs);
 
     // Get local refs and compare.
-    let outdated_refs = get_references_to_send(&mut refs)?;
+    let (outdated_refs, rejected) = references_to_send(&mut refs, &specs)?;
 
     // Prepare the references to send.
     let send_refs = outdated_refs
@@ -148,12 +281,50 @@ pub fn __push(remote: &str) -> io::Result<()> {
 
     println!("send_refs: {send_refs}");
 
-    let hashes = outdated_refs.into_iter().map(|p| p.new_id).collect();
+    // Deleted references have no objects.
+    let hashes = outdated_refs
+        .into_iter()
+        .map(|p| p.new_id)
+        .filter(|hash| hash != ZERO_ID)
+        .collect();
     let pack = Pack::from(hashes)?.as_bytes()?;
 
     // Send the references and the pack.
     transmiter.write_all(send_refs.as_bytes())?;
     transmiter.write_all(&pack)?;
 
-    Ok(())
+    check_rejected(rejected)
+}
+
+// Pushes to a repository in this machine, copying the objects it's
+// missing and updating it's references in place.
+fn push_local(git_dir: &str, specs: &[Refspec]) -> io::Result<()> {
+    let mut refs = local_refs(git_dir)?;
+    let (outdated_refs, rejected) = references_to_send(&mut refs, specs)?;
+
+    // The branch checked out in a working directory would no
+    // longer match its files.
+    let checked_out = match Path::new(git_dir).ends_with(".git") {
+        true => local_head(git_dir).map(|(_, name)| name),
+        false => None,
+    };
+    if let Some(entry) = outdated_refs
+        .iter()
+        .find(|entry| Some(&entry.reference_path) == checked_out.as_ref())
+    {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "refusing to update checked out branch: {}",
+                entry.reference_path
+            ),
+        ));
+    }
+
+    copy_objects(&common_dir(), git_dir)?;
+    for entry in outdated_refs {
+        update_local_ref(git_dir, &entry.reference_path, &entry.new_id)?;
+    }
+
+    check_rejected(rejected)
 }
@@ -1,11 +1,18 @@
 use super::super::commands::ls_tree;
+use super::sparse::SparseCheckout;
 use crate::io_err;
 use std::io;
 use utils::index_file::index_entry::IndexEntry;
 use utils::plumbing::ls_tree::parse_ls_tree_entry;
 
-// Underlying implementation of read_tree.
-pub fn __read_tree(hash: &str, entries: &mut Vec<IndexEntry>, path: String) -> io::Result<()> {
+// Underlying implementation of read_tree. Files left out of the
+// sparse checkout are marked as skip-worktree.
+pub fn __read_tree(
+    hash: &str,
+    entries: &mut Vec<IndexEntry>,
+    path: String,
+    sparse: Option<&SparseCheckout>,
+) -> io::Result<()> {
     // Get a String representation of tree.
     let tree = ls_tree(hash)?;
 
@@ -13,15 +20,21 @@ pub fn __read_tree(hash: &str, entries: &mut Vec<IndexEntry>, path: String) -> i
     for line in tree.lines() {
         // Get data about the object.
 
-        let (_, otype, hash, name) = parse_ls_tree_entry(line);
+        let (mode, otype, hash, name) = parse_ls_tree_entry(line);
         let path = match path.as_str() {
             "" => name.to_string(),
             _ => path.clone() + "/" + &name,
         };
         //println!("path: {}", path);
         match otype {
+            "blob" if sparse.is_some_and(|sparse| !sparse.includes(&path)) => {
+                let mut entry = IndexEntry::new_from_repo_with_hash(&path, mode, hash)?;
+                entry.set_skip_worktree(true);
+                entries.push(entry);
+            }
             "blob" => entries.push(IndexEntry::new(&path, false, false)?),
-            "tree" => __read_tree(hash, entries, path)?,
+            "tree" => __read_tree(hash, entries, path, sparse)?,
+            "commit" => entries.push(IndexEntry::new_gitlink(&path, hash, false)?),
             _ => return Err(io_err!("invalid object type")),
         }
     }
@@ -1,4 +1,9 @@
-use std::{fs, io};
+use super::commands::get_head;
+use super::commit::get_parent_commits;
+use crate::io_err;
+use std::io;
+use utils::object::object_db::{expand_hash_with_offset, get_object};
+use utils::{common_dir, get_local_refs, parse_tag, read_ref_with_offset};
 
 // Validates that the given branch exists in refs/heads.
 // Returns the hash of it's latest commit.
@@ -15,10 +20,9 @@ pub fn get_remote_ref(remote: &str) -> io::Result<String> {
 /// Returns the hash of the commit that .git/refs/<subfolder>/<name> points to.
 pub fn get_ref(branch: &str, subfolder: &str) -> io::Result<String> {
     // por ahora saqué: el map
-    let path = format!(".git/refs/{}/{}", subfolder, branch);
-    let s = fs::read_to_string(path);
-    match s {
-        Ok(s) => Ok(s.replace('\n', "")),
+    let name = format!("refs/{}/{}", subfolder, branch);
+    match read_ref_with_offset(&name, &common_dir()) {
+        Ok(s) => Ok(s),
         Err(err) => {
             println!("{}", err);
             Err(err)
@@ -28,14 +32,75 @@ pub fn get_ref(branch: &str, subfolder: &str) -> io::Result<String> {
 
 /// Returns a list with all the local branches
 pub fn get_local_branches() -> io::Result<Vec<String>> {
-    let path = ".git/refs/heads";
-    let mut branches = Vec::new();
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if let Some(branch) = path.file_name() {
-            branches.push(branch.to_string_lossy().to_string());
+    let mut branches: Vec<_> = get_local_refs()?
+        .into_keys()
+        .filter_map(|name| name.strip_prefix("refs/heads/").map(str::to_string))
+        .collect();
+
+    branches.sort();
+    Ok(branches)
+}
+
+// Returns the nth parent of a commit.
+fn nth_parent(hash: &str, n: usize) -> io::Result<String> {
+    let (_, _, data) = get_object(hash)?;
+    get_parent_commits(&data)
+        .unwrap_or_default()
+        .into_iter()
+        .nth(n - 1)
+        .ok_or(io_err!("Revision has no such parent"))
+}
+
+/// Resolves a revision to the hash of the commit it points to. Revisions
+/// are HEAD, local or remote branches, tags or abbreviated hashes,
+/// followed by any number of `~<n>` for the nth first parent ancestor
+/// and `^<n>` for the nth parent.
+pub fn rev_parse(rev: &str) -> io::Result<String> {
+    let (rev, mut suffix) = rev.split_at(rev.find(['~', '^']).unwrap_or(rev.len()));
+
+    let named = match rev {
+        "HEAD" => get_head(),
+        _ => ["refs/heads", "refs/tags", "refs/remotes", "refs"]
+            .iter()
+            .find_map(|dir| read_ref_with_offset(&format!("{dir}/{rev}"), &common_dir()).ok()),
+    };
+
+    let mut hash = match named {
+        Some(hash) => hash,
+        None => expand_hash_with_offset(rev, &common_dir())?,
+    };
+
+    // Annotated tags point to the commit through a tag object.
+    loop {
+        match get_object(&hash)? {
+            (otype, _, _) if otype == "commit" => break,
+            (otype, _, data) if otype == "tag" => {
+                hash = parse_tag(&data).map_err(|_| io_err!("Invalid tag object"))?;
+            }
+            _ => return Err(io_err!("Revision is not a commit")),
         }
     }
-    Ok(branches)
+
+    while let Some(op) = suffix.chars().next() {
+        let rest = &suffix[1..];
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let n = match &rest[..digits] {
+            "" => 1,
+            n => n.parse().map_err(|_| io_err!("Invalid revision"))?,
+        };
+        suffix = &rest[digits..];
+
+        match op {
+            '~' => {
+                for _ in 0..n {
+                    hash = nth_parent(&hash, 1)?;
+                }
+            }
+            '^' if n > 0 => hash = nth_parent(&hash, n)?,
+            '^' => {}
+            _ => return Err(io_err!("Invalid revision")),
+        }
+    }
+
+    Ok(hash)
 }
@@ -0,0 +1,185 @@
+use std::{io, str::FromStr};
+
+/// A refspec like `+refs/heads/*:refs/remotes/origin/*`: the
+/// references taken from the source and where they are stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refspec {
+    /// Updates that aren't fast-forwards are allowed, `+`.
+    pub force: bool,
+    /// Excludes the references it matches, `^`.
+    pub negative: bool,
+    /// Empty when deleting the destination, `:dst`.
+    pub src: String,
+    /// Empty when the source isn't stored anywhere, `src`.
+    pub dst: String,
+}
+
+impl FromStr for Refspec {
+    type Err = io::Error;
+
+    fn from_str(refspec: &str) -> Result<Self, Self::Err> {
+        let (negative, s) = match refspec.strip_prefix('^') {
+            Some(s) => (true, s),
+            None => (false, refspec),
+        };
+        let (force, s) = match s.strip_prefix('+') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+
+        let (src, dst) = s.split_once(':').unwrap_or((s, ""));
+        let spec = Self {
+            force,
+            negative,
+            src: src.to_string(),
+            dst: dst.to_string(),
+        };
+
+        // Patterns have a single `*`, on both sides if they're stored.
+        let (src_globs, dst_globs) = (src.matches('*').count(), dst.matches('*').count());
+        let valid = match spec.negative {
+            true => !force && dst.is_empty() && !src.is_empty() && src_globs <= 1,
+            false => src_globs <= 1 && (dst_globs == src_globs || dst.is_empty()),
+        };
+
+        match valid {
+            true => Ok(spec),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid refspec '{refspec}'"),
+            )),
+        }
+    }
+}
+
+// Returns the names a short name may stand for.
+fn full_names(name: &str) -> [String; 4] {
+    [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/tags/{name}"),
+    ]
+}
+
+impl Refspec {
+    /// Returns true if the source is a pattern.
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    // Returns what the `*` of the source matched in the name,
+    // empty if it isn't a pattern. None if it doesn't match.
+    fn match_src<'a>(&self, name: &'a str) -> Option<&'a str> {
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => name
+                .strip_prefix(prefix)?
+                .strip_suffix(suffix)
+                .filter(|matched| !matched.is_empty()),
+            None if self.src.is_empty() => None,
+            None => full_names(&self.src)
+                .iter()
+                .any(|full| full == name)
+                .then_some(""),
+        }
+    }
+
+    /// Returns true if the reference is one the source names.
+    pub fn matches(&self, name: &str) -> bool {
+        self.match_src(name).is_some()
+    }
+
+    /// Returns where the reference is stored, None if it doesn't
+    /// match or this refspec doesn't store it.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let matched = self.match_src(name)?;
+        match self.dst.is_empty() {
+            true => None,
+            false => Some(self.dst.replacen('*', matched, 1)),
+        }
+    }
+
+    /// Returns the reference stored in the given destination, None if
+    /// this refspec doesn't store anything there.
+    pub fn unmap(&self, name: &str) -> Option<String> {
+        let reversed = Self {
+            src: self.dst.clone(),
+            dst: self.src.clone(),
+            ..self.clone()
+        };
+        reversed.map(name)
+    }
+}
+
+/// Parses every refspec.
+pub fn parse_refspecs(specs: &[String]) -> io::Result<Vec<Refspec>> {
+    specs.iter().map(|spec| spec.parse()).collect()
+}
+
+/// Returns true if a negative refspec excludes the reference.
+pub fn is_excluded(specs: &[Refspec], name: &str) -> bool {
+    specs.iter().any(|spec| spec.negative && spec.matches(name))
+}
+
+/// Returns where the first refspec that takes the reference stores
+/// it and whether it may be forced. None if none of them does, or a
+/// negative one excludes it.
+pub fn map_ref(specs: &[Refspec], name: &str) -> Option<(String, bool)> {
+    if is_excluded(specs, name) {
+        return None;
+    }
+
+    specs
+        .iter()
+        .filter(|spec| !spec.negative)
+        .find_map(|spec| spec.map(name).map(|dst| (dst, spec.force)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let spec: Refspec = "+refs/heads/*:refs/remotes/origin/*".parse().unwrap();
+        assert!(spec.force && spec.is_glob());
+        assert_eq!(spec.dst, "refs/remotes/origin/*");
+
+        let spec: Refspec = ":old-branch".parse().unwrap();
+        assert_eq!((spec.src.as_str(), spec.dst.as_str()), ("", "old-branch"));
+
+        let spec: Refspec = "^refs/heads/wip/*".parse().unwrap();
+        assert!(spec.negative);
+
+        assert!("refs/heads/*:refs/remotes/origin/main"
+            .parse::<Refspec>()
+            .is_err());
+        assert!("^refs/heads/a:refs/heads/b".parse::<Refspec>().is_err());
+    }
+
+    #[test]
+    fn map() {
+        let specs = parse_refspecs(&[
+            "+refs/heads/*:refs/remotes/origin/*".to_string(),
+            "^refs/heads/wip/*".to_string(),
+            "feature:refs/remotes/origin/main".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            map_ref(&specs, "refs/heads/fix/a"),
+            Some(("refs/remotes/origin/fix/a".to_string(), true))
+        );
+        assert_eq!(map_ref(&specs, "refs/heads/wip/b"), None);
+        assert_eq!(map_ref(&specs, "refs/tags/v1"), None);
+        assert_eq!(
+            specs[2].map("refs/heads/feature"),
+            Some("refs/remotes/origin/main".to_string())
+        );
+        assert_eq!(
+            specs[0].unmap("refs/remotes/origin/fix/a"),
+            Some("refs/heads/fix/a".to_string())
+        );
+        assert_eq!(specs[0].unmap("refs/heads/fix/a"), None);
+    }
+}
@@ -2,8 +2,141 @@ use super::super::{
     commands::RemoteCommand,
     config_file::{config::Config, config_entry::ConfigEntry},
 };
+use super::fetch::{fetch_refspecs, ls_remote, prune, stale_refs_with_offset, tracking_ref};
+use super::log::{__log, LogOptions};
+use super::refspec::map_ref;
 use crate::io_err;
 use std::io;
+use utils::{common_dir, read_ref_with_offset};
+
+// Returns how many commits the branch has that it's upstream
+// doesn't, and how many the upstream has that it doesn't.
+fn ahead_behind(branch: &str, upstream: &str) -> io::Result<(usize, usize)> {
+    let count = |rev: &str, not: &str| -> io::Result<usize> {
+        let opts = LogOptions {
+            revs: vec![rev.to_string(), format!("^{not}")],
+            ..Default::default()
+        };
+        Ok(__log(&opts)?.len())
+    };
+
+    Ok((count(branch, upstream)?, count(upstream, branch)?))
+}
+
+// Describes the remote: it's urls, HEAD, which of it's branches are
+// tracked, new or stale, and the local branches that merge from it.
+fn show(name: &str, config: &Config) -> io::Result<Vec<String>> {
+    show_with_offset(name, config, &ls_remote(name)?, &common_dir())
+}
+
+// Describes the remote given it's references, for the
+// repository at the given offset.
+fn show_with_offset(
+    name: &str,
+    config: &Config,
+    remote_refs: &[(String, String)],
+    offset: &str,
+) -> io::Result<Vec<String>> {
+    let url = match config.remote(name) {
+        Some(ConfigEntry::Remote { url, .. }) => url,
+        _ => return Err(io_err!("Remote not found")),
+    };
+
+    let head = remote_refs
+        .iter()
+        .find_map(|(_, name)| name.strip_prefix("HEAD:"));
+    let specs = fetch_refspecs(config, name)?;
+    let short = |name: &str| name.strip_prefix("refs/heads/").unwrap_or(name).to_string();
+
+    let mut lines = vec![
+        format!("* remote {name}"),
+        format!("  Fetch URL: {url}"),
+        format!("  Push  URL: {url}"),
+        format!(
+            "  HEAD branch: {}",
+            head.map_or("(unknown)".to_string(), short)
+        ),
+    ];
+
+    let mut branches = vec![];
+    for (_, branch) in remote_refs
+        .iter()
+        .filter(|(_, n)| n.starts_with("refs/heads/"))
+    {
+        let state = match map_ref(&specs, branch) {
+            None => continue,
+            Some((tracking, _)) if read_ref_with_offset(&tracking, offset).is_ok() => {
+                "tracked".to_string()
+            }
+            Some(_) => format!("new (next fetch will store in remotes/{name})"),
+        };
+        branches.push((short(branch), state));
+    }
+
+    for tracking in stale_refs_with_offset(&specs, remote_refs, offset)? {
+        let branch = specs.iter().find_map(|spec| spec.unmap(&tracking));
+        let branch = short(&branch.unwrap_or(tracking));
+        branches.push((
+            branch,
+            "stale (use 'git remote prune' to remove)".to_string(),
+        ));
+    }
+
+    branches.sort();
+    let width = branches
+        .iter()
+        .map(|(branch, _)| branch.len())
+        .max()
+        .unwrap_or(0);
+    lines.push("  Remote branches:".to_string());
+    for (branch, state) in branches {
+        lines.push(format!("    {branch:<width$} {state}"));
+    }
+
+    // Only the branches the user set an upstream for are listed.
+    let mut locals: Vec<_> = config
+        .values()
+        .filter_map(|entry| match entry {
+            ConfigEntry::Branch {
+                name: branch,
+                remote,
+                merge,
+            } if remote == name => Some((branch.clone(), short(merge))),
+            _ => None,
+        })
+        .collect();
+    locals.sort();
+
+    if !locals.is_empty() {
+        lines.push("  Local branches configured for 'git pull':".to_string());
+    }
+
+    let width = locals
+        .iter()
+        .map(|(branch, _)| branch.len())
+        .max()
+        .unwrap_or(0);
+    for (branch, merge) in locals {
+        let local = read_ref_with_offset(&format!("refs/heads/{branch}"), offset);
+        let upstream = tracking_ref(name, &branch)
+            .and_then(|tracking| read_ref_with_offset(&tracking, offset));
+
+        let state = match (local, upstream) {
+            (Ok(local), Ok(upstream)) => match ahead_behind(&local, &upstream)? {
+                (0, 0) => " (up to date)".to_string(),
+                (ahead, 0) => format!(" (ahead {ahead})"),
+                (0, behind) => format!(" (behind {behind})"),
+                (ahead, behind) => format!(" (ahead {ahead}, behind {behind})"),
+            },
+            _ => String::new(),
+        };
+        lines.push(format!(
+            "    {branch:<width$} merges with remote {merge}{state}"
+        ));
+    }
+
+    Ok(lines)
+}
 
 // Underlying implementation of `git remote`.
 #[allow(dead_code)]
@@ -11,21 +144,22 @@ pub fn __remote(cmd: RemoteCommand, config: &mut Config) -> io::Result<Option<Ve
     use RemoteCommand::*;
     match cmd {
         Add { name, url } => {
-            if config.contains_key(&name) {
+            if config.remote(&name).is_some() {
                 return Err(io_err!("Remote already exists"));
             }
 
             let entry = ConfigEntry::Remote {
-                fetch: format!("+refs/heads/*:refs/remotes/{}/*", &name),
+                fetch: vec![format!("+refs/heads/*:refs/remotes/{}/*", &name)],
+                push: vec![],
                 name: name.clone(),
                 url,
             };
 
-            config.insert(name, entry);
+            config.add(entry);
         }
 
         Rem { name } => {
-            let _ = config.remove(&name);
+            let _ = config.delete("remote", &name);
         }
 
         List => {
@@ -38,6 +172,22 @@ pub fn __remote(cmd: RemoteCommand, config: &mut Config) -> io::Result<Option<Ve
             remotes.sort_by(|a, b| a.1.name().cmp(&b.1.name()));
             return Ok(Some(remotes.into_iter().map(|e| e.1.name()).collect()));
         }
+
+        Prune { name } => {
+            let pruned = prune(&name)?;
+            let mut lines = vec![];
+            if !pruned.is_empty() {
+                lines.push(format!("Pruning {name}"));
+            }
+
+            lines.extend(pruned.iter().map(|tracking| {
+                let branch = tracking.strip_prefix("refs/remotes/").unwrap_or(tracking);
+                format!(" * [pruned] {branch}")
+            }));
+            return Ok(Some(lines));
+        }
+
+        Show { name } => return show(&name, config).map(Some),
     }
 
     Ok(None)
@@ -97,7 +247,7 @@ mod tests {
             Some(_) => panic!("Expected None"),
             None => {
                 assert_eq!(4, config.len());
-                assert!(config.contains_key("fetch"));
+                assert!(config.remote("fetch").is_some());
             }
         }
     }
@@ -118,8 +268,48 @@ mod tests {
             Some(_) => panic!("Expected None"),
             None => {
                 assert_eq!(2, config.len());
-                assert!(!config.contains_key("origin"));
+                assert!(config.remote("origin").is_none());
             }
         }
     }
+
+    #[test]
+    fn show() {
+        let offset = format!(
+            "{}/remote-show-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let _ = std::fs::remove_dir_all(&offset);
+        for name in ["main", "gone"] {
+            std::fs::create_dir_all(format!("{offset}/refs/remotes/origin")).unwrap();
+            std::fs::write(format!("{offset}/refs/remotes/origin/{name}"), "a1\n").unwrap();
+        }
+
+        let config = Config::from_str(test_str!()).unwrap();
+        let remote_refs: Vec<_> = ["HEAD:refs/heads/main", "refs/heads/main", "refs/heads/new"]
+            .iter()
+            .map(|name| ("a1".to_string(), name.to_string()))
+            .collect();
+        let url = "https://github.com/taller-1-fiuba-rust/23C2-Los-Krabby-Patty.git";
+
+        assert_eq!(
+            show_with_offset("origin", &config, &remote_refs, &offset).unwrap(),
+            [
+                "* remote origin".to_string(),
+                format!("  Fetch URL: {url}"),
+                format!("  Push  URL: {url}"),
+                "  HEAD branch: main".to_string(),
+                "  Remote branches:".to_string(),
+                "    gone stale (use 'git remote prune' to remove)".to_string(),
+                "    main tracked".to_string(),
+                "    new  new (next fetch will store in remotes/origin)".to_string(),
+                "  Local branches configured for 'git pull':".to_string(),
+                "    main merges with remote main".to_string(),
+                "    test merges with remote test".to_string(),
+            ]
+        );
+
+        std::fs::remove_dir_all(offset).unwrap();
+    }
 }
@@ -0,0 +1,289 @@
+use super::commands::{read_index, write_index};
+use super::work_dir::write_blob;
+use crate::commands::SparseCheckoutCommand;
+use crate::config_file::config::Config;
+use crate::io_err;
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+use utils::index_file::index_entry::IndexEntry;
+use utils::plumbing::{hash_object::__hash_object, ls_tree::hash_to_str};
+use utils::{common_dir, git_dir};
+
+/// The directories of a cone mode sparse checkout. Files directly
+/// in the root, in these directories or in the directories leading
+/// to them are checked out, the rest are only kept in the index.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SparseCheckout {
+    dirs: BTreeSet<String>,
+}
+
+fn sparse_file() -> String {
+    format!("{}/info/sparse-checkout", git_dir())
+}
+
+// Returns the path relative to the root of the working directory.
+fn relative(path: &str) -> &str {
+    let mut path = path;
+    while let Some(stripped) = path.strip_prefix("./") {
+        path = stripped;
+    }
+
+    match path {
+        "." => "",
+        path => path.trim_end_matches('/'),
+    }
+}
+
+impl SparseCheckout {
+    /// Creates a sparse checkout of the given directories. Directories
+    /// inside others are left out, they are already checked out.
+    pub fn with<S: AsRef<str>>(dirs: &[S]) -> Self {
+        let all: BTreeSet<_> = dirs
+            .iter()
+            .map(|dir| relative(dir.as_ref().trim_start_matches('/')).to_string())
+            .filter(|dir| !dir.is_empty())
+            .collect();
+
+        // Sorted, a directory comes right before the ones inside it.
+        let mut dirs: BTreeSet<String> = BTreeSet::new();
+        for dir in all {
+            if !dirs.iter().any(|d| dir.starts_with(&format!("{d}/"))) {
+                dirs.insert(dir);
+            }
+        }
+
+        Self { dirs }
+    }
+
+    /// Reads the sparse checkout of the worktree, None if it's disabled.
+    pub fn read() -> io::Result<Option<Self>> {
+        if !Config::read().is_ok_and(|config| config.sparse_checkout()) {
+            return Ok(None);
+        }
+
+        match fs::read_to_string(sparse_file()) {
+            Ok(patterns) => Ok(Some(Self::parse(&patterns))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Parses cone mode patterns. Every directory is listed as `/dir/`,
+    // the ones leading to others are followed by `!/dir/*/`.
+    fn parse(patterns: &str) -> Self {
+        let mut dirs = BTreeSet::new();
+        let mut parents = BTreeSet::new();
+
+        for line in patterns.lines().map(str::trim) {
+            if let Some(dir) = line.strip_prefix("!/").and_then(|l| l.strip_suffix("/*/")) {
+                parents.insert(dir.to_string());
+            } else if let Some(dir) = line.strip_prefix('/').and_then(|l| l.strip_suffix('/')) {
+                if dir != "*" && !dir.is_empty() {
+                    dirs.insert(dir.to_string());
+                }
+            }
+        }
+
+        let dirs: Vec<_> = dirs.difference(&parents).collect();
+        Self::with(&dirs)
+    }
+
+    // Returns the patterns git writes for the directories.
+    fn patterns(&self) -> String {
+        let mut parents = BTreeSet::new();
+        for dir in &self.dirs {
+            let mut parent = Path::new(dir).parent();
+            while let Some(dir) = parent.and_then(|p| p.to_str()).filter(|p| !p.is_empty()) {
+                parents.insert(dir.to_string());
+                parent = Path::new(dir).parent();
+            }
+        }
+
+        let mut ret = String::from("/*\n!/*/\n");
+        for parent in parents {
+            ret.push_str(&format!("/{parent}/\n!/{parent}/*/\n"));
+        }
+
+        for dir in &self.dirs {
+            ret.push_str(&format!("/{dir}/\n"));
+        }
+
+        ret
+    }
+
+    /// Returns the directories checked out.
+    pub fn dirs(&self) -> Vec<String> {
+        self.dirs.iter().cloned().collect()
+    }
+
+    /// Returns true if the files directly inside the directory are
+    /// checked out. That is, if it's in the sparse checkout or leads to it.
+    pub fn includes_dir(&self, dir: &str) -> bool {
+        let dir = relative(dir);
+        dir.is_empty()
+            || self.dirs.iter().any(|d| {
+                dir == d || dir.starts_with(&format!("{d}/")) || d.starts_with(&format!("{dir}/"))
+            })
+    }
+
+    /// Returns true if the file at the given path is checked out.
+    pub fn includes(&self, path: &str) -> bool {
+        match relative(path).rsplit_once('/') {
+            Some((dir, _)) => self.includes_dir(dir),
+            None => true,
+        }
+    }
+}
+
+// Returns true if the file differs from it's entry in the index.
+fn is_modified(entry: &IndexEntry) -> io::Result<bool> {
+    let data = IndexEntry::read_content(entry.get_path())?;
+    Ok(__hash_object(&data, "blob", false, &common_dir())?.0 != entry.get_hash())
+}
+
+// Removes the empty directories leading to the given path.
+fn remove_empty_dirs(path: &str) {
+    let mut dir = Path::new(path).parent();
+    while let Some(path) = dir.filter(|dir| !dir.as_os_str().is_empty()) {
+        if fs::remove_dir(path).is_err() {
+            break;
+        }
+        dir = path.parent();
+    }
+}
+
+/// Updates the working directory to the sparse checkout, or to
+/// every file when there is none. Files left out are removed and
+/// marked as skip-worktree in the index, modified ones are kept.
+pub fn apply_sparse_checkout(sparse: Option<&SparseCheckout>) -> io::Result<Vec<String>> {
+    let mut index = read_index()?;
+    let mut kept = vec![];
+
+    for (path, entry) in index.iter_mut() {
+        if entry.is_gitlink() {
+            continue;
+        }
+
+        let included = sparse.is_none_or(|sparse| sparse.includes(path));
+        if included && entry.is_skip_worktree() {
+            if let Some(dir) = Path::new(path).parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            write_blob(path, entry.get_mode(), &hash_to_str(entry.get_hash()))?;
+            entry.set_skip_worktree(false);
+        } else if !included && !entry.is_skip_worktree() {
+            match is_modified(entry) {
+                Ok(true) => {
+                    kept.push(path.clone());
+                    continue;
+                }
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                Ok(false) => {
+                    fs::remove_file(path)?;
+                    remove_empty_dirs(path);
+                }
+                Err(_) => {}
+            }
+
+            entry.set_skip_worktree(true);
+        }
+    }
+
+    write_index(index)?;
+    kept.sort();
+    Ok(kept)
+}
+
+// Saves the sparse checkout, enabling it, and updates the working directory.
+fn set(sparse: SparseCheckout) -> io::Result<String> {
+    let path = sparse_file();
+    if let Some(dir) = Path::new(&path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, sparse.patterns())?;
+
+    let mut config = Config::read()?;
+    config.set_sparse_checkout(true);
+    config.write()?;
+
+    let kept = apply_sparse_checkout(Some(&sparse))?;
+    Ok(format_kept(&kept))
+}
+
+fn format_kept(kept: &[String]) -> String {
+    if kept.is_empty() {
+        return String::new();
+    }
+
+    let mut ret = "warning: The following paths were modified, not removed:\n".to_string();
+    for path in kept {
+        ret.push_str(&format!("  {path}\n"));
+    }
+
+    ret
+}
+
+/// Underlying implementation of `git sparse-checkout`, in cone mode.
+/// The directories are stored in `.git/info/sparse-checkout`.
+pub fn __sparse_checkout(cmd: SparseCheckoutCommand) -> io::Result<String> {
+    match cmd {
+        SparseCheckoutCommand::Set(dirs) => set(SparseCheckout::with(&dirs)),
+        SparseCheckoutCommand::Add(dirs) => {
+            let sparse = SparseCheckout::read()?
+                .ok_or(io_err!("No sparse checkout to add to, use set first"))?;
+            let mut all = sparse.dirs();
+            all.extend(dirs);
+            set(SparseCheckout::with(&all))
+        }
+        SparseCheckoutCommand::List => {
+            let sparse = SparseCheckout::read()?.ok_or(io_err!("This worktree is not sparse"))?;
+            Ok(sparse.dirs().iter().map(|dir| format!("{dir}\n")).collect())
+        }
+        SparseCheckoutCommand::Disable => {
+            let mut config = Config::read()?;
+            config.set_sparse_checkout(false);
+            config.write()?;
+
+            apply_sparse_checkout(None)?;
+            Ok(String::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cone_patterns() {
+        let sparse = SparseCheckout::with(&["client/src/", "./docs", "client/src/ui"]);
+        assert_eq!(sparse.dirs(), vec!["client/src", "docs"]);
+
+        let patterns = sparse.patterns();
+        assert_eq!(
+            patterns,
+            "/*\n!/*/\n/client/\n!/client/*/\n/client/src/\n/docs/\n"
+        );
+        assert_eq!(SparseCheckout::parse(&patterns), sparse);
+    }
+
+    #[test]
+    fn included_paths() {
+        let sparse = SparseCheckout::with(&["client/src"]);
+
+        assert!(sparse.includes("Cargo.toml"));
+        assert!(sparse.includes("./client/Cargo.toml"));
+        assert!(sparse.includes("client/src/plumbing/mod.rs"));
+        assert!(!sparse.includes("client/tests/a.rs"));
+        assert!(!sparse.includes("server/src/main.rs"));
+
+        assert!(sparse.includes_dir("client"));
+        assert!(sparse.includes_dir("./client/src/ui"));
+        assert!(!sparse.includes_dir("clients"));
+    }
+}
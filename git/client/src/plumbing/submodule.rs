@@ -0,0 +1,194 @@
+use super::{
+    checkout::refactor_root_dir,
+    clone::__clone_into,
+    commands::{get_head, read_index, read_tree, write_index},
+    diff::diff_tree::diff_tree,
+    fetch::__fetch,
+    heads::detach_head,
+};
+use crate::commands::{ls_tree, SubmoduleCommand};
+use crate::config_file::{config::Config, config_entry::ConfigEntry, git_config::ConfigFile};
+use crate::protocol::ext_command;
+use std::{env, fs, io};
+use utils::{
+    get_head_with_offset,
+    object::object_db::get_object,
+    plumbing::{commit::get_commit_root, ls_tree::hash_to_str},
+};
+
+/// Parses the content of a `.gitmodules` file
+/// returning the submodules declared in it.
+pub fn parse_gitmodules(s: &str) -> io::Result<Vec<ConfigEntry>> {
+    let file = ConfigFile::parse(".gitmodules", s)?;
+    let mut submodules = vec![];
+    for (section, subsection) in file.sections() {
+        if let (true, Some(name)) = (section == "submodule", subsection) {
+            submodules.extend(ConfigEntry::from_file(&section, &name, &file)?);
+        }
+    }
+
+    Ok(submodules)
+}
+
+// Returns the (path, hash) of every gitlink in the index.
+fn gitlinks() -> io::Result<Vec<(String, String)>> {
+    let mut links: Vec<_> = read_index()?
+        .get_entries()
+        .into_iter()
+        .filter(|entry| entry.is_gitlink())
+        .map(|entry| (entry.get_path().to_string(), hash_to_str(entry.get_hash())))
+        .collect();
+
+    links.sort();
+    Ok(links)
+}
+
+// Checks out the given commit in the current repository, detaching HEAD.
+fn checkout_commit(hash: &str) -> io::Result<()> {
+    let head = get_head();
+    if head.as_deref() == Some(hash) {
+        return Ok(());
+    }
+
+    // Get the trees of the current and target commits.
+    let (_, _, commit) = get_object(hash)?;
+    let root = get_commit_root(&commit)?;
+    let tree = ls_tree(&root)?;
+    let cur_tree = match head {
+        Some(head) => ls_tree(&get_commit_root(&get_object(&head)?.2)?)?,
+        None => String::new(),
+    };
+
+    // Apply the differences to the work dir.
+    let diffs = diff_tree(&cur_tree, &tree).collect();
+    refactor_root_dir(diffs, ".")?;
+
+    write_index(read_tree(&root, "")?)?;
+    detach_head(hash)
+}
+
+// Clones the submodule if needed and checks out the given commit.
+// Moves the cwd into the submodule. Urls come from .gitmodules, which
+// anyone can write, so `ext::` urls that run commands are refused.
+fn __update_submodule(path: &str, url: &str, hash: &str) -> io::Result<()> {
+    if ext_command(url).is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("transport 'ext' not allowed for submodule '{path}'"),
+        ));
+    }
+
+    if fs::metadata(format!("{path}/.git")).is_err() {
+        fs::create_dir_all(path)?;
+        __clone_into(url, path)?;
+    } else {
+        env::set_current_dir(path)?;
+    }
+
+    // Bring the commit if the submodule doesn't have it yet.
+    if get_object(hash).is_err() {
+        __fetch("origin")?;
+    }
+
+    checkout_commit(hash)
+}
+
+fn update_submodule(path: &str, url: &str, hash: &str) -> io::Result<()> {
+    let cwd = env::current_dir()?;
+    let result = __update_submodule(path, url, hash);
+    env::set_current_dir(cwd)?;
+    result
+}
+
+/// Underlying implementation of `git submodule`.
+pub fn __submodule(cmd: SubmoduleCommand) -> io::Result<Option<Vec<String>>> {
+    match cmd {
+        // Registers the submodules in .gitmodules in the config file.
+        SubmoduleCommand::Init => {
+            let gitmodules = fs::read_to_string(".gitmodules")?;
+            let mut config = Config::read()?;
+            for entry in parse_gitmodules(&gitmodules)? {
+                if let ConfigEntry::Submodule { .. } = entry {
+                    if config.entry("submodule", &entry.name()).is_none() {
+                        config.add(entry);
+                    }
+                }
+            }
+
+            config.write()?;
+            Ok(None)
+        }
+
+        // Clones the initialized submodules and checks
+        // out the commits recorded in the index.
+        SubmoduleCommand::Update => {
+            let config = Config::read()?;
+            for (path, hash) in gitlinks()? {
+                let url = config.values().find_map(|entry| match entry {
+                    ConfigEntry::Submodule { path: p, url, .. } if *p == path => Some(url),
+                    _ => None,
+                });
+
+                // Submodules that weren't initialized are skipped.
+                if let Some(url) = url {
+                    update_submodule(&path, url, &hash)?;
+                }
+            }
+
+            Ok(None)
+        }
+
+        // Lists submodules prefixed with '-' if they aren't checked out
+        // and '+' if they don't match the commit recorded in the index.
+        SubmoduleCommand::Status => {
+            let mut lines = vec![];
+            for (path, hash) in gitlinks()? {
+                let line = match get_head_with_offset(&format!("{path}/.git")) {
+                    None => format!("-{hash} {path}"),
+                    Some(head) if head != hash => format!("+{head} {path}"),
+                    Some(_) => format!(" {hash} {path}"),
+                };
+
+                lines.push(line);
+            }
+
+            Ok(Some(lines))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_ext_urls() {
+        let path = "refuses-ext-urls";
+        let err =
+            update_submodule(path, "ext::sh -c touch% pwned", "1".repeat(40).as_str()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(fs::metadata(path).is_err());
+    }
+
+    #[test]
+    fn gitmodules() {
+        let s = r#"[submodule "lib"]
+	path = vendor/lib
+	url = git://127.0.0.1:9418/lib.git
+[submodule "docs"]
+	path = docs
+	url = git://127.0.0.1:9418/docs.git
+"#;
+
+        let entries = parse_gitmodules(s).unwrap();
+        assert_eq!(entries.len(), 2);
+        match &entries[0] {
+            ConfigEntry::Submodule { name, path, url } => {
+                assert_eq!(name, "lib");
+                assert_eq!(path, "vendor/lib");
+                assert_eq!(url, "git://127.0.0.1:9418/lib.git");
+            }
+            _ => panic!("Expected a submodule entry"),
+        }
+    }
+}
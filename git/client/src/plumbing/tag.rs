@@ -1,49 +1,112 @@
-use utils::object::object_db::get_object;
+use utils::{
+    common_dir, delete_packed_ref_with_offset, get_refs_from_with_prefix,
+    object::object_db::get_object, read_ref_with_offset,
+};
 
 use crate::{commands::TagCommand, io_err, plumbing::commands::get_head};
 use std::{fs, io, path::PathBuf};
 
 use super::{
-    commands::{get_userconfig, hash_object},
+    commands::{get_identity, hash_object},
     commit::get_time_fmt,
+    identity::Role,
+    signing::{read_signing_key, sign},
 };
 
-const TAGS_DIR: &str = ".git/refs/tags";
+const TAGS_DIR: &str = "refs/tags";
 
-/// Returns an iterator over tuples (hash, tag_name) for every tag in the repo.
-pub fn get_tags_with_offset(offset: &str) -> io::Result<impl Iterator<Item = (String, String)>> {
-    let dir = fs::read_dir(format!("{offset}/{TAGS_DIR}"))?;
+// Lists the tags of the repository with the given git directory.
+fn get_tags_from(git_dir: &str) -> io::Result<impl Iterator<Item = (String, String)>> {
+    let refs = get_refs_from_with_prefix(&format!("{git_dir}/{TAGS_DIR}"), &format!("{git_dir}/"))?;
     let mut tags = vec![];
 
-    for entry in dir.flatten() {
-        let path = entry.path();
-        let name = match path.file_name() {
-            Some(name) => name.to_string_lossy().to_string(),
-            None => continue,
-        };
-
-        let hash = fs::read_to_string(path)?.trim().to_string();
-        tags.push((hash, name));
+    for (name, hash) in refs {
+        if let Some(name) = name.strip_prefix("refs/tags/") {
+            tags.push((hash, name.to_string()));
+        }
     }
 
+    tags.sort_by(|a, b| a.1.cmp(&b.1));
     Ok(tags.into_iter())
 }
 
+/// Returns an iterator over tuples (hash, tag_name) for every tag in the repo.
+pub fn get_tags_with_offset(offset: &str) -> io::Result<impl Iterator<Item = (String, String)>> {
+    get_tags_from(&format!("{offset}/.git"))
+}
+
 /// Returns an iterator over tuples (hash, tag_name) for every tag in the repo.
 pub fn get_tags() -> io::Result<impl Iterator<Item = (String, String)>> {
-    get_tags_with_offset(".")
+    get_tags_from(&common_dir())
 }
 
-fn build_tag(hash: &str, msg: &str, otype: &str, name: &str) -> io::Result<String> {
-    let tagger = get_userconfig()?.to_string();
+// Writes an annotated tag object. When a key is given, it's
+// signature is appended to the message.
+fn build_tag(
+    hash: &str,
+    msg: &str,
+    otype: &str,
+    name: &str,
+    key: Option<&str>,
+) -> io::Result<String> {
+    let key = key.map(read_signing_key).transpose()?;
+    let tagger = get_identity(Role::Committer)?;
     let time = get_time_fmt(chrono::Local::now());
 
-    let object =
+    let mut object =
         format!("object {hash}\ntype {otype}\ntag {name}\ntagger {tagger} {time}\n\n{msg}\n");
 
+    if let Some(key) = key {
+        object.push_str(&sign(object.as_bytes(), &key));
+    }
+
     hash_object(object.as_bytes(), "tag", true)
 }
 
+// Writes the tag, pointing to HEAD when no hash is given.
+// Tags with a message are annotated.
+fn write_tag(
+    name: &str,
+    hash: Option<String>,
+    msg: Option<String>,
+    key: Option<&str>,
+) -> io::Result<()> {
+    let hash = match hash {
+        Some(hash) => hash,
+        None => get_head().ok_or(io_err!("no commits yet"))?,
+    };
+
+    // Check if the hash is valid.
+    let otype = match get_object(&hash) {
+        Ok((otype, _, _)) => otype,
+        Err(_) => return Err(io_err!("invalid hash")),
+    };
+
+    let path = format!("{}/{TAGS_DIR}/{name}", common_dir());
+
+    // Create the path until the tag file.
+    match PathBuf::from(&path).parent() {
+        Some(parent) => fs::create_dir_all(parent)?,
+        None => return Err(io_err!("invalid tag name")),
+    }
+
+    let content = match msg {
+        None => format!("{hash}\n"),
+        Some(msg) => build_tag(&hash, &msg, &otype, name, key)?,
+    };
+
+    // Write the file.
+    fs::write(path, content)
+}
+
+// Returns an error if the tag already exists.
+fn check_new_tag(name: &str) -> io::Result<()> {
+    match read_ref_with_offset(&format!("refs/tags/{name}"), &common_dir()) {
+        Ok(_) => Err(io_err!("tag already exists")),
+        Err(_) => Ok(()),
+    }
+}
+
 /// Underlying implementation of `git tag`.
 pub fn __tag(cmd: TagCommand) -> io::Result<Option<Vec<String>>> {
     use TagCommand::*;
@@ -51,52 +114,38 @@ pub fn __tag(cmd: TagCommand) -> io::Result<Option<Vec<String>>> {
         List => Ok(Some(get_tags()?.map(|(_, name)| name).collect())),
 
         Add { name, hash, msg } => {
-            let path = format!("{TAGS_DIR}/{name}");
-
-            // If the tag already exists, exit.
-            if fs::metadata(path).is_ok() {
-                return Err(io_err!("tag already exists"));
-            }
-
-            __tag(AddForce { name, hash, msg })
+            check_new_tag(&name)?;
+            write_tag(&name, hash, msg, None)?;
+            Ok(None)
         }
 
         AddForce { name, hash, msg } => {
-            let hash = match hash {
-                Some(hash) => hash,
-                None => get_head().ok_or(io_err!("no commits yet"))?,
-            };
-
-            // Check if the hash is valid.
-            let otype = match get_object(&hash) {
-                Ok((otype, _, _)) => otype,
-                Err(_) => return Err(io_err!("invalid hash")),
-            };
-
-            let path = format!("{TAGS_DIR}/{name}");
-
-            // Create the path until the tag file.
-            match PathBuf::from(&path).parent() {
-                Some(parent) => fs::create_dir_all(parent)?,
-                None => return Err(io_err!("invalid tag name")),
-            }
-
-            let content = match msg {
-                None => format!("{hash}\n"),
-                Some(msg) => build_tag(&hash, &msg, &otype, &name)?,
-            };
+            write_tag(&name, hash, msg, None)?;
+            Ok(None)
+        }
 
-            // Write the file.
-            fs::write(path, content)?;
+        AddSigned {
+            name,
+            hash,
+            msg,
+            key,
+        } => {
+            check_new_tag(&name)?;
+            write_tag(&name, hash, Some(msg), Some(&key))?;
             Ok(None)
         }
 
         Del { name } => {
-            let path = format!("{TAGS_DIR}/{name}");
+            let path = format!("{}/{TAGS_DIR}/{name}", common_dir());
+
+            // The tag may be loose, packed or both.
+            let loose = fs::remove_file(path).is_ok();
+            let packed =
+                delete_packed_ref_with_offset(&format!("refs/tags/{name}"), &common_dir())?;
 
-            match fs::remove_file(path) {
-                Ok(_) => Ok(None),
-                Err(_) => Err(io_err!("tag does not exist")),
+            match loose || packed {
+                true => Ok(None),
+                false => Err(io_err!("tag does not exist")),
             }
         }
     }
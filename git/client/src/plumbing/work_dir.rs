@@ -1,8 +1,10 @@
 use super::super::commands::ls_tree;
+use super::sparse::SparseCheckout;
 use crate::io_err;
 use std::{
     fs::{self, File},
     io::{self, Write},
+    os::unix::fs::{symlink, PermissionsExt},
     path::PathBuf,
 };
 use utils::{object::object_db::get_object, plumbing::ls_tree::parse_ls_tree_entry};
@@ -14,11 +16,15 @@ fn __parse_work_dir(path: &PathBuf, cwdlen: usize) -> io::Result<Vec<String>> {
     for entry in fs::read_dir(path)?.flatten() {
         let path = entry.path();
 
-        if path.is_dir() {
-            match path.file_name() {
-                Some(name) if name != ".git" => paths.append(&mut __parse_work_dir(&path, cwdlen)?),
-                _ => {}
-            }
+        // Linked worktrees have a `.git` file instead of a directory.
+        if path.file_name().is_some_and(|name| name == ".git") {
+            continue;
+        }
+
+        // Symlinks are tracked as files even if they point to a directory.
+        // Submodule checkouts are tracked as a single gitlink path.
+        if entry.file_type()?.is_dir() && !path.join(".git").exists() {
+            paths.append(&mut __parse_work_dir(&path, cwdlen)?);
         } else {
             let path = path.to_string_lossy();
             let path = match path.get(cwdlen..) {
@@ -40,25 +46,58 @@ pub fn parse_work_dir() -> io::Result<Vec<String>> {
     __parse_work_dir(&path, path.to_string_lossy().len())
 }
 
-/// Adds all the entries inside this hash's tree to the path.
+/// Writes the blob with the given hash to path honouring it's mode.
+/// Mode 120000 blobs hold a link target and are created as symlinks,
+/// 100755 blobs get the executable bit set.
+pub fn write_blob(path: &str, mode: &str, hash: &str) -> io::Result<()> {
+    let (_, _, data) = get_object(hash)?;
+
+    // Remove whatever is in the way, File::create
+    // would otherwise write through a symlink.
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+
+    if mode == "120000" {
+        let target = String::from_utf8_lossy(&data).to_string();
+        return symlink(target, path);
+    }
+
+    let mut file = File::create(path)?;
+    file.write_all(&data)?;
+
+    let perm = if mode == "100755" { 0o755 } else { 0o644 };
+    fs::set_permissions(path, fs::Permissions::from_mode(perm))
+}
+
+/// Adds all the entries inside this hash's tree to the path,
+/// only the ones in the sparse checkout if there is one.
 pub fn directify_tree(hash: &str, path: &str) -> io::Result<()> {
+    __directify_tree(hash, path, SparseCheckout::read()?.as_ref())
+}
+
+/// Underlying implementation of directify_tree. Paths are
+/// matched against the sparse checkout relative to the
+/// working directory.
+pub fn __directify_tree(hash: &str, path: &str, sparse: Option<&SparseCheckout>) -> io::Result<()> {
     let tree = ls_tree(hash)?;
 
     for line in tree.lines() {
-        let (_, otype, hash, name) = parse_ls_tree_entry(line);
+        let (mode, otype, hash, name) = parse_ls_tree_entry(line);
         let path = format!("{}/", path) + &name;
         match otype {
-            "blob" => {
-                let mut file = File::create(&path)?;
-                let (_, _, data) = get_object(hash)?;
-                file.write_all(&data)?;
-            }
+            "blob" if sparse.is_some_and(|sparse| !sparse.includes(&path)) => {}
+            "blob" => write_blob(&path, mode, hash)?,
 
+            "tree" if sparse.is_some_and(|sparse| !sparse.includes_dir(&path)) => {}
             "tree" => {
                 fs::create_dir(&path)?;
-                directify_tree(hash, &path)?;
+                __directify_tree(hash, &path, sparse)?;
             }
 
+            // Submodules are left empty until `submodule update`.
+            "commit" => fs::create_dir_all(&path)?,
+
             _ => return Err(io_err!("Invalid object type")),
         }
     }
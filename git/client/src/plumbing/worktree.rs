@@ -0,0 +1,400 @@
+use super::log::LogCommit;
+use super::refs::rev_parse;
+use super::work_dir::__directify_tree;
+use crate::commands::WorktreeCommand;
+use crate::io_err;
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+use utils::index_file::commands::{__read_index, __write_index};
+use utils::index_file::index_entry::IndexEntry;
+use utils::object::object_db::get_object;
+use utils::plumbing::{commit::get_commit_root, hash_object::__hash_object};
+use utils::{
+    __get_head_commit_with_offset, common_dir, read_ref_with_offset, read_tree_from_repo,
+    update_branch_from_repo,
+};
+
+/// A working tree of the repository.
+#[derive(Debug, Clone, PartialEq)]
+struct Worktree {
+    path: PathBuf,
+    /// The git directory with it's HEAD and index.
+    git_dir: PathBuf,
+    /// Commit checked out, None for unborn branches.
+    head: Option<String>,
+    /// Branch checked out, None when HEAD is detached.
+    branch: Option<String>,
+    /// Linked worktrees whose directory no longer exists.
+    prunable: bool,
+}
+
+impl Worktree {
+    fn read(path: PathBuf, git_dir: PathBuf, common_dir: &str) -> Self {
+        let head = fs::read_to_string(git_dir.join("HEAD")).unwrap_or_default();
+        let branch = head
+            .trim()
+            .strip_prefix("ref: refs/heads/")
+            .map(String::from);
+        let head = __get_head_commit_with_offset(head.as_bytes(), common_dir).ok();
+        let prunable = !path.join(".git").exists();
+
+        Self {
+            path,
+            git_dir,
+            head,
+            branch,
+            prunable,
+        }
+    }
+}
+
+// Returns the absolute version of a path, without `.` or `..`.
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    let mut ret = match path.is_absolute() {
+        true => PathBuf::new(),
+        false => env::current_dir()?,
+    };
+
+    for component in path.components() {
+        match component.as_os_str().to_str() {
+            Some(".") => {}
+            Some("..") => {
+                ret.pop();
+            }
+            _ => ret.push(component),
+        }
+    }
+
+    Ok(ret)
+}
+
+// Lists the main worktree followed by the linked ones.
+fn worktrees() -> io::Result<Vec<Worktree>> {
+    let common_dir = common_dir();
+    let main_dir = absolute(Path::new(&common_dir))?;
+    let main_path = main_dir.parent().ok_or(io_err!("Invalid git directory"))?;
+    let mut worktrees = vec![Worktree::read(
+        main_path.to_path_buf(),
+        main_dir.clone(),
+        &common_dir,
+    )];
+
+    let mut linked = vec![];
+    match fs::read_dir(main_dir.join("worktrees")) {
+        Ok(entries) => {
+            for entry in entries {
+                let git_dir = entry?.path();
+
+                // The gitdir file points to the `.git` file of the worktree.
+                let gitdir = fs::read_to_string(git_dir.join("gitdir")).unwrap_or_default();
+                let path = Path::new(gitdir.trim()).parent().unwrap_or(Path::new(""));
+                linked.push(Worktree::read(path.to_path_buf(), git_dir, &common_dir));
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    linked.sort_by(|a, b| a.path.cmp(&b.path));
+    worktrees.extend(linked);
+    Ok(worktrees)
+}
+
+// Returns the worktree at the given path.
+fn find_worktree(path: &str) -> io::Result<Worktree> {
+    let path = absolute(Path::new(path))?;
+    worktrees()?
+        .into_iter()
+        .find(|worktree| worktree.path == path)
+        .ok_or(io_err!("Not a working tree"))
+}
+
+fn name_of(path: &Path) -> io::Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(io_err!("Invalid worktree path"))
+}
+
+// Picks a name for the git directory of a new worktree that
+// isn't taken, the name of it's directory plus a number if needed.
+fn unique_name(worktrees_dir: &Path, path: &Path) -> io::Result<String> {
+    let name = name_of(path)?;
+    let mut unique = name.to_string();
+    let mut i = 1;
+    while worktrees_dir.join(&unique).exists() {
+        unique = format!("{name}{i}");
+        i += 1;
+    }
+
+    Ok(unique)
+}
+
+// Fails if the branch is checked out in any worktree.
+fn check_not_checked_out(branch: &str) -> io::Result<()> {
+    match worktrees()?
+        .iter()
+        .find(|worktree| worktree.branch.as_deref() == Some(branch))
+    {
+        Some(_) => Err(io_err!("The branch is already checked out in a worktree")),
+        None => Ok(()),
+    }
+}
+
+// Creates a worktree at path checking out the branch or, when
+// there is none, detaching HEAD at the commit.
+fn add(path: &str, commit: Option<String>, new_branch: Option<String>) -> io::Result<String> {
+    let path = absolute(Path::new(path))?;
+    if fs::read_dir(&path).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(io_err!("The worktree path already exists"));
+    }
+
+    let common_dir = common_dir();
+    let is_branch = |name: &str| read_ref_with_offset(&format!("refs/heads/{name}"), &common_dir);
+
+    // Like git, without a commit a branch named after the worktree is used.
+    let (branch, hash, header) = match (new_branch, commit) {
+        (Some(branch), commit) => {
+            if is_branch(&branch).is_ok() {
+                return Err(io_err!("A branch with that name already exists"));
+            }
+
+            let hash = rev_parse(commit.as_deref().unwrap_or("HEAD"))?;
+            update_branch_from_repo(&branch, &hash, &common_dir)?;
+            let header = format!("new branch '{branch}'");
+            (Some(branch), hash, header)
+        }
+        (None, Some(commit)) => match is_branch(&commit) {
+            Ok(hash) => {
+                check_not_checked_out(&commit)?;
+                let header = format!("checking out '{commit}'");
+                (Some(commit), hash, header)
+            }
+            Err(_) => {
+                let hash = rev_parse(&commit)?;
+                let header = format!("detached HEAD {}", &hash[..7]);
+                (None, hash, header)
+            }
+        },
+        (None, None) => {
+            let name = name_of(&path)?.to_string();
+            match is_branch(&name) {
+                Ok(hash) => {
+                    check_not_checked_out(&name)?;
+                    let header = format!("checking out '{name}'");
+                    (Some(name), hash, header)
+                }
+                Err(_) => {
+                    let hash = rev_parse("HEAD")?;
+                    update_branch_from_repo(&name, &hash, &common_dir)?;
+                    let header = format!("new branch '{name}'");
+                    (Some(name), hash, header)
+                }
+            }
+        }
+    };
+
+    // Set up it's git directory, pointing to both the worktree and
+    // the common directory, and the `.git` file pointing back to it.
+    let worktrees_dir = absolute(Path::new(&common_dir))?.join("worktrees");
+    let git_dir = worktrees_dir.join(unique_name(&worktrees_dir, &path)?);
+    fs::create_dir_all(&git_dir)?;
+    fs::create_dir_all(&path)?;
+
+    let head = match &branch {
+        Some(branch) => format!("ref: refs/heads/{branch}\n"),
+        None => format!("{hash}\n"),
+    };
+    fs::write(git_dir.join("HEAD"), head)?;
+    fs::write(git_dir.join("commondir"), "../..\n")?;
+    fs::write(
+        git_dir.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+    fs::write(
+        path.join(".git"),
+        format!("gitdir: {}\n", git_dir.display()),
+    )?;
+
+    // Populate it and it's index.
+    let (_, _, data) = get_object(&hash)?;
+    let tree = get_commit_root(&data)?;
+    // The sparse checkout of this worktree doesn't apply to others.
+    __directify_tree(&tree, &path.to_string_lossy(), None)?;
+
+    let mut entries = read_tree_from_repo(&tree, "", &common_dir)?.get_entries();
+    entries.sort_by_key(|e| e.get_path().to_string());
+    __write_index(entries, File::create(git_dir.join("index"))?)?;
+
+    let subject = LogCommit::read(&hash)?.subject().to_string();
+    Ok(format!(
+        "Preparing worktree ({header})\nHEAD is now at {} {subject}\n",
+        &hash[..7]
+    ))
+}
+
+// Returns true if any file of the worktree differs from it's index,
+// or isn't in it.
+fn is_dirty(worktree: &Worktree) -> io::Result<bool> {
+    let index = File::open(worktree.git_dir.join("index"))?;
+    let entries = __read_index(index)?;
+    let common_dir = common_dir();
+
+    let mut tracked = HashSet::new();
+    for entry in entries.iter().filter(|entry| !entry.is_gitlink()) {
+        let path = worktree.path.join(entry.get_path());
+        let data = match IndexEntry::read_content(&path.to_string_lossy()) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+
+        if __hash_object(&data, "blob", false, &common_dir)?.0 != entry.get_hash() {
+            return Ok(true);
+        }
+
+        tracked.insert(path);
+    }
+
+    // Submodules are skipped whole.
+    let mut stack = vec![worktree.path.clone()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.join(".git").exists() || path == worktree.path.join(".git") {
+                continue;
+            }
+
+            if path.is_dir() {
+                stack.push(path);
+            } else if !tracked.contains(&path) {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+fn remove(path: &str, force: bool) -> io::Result<String> {
+    let worktree = find_worktree(path)?;
+    if worktree.git_dir == absolute(Path::new(&common_dir()))? {
+        return Err(io_err!("The main working tree can't be removed"));
+    }
+
+    if !force && !worktree.prunable && is_dirty(&worktree)? {
+        return Err(io_err!(
+            "The worktree contains modified or untracked files, use force to remove it"
+        ));
+    }
+
+    if !worktree.prunable {
+        fs::remove_dir_all(&worktree.path)?;
+    }
+
+    fs::remove_dir_all(&worktree.git_dir)?;
+    Ok(String::new())
+}
+
+// Removes the git directories of worktrees that no longer exist.
+fn prune() -> io::Result<String> {
+    let mut ret = String::new();
+    for worktree in worktrees()?.into_iter().skip(1) {
+        if worktree.prunable {
+            fs::remove_dir_all(&worktree.git_dir)?;
+            let name = worktree.git_dir.file_name().unwrap_or_default();
+            ret.push_str(&format!(
+                "Removing worktrees/{}: gitdir file points to non-existent location\n",
+                name.to_string_lossy()
+            ));
+        }
+    }
+
+    Ok(ret)
+}
+
+// Formats the worktrees like `git worktree list`.
+fn format_worktrees(worktrees: &[Worktree]) -> String {
+    let width = worktrees
+        .iter()
+        .map(|worktree| worktree.path.to_string_lossy().len())
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    let mut ret = String::new();
+    for worktree in worktrees {
+        let hash = match &worktree.head {
+            Some(hash) => &hash[..7.min(hash.len())],
+            None => "0000000",
+        };
+        let branch = match &worktree.branch {
+            Some(branch) => format!("[{branch}]"),
+            None => "(detached HEAD)".to_string(),
+        };
+
+        let path = worktree.path.to_string_lossy();
+        ret.push_str(&format!("{path:<width$} {hash} {branch}"));
+        if worktree.prunable {
+            ret.push_str(" prunable");
+        }
+        ret.push('\n');
+    }
+
+    ret
+}
+
+/// Underlying implementation of `git worktree`. Linked worktrees keep
+/// their HEAD and index in `.git/worktrees/<name>`, sharing the objects
+/// and references of the repository.
+pub fn __worktree(cmd: WorktreeCommand) -> io::Result<String> {
+    match cmd {
+        WorktreeCommand::Add {
+            path,
+            commit,
+            branch,
+        } => add(&path, commit, branch),
+        WorktreeCommand::List => Ok(format_worktrees(&worktrees()?)),
+        WorktreeCommand::Remove { path, force } => remove(&path, force),
+        WorktreeCommand::Prune => prune(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn absolute_paths() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(absolute(Path::new("a/./b/../c")).unwrap(), cwd.join("a/c"));
+        assert_eq!(absolute(Path::new("/a/b/..")).unwrap(), PathBuf::from("/a"));
+    }
+
+    #[test]
+    fn list_format() {
+        let worktree = |path: &str, head: Option<&str>, branch: Option<&str>, prunable| Worktree {
+            path: PathBuf::from(path),
+            git_dir: PathBuf::new(),
+            head: head.map(String::from),
+            branch: branch.map(String::from),
+            prunable,
+        };
+
+        let worktrees = [
+            worktree("/repo", Some("125d322e512b"), Some("master"), false),
+            worktree("/hotfix-1", Some("fb80bb718b81"), None, false),
+            worktree("/gone", Some("125d322e512b"), Some("feature"), true),
+        ];
+        assert_eq!(
+            format_worktrees(&worktrees),
+            "/repo      125d322 [master]\n\
+             /hotfix-1  fb80bb7 (detached HEAD)\n\
+             /gone      125d322 [feature] prunable\n"
+        );
+    }
+}
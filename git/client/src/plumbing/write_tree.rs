@@ -1,4 +1,5 @@
 use std::io::{self, Write};
+use utils::common_dir;
 use utils::index_file::index_entry::IndexEntry;
 use utils::plumbing::hash_object::__hash_object;
 
@@ -46,7 +47,7 @@ pub fn __write_tree(entries: &mut [IndexEntry], save: bool) -> io::Result<Vec<u8
         }
     }
 
-    Ok(__hash_object(&tree_entries, "tree", save, ".git")?.0)
+    Ok(__hash_object(&tree_entries, "tree", save, &common_dir())?.0)
 }
 
 #[cfg(test)]
@@ -2,14 +2,19 @@
 //!
 //! Models the Git Client which is responsible for sending requests to the Git Server
 use super::plumbing::commit::get_parent_commits;
+use crate::config_file::git_config::ConfigSet;
 use crate::{io_err, DEFAULT_GIT_PORT};
 use std::{
-    fs,
-    io::{self, BufRead, BufReader, Read, Write},
+    collections::HashSet,
+    env,
+    io::{self, BufReader, ErrorKind, Read, Write},
     net::TcpStream,
+    process::{Child, Command, Stdio},
 };
 use utils::object::object_db::get_object;
 use utils::package::pack::Pack;
+use utils::plumbing::commit_graph::History;
+use utils::{common_dir, get_current_refs, pkt_line, read_pkt_line, read_pkt_section, PktLine};
 
 pub fn parse_url(url: &str) -> io::Result<(String, String)> {
     // git://127.0.0.1:8080/path/to/repo.git
@@ -33,49 +38,326 @@ pub fn parse_url(url: &str) -> io::Result<(String, String)> {
     }
 }
 
-pub fn connect_to_server(protocol: &str, link: &str, repo: &str) -> io::Result<TcpStream> {
+pub fn connect_to_server(
+    protocol: &str,
+    link: &str,
+    repo: &str,
+    version: u8,
+) -> io::Result<TcpStream> {
     let mut transmiter = TcpStream::connect(link)?;
 
     let mut send = protocol.to_string() + " " + repo + "\0";
+
+    // Extra parameters go after a second NUL, servers that
+    // don't know them ignore them.
+    if version == 2 {
+        send.push_str("\0version=2\0");
+    }
     send.insert_str(0, format!("{:04x}", send.len() + 4).as_str());
     transmiter.write_all(send.as_bytes())?;
 
     Ok(transmiter)
 }
 
+const EXT_PREFIX: &str = "ext::";
+
+/// Returns the command of an `ext::` url, None for other urls.
+pub fn ext_command(url: &str) -> Option<&str> {
+    url.strip_prefix(EXT_PREFIX)
+}
+
+/// Whether `ext::` urls may be used given the value of the
+/// `protocol.ext.allow` setting: `always`, `never`, which is the
+/// default, or `user`, only when they come from the user.
+pub fn ext_allowed(allow: Option<&str>, from_user: bool) -> bool {
+    match allow {
+        Some("always") => true,
+        Some("user") => from_user,
+        _ => false,
+    }
+}
+
+// Refuses `ext::` urls unless the configuration allows them. Urls
+// don't come from the user when GIT_PROTOCOL_FROM_USER is 0.
+fn check_ext_allowed() -> io::Result<()> {
+    let allow = ConfigSet::read(None)?.get("protocol.ext.allow")?;
+    let from_user = env::var("GIT_PROTOCOL_FROM_USER").map_or(true, |value| value != "0");
+    if !ext_allowed(allow.as_deref(), from_user) {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "transport 'ext' not allowed",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Builds the command of an `ext::` url for a service like
+/// git-upload-pack. `%S` is replaced by the service and `%s`
+/// by its name without the `git-` prefix.
+fn ext_args(command: &str, service: &str) -> Vec<String> {
+    let name = service.strip_prefix("git-").unwrap_or(service);
+    command
+        .split_whitespace()
+        .map(|arg| arg.replace("%S", service).replace("%s", name))
+        .collect()
+}
+
+/// A connection to a server: a TCP socket, or the standard input
+/// and output of a server process spawned for it.
+pub enum Connection {
+    Tcp(TcpStream),
+    Process(Child),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Process(child) => match child.stdout.as_mut() {
+                Some(stdout) => stdout.read(buf),
+                None => Ok(0),
+            },
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            Self::Process(child) => child
+                .stdin
+                .as_mut()
+                .ok_or(io_err!("Server input is closed"))?
+                .write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            Self::Process(child) => match child.stdin.as_mut() {
+                Some(stdin) => stdin.flush(),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+impl Drop for Connection {
+    // The server reads until its input is closed, and it's waited
+    // for so that it's done with the repository when we return.
+    fn drop(&mut self) {
+        if let Self::Process(child) = self {
+            drop(child.stdin.take());
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Connects to the server of the url for the given service, either
+/// `git-upload-pack` or `git-receive-pack`, asking for the protocol
+/// version. `ext::<command>` urls spawn the command and talk to it
+/// through its standard input and output, if `protocol.ext.allow`
+/// allows them. Others connect to a git daemon.
+pub fn connect(service: &str, url: &str, version: u8) -> io::Result<Connection> {
+    let command = match ext_command(url) {
+        Some(command) => {
+            check_ext_allowed()?;
+            ext_args(command, service)
+        }
+        None => {
+            let (link, repo) = parse_url(url)?;
+            let stream = connect_to_server(service, &link, &repo, version)?;
+            return Ok(Connection::Tcp(stream));
+        }
+    };
+
+    let (program, args) = command.split_first().ok_or(io_err!("Invalid url"))?;
+    let mut command = Command::new(program);
+    if version == 2 {
+        command.env("GIT_PROTOCOL", "version=2");
+    }
+
+    let child = command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    Ok(Connection::Process(child))
+}
+
+/// Fetches the objects of the references that are missing with
+/// protocol v0 want and have lines, returning the pack file. None
+/// if none of them is missing.
+pub fn fetch_pack_v0<T: Read + Write>(
+    transmiter: &mut T,
+    refs: &[(String, String)],
+) -> io::Result<Option<Vec<u8>>> {
+    // Send want lines.
+    let sent = send_want_lines(refs, &mut *transmiter)?;
+    transmiter.write_all(b"0000")?;
+    if sent == 0 {
+        return Ok(None);
+    }
+
+    // Send have lines and done.
+    send_have_lines(&mut *transmiter)?;
+    let done_line = to_pkt_line_format("done", "");
+    transmiter.write_all(done_line.as_bytes())?;
+
+    // Process ack lines.
+    process_ack_line(transmiter)?;
+    Ok(Some(get_response(transmiter)))
+}
+
+/// Returns true if the server answered with the capabilities of
+/// protocol v2 instead of advertising its references.
+pub fn is_v2(advertisement: &[u8]) -> bool {
+    advertisement
+        .get(4..)
+        .is_some_and(|line| line.starts_with(b"version 2\n"))
+}
+
+/// Lists the references of a protocol v2 server starting with the
+/// prefixes, as the v0 advertisement does: HEAD first as
+/// `HEAD:<branch>` and the objects tags point to as `<tag>^{}`.
+pub fn ls_refs<T: Read + Write>(
+    transmiter: &mut T,
+    prefixes: &[&str],
+) -> io::Result<Vec<(String, String)>> {
+    let mut request = pkt_line("command=ls-refs") + "0001" + &pkt_line("peel");
+    request.push_str(&pkt_line("symrefs"));
+    for prefix in prefixes {
+        request.push_str(&pkt_line(&format!("ref-prefix {prefix}")));
+    }
+    request.push_str("0000");
+    transmiter.write_all(request.as_bytes())?;
+
+    let (lines, _) = read_pkt_section(transmiter)?.ok_or(io_err!("No refs received"))?;
+    let mut refs = vec![];
+    for line in lines {
+        let mut fields = line.split(' ');
+        let (hash, mut name) = match (fields.next(), fields.next()) {
+            (Some(hash), Some(name)) => (hash.to_string(), name.to_string()),
+            _ => return Err(io_err!("Invalid ls-refs line")),
+        };
+
+        let mut peeled = None;
+        for attribute in fields {
+            if let Some(target) = attribute.strip_prefix("symref-target:") {
+                name = format!("{name}:{target}");
+            } else if let Some(hash) = attribute.strip_prefix("peeled:") {
+                peeled = Some((hash.to_string(), format!("{name}^{{}}")));
+            }
+        }
+
+        refs.push((hash, name));
+        refs.extend(peeled);
+    }
+
+    Ok(refs)
+}
+
+/// Fetches the objects of the references that are missing with a
+/// protocol v2 `fetch` command, returning the pack file. None if
+/// none of them is missing.
+pub fn fetch_pack<T: Read + Write>(
+    transmiter: &mut T,
+    refs: &[(String, String)],
+) -> io::Result<Option<Vec<u8>>> {
+    let mut wants = vec![];
+    for (hash, _) in refs {
+        if get_object(hash).is_err() && !wants.contains(hash) {
+            wants.push(hash.clone());
+        }
+    }
+
+    if wants.is_empty() {
+        return Ok(None);
+    }
+
+    let mut request = pkt_line("command=fetch") + "0001";
+    for want in &wants {
+        request.push_str(&pkt_line(&format!("want {want}")));
+    }
+    transmiter.write_all(request.as_bytes())?;
+    send_have_lines(&mut *transmiter)?;
+    transmiter.write_all((pkt_line("done") + "0000").as_bytes())?;
+
+    // The pack comes in band 1 of the packets after the `packfile`
+    // section header, progress in band 2 and errors in band 3.
+    let mut pack = vec![];
+    let mut in_pack = false;
+    loop {
+        match read_pkt_line(transmiter)? {
+            None | Some(PktLine::Flush) => break,
+            Some(PktLine::Delim) => {}
+            Some(PktLine::Data(data)) if in_pack => match data.split_first() {
+                Some((1, data)) => pack.extend(data),
+                Some((2, progress)) => eprint!("{}", String::from_utf8_lossy(progress)),
+                Some((3, error)) => return Err(io::Error::other(String::from_utf8_lossy(error))),
+                _ => return Err(io_err!("Invalid side-band packet")),
+            },
+            Some(PktLine::Data(data)) => in_pack = data == b"packfile\n",
+        }
+    }
+
+    Ok(Some(pack))
+}
+
 // Returns a have line in the format pkt.
 #[allow(dead_code)]
 fn have_line(obj_id: &str) -> String {
     to_pkt_line_format("have", obj_id)
 }
 
-/// Sends the hashes of the objects the client has in it's object database.
-#[allow(dead_code)]
-pub fn send_have_lines(mut transmiter: &TcpStream) -> io::Result<()> {
-    for entry in fs::read_dir(".git/objects")?.flatten() {
-        let dir_name = entry.file_name().to_string_lossy().to_string();
-
-        // If len == 2 => dir_name is a directory with objects.
-        if dir_name.len() == 2 {
-            let path = entry.path();
-            for entry in fs::read_dir(path)?.flatten() {
-                let file_name = entry.file_name().to_string_lossy().to_string();
-                let sha = format!("{dir_name}{file_name}");
-                transmiter.write_all(have_line(&sha).as_bytes())?;
-            }
+// Returns the commits the client has: the ones it's references
+// point to and their ancestors.
+fn have_commits() -> io::Result<Vec<String>> {
+    let history = History::with_offset(&common_dir());
+    let mut pending: Vec<_> = get_current_refs()?.into_values().collect();
+    let mut seen = HashSet::new();
+    let mut haves = vec![];
+
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        // References to tags or missing objects are skipped.
+        if let Ok(commit) = history.commit(&hash) {
+            pending.extend(commit.parents);
+            haves.push(hash);
         }
     }
 
+    Ok(haves)
+}
+
+/// Sends the hashes of the commits the client has, so the
+/// server leaves what they reach out of the pack file.
+#[allow(dead_code)]
+pub fn send_have_lines<W: Write>(mut transmiter: W) -> io::Result<()> {
+    for hash in have_commits()? {
+        transmiter.write_all(have_line(&hash).as_bytes())?;
+    }
+
     Ok(())
 }
 
-pub fn send_want_lines(refs: &[(String, String)], transmiter: &TcpStream) -> io::Result<usize> {
+pub fn send_want_lines<W: Write>(
+    refs: &[(String, String)],
+    mut transmiter: W,
+) -> io::Result<usize> {
     let mut i = 0;
     for reference in refs {
         if get_object(&reference.0).is_err() {
             match i {
-                0 => send_first_want_line(&reference.0, transmiter)?,
-                _ => send_want_line(&reference.0, transmiter)?,
+                0 => send_first_want_line(&reference.0, &mut transmiter)?,
+                _ => send_want_line(&reference.0, &mut transmiter)?,
             }
 
             i += 1;
@@ -208,35 +490,13 @@ pub fn parse_references(refs: Vec<u8>) -> io::Result<Vec<(String, String)>> {
     Ok(want_refs)
 }
 
-pub fn get_current_refs(path: &str) -> io::Result<Vec<(String, String)>> {
-    let entries = fs::read_dir(path)?;
-    let mut refs = vec![];
-
-    for entry in entries.flatten() {
-        let file_name_os_str = entry.file_name();
-        let file_name = match file_name_os_str.to_str() {
-            None => return Err(io_err!("Invalid file name")),
-            Some(name) => name,
-        };
-
-        let file_path = format!("{path}/{file_name}");
-        if entry.file_type()?.is_dir() {
-            refs.append(&mut get_current_refs(&file_path)?);
-        } else {
-            let content = fs::read_to_string(entry.path())?.trim().to_string();
-            if let Some(stripped) = file_path.strip_prefix(".git/") {
-                refs.push((content, stripped.to_string()));
-            }
-        }
-    }
-
-    Ok(refs)
-}
-
 /// Returns a Vec of tuples (HASH, REFERENCE) of the current
 /// references in the repository.
 pub fn get_refs_vec() -> io::Result<Vec<(String, String)>> {
-    let mut refs = get_current_refs(".git/refs")?;
+    let mut refs: Vec<_> = get_current_refs()?
+        .into_iter()
+        .map(|(name, hash)| (hash, name))
+        .collect();
     refs.sort_by(|a, b| a.1.cmp(&b.1));
     Ok(refs)
 }
@@ -254,15 +514,19 @@ pub fn get_refs_fmt() -> io::Result<String> {
     Ok(ret)
 }
 
-/// Processes the ACK lines in the server response.
-pub fn process_ack_line(transmiter: &mut TcpStream) -> io::Result<String> {
-    let mut reader = BufReader::new(transmiter);
+/// Processes the ACK lines in the server response. Only the line is
+/// read, the pack file that follows is left in the transmiter.
+pub fn process_ack_line<R: Read>(transmiter: &mut R) -> io::Result<String> {
+    let mut size = [0; 4];
+    transmiter.read_exact(&mut size)?;
+    let size = usize::from_str_radix(&String::from_utf8_lossy(&size), 16)
+        .map_err(|_| io_err!("Invalid pkt-line size"))?;
 
     // Read first ACK.
-    let mut line = String::new();
-    reader.read_line(&mut line)?;
+    let mut line = vec![0; size.saturating_sub(4)];
+    transmiter.read_exact(&mut line)?;
 
-    Ok(line)
+    Ok(String::from_utf8_lossy(&line).to_string())
 }
 
 /// Process pack file sent by server
@@ -285,7 +549,7 @@ pub fn to_pkt_line_format(request_command: &str, content: &str) -> String {
 }
 
 /// Send the first line of references HEAD, write the capabilities
-fn send_first_want_line(first_reference: &str, mut transmiter: &TcpStream) -> io::Result<()> {
+fn send_first_want_line<W: Write>(first_reference: &str, mut transmiter: W) -> io::Result<()> {
     let content = format!("{first_reference}\0\n"); // Capabilities.
     let first_want = to_pkt_line_format("want", &content);
     transmiter.write_all(first_want.as_bytes())?;
@@ -293,7 +557,7 @@ fn send_first_want_line(first_reference: &str, mut transmiter: &TcpStream) -> io
 }
 
 // Send a want line
-fn send_want_line(obj_id: &str, mut transmiter: &TcpStream) -> io::Result<()> {
+fn send_want_line<W: Write>(obj_id: &str, mut transmiter: W) -> io::Result<()> {
     let content = format!("{}\n", obj_id);
     let want_line = to_pkt_line_format("want", &content);
     transmiter.write_all(want_line.as_bytes())?;
@@ -322,3 +586,81 @@ pub fn get_response<R: Read>(transmiter: &mut R) -> Vec<u8> {
 
     references
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ext_urls() {
+        let command = ext_command("ext::server %s /srv/repo").unwrap();
+        assert_eq!(
+            ext_args(command, "git-upload-pack"),
+            ["server", "upload-pack", "/srv/repo"]
+        );
+        assert_eq!(
+            ext_args("ssh host %S", "git-receive-pack")[2],
+            "git-receive-pack"
+        );
+        assert_eq!(ext_command("git://127.0.0.1/repo.git"), None);
+    }
+
+    #[test]
+    fn ext_policy() {
+        assert!(!ext_allowed(None, true));
+        assert!(!ext_allowed(Some("never"), true));
+        assert!(ext_allowed(Some("always"), false));
+        assert!(ext_allowed(Some("user"), true));
+        assert!(!ext_allowed(Some("user"), false));
+    }
+
+    // A server that answers with the given bytes.
+    struct Mock<'a> {
+        response: &'a [u8],
+        request: Vec<u8>,
+    }
+
+    impl Read for Mock<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Mock<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.request.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ls_refs_v2() {
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+        let response = pkt_line(&format!("{a} HEAD symref-target:refs/heads/main"))
+            + &pkt_line(&format!("{a} refs/heads/main"))
+            + &pkt_line(&format!("{b} refs/tags/v1 peeled:{a}"))
+            + "0000";
+        let mut mock = Mock {
+            response: response.as_bytes(),
+            request: vec![],
+        };
+
+        let refs = ls_refs(&mut mock, &["refs/tags/"]).unwrap();
+        assert_eq!(
+            refs,
+            [
+                (a.clone(), "HEAD:refs/heads/main".to_string()),
+                (a.clone(), "refs/heads/main".to_string()),
+                (b, "refs/tags/v1".to_string()),
+                (a, "refs/tags/v1^{}".to_string()),
+            ]
+        );
+
+        let request = String::from_utf8(mock.request).unwrap();
+        assert!(request.starts_with("0014command=ls-refs\n0001"));
+        assert!(request.ends_with("ref-prefix refs/tags/\n0000"));
+    }
+}
@@ -0,0 +1,62 @@
+use super::super::commands::*;
+use crate::logging::log_command;
+use crate::logging::LogMsgStatus;
+use crate::ui::history_listbox::parse_file_row;
+use crate::ui::principal_window::GitApp;
+use gtk::prelude::*;
+use gtk::{ListBox, TextView};
+use std::io;
+use std::rc::Weak;
+use utils::log_err;
+use utils::log_ok;
+
+pub fn connect_blame_button(git_app: &Weak<GitApp>) {
+    if let Some(strong_ref) = git_app.upgrade() {
+        let file_listbox = strong_ref.file_listbox.clone();
+        let file_text_view = strong_ref.file_text_view.clone();
+
+        strong_ref.blame_button.connect_clicked(move |_| {
+            if let Ok(()) = blame_button_handler(file_listbox.clone(), file_text_view.clone()) {
+            } else {
+                println!("Error al conectar botón de blame");
+            }
+        });
+    } else {
+        println!("Error al hacer upgrade");
+    }
+}
+
+/// Shows the blame of the file selected in the file listbox,
+/// at the commit it was selected from.
+pub fn blame_button_handler(file_listbox: ListBox, file_text_view: TextView) -> io::Result<()> {
+    let label = file_listbox
+        .selected_rows()
+        .first()
+        .and_then(|row| row.child())
+        .and_then(|label| label.downcast::<gtk::Label>().ok());
+
+    if let Some(label) = label {
+        let (path_name, hash) = parse_file_row(label.text().as_str());
+        let opts = BlameOptions {
+            rev: Some(hash),
+            ..Default::default()
+        };
+
+        match blame(&path_name, opts) {
+            Ok(text) => {
+                log_ok!("blame");
+                if let Some(buffer) = file_text_view.buffer() {
+                    buffer.set_text(&text);
+                }
+            }
+            Err(err) => {
+                log_err!("blame", err);
+                eprintln!("Error al ejecutar blame: {}", err);
+            }
+        };
+    } else {
+        println!("No hay ningún archivo seleccionado");
+    }
+
+    Ok(())
+}
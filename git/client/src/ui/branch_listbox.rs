@@ -1,11 +1,10 @@
-use crate::plumbing::log::__log;
+use crate::plumbing::log::{__log, format_commit, LogFormat, LogOptions};
 use crate::plumbing::refs::{get_branch_ref, get_local_branches};
 use crate::ui::history_listbox::set_list_box_row;
 use crate::ui::principal_window::GitApp;
 use gtk::glib::Cast;
 use gtk::prelude::{BinExt, ContainerExt, LabelExt, ListBoxExt, WidgetExt};
 use gtk::{ListBox, ListBoxRow};
-use std::collections::HashSet;
 use std::io;
 use std::rc::Weak;
 
@@ -37,7 +36,16 @@ pub fn add_new_branch(branches_listbox: ListBox, branch_name: String) -> io::Res
 // get specified branch commit history using log command
 fn get_commit_history(branch_name: String) -> io::Result<Vec<String>> {
     let hash_commit = get_branch_ref(&branch_name)?;
-    __log(&hash_commit, &mut HashSet::new())
+    let opts = LogOptions {
+        revs: vec![hash_commit],
+        ..Default::default()
+    };
+
+    let commits = __log(&opts)?;
+    Ok(commits
+        .iter()
+        .map(|commit| format_commit(commit, &LogFormat::Medium))
+        .collect())
 }
 
 // connect branches listbox
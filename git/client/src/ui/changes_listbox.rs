@@ -79,6 +79,11 @@ fn remove_status(input: &str) -> &str {
             Some(s) => s,
             None => input,
         }
+    } else if input.starts_with("Mode changed: ") {
+        match input.strip_prefix("Mode changed: ") {
+            Some(s) => s,
+            None => input,
+        }
     } else {
         input
     }
@@ -90,5 +95,6 @@ pub fn file_status_to_string(file_status: &FileStatus) -> String {
         FileStatus::Staged(filename) => format!("Staged: {}", filename),
         FileStatus::Modified(filename) => format!("Modified: {}", filename),
         FileStatus::Deleted(filename) => format!("Deleted: {}", filename),
+        FileStatus::ModeChanged(filename) => format!("Mode changed: {}", filename),
     }
 }
@@ -3,7 +3,7 @@ use crate::plumbing::diff::diff_commit::get_patch_of_tree_diffs;
 use crate::plumbing::diff::diff_commit::{diff_commit, differences_beetween_files, Patch};
 use crate::plumbing::diff::diff_tree;
 use crate::plumbing::heads::__get_head_commit;
-use crate::plumbing::log::__log;
+use crate::plumbing::log::{__log, format_commit, LogFormat, LogOptions};
 use crate::ui::history_listbox::diff_tree::diff_tree;
 use crate::ui::principal_window::GitApp;
 use gtk::glib::Cast;
@@ -11,10 +11,10 @@ use gtk::prelude::TextViewExt;
 use gtk::prelude::{BinExt, ContainerExt, LabelExt, ListBoxExt, WidgetExt};
 use gtk::traits::TextBufferExt;
 use gtk::{ListBox, ListBoxRow, TextView};
-use std::collections::HashSet;
 use std::fs::File;
 use std::io;
 use std::rc::Weak;
+use utils::git_dir;
 use utils::object::object_db::get_object;
 use utils::plumbing::commit::get_commit_root;
 use utils::plumbing::ls_tree::ls_tree;
@@ -48,9 +48,18 @@ pub fn set_list_box_row(history_listbox: &ListBox, line: &str) {
 // get head commit history using log command
 fn get_head_commit_history() -> io::Result<Vec<String>> {
     // En realidad, se debería mostrar por rama, pero por ahora se deja solo la actual.
-    let file = File::open(".git/HEAD")?;
+    let file = File::open(format!("{}/HEAD", git_dir()))?;
     let hash_commit = __get_head_commit(file)?;
-    __log(&hash_commit, &mut HashSet::new())
+    let opts = LogOptions {
+        revs: vec![hash_commit],
+        ..Default::default()
+    };
+
+    let commits = __log(&opts)?;
+    Ok(commits
+        .iter()
+        .map(|commit| format_commit(commit, &LogFormat::Medium))
+        .collect())
 }
 
 pub fn connect_history_listbox(git_app: &Weak<GitApp>) {
@@ -94,18 +103,11 @@ pub fn file_listbox_handler(row: Option<&ListBoxRow>, file_text_view: TextView)
         {
             let label_file_text = label.text().as_str().to_string();
             println!("Archivo seleccionado: {}", label_file_text);
-            let hash = label_file_text.split('|').collect::<Vec<&str>>()[1];
-            let mut path_name = label_file_text.split('|').collect::<Vec<&str>>()[0];
-
-            path_name = if path_name.split_whitespace().collect::<Vec<&str>>().len() > 1 {
-                path_name.split_whitespace().collect::<Vec<&str>>()[1]
-            } else {
-                path_name
-            };
+            let (path_name, hash) = parse_file_row(&label_file_text);
             println!("path name: {}", path_name);
-            let patches = get_patches(hash.trim())?;
+            let patches = get_patches(&hash)?;
             // find exact patch related to the name file
-            let patch = patches.iter().find(|patch| patch.path == path_name.trim());
+            let patch = patches.iter().find(|patch| patch.path == path_name);
             if let Some(patch) = patch {
                 let text = differences_beetween_files(patch);
                 if let Some(buffer) = file_text_view.buffer() {
@@ -119,6 +121,18 @@ pub fn file_listbox_handler(row: Option<&ListBoxRow>, file_text_view: TextView)
     Ok(())
 }
 
+/// Parses a row of the file listbox, "<status> <path> | <hash>",
+/// returning the path of the file and the hash of the commit.
+pub fn parse_file_row(row: &str) -> (String, String) {
+    let (path_name, hash) = row.split_once('|').unwrap_or((row, ""));
+    let path_name = match path_name.split_whitespace().collect::<Vec<&str>>()[..] {
+        [_, path, ..] => path,
+        _ => path_name,
+    };
+
+    (path_name.trim().to_string(), hash.trim().to_string())
+}
+
 ///Based on commit returns vector of patches that represent the difference between the commit and one of its parents
 fn get_patches(hash: &str) -> io::Result<Vec<Patch>> {
     let hash = get_hash(hash);
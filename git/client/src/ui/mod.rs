@@ -1,5 +1,6 @@
 mod add_button;
 mod application_window;
+mod blame_button;
 mod branch_button;
 mod branch_listbox;
 mod cat_file_button;
@@ -1,7 +1,7 @@
 use crate::plumbing::heads::get_head_name;
 use crate::ui::current_branch_text_view::show_current_branch;
 use crate::ui::{
-    add_button, application_window, branch_button, branch_listbox, cat_file_button,
+    add_button, application_window, blame_button, branch_button, branch_listbox, cat_file_button,
     changes_listbox, checkout_button, clone_button, commit_button, commit_message_entry,
     fetch_button, history_listbox, init_button, ls_tree_button, merge_button, pull_button,
     push_button, rebase_button, remote_button, remove_button, status_button, tag_annotated_button,
@@ -43,6 +43,7 @@ pub struct GitApp {
     pub(crate) branches_listbox: gtk::ListBox,
     pub(crate) rebase_button: gtk::MenuItem,
     pub(crate) file_listbox: gtk::ListBox,
+    pub(crate) blame_button: gtk::Button,
     pub(crate) tag_annotated_button: gtk::MenuItem,
     pub(crate) tag_button: gtk::MenuItem,
     pub(crate) terminal_text_view: gtk::TextView,
@@ -131,6 +132,9 @@ impl GitApp {
         // Files ListBox
         let file_listbox: gtk::ListBox = builder.object("file_listbox")?;
 
+        // Blame Button
+        let blame_button: gtk::Button = builder.object("blame_button")?;
+
         // Rebase Button
         let rebase_button: gtk::MenuItem = builder.object("menu_button_rebase")?;
 
@@ -171,6 +175,7 @@ impl GitApp {
             branches_listbox,
             rebase_button,
             file_listbox,
+            blame_button,
             tag_button,
             tag_annotated_button,
             terminal_text_view,
@@ -254,6 +259,9 @@ impl GitApp {
 
         history_listbox::connect_file_list_box(&weak_ref);
 
+        // Blame Button handler
+        blame_button::connect_blame_button(&weak_ref);
+
         // Setup application main loop
         let principal_window_clone = principal_window.clone();
         rx.attach(None, move |event| {
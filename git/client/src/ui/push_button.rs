@@ -23,18 +23,22 @@ pub fn push_button_handler(push_entry: Entry) {
 
     let entries: Vec<&str> = entry.split_whitespace().collect();
 
-    let action = if entry.is_empty() {
-        PushCommand::Push
-    } else {
-        if entries.len() != 2 {
+    // `remote branch` sets the upstream, `remote refspec...` pushes
+    // the refspecs, like `origin feature:main` or `origin :old`.
+    let action = match entries.as_slice() {
+        [] => PushCommand::Push,
+        [remote, branch] if !branch.contains(':') => PushCommand::SetUpstream {
+            remote: remote.to_string(),
+            branch: branch.to_string(),
+        },
+        [remote, refspecs @ ..] if !refspecs.is_empty() => PushCommand::Refspecs {
+            remote: remote.to_string(),
+            refspecs: refspecs.iter().map(|spec| spec.to_string()).collect(),
+        },
+        _ => {
             println!("Cantidad erronea de parametros");
             return;
         }
-
-        PushCommand::SetUpstream {
-            remote: entries[0].to_string(),
-            branch: entries[1].to_string(),
-        }
     };
 
     match push(action) {
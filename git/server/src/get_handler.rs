@@ -1,4 +1,5 @@
 use crate::{commit::Commit, pullrequest_controller};
+use utils::plumbing::fsck::fsck_from_repo;
 
 /// Handles GET requests from client
 pub fn handle_get(path: &str) -> String {
@@ -13,8 +14,11 @@ pub fn handle_get(path: &str) -> String {
 /// * List pull requests: GET /repos/{repo}/pulls
 /// * List commits en un pull request: GET /repos/{repo}/pulls/{pull_number}/commits
 /// * Obtain a pull request: GET /repos/{repo}/pulls/{pulls_number}
+/// * Check the repository's integrity: GET /repos/{repo}/fsck
 fn handle_get_request(path: &str) -> String {
-    if path.ends_with("/pulls") {
+    if path.ends_with("/fsck") {
+        fsck_request(path)
+    } else if path.ends_with("/pulls") {
         pullrequest_controller::list_pull_requests(path)
     } else if path.ends_with("/commits") {
         list_commits_request(path)
@@ -60,6 +64,26 @@ fn list_commits_request(path: &str) -> String {
     "404 Not Found\r\n\r\n".to_string()
 }
 
+// Return a HTTP response with the problems found in the repository.
+fn fsck_request(path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').collect();
+
+    if parts.len() == 4 && !parts[2].is_empty() {
+        let repo = parts[2].to_string() + ".git";
+        let problems = match fsck_from_repo(&repo) {
+            Ok(problems) => problems,
+            Err(_) => return "500 Error at fsck\r\n\r\n".to_string(),
+        };
+
+        return match serde_json::to_string(&problems) {
+            Ok(json) => format!("200 OK\r\nContent-Type: application/json\r\n\r\n{json}"),
+            Err(_) => "500 Error at fsck\r\n\r\n".to_string(),
+        };
+    }
+
+    "404 Not Found\r\n\r\n".to_string()
+}
+
 /// Get number of pull request and call a fucntion to send response with
 /// information about that pull request.
 /// GET /repos/{repo}/pulls/{pull_number}
@@ -1,10 +1,10 @@
 use server::config::ServerConfig;
-use server::server::Server;
+use server::server::{Server, Stdio};
 use server::serverhttp::ServerHttp;
 use server::{handle_error, ServerError};
 use std::str::FromStr;
 use std::thread;
-use std::{env, fs};
+use std::{env, fs, path::Path};
 const DEFAULT_GIT_PORT: u32 = 9418; //from Daemon documentation
 const DEFAULT_HTTP_PORT: u32 = 8080;
 
@@ -19,6 +19,31 @@ fn get_http_port() -> u32 {
     DEFAULT_HTTP_PORT
 }
 
+// Runs upload-pack or receive-pack for the repository at the path over
+// stdin and stdout, for clients that spawn the server themselves.
+fn serve_stdio(service: &str, repo: Option<String>) -> Result<(), ServerError> {
+    let repo = match repo {
+        Some(repo) => repo,
+        None => return Err(format!("usage: server {service} <repo>").into()),
+    };
+
+    // Repositories with a working directory are served from their .git.
+    let git_dir = Path::new(&repo).join(".git");
+    let repo = match git_dir.is_dir() {
+        true => git_dir.to_string_lossy().to_string(),
+        false => repo,
+    };
+
+    // Clients ask for protocol v2 through the environment.
+    let version = match env::var("GIT_PROTOCOL") {
+        Ok(protocol) if protocol.split(':').any(|field| field == "version=2") => 2,
+        _ => 0,
+    };
+
+    Server::serve(Stdio::new(), &format!("git-{service}"), &repo, version)?;
+    Ok(())
+}
+
 // Binding with a port number of 0 will request that
 // the OS assigns a port to this listener. The port
 // allocated can be queried via the TcpListener::local_addr method.
@@ -34,9 +59,12 @@ fn main() -> Result<(), ServerError> {
         }
     };
 
-    // Get port.
+    // Get port, or the service to run over stdin and stdout.
     let port = match args.next() {
         None => DEFAULT_GIT_PORT.to_string(),
+        Some(service) if service == "upload-pack" || service == "receive-pack" => {
+            return serve_stdio(&service, args.next());
+        }
         Some(port) => {
             if port.parse::<u32>().is_err() {
                 eprintln!("Invalid port number");
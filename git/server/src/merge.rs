@@ -191,6 +191,43 @@ fn refactor_dir(
                     }
                 }
 
+                // Gitlinks can't be merged, they only
+                // follow the side that moved them.
+                (Unchanged, Unchanged, "commit") => {
+                    index.add_gitlink(path, hash1, false)?;
+                }
+
+                (Unchanged, Modified(line), "commit") | (Modified(line), Unchanged, "commit") => {
+                    let (_, _, hash, _) = parse_ls_tree_entry(&line);
+                    index.add_gitlink(path, hash, false)?;
+                }
+
+                (Modified(line1), Modified(line2), "commit") => {
+                    let (_, _, hash1, _) = parse_ls_tree_entry(&line1);
+                    let (_, _, hash2, _) = parse_ls_tree_entry(&line2);
+                    if hash1 != hash2 {
+                        return Err(io_err!("Conflicting submodule changes."));
+                    }
+
+                    index.add_gitlink(path, hash1, false)?;
+                }
+
+                (Added, Added, "commit") => {
+                    if hash1 != hash2 {
+                        return Err(io_err!("Conflicting submodule changes."));
+                    }
+
+                    index.add_gitlink(path, hash1, false)?;
+                }
+
+                (Modified(_), Removed, "commit") | (Removed, Modified(_), "commit") => {
+                    return Err(io_err!("Conflicting submodule changes."));
+                }
+
+                // Removed on one side and left
+                // alone (or removed) on the other.
+                (Removed, Unchanged | Removed, "commit") | (Unchanged, Removed, "commit") => {}
+
                 (Unchanged, Unchanged, "tree") => {
                     let index2 = read_tree_from_repo(hash1, &path, repo)?;
                     index2.merge(index);
@@ -233,6 +270,8 @@ fn refactor_dir(
                     index2.merge(index);
                 }
 
+                "commit" => index.add_gitlink(path, hash, false)?,
+
                 _ => return Err(io_err!("Invalid object type.")),
             }
         }
@@ -261,6 +300,8 @@ fn refactor_dir(
                 index2.merge(index);
             }
 
+            "commit" => index.add_gitlink(path, hash, false)?,
+
             _ => return Err(io_err!("Invalid object type.")),
         }
     }
@@ -307,7 +348,7 @@ pub fn merge_pr(repo: &str, base: &str, head: &str) -> io::Result<String> {
         // Get base and head commits.
         let (_, _, base_data) = get_object_from_repo(&base_hash, repo)?;
         let (_, _, head_data) = get_object_from_repo(&head_hash, repo)?;
-        let ancestor = get_ancestor_from_repo(&base_data, &head_data, repo)?;
+        let ancestor = get_ancestor_from_repo(&base_hash, &head_hash, repo)?;
         let (_, _, ancestor_data) = get_object_from_repo(&ancestor, repo)?;
 
         // Get trees' commit hashes.
@@ -337,3 +378,63 @@ pub fn merge_pr(repo: &str, base: &str, head: &str) -> io::Result<String> {
         Ok(commit)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "1111111111111111111111111111111111111111";
+    const B: &str = "2222222222222222222222222222222222222222";
+    const C: &str = "3333333333333333333333333333333333333333";
+
+    fn tree(hash: Option<&str>) -> String {
+        hash.map(|hash| format!("160000 commit {hash}\tsub\n"))
+            .unwrap_or_default()
+    }
+
+    fn merge(
+        ancestor: Option<&str>,
+        base: Option<&str>,
+        head: Option<&str>,
+    ) -> io::Result<Vec<(String, String)>> {
+        let diffs1 = diff_2_map!(diff_tree(&tree(ancestor), &tree(base)));
+        let diffs2 = diff_2_map!(diff_tree(&tree(ancestor), &tree(head)));
+
+        let mut index = Index::new();
+        refactor_dir(diffs1, diffs2, String::new(), &mut index, "repo.git")?;
+        Ok(index
+            .get_entries()
+            .iter()
+            .map(|entry| (entry.get_path().to_string(), hash_to_str(entry.get_hash())))
+            .collect())
+    }
+
+    #[test]
+    fn gitlinks() {
+        let sub = |hash: &str| vec![("sub".to_string(), hash.to_string())];
+
+        // Only one side moved the gitlink.
+        assert_eq!(merge(Some(A), Some(A), Some(A)).unwrap(), sub(A));
+        assert_eq!(merge(Some(A), Some(B), Some(A)).unwrap(), sub(B));
+        assert_eq!(merge(Some(A), Some(A), Some(B)).unwrap(), sub(B));
+
+        // Both sides agree.
+        assert_eq!(merge(Some(A), Some(B), Some(B)).unwrap(), sub(B));
+        assert_eq!(merge(None, Some(B), Some(B)).unwrap(), sub(B));
+        assert_eq!(merge(None, Some(B), None).unwrap(), sub(B));
+        assert_eq!(merge(None, None, Some(B)).unwrap(), sub(B));
+
+        // Removals.
+        assert_eq!(merge(Some(A), None, Some(A)).unwrap(), vec![]);
+        assert_eq!(merge(Some(A), Some(A), None).unwrap(), vec![]);
+        assert_eq!(merge(Some(A), None, None).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn gitlink_conflicts() {
+        assert!(merge(Some(A), Some(B), Some(C)).is_err());
+        assert!(merge(None, Some(B), Some(C)).is_err());
+        assert!(merge(Some(A), Some(B), None).is_err());
+        assert!(merge(Some(A), None, Some(C)).is_err());
+    }
+}
@@ -134,6 +134,18 @@ fn check_conflicts(
                     }
                 }
 
+                // Gitlinks moved to different
+                // commits can't be merged.
+                (Modified(line1), Modified(line2), "commit")
+                    if parse_ls_tree_entry(&line1).2 != parse_ls_tree_entry(&line2).2 =>
+                {
+                    return Err(io_err!("Conflict"));
+                }
+
+                (Added, Added, "commit") if hash1 != hash2 => {
+                    return Err(io_err!("Conflict"));
+                }
+
                 (Modified(line1), Modified(line2), "tree") => {
                     // Get the ancestor's state of the tree.
                     let ancestor_tree = ls_tree_from_repo(hash1, repo)?;
@@ -181,7 +193,7 @@ pub fn validate_merge(repo: &str, base_hash: &str, head_hash: &str) -> io::Resul
     // Get the commits' data.
     let (_, _, base_data) = get_object_from_repo(base_hash, repo)?;
     let (_, _, head_data) = get_object_from_repo(head_hash, repo)?;
-    let ancestor = get_ancestor_from_repo(&base_data, &head_data, repo)?;
+    let ancestor = get_ancestor_from_repo(base_hash, head_hash, repo)?;
     let (_, _, ancestor_data) = get_object_from_repo(&ancestor, repo)?;
 
     // Get their tree roots.
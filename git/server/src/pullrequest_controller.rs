@@ -1,5 +1,5 @@
 use utils::{
-    get_ancestor_from_repo, get_branch_from_repo, io_err, object::object_db::get_object_from_repo,
+    get_ancestor_from_repo, get_branch_from_repo, io_err,
     plumbing::commit::get_commits_until_from_repo,
 };
 
@@ -116,24 +116,7 @@ pub fn get_commits(pull_number: usize, repo: &str) -> io::Result<Vec<String>> {
         Ok(hash_head) => hash_head,
     };
 
-    // Get data from base and head commits.
-    let (_, _, base_data) = match get_object_from_repo(&hash_base, repo) {
-        Err(e) => {
-            println!("{:?}", e);
-            return Err(io_err!("Error getting base data"));
-        }
-        Ok(data) => data,
-    };
-
-    let (_, _, head_data) = match get_object_from_repo(&hash_head, repo) {
-        Err(e) => {
-            println!("{:?}", e);
-            return Err(io_err!("Error getting head data"));
-        }
-        Ok(data) => data,
-    };
-
-    let ancestor = get_ancestor_from_repo(&base_data, &head_data, repo)?;
+    let ancestor = get_ancestor_from_repo(&hash_base, &hash_head, repo)?;
     let commits = get_commits_until_from_repo(&hash_head, &ancestor, repo)?;
     Ok(commits)
 }
@@ -18,6 +18,18 @@ use utils::*;
 pub const THREAD_POOL_SIZE: usize = 10;
 const ZERO_ID: &str = "0000000000000000000000000000000000000000";
 
+// What upload-pack supports when speaking protocol v2.
+const V2_CAPABILITIES: [&str; 5] = [
+    "version 2",
+    "agent=rusty-git",
+    "ls-refs",
+    "fetch",
+    "object-format=sha1",
+];
+
+// Largest data in a side-band-64k packet, after its length and band.
+const SIDEBAND_MAX: usize = 65515;
+
 fn log_cmd(reader: mpsc::Receiver<String>) -> io::Result<()> {
     let mut file = OpenOptions::new()
         .append(true)
@@ -49,6 +61,47 @@ fn log_connection(execution_status: LogMsgStatus, sender: Arc<Mutex<Sender<Strin
         let _ = guard.send(log_msg);
     }
 }
+/// The standard input and output as a single transmiter, for a
+/// server spawned by the client. Writes are flushed right away, the
+/// client waits for every line before answering.
+pub struct Stdio {
+    stdin: io::Stdin,
+    stdout: io::Stdout,
+}
+
+impl Stdio {
+    pub fn new() -> Self {
+        Self {
+            stdin: io::stdin(),
+            stdout: io::stdout(),
+        }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Read for Stdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdin.read(buf)
+    }
+}
+
+impl Write for Stdio {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.stdout.write(buf)?;
+        self.stdout.flush()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
 // Initial Server struct implementation.
 pub struct Server {
     address: String,
@@ -92,25 +145,46 @@ impl Server {
                 repo = ".git";
             }
 
-            // Execute request.
-            let res: Result<String, ServerError> = match protocol {
-                "git-upload-pack" => match Self::upload_pack(transmiter, repo) {
-                    Ok(()) => Ok(format!("{}   {}", "git-upload-pack", repo)),
-                    Err(serv_err) => Err(serv_err),
-                },
-                "git-receive-pack" => match Self::receive_pack(transmiter, repo) {
-                    Ok(()) => Ok(format!("{}   {}", "git-receive-pack", repo)),
-                    Err(serv_err) => Err(serv_err),
-                },
-                _ => Err(server_err!("Invalid protocol type")),
+            // Protocol v2 is requested in the extra parameters.
+            let version = match response.split('\0').any(|field| field == "version=2") {
+                true => 2,
+                false => 0,
             };
 
-            res
+            // Execute request.
+            Self::serve(transmiter, protocol, repo, version)
         } else {
             Err(server_err!("Invalid request"))
         }
     }
 
+    /// Runs git-upload-pack or git-receive-pack for the repository
+    /// over the given transmiter, a socket or the standard input and
+    /// output of a process. Upload-pack speaks protocol v2 if the
+    /// client asked for it, receive-pack always uses v0.
+    pub fn serve<T: Write + Read>(
+        transmiter: T,
+        protocol: &str,
+        repo: &str,
+        version: u8,
+    ) -> Result<String, ServerError> {
+        match protocol {
+            "git-upload-pack" if version == 2 => match Self::upload_pack_v2(transmiter, repo) {
+                Ok(()) => Ok(format!("{}   {}", "git-upload-pack v2", repo)),
+                Err(serv_err) => Err(serv_err),
+            },
+            "git-upload-pack" => match Self::upload_pack(transmiter, repo) {
+                Ok(()) => Ok(format!("{}   {}", "git-upload-pack", repo)),
+                Err(serv_err) => Err(serv_err),
+            },
+            "git-receive-pack" => match Self::receive_pack(transmiter, repo) {
+                Ok(()) => Ok(format!("{}   {}", "git-receive-pack", repo)),
+                Err(serv_err) => Err(serv_err),
+            },
+            _ => Err(server_err!("Invalid protocol type")),
+        }
+    }
+
     /// Binds to self's address and listens for requests.
     ///
     /// Upon success listens to the port waiting for a client to stablish a connection.
@@ -184,6 +258,16 @@ impl Server {
         )?;
 
         let mut sv_refs = Vec::from_iter(refs);
+
+        // Notes are optional, the directory may not exist.
+        sv_refs.extend(
+            get_refs_from_with_prefix(
+                &(repo.to_string() + "/refs/notes"),
+                &(repo.to_string() + "/"),
+            )
+            .unwrap_or_default(),
+        );
+
         let tags = get_refs_from_with_prefix(
             &(repo.to_string() + "/refs/tags"),
             &(repo.to_string() + "/"),
@@ -234,22 +318,156 @@ impl Server {
         }
 
         let have_lines = get_have_lines(&mut transmiter)?;
-        println!("want_lines: {want_lines:?}");
-        println!("have_lines: {have_lines:?}");
-
-        // Send NAK and packfile.
-        transmiter.write_all(b"0008NAK\n")?;
-        let pack = Pack::from_with_offset(want_lines, repo)?.as_bytes()?;
+        let common: Vec<_> = have_lines
+            .into_iter()
+            .filter(|have| get_object_with_offset(have, repo).is_ok())
+            .collect();
+
+        // Acknowledge the first common commit, or send NAK, and the packfile.
+        match common.first() {
+            Some(have) => transmiter.write_all(pkt_line(&format!("ACK {have}")).as_bytes())?,
+            None => transmiter.write_all(b"0008NAK\n")?,
+        }
+        let pack = Pack::from_haves_with_offset(want_lines, &common, repo)?.as_bytes()?;
         transmiter.write_all(&pack)?;
         Ok(())
     }
 
+    /// Upload-pack with protocol v2. Instead of the references, the
+    /// capabilities are advertised and then the client sends `ls-refs`
+    /// and `fetch` commands until it closes the connection.
+    fn upload_pack_v2<T: Write + Read>(mut transmiter: T, repo: &str) -> Result<(), ServerError> {
+        let capabilities: String = V2_CAPABILITIES.iter().map(|c| pkt_line(c)).collect();
+        transmiter.write_all(capabilities.as_bytes())?;
+        transmiter.write_all(b"0000")?;
+
+        // A command is a header with the command and its capabilities,
+        // and, after a delimiter, its arguments.
+        while let Some((header, end)) = read_pkt_section(&mut transmiter)? {
+            let args = match end {
+                PktLine::Delim => read_pkt_section(&mut transmiter)?
+                    .map(|(args, _)| args)
+                    .unwrap_or_default(),
+                _ => vec![],
+            };
+
+            let command = header
+                .first()
+                .and_then(|line| line.strip_prefix("command="));
+            match command {
+                Some("ls-refs") => Self::ls_refs(&mut transmiter, repo, &args)?,
+                Some("fetch") => Self::fetch(&mut transmiter, repo, &args)?,
+                // The client ends the session with a flush.
+                None if header.is_empty() => break,
+                _ => return Err(server_err!("Unknown command")),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Lists the references that start with any of the `ref-prefix`
+    // arguments. `symrefs` adds the branch HEAD points to and `peel`
+    // the object annotated tags point to.
+    fn ls_refs<W: Write>(writer: &mut W, repo: &str, args: &[String]) -> Result<(), ServerError> {
+        let peel = args.iter().any(|arg| arg == "peel");
+        let symrefs = args.iter().any(|arg| arg == "symrefs");
+        let prefixes: Vec<_> = args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("ref-prefix "))
+            .collect();
+        let listed = |name: &str| {
+            prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix))
+        };
+
+        let mut lines = vec![];
+        if let Some(head) = get_head_with_offset(repo).filter(|_| listed("HEAD")) {
+            let mut line = format!("{head} HEAD");
+            let headfile = fs::read_to_string(format!("{repo}/HEAD"))?;
+            if let Some(target) = headfile.strip_prefix("ref: ").filter(|_| symrefs) {
+                line.push_str(&format!(" symref-target:{}", target.trim()));
+            }
+            lines.push(line);
+        }
+
+        // Peeled tags follow their tag as `<tag>^{}`, the last one
+        // is the object at the end of the chain.
+        let refs = Self::get_all_refs(repo)?;
+        for (name, hash) in &refs {
+            if name.ends_with("^{}") || !listed(name) {
+                continue;
+            }
+
+            let mut line = format!("{hash} {name}");
+            let peeled_name = format!("{name}^{{}}");
+            if let Some((_, peeled)) = refs.iter().rfind(|(n, _)| *n == peeled_name) {
+                if peel {
+                    line.push_str(&format!(" peeled:{peeled}"));
+                }
+            }
+            lines.push(line);
+        }
+
+        let lines: String = lines.iter().map(|line| pkt_line(line)).collect();
+        writer.write_all(lines.as_bytes())?;
+        writer.write_all(b"0000")?;
+        Ok(())
+    }
+
+    // Sends the pack with the objects the client wants. Until it says
+    // it's done, the haves we have are acknowledged first, and we are
+    // always ready to send the pack right away.
+    fn fetch<W: Write>(writer: &mut W, repo: &str, args: &[String]) -> Result<(), ServerError> {
+        let wants: Vec<_> = args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("want "))
+            .map(|want| want.trim().to_string())
+            .collect();
+        if wants.is_empty() {
+            return Err(server_err!("Client is up to date"));
+        }
+
+        let common: Vec<_> = args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("have "))
+            .map(|have| have.trim().to_string())
+            .filter(|have| get_object_with_offset(have, repo).is_ok())
+            .collect();
+
+        if !args.iter().any(|arg| arg == "done") {
+            let mut acks = pkt_line("acknowledgments");
+            match common.is_empty() {
+                true => acks.push_str(&pkt_line("NAK")),
+                false => common
+                    .iter()
+                    .for_each(|have| acks.push_str(&pkt_line(&format!("ACK {have}")))),
+            }
+
+            acks.push_str(&pkt_line("ready"));
+            writer.write_all(acks.as_bytes())?;
+            writer.write_all(b"0001")?;
+        }
+
+        // The pack goes in band 1 of side-band-64k packets, without
+        // what the client can reach from the common commits.
+        writer.write_all(pkt_line("packfile").as_bytes())?;
+        let pack = Pack::from_haves_with_offset(wants, &common, repo)?.as_bytes()?;
+        for chunk in pack.chunks(SIDEBAND_MAX) {
+            writer.write_all(format!("{:04x}\x01", chunk.len() + 5).as_bytes())?;
+            writer.write_all(chunk)?;
+        }
+
+        writer.write_all(b"0000")?;
+        Ok(())
+    }
+
     // Updates a single ref.
     fn update_ref(path: &str, _: &str, new: &str, repo: &str) -> Result<(), ServerError> {
         let path = path.replace('\0', "");
         if new == ZERO_ID {
-            // Delete reference.
+            // Delete reference, it may be packed too.
             let _ = fs::remove_file(format!("{repo}/{path}"));
+            delete_packed_ref_with_offset(&path, repo)?;
         } else {
             // Create path till file if it doesn't exist yet.
             let path_split = path.split('/').collect::<Vec<&str>>();
@@ -258,7 +476,6 @@ impl Server {
             fs::create_dir_all(format!("{repo}/{path_till_file}"))?;
 
             // Write to file.
-            println!("path: {path:?}");
             let mut file = File::create(format!("{repo}/{path}"))?;
             file.write_all(new.as_bytes())?;
         }
@@ -295,7 +512,6 @@ impl Server {
             // Update reference.
             match (split.next(), split.next(), split.next()) {
                 (Some(old), Some(new), Some(name)) => {
-                    println!("-------------");
                     Self::update_ref(name, old, new, repo)?;
                 }
 
@@ -309,23 +525,15 @@ impl Server {
     /// The process invoked for the Git Client to send data to Git Server
     /// Should respond with list of all references the repository has
     fn receive_pack<T: Write + Read>(mut transmiter: T, repo: &str) -> Result<(), ServerError> {
-        println!("0");
-
         Self::send_all_references(&mut transmiter, repo, false)?;
 
-        println!("1");
-
         // Read the references from client and parse them.
         Self::update_refs(&mut transmiter, repo)?;
 
-        println!("2");
-
         // Read Packfile.
         let reader = BufReader::new(&mut transmiter);
         Pack::unpack_with_offset(reader, repo)?;
 
-        println!("3");
-
         Ok(())
     }
 
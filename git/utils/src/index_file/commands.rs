@@ -5,13 +5,15 @@ use std::io::{self, Read, Seek, Write};
 
 const HEADER_TYPE: &[u8; 4] = b"DIRC";
 const VERSION: u32 = 2;
+// Needed by entries with extended flags.
+const EXTENDED_VERSION: u32 = 3;
 
 fn valid_header(buf: [u8; 4]) -> bool {
     buf == *HEADER_TYPE
 }
 
 fn valid_version(buf: [u8; 4]) -> bool {
-    u32::from_be_bytes(buf) == VERSION
+    matches!(u32::from_be_bytes(buf), VERSION | EXTENDED_VERSION)
 }
 
 /// Underlying implementation of read_index.
@@ -51,7 +53,11 @@ pub fn __write_index<W: Write>(entries: Vec<IndexEntry>, mut output: W) -> io::R
     out.write_all(HEADER_TYPE)?;
 
     // 4-bytes: version.
-    out.write_all(&VERSION.to_be_bytes())?;
+    let version = match entries.iter().any(|entry| entry.is_extended()) {
+        true => EXTENDED_VERSION,
+        false => VERSION,
+    };
+    out.write_all(&version.to_be_bytes())?;
 
     // 4-bytes: Amount of entries.
     out.write_all(&(entries.len() as u32).to_be_bytes())?;
@@ -66,6 +72,7 @@ pub fn __write_index<W: Write>(entries: Vec<IndexEntry>, mut output: W) -> io::R
     output.write_all(&out)
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -153,4 +160,29 @@ mod tests {
         let entries = __read_index(mock).unwrap();
         assert_eq!(entries[0], entry);
     }
+
+    #[test]
+    fn skip_worktree() {
+        let hash = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let mut skipped = IndexEntry::new_from_repo_with_hash("docs/a.md", "100644", hash).unwrap();
+        skipped.set_skip_worktree(true);
+        let entries = vec![
+            skipped.clone(),
+            IndexEntry::new_from_repo_with_hash("src/main.rs", "100644", hash).unwrap(),
+        ];
+
+        let mut mock = IndexEntryMock {
+            bytes: vec![],
+            iterpos: 0,
+        };
+
+        __write_index(entries, &mut mock).unwrap();
+        assert_eq!(mock.bytes[4..8], EXTENDED_VERSION.to_be_bytes());
+        mock.flush().unwrap();
+
+        let entries = __read_index(mock).unwrap();
+        assert_eq!(entries[0], skipped);
+        assert!(entries[0].is_skip_worktree());
+        assert!(!entries[1].is_skip_worktree());
+    }
 }
@@ -1,10 +1,14 @@
-use crate::plumbing::hash_object::__hash_object;
+use crate::{
+    common_dir, get_head_with_offset, io_err,
+    plumbing::{hash_object::__hash_object, ls_tree::hash_to_str},
+};
 
 use super::index_entry::IndexEntry;
 use std::{
     collections::HashMap,
-    fs, io,
+    io,
     ops::{Deref, DerefMut},
+    path::Path,
 };
 
 /// git index file representation.
@@ -12,6 +16,8 @@ use std::{
 #[derive(Default, Debug)]
 pub struct Index {
     entries: HashMap<String, IndexEntry>,
+    // Set when core.filemode is false.
+    ignore_filemode: bool,
 }
 
 impl Deref for Index {
@@ -34,7 +40,31 @@ impl Index {
 
     /// Creates a new index with the given entries.
     pub fn with(entries: HashMap<String, IndexEntry>) -> Self {
-        Self { entries }
+        Self {
+            entries,
+            ..Default::default()
+        }
+    }
+
+    /// Sets whether the executable bit of files in the
+    /// working directory should be trusted (core.filemode).
+    pub fn set_filemode(&mut self, filemode: bool) {
+        self.ignore_filemode = !filemode;
+    }
+
+    /// Returns the mode the file at the given path would have in the
+    /// index. If core.filemode is false the executable bit is taken
+    /// from the current entry instead of the working directory.
+    pub fn mode_of(&self, path: &str) -> io::Result<&'static str> {
+        let mode = IndexEntry::file_mode(path)?;
+        if !self.ignore_filemode || matches!(mode, "120000" | "160000") {
+            return Ok(mode);
+        }
+
+        Ok(match self.entries.get(path).map(|e| e.get_mode()) {
+            Some(mode @ "100755") => mode,
+            _ => "100644",
+        })
     }
 
     /// Returns a vector of the entries in the index.
@@ -45,30 +75,55 @@ impl Index {
     }
 
     fn hash_file(path: &str) -> io::Result<Vec<u8>> {
-        let data = fs::read(path)?;
-        Ok(__hash_object(&data, "blob", false, ".git")?.0)
+        let data = IndexEntry::read_content(path)?;
+        Ok(__hash_object(&data, "blob", false, &common_dir())?.0)
     }
 
     /// Adds a new entry to the index.
     pub fn add(&mut self, file: String, stage: bool, db: bool) -> io::Result<()> {
+        // A directory holding a repository is a submodule,
+        // record the commit it has checked out.
+        let git_dir = format!("{file}/.git");
+        if Path::new(&git_dir).exists() {
+            let hash = get_head_with_offset(&git_dir)
+                .ok_or(io_err!("Submodule HEAD is not pointing to any commit"))?;
+            return self.add_gitlink(file, &hash, stage);
+        }
+
+        let mode = self.mode_of(&file)?;
+
         if let Some(entry) = self.entries.remove(&file) {
-            // Compare the hashes of the file.
+            // Compare the hashes and modes of the file.
             let hash = entry.get_hash();
             let new_hash = Self::hash_file(&file)?;
 
             // If file has not changed then
             // don't mark it as staged.
-            if hash == new_hash {
+            if hash == new_hash && entry.get_mode() == mode {
                 self.insert(file, entry);
                 return Ok(());
             }
         }
 
-        let entry = IndexEntry::new(&file, stage, db)?;
+        let mut entry = IndexEntry::new(&file, stage, db)?;
+        entry.set_mode(mode);
         self.insert(file, entry);
         Ok(())
     }
 
+    /// Adds a gitlink entry pointing to the given submodule commit.
+    pub fn add_gitlink(&mut self, path: String, hash: &str, stage: bool) -> io::Result<()> {
+        if let Some(entry) = self.entries.get(&path) {
+            if entry.is_gitlink() && hash_to_str(entry.get_hash()) == hash {
+                return Ok(());
+            }
+        }
+
+        let entry = IndexEntry::new_gitlink(&path, hash, stage)?;
+        self.insert(path, entry);
+        Ok(())
+    }
+
     pub fn add_from_repo(
         &mut self,
         path: String,
@@ -82,6 +137,15 @@ impl Index {
         Ok(())
     }
 
+    /// Adds an entry for a file left out of the working
+    /// directory by a sparse checkout.
+    pub fn add_skip_worktree(&mut self, path: String, mode: &str, hash: &str) -> io::Result<()> {
+        let mut entry = IndexEntry::new_from_repo_with_hash(&path, mode, hash)?;
+        entry.set_skip_worktree(true);
+        self.insert(path, entry);
+        Ok(())
+    }
+
     /// Removes an entry from the index.
     pub fn remove(&mut self, file: &str) {
         self.entries.remove(file);
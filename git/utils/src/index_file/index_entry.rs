@@ -1,10 +1,16 @@
-use crate::plumbing::hash_object::__hash_object;
+use crate::{common_dir, io_err, plumbing::hash_object::__hash_object};
 use std::{
     fs::{self, Metadata},
     io::{self, Read, Seek, Write},
-    os::unix::fs::{MetadataExt, PermissionsExt},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt},
+    },
 };
 
+const EXTENDED: u16 = 0x4000;
+const SKIP_WORKTREE: u16 = 0x4000;
+
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct IndexEntry {
     ctime_secs: u32, // ctime seconds, the last time a file's metadata changed.
@@ -26,6 +32,11 @@ pub struct IndexEntry {
     // 1-bit extended flag (must be zero in version 2).
     // 2-bit stage (during merge).
     // 12-bit name length if the length is less than 0xFFF; otherwise 0xFFF is stored in this field.
+    extended_flags: u16, // Only stored when the extended flag is set (version 3).
+    // 1-bit reserved.
+    // 1-bit skip-worktree flag, the file is left out of a sparse checkout.
+    // 1-bit intent-to-add flag.
+    // 13-bit unused.
     path_name: String, // Entry path name (variable length) relative to top level directory (without leading slash).
 } // 1-8 nul bytes as necessary to pad the entry to a multiple of eight bytes while keeping the name NUL-terminated.
 
@@ -54,6 +65,14 @@ impl IndexEntry {
         }
     }
 
+    // Returns the size of the fields before the path name.
+    fn fixed_size(flags: u16) -> usize {
+        match flags & EXTENDED {
+            0 => 62,
+            _ => 64,
+        }
+    }
+
     // Returns the flag field of the IndexEntry struct
     // in the format git expects it.
     fn get_git_flags(path: &str) -> u16 {
@@ -64,17 +83,46 @@ impl IndexEntry {
         self.path_name = path.to_string();
     }
 
+    /// Returns true if the file is left out of the working directory
+    /// by a sparse checkout.
+    pub fn is_skip_worktree(&self) -> bool {
+        self.extended_flags & SKIP_WORKTREE != 0
+    }
+
+    /// Marks the file as left out of the working directory.
+    pub fn set_skip_worktree(&mut self, skip: bool) {
+        match skip {
+            true => self.extended_flags |= SKIP_WORKTREE,
+            false => self.extended_flags &= !SKIP_WORKTREE,
+        }
+
+        match self.extended_flags {
+            0 => self.flags &= !EXTENDED,
+            _ => self.flags |= EXTENDED,
+        }
+    }
+
+    /// Returns true if the entry needs the extended flags of version 3.
+    pub fn is_extended(&self) -> bool {
+        self.flags & EXTENDED != 0
+    }
+
     pub fn get_stage(&self) -> u16 {
         (self.flags & 0x3000) >> 12
     }
 
     pub fn get_mode(&self) -> &'static str {
-        match self.mode >> 12 {
+        Self::mode_to_str(self.mode)
+    }
+
+    // Converts a u32 mode to the string git uses in tree objects.
+    fn mode_to_str(mode: u32) -> &'static str {
+        match mode >> 12 {
             // Symbolic link.
             0b1010 => "120000",
             // Gitlink.
             0b1110 => "160000",
-            _ => match self.mode >> 9 & 0b111 {
+            _ => match mode & 0o111 {
                 // Non-executable file.
                 0 => "100644",
                 // Executable file.
@@ -83,6 +131,21 @@ impl IndexEntry {
         }
     }
 
+    /// Overrides the entry's mode with the given git mode.
+    pub fn set_mode(&mut self, mode: &str) {
+        self.mode = Self::mode_from_str(mode);
+    }
+
+    // Converts a git mode string to it's u32 representation.
+    fn mode_from_str(mode: &str) -> u32 {
+        match mode {
+            "100755" => 0o100755,
+            "120000" => 0o120000,
+            "160000" => 0o160000,
+            _ => 0o100644,
+        }
+    }
+
     pub fn get_hash(&self) -> &[u8] {
         &self.sha_hash
     }
@@ -99,13 +162,36 @@ impl IndexEntry {
         self.flags & (1 << 15) != 0
     }
 
+    /// Returns true if the entry is a submodule's commit.
+    pub fn is_gitlink(&self) -> bool {
+        self.mode >> 12 == 0b1110
+    }
+
+    /// Returns the content git stores for the file at the given
+    /// path. For symbolic links this is the link's target.
+    pub fn read_content(path: &str) -> io::Result<Vec<u8>> {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(path)?;
+            Ok(target.as_os_str().as_bytes().to_vec())
+        } else {
+            fs::read(path)
+        }
+    }
+
+    /// Returns the git mode of the file at the given path.
+    pub fn file_mode(path: &str) -> io::Result<&'static str> {
+        let metadata = fs::symlink_metadata(path)?;
+        Ok(Self::mode_to_str(Self::get_git_mode(&metadata)))
+    }
+
     /// Creates a new entry based on a file
     pub fn new(path: &str, stage: bool, db: bool) -> io::Result<Self> {
-        let metadata = fs::metadata(path)?;
+        let metadata = fs::symlink_metadata(path)?;
         let mut sha_hash = [0; 20];
 
-        let content = fs::read_to_string(path)?;
-        let hash = __hash_object(content.as_bytes(), "blob", db, ".git")?.0;
+        let content = Self::read_content(path)?;
+        let hash = __hash_object(&content, "blob", db, &common_dir())?.0;
         sha_hash.copy_from_slice(&hash);
 
         // Mark this entry as a stage entry.
@@ -123,6 +209,7 @@ impl IndexEntry {
             gid: metadata.gid(),
             file_size: metadata.size() as u32,
             flags: Self::get_git_flags(path) | for_stage,
+            extended_flags: 0,
             sha_hash,
             path_name: path.to_string(),
         })
@@ -162,6 +249,12 @@ impl IndexEntry {
         file.read_exact(&mut buf_2)?;
         let flags = u16::from_be_bytes(buf_2);
 
+        let mut extended_flags = 0;
+        if flags & EXTENDED != 0 {
+            file.read_exact(&mut buf_2)?;
+            extended_flags = u16::from_be_bytes(buf_2);
+        }
+
         // Read path name.
         let path_len = flags & 0xFFF;
         let mut path_name = vec![0; path_len as usize];
@@ -169,8 +262,8 @@ impl IndexEntry {
         let path_name = String::from_utf8_lossy(&path_name).to_string();
 
         // Get through padding.
-        let padding = 8 - (62 + path_len) % 8;
-        file.read_exact(&mut vec![0; padding as usize])?;
+        let padding = 8 - (Self::fixed_size(flags) + path_len as usize) % 8;
+        file.read_exact(&mut vec![0; padding])?;
 
         Ok(Self {
             ctime_secs,
@@ -185,6 +278,7 @@ impl IndexEntry {
             file_size,
             sha_hash,
             flags,
+            extended_flags,
             path_name,
         })
     }
@@ -210,28 +304,48 @@ impl IndexEntry {
 
         bytes.write_all(&self.sha_hash)?;
         bytes.write_all(&self.flags.to_be_bytes())?;
+        if self.is_extended() {
+            bytes.write_all(&self.extended_flags.to_be_bytes())?;
+        }
 
         // Pathname.
         bytes.write_all(self.path_name.as_bytes())?;
 
         // Padding.
-        let padding = 8 - (62 + self.path_name.len()) % 8;
+        let padding = 8 - (Self::fixed_size(self.flags) + self.path_name.len()) % 8;
         bytes.write_all(&vec![0; padding])?;
 
         Ok(bytes)
     }
 
-    pub fn new_from_repo_with_hash(path: &str, hash: &str) -> io::Result<Self> {
+    pub fn new_from_repo_with_hash(path: &str, mode: &str, hash: &str) -> io::Result<Self> {
         let mut sha_hash = [0; 20];
-        sha_hash.copy_from_slice(hash.as_bytes());
+        for (i, byte) in sha_hash.iter_mut().enumerate() {
+            *byte = hash
+                .get(i * 2..i * 2 + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or(io_err!("Invalid hash"))?;
+        }
 
         Ok(Self {
             sha_hash,
+            mode: Self::mode_from_str(mode),
+            flags: Self::get_git_flags(path),
             path_name: path.to_string(),
             ..Default::default()
         })
     }
 
+    /// Creates a gitlink entry pointing to the given submodule commit.
+    pub fn new_gitlink(path: &str, hash: &str, stage: bool) -> io::Result<Self> {
+        let mut entry = Self::new_from_repo_with_hash(path, "160000", hash)?;
+        if stage {
+            entry.flags |= 1 << 15;
+        }
+
+        Ok(entry)
+    }
+
     pub fn new_from_repo(
         path: &str,
         repo: &str,
@@ -243,17 +357,10 @@ impl IndexEntry {
         let hash = __hash_object(&data, "blob", write, repo)?.0;
         sha_hash.copy_from_slice(&hash);
 
-        // Convert mode to u32.
-        let mode = match mode {
-            "100755" => 0o100755,
-            "120000" => 0o120000,
-            "160000" => 0o160000,
-            _ => 0o100644,
-        };
-
         Ok(Self {
             sha_hash,
-            mode,
+            mode: Self::mode_from_str(mode),
+            flags: Self::get_git_flags(path),
             path_name: path.to_string(),
             ..Default::default()
         })
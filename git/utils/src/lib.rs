@@ -3,16 +3,16 @@ pub mod object;
 pub mod package;
 pub mod plumbing;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     fs::{self, File},
     io::{self, Read, Write},
     net::TcpStream,
+    path::Path,
 };
 
 use index_file::{index::Index, index_entry::IndexEntry};
-use object::object_db::get_object_from_repo;
 use plumbing::{
-    commit::get_parent_commits,
+    commit_graph::History,
     hash_object::__hash_object,
     ls_tree::{ls_tree_from_repo, parse_ls_tree_entry},
 };
@@ -48,26 +48,138 @@ macro_rules! log_err {
     };
 }
 
+/// Returns the directory holding the state of the current worktree,
+/// like HEAD and the index. It's `.git`, or the directory a `.git`
+/// file points to in linked worktrees.
+pub fn git_dir() -> String {
+    match fs::read_to_string(".git") {
+        Ok(data) => match data.strip_prefix("gitdir: ") {
+            Some(dir) => dir.trim().to_string(),
+            None => ".git".to_string(),
+        },
+        Err(_) => ".git".to_string(),
+    }
+}
+
+/// Returns the directory shared by every worktree of a repository,
+/// with the objects, references and configuration. Linked worktrees
+/// point to it from the `commondir` file of their git directory.
+pub fn common_dir() -> String {
+    let git_dir = git_dir();
+    match fs::read_to_string(format!("{git_dir}/commondir")) {
+        Ok(dir) => {
+            let dir = Path::new(&git_dir).join(dir.trim());
+            let dir = fs::canonicalize(&dir).unwrap_or(dir);
+            dir.to_string_lossy().to_string()
+        }
+        Err(_) => git_dir,
+    }
+}
+
 pub fn send_a_flush_pkt(mut transmiter: &TcpStream) -> io::Result<()> {
     transmiter.write_all(b"0000")
 }
 
 /// Returns a hashmap of the current branches and its respectives commits (k: branch_path, v: obj_id)
 pub fn get_current_refs() -> io::Result<HashMap<String, String>> {
-    get_refs_from_with_prefix(".git/refs", ".git/")
+    let common_dir = common_dir();
+    get_refs_from_with_prefix(&format!("{common_dir}/refs"), &format!("{common_dir}/"))
 }
 
 pub fn get_local_refs() -> io::Result<HashMap<String, String>> {
-    get_refs_from_with_prefix(".git/refs/heads", ".git/")
+    let common_dir = common_dir();
+    get_refs_from_with_prefix(
+        &format!("{common_dir}/refs/heads"),
+        &format!("{common_dir}/"),
+    )
 }
 
 pub fn get_remote_refs() -> io::Result<HashMap<String, String>> {
-    get_refs_from_with_prefix(".git/refs/remotes", ".git/")
+    let common_dir = common_dir();
+    get_refs_from_with_prefix(
+        &format!("{common_dir}/refs/remotes"),
+        &format!("{common_dir}/"),
+    )
+}
+
+/// Returns the references stored in the packed-refs file of a
+/// repository in the form of (ref path, hash). Peeled lines are skipped.
+pub fn get_packed_refs_with_offset(offset: &str) -> HashMap<String, String> {
+    let content = fs::read_to_string(format!("{offset}/packed-refs")).unwrap_or_default();
+
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| line.split_once(' '))
+        .map(|(hash, name)| (name.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// Writes the packed-refs file of a repository, removing it if empty.
+pub fn write_packed_refs_with_offset(
+    refs: &HashMap<String, String>,
+    offset: &str,
+) -> io::Result<()> {
+    let path = format!("{offset}/packed-refs");
+    if refs.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    let mut refs: Vec<_> = refs.iter().collect();
+    refs.sort();
+
+    let mut content = String::from("# pack-refs with: sorted\n");
+    for (name, hash) in refs {
+        content.push_str(&format!("{hash} {name}\n"));
+    }
+
+    fs::write(path, content)
+}
+
+/// Removes a reference from the packed-refs file.
+/// Returns true if the reference was packed.
+pub fn delete_packed_ref_with_offset(name: &str, offset: &str) -> io::Result<bool> {
+    let mut refs = get_packed_refs_with_offset(offset);
+    if refs.remove(name).is_none() {
+        return Ok(false);
+    }
+
+    write_packed_refs_with_offset(&refs, offset)?;
+    Ok(true)
+}
+
+/// Returns the hash a reference like refs/heads/master points to,
+/// falling back to the packed-refs file if it isn't a loose file.
+pub fn read_ref_with_offset(name: &str, offset: &str) -> io::Result<String> {
+    match fs::read_to_string(format!("{offset}/{name}")) {
+        Ok(hash) => Ok(hash.trim().to_string()),
+        Err(e) => get_packed_refs_with_offset(offset).remove(name).ok_or(e),
+    }
 }
 
 // Returns a hashmap of the current branches and its respective commits
-// with a certain offset of where the .git folder is.
+// with a certain offset of where the .git folder is. Packed references
+// are included when the prefix is the .git folder, loose ones win.
 pub fn get_refs_from_with_prefix(path: &str, prefix: &str) -> io::Result<HashMap<String, String>> {
+    let mut refs = HashMap::new();
+
+    if let Some(dir) = path.strip_prefix(prefix) {
+        let packed = get_packed_refs_with_offset(prefix.trim_end_matches('/'));
+        refs.extend(
+            packed
+                .into_iter()
+                .filter(|(name, _)| name.starts_with(&format!("{dir}/"))),
+        );
+    }
+
+    refs.extend(get_loose_refs(path, prefix)?);
+    Ok(refs)
+}
+
+fn get_loose_refs(path: &str, prefix: &str) -> io::Result<HashMap<String, String>> {
     let entries = fs::read_dir(path)?;
     let mut refs = HashMap::new();
 
@@ -81,7 +193,7 @@ pub fn get_refs_from_with_prefix(path: &str, prefix: &str) -> io::Result<HashMap
         let file_path = format!("{path}/{file_name}");
 
         if entry.file_type()?.is_dir() {
-            refs.extend(get_refs_from_with_prefix(&file_path, prefix)?.into_iter());
+            refs.extend(get_loose_refs(&file_path, prefix)?);
         } else {
             let content = fs::read_to_string(entry.path())?.trim().to_string();
             if let Some(stripped) = file_path.strip_prefix(prefix) {
@@ -95,12 +207,17 @@ pub fn get_refs_from_with_prefix(path: &str, prefix: &str) -> io::Result<HashMap
 
 /// Returns tags in the form of (ref path, hash).
 pub fn get_tags() -> io::Result<HashMap<String, String>> {
-    get_refs_from_with_prefix(".git/refs/tags", ".git/")
+    let common_dir = common_dir();
+    get_refs_from_with_prefix(
+        &format!("{common_dir}/refs/tags"),
+        &format!("{common_dir}/"),
+    )
 }
 
 /// Returns the hash of the commit object pointed to by HEAD.
 pub fn get_head() -> Option<String> {
-    get_head_with_offset(".git")
+    let file = File::open(format!("{}/HEAD", git_dir())).ok()?;
+    __get_head_commit(file).ok()
 }
 
 /// Returns the hash of the commit object pointet to by HEAD
@@ -137,14 +254,14 @@ fn cur_branch_file_path_with_offset<R: Read>(mut head: R, offset: &str) -> Head<
 
 // Opens the current file containing the last commit pointed by HEAD.
 fn cur_branch_file_path<R: Read>(head: R) -> Head<io::Error> {
-    cur_branch_file_path_with_offset(head, ".git")
+    cur_branch_file_path_with_offset(head, &common_dir())
 }
 
 pub fn __get_head_commit_with_offset<R: Read>(head: R, offset: &str) -> io::Result<String> {
     match cur_branch_file_path_with_offset(head, offset) {
         Head::Refered(branch) => {
-            let s = fs::read_to_string(branch)?;
-            Ok(s.replace('\n', ""))
+            let name = &branch[offset.len() + 1..];
+            read_ref_with_offset(name, offset)
         }
         Head::Detached(hash) => Ok(hash),
         Head::Err(e) => Err(e),
@@ -153,7 +270,7 @@ pub fn __get_head_commit_with_offset<R: Read>(head: R, offset: &str) -> io::Resu
 
 /// Underlying imlementation of get_head.
 pub fn __get_head_commit<R: Read>(head: R) -> io::Result<String> {
-    __get_head_commit_with_offset(head, ".git")
+    __get_head_commit_with_offset(head, &common_dir())
 }
 
 /// Underlying implementation of update_head.
@@ -162,7 +279,8 @@ pub fn __get_head_commit<R: Read>(head: R) -> io::Result<String> {
 /// It doesn't change the current branch, it just changes the commit.
 pub fn __update_head_commit(hash_commit: &str) -> io::Result<()> {
     // update HEAD file to point to branch
-    let head = File::open(".git/HEAD")?;
+    let head_path = format!("{}/HEAD", git_dir());
+    let head = File::open(&head_path)?;
 
     match cur_branch_file_path(head) {
         Head::Refered(branch) => {
@@ -172,9 +290,9 @@ pub fn __update_head_commit(hash_commit: &str) -> io::Result<()> {
 
             Ok(())
         }
-        Head::Detached(hash) => {
-            let mut head = File::create(".git/HEAD")?;
-            head.write_all(hash.as_bytes())?;
+        Head::Detached(_) => {
+            let mut head = File::create(head_path)?;
+            head.write_all(hash_commit.as_bytes())?;
             head.write_all(b"\n")?;
             Ok(())
         }
@@ -212,13 +330,68 @@ pub fn get_response<R: Read>(transmiter: &mut R) -> Vec<u8> {
     references
 }
 
+/// A pkt-line: flush and delimiter packets end sections, the
+/// others carry data.
+#[derive(Debug, PartialEq)]
+pub enum PktLine {
+    Flush,
+    Delim,
+    Data(Vec<u8>),
+}
+
+/// Reads a pkt-line, None if the connection was closed.
+pub fn read_pkt_line<R: Read>(reader: &mut R) -> io::Result<Option<PktLine>> {
+    let mut buf = [0; 4];
+    match reader.read_exact(&mut buf) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    // size has the length of the line in hexadecimal.
+    let size = u32::from_str_radix(&String::from_utf8_lossy(&buf), 16)
+        .map_err(|_| io_err!("Invalid hexadecimal size"))?;
+
+    match size {
+        0 => Ok(Some(PktLine::Flush)),
+        1 => Ok(Some(PktLine::Delim)),
+        2..=4 => Err(io_err!("Invalid pkt-line")),
+        size => {
+            let mut content = vec![0; size as usize - 4];
+            reader.read_exact(&mut content)?;
+            Ok(Some(PktLine::Data(content)))
+        }
+    }
+}
+
+/// Reads the text lines of a section, without their newlines, up to
+/// the flush or delimiter packet that ends it, which is returned too.
+/// None if the connection was closed before it started.
+pub fn read_pkt_section<R: Read>(reader: &mut R) -> io::Result<Option<(Vec<String>, PktLine)>> {
+    let mut lines = vec![];
+    loop {
+        match read_pkt_line(reader)? {
+            None if lines.is_empty() => return Ok(None),
+            None => return Err(io_err!("Unexpected end of pkt-line section")),
+            Some(PktLine::Data(data)) => {
+                let line = String::from_utf8_lossy(&data);
+                lines.push(line.strip_suffix('\n').unwrap_or(&line).to_string());
+            }
+            Some(end) => return Ok(Some((lines, end))),
+        }
+    }
+}
+
+/// Formats a text line as a pkt-line, adding the newline.
+pub fn pkt_line(line: &str) -> String {
+    format!("{:04x}{line}\n", line.len() + 5)
+}
+
 fn read_line<R: Read>(reader: &mut R) -> io::Result<String> {
     let mut buf = [0; 4];
     reader.read_exact(&mut buf)?;
 
     // Check if the line is a flush-pkt.
     if &buf == b"0000" {
-        println!("EXIT FLUSH");
         return Ok(String::new());
     }
 
@@ -235,7 +408,8 @@ fn read_line<R: Read>(reader: &mut R) -> io::Result<String> {
     let mut content = vec![0; size];
     reader.read_exact(&mut content)?;
 
-    if &content == b"done\n" {
+    // The trailing newline of a pkt-line is optional.
+    if content.strip_suffix(b"\n").unwrap_or(&content) == b"done" {
         return Ok(String::new());
     }
 
@@ -248,7 +422,8 @@ pub fn get_want_lines<R: Read>(transmiter: &mut R) -> io::Result<Vec<String>> {
     let mut want_lines = vec![];
 
     if let Some(stripped) = first_want.strip_prefix("want ") {
-        if let Some(i) = stripped.find('\n') {
+        // Capabilities follow the hash, after a space or a NUL.
+        if let Some(i) = stripped.find(['\n', ' ', '\0']) {
             let (want, _) = stripped.split_at(i);
             want_lines.push(want.trim().to_string());
         } else {
@@ -260,7 +435,6 @@ pub fn get_want_lines<R: Read>(transmiter: &mut R) -> io::Result<Vec<String>> {
 
     loop {
         let line = read_line(transmiter)?;
-        println!("line: {:?}, len: {}", line, line.len());
         if let Some(stripped) = line.strip_prefix("want ") {
             want_lines.push(stripped.trim().to_string());
         } else if line.is_empty() {
@@ -316,98 +490,26 @@ pub fn parse_tag(data: &[u8]) -> Result<String, &'static str> {
 
 /// Returns the hash of the commit object pointed to by a branch.
 pub fn get_branch_from_repo(name: &str, repo: &str) -> io::Result<String> {
-    Ok(fs::read_to_string(format!("{repo}/refs/heads/{name}"))?
-        .trim()
-        .to_string())
-}
-
-fn __is_ancestor_from_repo(
-    a: &str,
-    b: &str,
-    repo: &str,
-    steps: &mut HashSet<String>,
-) -> io::Result<bool> {
-    // Check if we have already visited b.
-    if steps.contains(b) {
-        return Ok(false);
-    }
-
-    // Added b to steps.
-    steps.insert(b.to_string());
-
-    // Check if a is b.
-    if a == b {
-        return Ok(true);
-    }
-
-    // Get b's data.
-    let (_, _, data) = get_object_from_repo(b, repo)?;
-    match get_parent_commits(&data) {
-        Some(parents) => {
-            for parent in parents {
-                if __is_ancestor_from_repo(a, &parent, repo, steps)? {
-                    return Ok(true);
-                }
-            }
-
-            Ok(false)
-        }
-        None => Ok(false),
-    }
+    read_ref_with_offset(&format!("refs/heads/{name}"), repo)
 }
 
 // Returns true if a is ancestor of b within a given repo.
 pub fn is_ancestor_from_repo(a: &str, b: &str, repo: &str) -> io::Result<bool> {
-    __is_ancestor_from_repo(a, b, repo, &mut HashSet::new())
+    History::with_offset(repo).is_ancestor(a, b)
 }
 
-/// Gets the common ancestor of two commits.
-fn __get_ancestor_from_repo(
-    mut a: Vec<u8>,
-    mut b: Vec<u8>,
-    repo: &str,
-    steps1: &mut HashSet<String>,
-    steps2: &mut HashSet<String>,
-) -> io::Result<String> {
-    loop {
-        let parents1 = get_parent_commits(&a);
-        let parents2 = get_parent_commits(&b);
-
-        if let Some(parents) = parents1 {
-            for parent in parents {
-                if steps2.contains(&parent) {
-                    return Ok(parent);
-                }
-
-                steps1.insert(parent.clone());
-                let (_, _, data) = get_object_from_repo(&parent, repo)?;
-                a = data;
-            }
-        }
-
-        if let Some(parents) = parents2 {
-            for parent in parents {
-                if steps1.contains(&parent) {
-                    return Ok(parent);
-                }
-
-                steps2.insert(parent.clone());
-                let (_, _, data) = get_object_from_repo(&parent, repo)?;
-                b = data;
-            }
-        }
-    }
+/// Returns every best common ancestor of two commits within a given repo.
+pub fn get_merge_bases_from_repo(a: &str, b: &str, repo: &str) -> io::Result<Vec<String>> {
+    History::with_offset(repo).merge_bases(a, b)
 }
 
-/// Returns the common ancestor of two commits.
-pub fn get_ancestor_from_repo(a: &[u8], b: &[u8], repo: &str) -> io::Result<String> {
-    __get_ancestor_from_repo(
-        a.to_vec(),
-        b.to_vec(),
-        repo,
-        &mut HashSet::new(),
-        &mut HashSet::new(),
-    )
+/// Returns the common ancestor of two commits. When
+/// there are many merge bases the first one is returned.
+pub fn get_ancestor_from_repo(a: &str, b: &str, repo: &str) -> io::Result<String> {
+    get_merge_bases_from_repo(a, b, repo)?
+        .into_iter()
+        .next()
+        .ok_or(io_err!("No common ancestor"))
 }
 
 /// Moves a branch to a new commit within a repository.
@@ -430,7 +532,7 @@ fn __read_tree_from_repo(
     // Iterate over the objects in the tree.
     for line in tree.lines() {
         // Get data about the object.
-        let (_, otype, hash, name) = parse_ls_tree_entry(line);
+        let (mode, otype, hash, name) = parse_ls_tree_entry(line);
         let path = match path.as_str() {
             "" => name.to_string(),
             _ => path.clone() + "/" + &name,
@@ -438,11 +540,12 @@ fn __read_tree_from_repo(
 
         match otype {
             "blob" => {
-                let entry = IndexEntry::new_from_repo_with_hash(&path, hash)?;
+                let entry = IndexEntry::new_from_repo_with_hash(&path, mode, hash)?;
                 entries.push(entry);
             }
 
             "tree" => __read_tree_from_repo(hash, entries, path, repo)?,
+            "commit" => entries.push(IndexEntry::new_gitlink(&path, hash, false)?),
             _ => return Err(io_err!("invalid object type")),
         }
     }
@@ -527,4 +630,25 @@ mod tests {
         let remotes = get_remote_refs().unwrap();
         println!("remotes: {remotes:?}");
     }
+
+    #[test]
+    fn pkt_lines() {
+        let data = format!(
+            "{}{}0001{}0000",
+            pkt_line("command=ls-refs"),
+            pkt_line("agent=x"),
+            pkt_line("peel")
+        );
+        let mut reader = data.as_bytes();
+
+        let (lines, end) = read_pkt_section(&mut reader).unwrap().unwrap();
+        assert_eq!(lines, ["command=ls-refs", "agent=x"]);
+        assert_eq!(end, PktLine::Delim);
+
+        let (lines, end) = read_pkt_section(&mut reader).unwrap().unwrap();
+        assert_eq!(lines, ["peel"]);
+        assert_eq!(end, PktLine::Flush);
+        assert!(read_pkt_section(&mut reader).unwrap().is_none());
+        assert!(read_pkt_line(&mut "0003".as_bytes()).is_err());
+    }
 }
@@ -1 +1,2 @@
+pub mod object_cache;
 pub mod object_db;
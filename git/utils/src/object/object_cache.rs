@@ -0,0 +1,235 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    sync::{Mutex, OnceLock},
+};
+
+/// Maximum bytes of object data kept by the shared cache.
+pub const CACHE_CAPACITY: usize = 64 * 1024 * 1024;
+
+// An inflated object: (type, size, data).
+type Object = (String, String, Vec<u8>);
+
+// Objects are cached per object database, a hash present
+// in one database may be missing from another. The shared
+// cache keys databases by their canonical path.
+type Key = (String, String);
+
+/// Counters of the object cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// A bounded least recently used cache of inflated objects.
+#[derive(Debug)]
+pub struct ObjectCache {
+    capacity: usize,
+    bytes: usize,
+    tick: u64,
+    objects: HashMap<Key, (Object, u64)>,
+    // Keys by the last time they were used.
+    order: BTreeMap<u64, Key>,
+    hits: u64,
+    misses: u64,
+}
+
+impl ObjectCache {
+    /// Creates a cache holding up to capacity bytes of object data.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            bytes: 0,
+            tick: 0,
+            objects: HashMap::new(),
+            order: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns a copy of the object if it's cached, marking it as recently used.
+    pub fn get(&mut self, offset: &str, hash: &str) -> Option<Object> {
+        let key = (offset.to_string(), hash.to_string());
+        let (object, tick) = match self.objects.get_mut(&key) {
+            Some(entry) => entry,
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+
+        self.hits += 1;
+        self.tick += 1;
+        self.order.remove(tick);
+        self.order.insert(self.tick, key);
+        *tick = self.tick;
+
+        Some(object.clone())
+    }
+
+    /// Caches an object, evicting the least recently used ones
+    /// to make room. Objects bigger than the cache are skipped.
+    pub fn insert(&mut self, offset: &str, hash: &str, object: Object) {
+        let size = object.2.len();
+        if size > self.capacity {
+            return;
+        }
+
+        let key = (offset.to_string(), hash.to_string());
+        self.remove(&key);
+
+        while self.bytes + size > self.capacity {
+            let oldest = match self.order.values().next() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+
+            self.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.bytes += size;
+        self.order.insert(self.tick, key.clone());
+        self.objects.insert(key, (object, self.tick));
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some((object, tick)) = self.objects.remove(key) {
+            self.bytes -= object.2.len();
+            self.order.remove(&tick);
+        }
+    }
+
+    /// Returns the cache's counters.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.objects.len(),
+            bytes: self.bytes,
+        }
+    }
+
+    /// Removes every object and resets the counters.
+    pub fn clear(&mut self) {
+        *self = Self::new(self.capacity);
+    }
+}
+
+// The cache shared by every thread of the process.
+fn cache() -> &'static Mutex<ObjectCache> {
+    static CACHE: OnceLock<Mutex<ObjectCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(ObjectCache::new(CACHE_CAPACITY)))
+}
+
+// A panic while holding the lock can't leave the cache
+// inconsistent enough to matter, so poisoning is ignored.
+fn with_cache<T>(f: impl FnOnce(&mut ObjectCache) -> T) -> T {
+    let mut cache = cache().lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut cache)
+}
+
+// The same database may be reached through different offsets, like
+// ".git" before and after changing the working directory.
+fn object_dir(offset: &str) -> String {
+    let dir = format!("{offset}/objects");
+    match fs::canonicalize(&dir) {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => dir,
+    }
+}
+
+/// Looks up an object in the shared cache.
+pub fn get_cached(offset: &str, hash: &str) -> Option<Object> {
+    let dir = object_dir(offset);
+    with_cache(|cache| cache.get(&dir, hash))
+}
+
+/// Adds an object to the shared cache.
+pub fn insert_cached(offset: &str, hash: &str, object: Object) {
+    let dir = object_dir(offset);
+    with_cache(|cache| cache.insert(&dir, hash, object))
+}
+
+/// Empties the shared cache.
+pub fn clear_cache() {
+    with_cache(|cache| cache.clear())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(data: &[u8]) -> Object {
+        ("blob".to_string(), data.len().to_string(), data.to_vec())
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let mut cache = ObjectCache::new(100);
+        assert_eq!(cache.get(".git", "a"), None);
+
+        cache.insert(".git", "a", blob(b"hello"));
+        assert_eq!(cache.get(".git", "a"), Some(blob(b"hello")));
+        assert_eq!(cache.get("repo", "a"), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!((stats.entries, stats.bytes), (1, 5));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ObjectCache::new(10);
+        cache.insert(".git", "a", blob(b"aaaa"));
+        cache.insert(".git", "b", blob(b"bbbb"));
+
+        // Using a makes b the oldest.
+        cache.get(".git", "a");
+        cache.insert(".git", "c", blob(b"cccc"));
+
+        assert!(cache.get(".git", "a").is_some());
+        assert!(cache.get(".git", "b").is_none());
+        assert!(cache.get(".git", "c").is_some());
+        assert_eq!(cache.stats().bytes, 8);
+    }
+
+    #[test]
+    fn skips_big_objects() {
+        let mut cache = ObjectCache::new(4);
+        cache.insert(".git", "a", blob(b"hello"));
+        cache.insert(".git", "b", blob(b"hey"));
+        cache.insert(".git", "b", blob(b"hey"));
+
+        assert!(cache.get(".git", "a").is_none());
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().bytes, 3);
+    }
+
+    #[test]
+    fn keys_by_object_dir() {
+        let offset = format!(
+            "{}/object-cache-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        fs::create_dir_all(format!("{offset}/objects")).unwrap();
+
+        let hash = "keys_by_object_dir";
+        insert_cached(&offset, hash, blob(b"hello"));
+        assert_eq!(
+            get_cached(
+                &format!("{offset}/../object-cache-{}", std::process::id()),
+                hash
+            ),
+            Some(blob(b"hello"))
+        );
+        assert_eq!(get_cached(".git", hash), None);
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+}
@@ -1,8 +1,12 @@
-use crate::io_err;
+use super::object_cache::{get_cached, insert_cached};
+use crate::{
+    common_dir, io_err,
+    package::{packentry::PackEntry, packindex::PackIndex},
+};
 use libflate::zlib::Decoder;
 use std::{
-    fs,
-    io::{self, Read},
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
     vec,
 };
 
@@ -16,13 +20,12 @@ pub fn header_split(header: &[&str]) -> (String, String) {
 // Looks for the object in the database associated
 // to the given hash and returns its content.
 fn __get_object(hash: &str) -> io::Result<Vec<u8>> {
-    __get_object_with_offset(hash, ".git")
+    __get_object_with_offset(hash, &common_dir())
 }
 
 fn __get_object_with_offset(hash: &str, offset: &str) -> io::Result<Vec<u8>> {
     //println!("offset: {}", offset);
     let path = format!("{offset}/objects/{}/{}", &hash[..2], &hash[2..]);
-    println!("path: {}", path);
     fs::read(path).map_err(|_| io_err!("Could not find object"))
 }
 
@@ -39,7 +42,68 @@ pub fn deflate_object(data: &[u8]) -> io::Result<Vec<u8>> {
 // Tries to find the object in the database, if it finds it, it deflates it
 // and returns a tuple of its header and content
 pub fn get_object(hash: &str) -> io::Result<(String, String, Vec<u8>)> {
-    get_object_with_offset(hash, ".git")
+    get_object_with_offset(hash, &common_dir())
+}
+
+// Returns the index of every PACK file in the
+// database along with the path to the PACK file.
+pub(crate) fn pack_indexes(offset: &str) -> io::Result<Vec<(PackIndex, String)>> {
+    let mut indexes = vec![];
+
+    if let Ok(dir) = fs::read_dir(format!("{offset}/objects/pack")) {
+        for file in dir.flatten() {
+            let path = file.path().to_string_lossy().to_string();
+            if let Some(pack) = path.strip_suffix(".idx") {
+                let index = PackIndex::from_bytes(&fs::read(&path)?)?;
+                indexes.push((index, format!("{pack}.pack")));
+            }
+        }
+    }
+
+    Ok(indexes)
+}
+
+// Looks for the object in the given PACK files of the database.
+fn get_packed_object(
+    hash: &str,
+    offset: &str,
+    indexes: &[(PackIndex, String)],
+) -> io::Result<Option<PackEntry>> {
+    if !hash.is_ascii() {
+        return Ok(None);
+    }
+
+    let hash: Vec<u8> = match (0..hash.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hash[i..i + 2], 16).ok())
+        .collect()
+    {
+        Some(hash) => hash,
+        None => return Ok(None),
+    };
+
+    for (index, path) in indexes {
+        let start = match index.find(&hash) {
+            Some(start) => start,
+            None => continue,
+        };
+
+        // Entries span until the next one or the PACK checksum.
+        let mut file = File::open(path)?;
+        let end = match index.next_offset(start) {
+            Some(end) => end,
+            None => file.metadata()?.len().saturating_sub(20),
+        };
+
+        let mut data = vec![0; end.saturating_sub(start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut data)?;
+
+        let mut reader = BufReader::with_capacity(data.len(), data.as_slice());
+        return PackEntry::read_with_offset(&mut reader, offset).map(Some);
+    }
+
+    Ok(None)
 }
 
 // Completes an incomplete hash by looking for it in the database.
@@ -49,28 +113,44 @@ fn complete_hash(hash: &str, offset: &str) -> io::Result<String> {
     let path = format!("{offset}/objects/{dir_hash}");
 
     let mut files = vec![];
-    for file in fs::read_dir(path)?.flatten() {
+    for file in fs::read_dir(path).into_iter().flatten().flatten() {
         let file_name = match file.file_name().into_string() {
             Ok(name) => name,
             Err(_) => continue,
         };
 
         if file_name.starts_with(file_hash) {
-            // If there are more than one file
-            // with the same hash, it is ambiguous.
-            if !files.is_empty() {
-                return Err(io_err!("Ambiguous hash"));
-            }
-
-            files.push(file_name);
+            files.push(dir_hash.to_string() + &file_name);
         }
     }
 
-    if files.is_empty() {
-        return Err(io_err!("No hash found"));
+    for (index, _) in pack_indexes(offset)? {
+        files.extend(index.hashes().filter(|h| h.starts_with(hash)));
     }
 
-    Ok(dir_hash.to_string() + &files.remove(0))
+    // Objects may be both loose and packed.
+    files.sort();
+    files.dedup();
+
+    // If there are more than one file
+    // with the same hash, it is ambiguous.
+    match files.len() {
+        0 => Err(io_err!("No hash found")),
+        1 => Ok(files.remove(0)),
+        _ => Err(io_err!("Ambiguous hash")),
+    }
+}
+
+/// Returns the full hash of the object an abbreviated
+/// hash, of at least 2 characters, refers to.
+pub fn expand_hash_with_offset(hash: &str, offset: &str) -> io::Result<String> {
+    if hash.len() < 2 || hash.len() > 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Err(io_err!("Invalid hash"))
+    } else if hash.len() < 40 {
+        complete_hash(hash, offset)
+    } else {
+        Ok(hash.to_string())
+    }
 }
 
 /// Looks for an object in a database with a path offset.
@@ -83,8 +163,57 @@ pub fn get_object_with_offset(hash: &str, offset: &str) -> io::Result<(String, S
         hash.to_string()
     };
 
-    let data = __get_object_with_offset(&hash, offset)?;
-    let data = deflate_object(&data)?;
+    read_cached_object(&hash, offset, None)
+}
+
+/// Looks for an object by it's full hash, using the already
+/// loaded PACK indexes of the database for packed objects.
+pub(crate) fn get_object_with_indexes(
+    hash: &str,
+    offset: &str,
+    indexes: &[(PackIndex, String)],
+) -> io::Result<(String, String, Vec<u8>)> {
+    read_cached_object(hash, offset, Some(indexes))
+}
+
+// Reads an object through the cache. The PACK
+// indexes are loaded if they aren't given.
+fn read_cached_object(
+    hash: &str,
+    offset: &str,
+    indexes: Option<&[(PackIndex, String)]>,
+) -> io::Result<(String, String, Vec<u8>)> {
+    // Objects never change, so they are only read once.
+    if let Some(object) = get_cached(offset, hash) {
+        return Ok(object);
+    }
+
+    let object = read_object(hash, offset, indexes)?;
+    insert_cached(offset, hash, object.clone());
+    Ok(object)
+}
+
+// Reads and inflates an object from the database.
+fn read_object(
+    hash: &str,
+    offset: &str,
+    indexes: Option<&[(PackIndex, String)]>,
+) -> io::Result<(String, String, Vec<u8>)> {
+    let packed = |hash| match indexes {
+        Some(indexes) => get_packed_object(hash, offset, indexes),
+        None => get_packed_object(hash, offset, &pack_indexes(offset)?),
+    };
+
+    let data = match __get_object_with_offset(hash, offset) {
+        Ok(data) => deflate_object(&data)?,
+        Err(e) => match packed(hash)? {
+            Some(PackEntry { otype, data }) => {
+                let osize = data.len().to_string();
+                return Ok((otype, osize, data));
+            }
+            None => return Err(e),
+        },
+    };
 
     // Find separator between header and data.
     let sep = match data.iter().position(|&x| x == b'\0') {
@@ -1,6 +1,6 @@
-use super::packentry::PackEntry;
+use super::{packentry::PackEntry, packindex::PackIndex};
 use crate::{
-    io_err,
+    common_dir, io_err,
     object::object_db::get_object_with_offset,
     parse_tag,
     plumbing::{
@@ -9,7 +9,10 @@ use crate::{
     },
 };
 use sha1::{Digest, Sha1};
-use std::io::{self, BufReader, Read};
+use std::{
+    collections::HashSet,
+    io::{self, BufReader, Read},
+};
 
 #[macro_export]
 macro_rules! pack_err {
@@ -18,6 +21,24 @@ macro_rules! pack_err {
     };
 }
 
+// Keeps a copy of every byte read so the
+// PACK checksum can be validated at the end.
+struct ChecksumReader<R> {
+    inner: R,
+    read: Vec<u8>,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// The (offset, crc32) of every entry of a PACK file.
+type EntryOffsets = Vec<(u64, u32)>;
+
 /// Models the contents of a PACK file.
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -29,11 +50,51 @@ pub struct Pack {
 impl Pack {
     /// Reads the packfile, storing it's entries in the object database.
     pub fn unpack<R: Read>(reader: BufReader<R>) -> io::Result<()> {
-        Self::unpack_with_offset(reader, ".git")
+        Self::unpack_with_offset(reader, &common_dir())
     }
 
     /// Reads the packfile, storing it's entries in an object databse with the given offset.
-    pub fn unpack_with_offset<R: Read>(mut reader: BufReader<R>, offset: &str) -> io::Result<()> {
+    pub fn unpack_with_offset<R: Read>(reader: BufReader<R>, offset: &str) -> io::Result<()> {
+        Self::read_entries_with_offset(reader, offset, |entry| entry.dump(offset))
+    }
+
+    /// Reads the packfile without storing it's entries.
+    pub fn read_with_offset<R: Read>(reader: BufReader<R>, offset: &str) -> io::Result<Self> {
+        let mut entries = vec![];
+        Self::read_entries_with_offset(reader, offset, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+
+        Ok(Self {
+            version: 2,
+            entries,
+        })
+    }
+
+    /// Returns the entries of the PACK file.
+    pub fn entries(&self) -> &[PackEntry] {
+        &self.entries
+    }
+
+    // Reads the packfile calling f with every entry
+    // and validates it's trailing checksum.
+    fn read_entries_with_offset<R: Read, F>(
+        reader: BufReader<R>,
+        offset: &str,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(PackEntry) -> io::Result<()>,
+    {
+        // Entries are decoded from the reader's buffer,
+        // so keep the capacity the caller chose.
+        let inner = ChecksumReader {
+            inner: reader,
+            read: vec![],
+        };
+        let mut reader = BufReader::with_capacity(inner.inner.capacity(), inner);
+
         // P A C K line
         let mut buf = [0; 4];
         reader.read_exact(&mut buf)?;
@@ -57,18 +118,34 @@ impl Pack {
 
         // Read all the entries.
         for _ in 0..entries_len {
-            PackEntry::unpack_with_offset(&mut reader, offset)?;
+            f(PackEntry::read_with_offset(&mut reader, offset)?)?;
         }
+
         // 20-byte SHA-1 checksum of the packed content.
+        let read = reader.get_ref().read.len() - reader.buffer().len();
+        let hash = Sha1::digest(&reader.get_ref().read[..read]);
+
+        let mut checksum = [0; 20];
+        reader.read_exact(&mut checksum)?;
+        if hash[..] != checksum {
+            return pack_err!("Invalid PACK checksum");
+        }
 
         Ok(())
     }
 
+    // Adds the object and the ones it points to, skipping
+    // those already seen: packs can't have duplicates.
     fn make_pack_entries_with_offset(
         hash: String,
         entries: &mut Vec<PackEntry>,
+        seen: &mut HashSet<String>,
         offset: &str,
     ) -> io::Result<()> {
+        if !seen.insert(hash.clone()) {
+            return Ok(());
+        }
+
         let (otype, _, data) = get_object_with_offset(hash.as_str(), offset)?;
 
         let entry = match otype.as_str() {
@@ -79,8 +156,15 @@ impl Pack {
 
                 // Add tree items to entries.
                 for line in tree.lines() {
-                    let (_, _, hash, _) = parse_ls_tree_entry(line);
-                    Self::make_pack_entries_with_offset(hash.to_string(), entries, offset)?;
+                    let (_, otype, hash, _) = parse_ls_tree_entry(line);
+
+                    // Gitlinks point to commits in other
+                    // repositories, we don't have them.
+                    if otype == "commit" {
+                        continue;
+                    }
+
+                    Self::make_pack_entries_with_offset(hash.to_string(), entries, seen, offset)?;
                 }
 
                 PackEntry::new(&otype, data)
@@ -89,9 +173,9 @@ impl Pack {
             "commit" => {
                 // Add commit tree to entries.
                 let hash = get_commit_root(&data)?;
-                Self::make_pack_entries_with_offset(hash, entries, offset)?;
+                Self::make_pack_entries_with_offset(hash, entries, seen, offset)?;
                 for parent in get_parent_commits(&data).unwrap_or_default() {
-                    Self::make_pack_entries_with_offset(parent, entries, offset)?;
+                    Self::make_pack_entries_with_offset(parent, entries, seen, offset)?;
                 }
 
                 PackEntry::new(&otype, data)
@@ -100,7 +184,7 @@ impl Pack {
             "tag" => {
                 // Add tag object to entries.
                 let object = parse_tag(&data).map_err(|_| io_err!("Invalid tag object"))?;
-                Self::make_pack_entries_with_offset(object, entries, offset)?;
+                Self::make_pack_entries_with_offset(object, entries, seen, offset)?;
                 PackEntry::new(&otype, data)
             }
 
@@ -111,10 +195,84 @@ impl Pack {
         Ok(())
     }
 
+    // Marks the commits reachable from the haves we have, and the
+    // objects in their trees, as seen so they are left out of the pack.
+    fn mark_common_with_offset(
+        haves: &[String],
+        seen: &mut HashSet<String>,
+        offset: &str,
+    ) -> io::Result<()> {
+        let mut commits: Vec<String> = haves
+            .iter()
+            .filter(|have| get_object_with_offset(have, offset).is_ok())
+            .cloned()
+            .collect();
+
+        for have in commits.clone() {
+            let (otype, _, data) = get_object_with_offset(&have, offset)?;
+            if otype == "commit" {
+                Self::mark_tree_with_offset(get_commit_root(&data)?, seen, offset)?;
+            }
+        }
+
+        while let Some(hash) = commits.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+
+            let (otype, _, data) = get_object_with_offset(&hash, offset)?;
+            if otype == "commit" {
+                commits.extend(get_parent_commits(&data).unwrap_or_default());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Marks the tree and everything in it as seen.
+    fn mark_tree_with_offset(
+        hash: String,
+        seen: &mut HashSet<String>,
+        offset: &str,
+    ) -> io::Result<()> {
+        if !seen.insert(hash.clone()) {
+            return Ok(());
+        }
+
+        for line in ls_tree_with_offset(&hash, offset)?.lines() {
+            let (_, otype, hash, _) = parse_ls_tree_entry(line);
+            match otype {
+                "tree" => Self::mark_tree_with_offset(hash.to_string(), seen, offset)?,
+                "blob" => {
+                    seen.insert(hash.to_string());
+                }
+                // Gitlinks are never packed.
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new Pack with the objects reachable from the given
+    /// references, in an object database with the given offset.
     pub fn from_with_offset(refs: Vec<String>, offset: &str) -> io::Result<Self> {
+        Self::from_haves_with_offset(refs, &[], offset)
+    }
+
+    /// Creates a new Pack with the objects reachable from the given
+    /// references that can't be reached from the haves, which are
+    /// the commits the other side already has.
+    pub fn from_haves_with_offset(
+        refs: Vec<String>,
+        haves: &[String],
+        offset: &str,
+    ) -> io::Result<Self> {
         let mut entries = vec![];
+        let mut seen = HashSet::new();
+        Self::mark_common_with_offset(haves, &mut seen, offset)?;
         for hash in refs {
-            Self::make_pack_entries_with_offset(hash, &mut entries, offset)?;
+            Self::make_pack_entries_with_offset(hash, &mut entries, &mut seen, offset)?;
         }
 
         Ok(Self {
@@ -125,12 +283,42 @@ impl Pack {
 
     /// Creates a new Pack from the given references.
     pub fn from(references: Vec<String>) -> io::Result<Self> {
-        Self::from_with_offset(references, ".git")
+        Self::from_with_offset(references, &common_dir())
+    }
+
+    /// Creates a new Pack with the given entries.
+    pub fn new(entries: Vec<PackEntry>) -> Self {
+        Self {
+            version: 2,
+            entries,
+        }
     }
 
     /// Returns the bytes of the PACK file.
     pub fn as_bytes(&self) -> io::Result<Vec<u8>> {
+        Ok(self.encode()?.0)
+    }
+
+    /// Returns the bytes of the PACK file along with the bytes of it's index.
+    pub fn as_bytes_with_index(&self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let (pack_file, offsets) = self.encode()?;
+
+        let mut entries = vec![];
+        for (entry, (offset, crc)) in self.entries.iter().zip(offsets) {
+            let mut object = format!("{} {}\0", entry.otype, entry.data.len()).into_bytes();
+            object.extend(&entry.data);
+            entries.push((Sha1::digest(&object).to_vec(), crc, offset));
+        }
+
+        let index = PackIndex::new(entries).as_bytes(&pack_file[pack_file.len() - 20..]);
+        Ok((pack_file, index))
+    }
+
+    // Encodes the PACK file, returning it's bytes and
+    // the (offset, crc32) of every entry.
+    fn encode(&self) -> io::Result<(Vec<u8>, EntryOffsets)> {
         let mut pack_file = vec![];
+        let mut offsets = vec![];
 
         // P A C K line
         pack_file.extend(b"PACK");
@@ -143,6 +331,7 @@ impl Pack {
 
         for entry in &self.entries {
             let entry = entry.as_bytes()?;
+            offsets.push((pack_file.len() as u64, PackIndex::crc32(&entry)));
             pack_file.extend(entry);
         }
 
@@ -150,6 +339,107 @@ impl Pack {
         let hash = Sha1::digest(&pack_file);
         pack_file.extend(&hash);
 
-        Ok(pack_file)
+        Ok((pack_file, offsets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plumbing::hash_object::__hash_object;
+    use std::fs;
+
+    fn pack_bytes() -> Vec<u8> {
+        let pack = Pack {
+            version: 2,
+            entries: vec![
+                PackEntry::new("blob", b"hello world\n".to_vec()),
+                PackEntry::new("blob", b"another blob\n".to_vec()),
+            ],
+        };
+
+        pack.as_bytes().unwrap()
+    }
+
+    #[test]
+    fn read_pack() {
+        let bytes = pack_bytes();
+        let reader = BufReader::with_capacity(bytes.len(), bytes.as_slice());
+        let pack = Pack::read_with_offset(reader, ".git").unwrap();
+
+        assert_eq!(pack.entries().len(), 2);
+        assert_eq!(pack.entries()[1].data, b"another blob\n");
+    }
+
+    #[test]
+    fn pack_index() {
+        let pack = Pack::new(vec![PackEntry::new("blob", b"hello world\n".to_vec())]);
+        let (bytes, index) = pack.as_bytes_with_index().unwrap();
+        let index = PackIndex::from_bytes(&index).unwrap();
+
+        let hash = index.hashes().next().unwrap();
+        assert_eq!(hash, "3b18e512dba79e4c8300dd08aeb37f8e728b8dad");
+        assert_eq!(
+            index.find(&Sha1::digest(b"blob 12\0hello world\n")),
+            Some(12)
+        );
+        assert_eq!(bytes[..4], *b"PACK");
+    }
+
+    #[test]
+    fn invalid_checksum() {
+        let mut bytes = pack_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let reader = BufReader::with_capacity(bytes.len(), bytes.as_slice());
+        assert!(Pack::read_with_offset(reader, ".git").is_err());
+    }
+
+    // Writes a commit with a single file, returning it's hash.
+    fn commit(offset: &str, content: &[u8], parent: Option<&str>) -> String {
+        let blob = __hash_object(content, "blob", true, offset).unwrap().0;
+        let mut tree = b"100644 a.txt\0".to_vec();
+        tree.extend(blob);
+        let tree = __hash_object(&tree, "tree", true, offset).unwrap().1;
+
+        let parent = parent.map(|p| format!("parent {p}\n")).unwrap_or_default();
+        let commit = format!(
+            "tree {tree}\n{parent}author pepito <pepito@fi.uba.ar> 1700000000 -0300\n\
+             committer pepito <pepito@fi.uba.ar> 1700000000 -0300\n\nmsg\n"
+        );
+        __hash_object(commit.as_bytes(), "commit", true, offset)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn leaves_out_common_objects() {
+        let offset = format!(
+            "{}/pack-haves-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let _ = fs::remove_dir_all(&offset);
+        fs::create_dir_all(format!("{offset}/objects")).unwrap();
+
+        let first = commit(&offset, b"hello\n", None);
+        let second = commit(&offset, b"bye\n", Some(&first));
+
+        // Commit, tree and blob of both commits.
+        let pack = Pack::from_with_offset(vec![second.clone()], &offset).unwrap();
+        assert_eq!(pack.entries().len(), 6);
+
+        // Only the new commit, tree and blob.
+        let pack = Pack::from_haves_with_offset(vec![second.clone()], &[first], &offset).unwrap();
+        assert_eq!(pack.entries().len(), 3);
+        assert!(pack.entries().iter().all(|entry| entry.data != b"hello\n"));
+
+        // Haves we don't have are ignored.
+        let missing = "0".repeat(40);
+        let pack = Pack::from_haves_with_offset(vec![second], &[missing], &offset).unwrap();
+        assert_eq!(pack.entries().len(), 6);
+
+        fs::remove_dir_all(offset).unwrap();
     }
 }
@@ -1,5 +1,5 @@
 use super::super::plumbing::hash_object::__hash_object;
-use crate::{io_err, object::object_db::get_object_with_offset, pack_err};
+use crate::{common_dir, io_err, object::object_db::get_object_with_offset, pack_err};
 use flate2::read::ZlibDecoder;
 use libflate::zlib::Encoder;
 use std::io::{self, BufRead, BufReader, Read, Write};
@@ -235,11 +235,17 @@ impl PackEntry {
 
     /// Saves the entry to the object database.
     pub fn unpack<R: Read>(reader: &mut BufReader<R>) -> io::Result<()> {
-        Self::unpack_with_offset(reader, ".git")
+        Self::unpack_with_offset(reader, &common_dir())
     }
 
     /// Saves the entry to the object database using an offset for the .git folder.
     pub fn unpack_with_offset<R: Read>(reader: &mut BufReader<R>, offset: &str) -> io::Result<()> {
+        Self::read_with_offset(reader, offset)?.dump(offset)
+    }
+
+    /// Reads the next entry of a PACK file. Delta bases are
+    /// looked up in the object database at the given offset.
+    pub fn read_with_offset<R: Read>(reader: &mut BufReader<R>, offset: &str) -> io::Result<Self> {
         let mut byte = [0; 1];
         let mut size = vec![];
 
@@ -251,7 +257,6 @@ impl PackEntry {
             Some(otype) => otype,
         };
 
-        print!("otype: {otype}, ");
         // Store first size part.
         size.push(byte[0] & LENGMASK);
 
@@ -264,16 +269,12 @@ impl PackEntry {
         // Calculate size.
         let size = size[0] as usize | Self::calculate_size(&size[1..], 4);
 
-        println!("size: {size} ");
         // Get object data.
-        let entry = match typenum {
+        match typenum {
             1..=4 => Self::process_loose(reader, otype, size),
             6 | 7 => Self::process_delta(reader, otype, offset),
             _ => pack_err!("Invalid object type")?,
-        }?;
-
-        entry.dump(offset)?;
-        Ok(())
+        }
     }
 
     /// Writes self in object database.
@@ -0,0 +1,184 @@
+use crate::{pack_err, plumbing::ls_tree::hash_to_str};
+use flate2::Crc;
+use sha1::{Digest, Sha1};
+use std::io;
+
+// Offsets with this bit set point to the 8-byte offsets table.
+const LARGEMASK: u32 = 0x80000000;
+
+/// Models a version 2 PACK index, which maps the
+/// hash of every object in a PACK file to it's offset.
+#[derive(Debug)]
+pub struct PackIndex {
+    // (hash, crc32, offset) sorted by hash.
+    entries: Vec<(Vec<u8>, u32, u64)>,
+}
+
+impl PackIndex {
+    /// Creates a new PackIndex from (hash, crc32, offset) entries.
+    pub fn new(mut entries: Vec<(Vec<u8>, u32, u64)>) -> Self {
+        entries.sort();
+        Self { entries }
+    }
+
+    /// Calculates the CRC32 of a PACK entry as stored in the index.
+    pub fn crc32(entry: &[u8]) -> u32 {
+        let mut crc = Crc::new();
+        crc.update(entry);
+        crc.sum()
+    }
+
+    /// Parses the bytes of a .idx file.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        if data.len() < 8 + 256 * 4 + 40 || data[..4] != *b"\xfftOc" {
+            return pack_err!("Invalid PACK index");
+        }
+
+        if data[4..8] != 2u32.to_be_bytes() {
+            return pack_err!("Wrong PACK index version");
+        }
+
+        let (content, checksum) = data.split_at(data.len() - 20);
+        if Sha1::digest(content)[..] != *checksum {
+            return pack_err!("Invalid PACK index checksum");
+        }
+
+        let u32_at = |i: usize| {
+            let mut buf = [0; 4];
+            buf.copy_from_slice(&data[i..i + 4]);
+            u32::from_be_bytes(buf)
+        };
+
+        // The last fanout entry is the number of objects.
+        let len = u32_at(8 + 255 * 4) as usize;
+        let hashes = 8 + 256 * 4;
+        let crcs = hashes + len * 20;
+        let offsets = crcs + len * 4;
+        let large = offsets + len * 4;
+        if data.len() < large + 40 {
+            return pack_err!("Invalid PACK index");
+        }
+
+        let mut entries = Vec::with_capacity(len);
+        for i in 0..len {
+            let hash = data[hashes + i * 20..hashes + (i + 1) * 20].to_vec();
+            let crc = u32_at(crcs + i * 4);
+            let offset = match u32_at(offsets + i * 4) {
+                offset if offset & LARGEMASK == 0 => offset as u64,
+                offset => {
+                    let i = large + (offset & !LARGEMASK) as usize * 8;
+                    if data.len() < i + 8 + 40 {
+                        return pack_err!("Invalid PACK index");
+                    }
+
+                    (u32_at(i) as u64) << 32 | u32_at(i + 4) as u64
+                }
+            };
+
+            entries.push((hash, crc, offset));
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Returns the bytes of the .idx file for the PACK file with the given checksum.
+    pub fn as_bytes(&self, pack_checksum: &[u8]) -> Vec<u8> {
+        let mut idx = b"\xfftOc".to_vec();
+        idx.extend(2u32.to_be_bytes());
+
+        // Number of objects whose first byte is less or equal to the index.
+        let mut fanout = [0u32; 256];
+        for (hash, _, _) in &self.entries {
+            fanout[hash[0] as usize] += 1;
+        }
+        for i in 1..256 {
+            fanout[i] += fanout[i - 1];
+        }
+        for count in fanout {
+            idx.extend(count.to_be_bytes());
+        }
+
+        for (hash, _, _) in &self.entries {
+            idx.extend(hash);
+        }
+
+        for (_, crc, _) in &self.entries {
+            idx.extend(crc.to_be_bytes());
+        }
+
+        let mut large = vec![];
+        for (_, _, offset) in &self.entries {
+            if *offset < LARGEMASK as u64 {
+                idx.extend((*offset as u32).to_be_bytes());
+            } else {
+                idx.extend((LARGEMASK | large.len() as u32).to_be_bytes());
+                large.push(*offset);
+            }
+        }
+
+        for offset in large {
+            idx.extend(offset.to_be_bytes());
+        }
+
+        idx.extend(pack_checksum);
+        let hash = Sha1::digest(&idx);
+        idx.extend(hash);
+        idx
+    }
+
+    /// Returns the offset of the object in the PACK file.
+    pub fn find(&self, hash: &[u8]) -> Option<u64> {
+        self.entries
+            .binary_search_by(|(h, _, _)| h.as_slice().cmp(hash))
+            .ok()
+            .map(|i| self.entries[i].2)
+    }
+
+    /// Returns the offset of the entry that follows the
+    /// one at the given offset, the end of the last entry
+    /// is the start of the PACK checksum.
+    pub fn next_offset(&self, offset: u64) -> Option<u64> {
+        self.entries
+            .iter()
+            .map(|(_, _, o)| *o)
+            .filter(|o| *o > offset)
+            .min()
+    }
+
+    /// Returns the hash of every object in the PACK file.
+    pub fn hashes(&self) -> impl Iterator<Item = String> + '_ {
+        self.entries.iter().map(|(hash, _, _)| hash_to_str(hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let index = PackIndex::new(vec![
+            (vec![0xbb; 20], 2, 100),
+            (vec![0xaa; 20], 1, 12),
+            (vec![0xcc; 20], 3, 1 << 32),
+        ]);
+
+        let bytes = index.as_bytes(&[0; 20]);
+        let index = PackIndex::from_bytes(&bytes).unwrap();
+
+        assert_eq!(index.find(&[0xaa; 20]), Some(12));
+        assert_eq!(index.find(&[0xcc; 20]), Some(1 << 32));
+        assert_eq!(index.find(&[0xdd; 20]), None);
+        assert_eq!(index.next_offset(12), Some(100));
+        assert_eq!(index.hashes().next(), Some("aa".repeat(20)));
+    }
+
+    #[test]
+    fn invalid_checksum() {
+        let index = PackIndex::new(vec![(vec![0xaa; 20], 1, 12)]);
+        let mut bytes = index.as_bytes(&[0; 20]);
+        bytes[8] ^= 0xff;
+
+        assert!(PackIndex::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,674 @@
+use crate::{
+    common_dir,
+    object::object_db::get_object_with_offset,
+    pack_err, parse_tag,
+    plumbing::{
+        commit::{get_commit_root, get_committer_and_time, get_parent_commits},
+        fsck::roots,
+        ls_tree::hash_to_str,
+    },
+};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs, io,
+};
+
+/// Generation of the commits missing from the commit-graph.
+pub const GENERATION_INFINITY: u32 = u32::MAX;
+
+// Parent positions with special meanings.
+const PARENT_NONE: u32 = 0x70000000;
+const PARENT_EDGE: u32 = 0x80000000;
+
+// Merge base walk flags.
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// A commit as needed by history walks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphCommit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub generation: u32,
+    pub date: i64,
+}
+
+/// Models the commit-graph file, which caches the ancestry of
+/// every reachable commit so history walks don't inflate them.
+#[derive(Debug, Default)]
+pub struct CommitGraph {
+    commits: HashMap<String, GraphCommit>,
+}
+
+// Parses a 20 byte hash from it's hexadecimal representation.
+fn hash_from_str(hash: &str) -> io::Result<Vec<u8>> {
+    if hash.len() != 40 || !hash.is_ascii() {
+        return pack_err!("Invalid hash");
+    }
+
+    (0..40)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hash[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .or(pack_err!("Invalid hash"))
+}
+
+// Reads a commit object, leaving it's generation to be calculated.
+fn parse_commit(hash: &str, offset: &str) -> io::Result<GraphCommit> {
+    let (otype, _, data) = get_object_with_offset(hash, offset)?;
+    if otype != "commit" {
+        return pack_err!("Not a commit");
+    }
+
+    let date = get_committer_and_time(&data)
+        .and_then(|(_, time)| time.split(' ').next()?.parse().ok())
+        .unwrap_or_default();
+
+    Ok(GraphCommit {
+        tree: get_commit_root(&data)?,
+        parents: get_parent_commits(&data).unwrap_or_default(),
+        generation: GENERATION_INFINITY,
+        date,
+    })
+}
+
+impl CommitGraph {
+    /// Creates a commit-graph from the given commits, calculating their
+    /// generation numbers. Every parent must be among the commits.
+    pub fn new(mut commits: HashMap<String, GraphCommit>) -> io::Result<Self> {
+        let hashes: Vec<_> = commits.keys().cloned().collect();
+        let mut generations: HashMap<String, u32> = HashMap::new();
+
+        // The commits whose parents are being calculated, the ones
+        // on the current path. Only reaching one of them is a cycle.
+        let mut visiting = HashSet::new();
+
+        for hash in hashes {
+            let mut stack = vec![hash];
+            while let Some(hash) = stack.last().cloned() {
+                if generations.contains_key(&hash) {
+                    stack.pop();
+                    continue;
+                }
+
+                let parents = match commits.get(&hash) {
+                    Some(commit) => &commit.parents,
+                    None => return pack_err!("Commit-graph is missing a parent"),
+                };
+
+                // Parents are calculated before their children.
+                let pending: Vec<_> = parents
+                    .iter()
+                    .filter(|parent| !generations.contains_key(*parent))
+                    .cloned()
+                    .collect();
+
+                if pending.is_empty() {
+                    let generation = parents.iter().map(|p| generations[p]).max().unwrap_or(0);
+                    visiting.remove(&hash);
+                    generations.insert(hash, generation + 1);
+                    stack.pop();
+                } else if pending.iter().any(|parent| visiting.contains(parent)) {
+                    return pack_err!("Commit-graph has a cycle");
+                } else {
+                    visiting.insert(hash);
+                    stack.extend(pending);
+                }
+            }
+        }
+
+        for (hash, commit) in commits.iter_mut() {
+            commit.generation = generations[hash];
+        }
+
+        Ok(Self { commits })
+    }
+
+    /// Returns the commit with the given hash if it's in the commit-graph.
+    pub fn get(&self, hash: &str) -> Option<&GraphCommit> {
+        self.commits.get(hash)
+    }
+
+    /// Returns the number of commits in the commit-graph.
+    pub fn len(&self) -> usize {
+        self.commits.len()
+    }
+
+    /// Returns true if the commit-graph has no commits.
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty()
+    }
+
+    /// Returns the bytes of the commit-graph file.
+    pub fn as_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut hashes: Vec<_> = self.commits.keys().collect();
+        hashes.sort();
+
+        let positions: HashMap<_, _> = hashes
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| (*hash, i as u32))
+            .collect();
+
+        // OID Fanout, OID Lookup, Commit Data and Extra Edge List chunks.
+        let mut fanout = [0u32; 256];
+        let mut lookup = vec![];
+        let mut data = vec![];
+        let mut edges: Vec<u32> = vec![];
+
+        for hash in &hashes {
+            let bytes = hash_from_str(hash)?;
+            fanout[bytes[0] as usize] += 1;
+            lookup.extend(bytes);
+
+            let commit = &self.commits[*hash];
+            data.extend(hash_from_str(&commit.tree)?);
+
+            let mut parents = vec![];
+            for parent in &commit.parents {
+                match positions.get(parent) {
+                    Some(position) => parents.push(*position),
+                    None => return pack_err!("Commit-graph is missing a parent"),
+                }
+            }
+
+            let (first, second) = match parents.as_slice() {
+                [] => (PARENT_NONE, PARENT_NONE),
+                [first] => (*first, PARENT_NONE),
+                [first, second] => (*first, *second),
+                [first, rest @ ..] => {
+                    let edge = PARENT_EDGE | edges.len() as u32;
+                    edges.extend(rest);
+                    if let Some(last) = edges.last_mut() {
+                        *last |= PARENT_EDGE;
+                    }
+
+                    (*first, edge)
+                }
+            };
+
+            data.extend(first.to_be_bytes());
+            data.extend(second.to_be_bytes());
+
+            // 30 bits of generation followed by 34 bits of date.
+            let date = commit.date.clamp(0, (1 << 34) - 1) as u64;
+            data.extend(((commit.generation as u64) << 34 | date).to_be_bytes());
+        }
+
+        for i in 1..fanout.len() {
+            fanout[i] += fanout[i - 1];
+        }
+
+        let mut chunks: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (
+                b"OIDF",
+                fanout.iter().flat_map(|n| n.to_be_bytes()).collect(),
+            ),
+            (b"OIDL", lookup),
+            (b"CDAT", data),
+        ];
+        if !edges.is_empty() {
+            chunks.push((
+                b"EDGE",
+                edges.iter().flat_map(|n| n.to_be_bytes()).collect(),
+            ));
+        }
+
+        // Header: signature, version, hash version, chunks, base graphs.
+        let mut graph = b"CGPH".to_vec();
+        graph.extend([1, 1, chunks.len() as u8, 0]);
+
+        // Table of contents, terminated by a zero id.
+        let mut offset = (graph.len() + (chunks.len() + 1) * 12) as u64;
+        for (id, chunk) in &chunks {
+            graph.extend(*id);
+            graph.extend(offset.to_be_bytes());
+            offset += chunk.len() as u64;
+        }
+        graph.extend([0; 4]);
+        graph.extend(offset.to_be_bytes());
+
+        for (_, chunk) in chunks {
+            graph.extend(chunk);
+        }
+
+        let hash = Sha1::digest(&graph);
+        graph.extend(hash);
+        Ok(graph)
+    }
+
+    /// Parses the bytes of a commit-graph file.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        if data.len() < 8 + 12 + 20 || data[..4] != *b"CGPH" || data[4] != 1 || data[5] != 1 {
+            return pack_err!("Invalid commit-graph");
+        }
+
+        let (content, checksum) = data.split_at(data.len() - 20);
+        if Sha1::digest(content)[..] != *checksum {
+            return pack_err!("Invalid commit-graph checksum");
+        }
+
+        let u32_at = |i: usize| {
+            let mut buf = [0; 4];
+            buf.copy_from_slice(&content[i..i + 4]);
+            u32::from_be_bytes(buf)
+        };
+
+        // Find where every chunk starts and ends.
+        let len = data[6] as usize;
+        if content.len() < 8 + (len + 1) * 12 {
+            return pack_err!("Invalid commit-graph");
+        }
+
+        let mut chunks = HashMap::new();
+        for i in 0..len {
+            let entry = 8 + i * 12;
+            let start = (u32_at(entry + 4) as usize) << 32 | u32_at(entry + 8) as usize;
+            let end = (u32_at(entry + 16) as usize) << 32 | u32_at(entry + 20) as usize;
+            match content.get(start..end) {
+                Some(chunk) => chunks.insert(&content[entry..entry + 4], chunk),
+                None => return pack_err!("Invalid commit-graph chunk"),
+            };
+        }
+
+        let (lookup, commits) = match (chunks.get(&b"OIDL"[..]), chunks.get(&b"CDAT"[..])) {
+            (Some(lookup), Some(commits)) if lookup.len() / 20 * 36 == commits.len() => {
+                (lookup, commits)
+            }
+            _ => return pack_err!("Invalid commit-graph chunk"),
+        };
+        let edges = chunks.get(&b"EDGE"[..]).copied().unwrap_or_default();
+
+        let hashes: Vec<_> = lookup.chunks(20).map(hash_to_str).collect();
+        let position = |p: u32| hashes.get(p as usize).cloned().ok_or(());
+        let be = |data: &[u8], i: usize| {
+            let mut buf = [0; 4];
+            buf.copy_from_slice(&data[i..i + 4]);
+            u32::from_be_bytes(buf)
+        };
+
+        let mut graph = HashMap::new();
+        for (hash, data) in hashes.iter().zip(commits.chunks(36)) {
+            let mut parents = vec![];
+            for p in [be(data, 20), be(data, 24)] {
+                if p == PARENT_NONE {
+                    continue;
+                }
+
+                if p & PARENT_EDGE == 0 {
+                    parents.push(position(p).or(pack_err!("Invalid parent position"))?);
+                    continue;
+                }
+
+                // Octopus merges list the rest of their parents in EDGE.
+                let mut i = (p & !PARENT_EDGE) as usize * 4;
+                loop {
+                    if edges.len() < i + 4 {
+                        return pack_err!("Invalid commit-graph edge");
+                    }
+
+                    let edge = be(edges, i);
+                    let parent = position(edge & !PARENT_EDGE);
+                    parents.push(parent.or(pack_err!("Invalid parent position"))?);
+                    if edge & PARENT_EDGE != 0 {
+                        break;
+                    }
+                    i += 4;
+                }
+            }
+
+            let generation = be(data, 28) >> 2;
+            let date = ((be(data, 28) as i64 & 0b11) << 32) | be(data, 32) as i64;
+            let commit = GraphCommit {
+                tree: hash_to_str(&data[..20]),
+                parents,
+                generation,
+                date,
+            };
+
+            graph.insert(hash.clone(), commit);
+        }
+
+        Ok(Self { commits: graph })
+    }
+}
+
+// Follows tags until an object that isn't one.
+fn peel(mut hash: String, offset: &str) -> String {
+    while let Ok((otype, _, data)) = get_object_with_offset(&hash, offset) {
+        match (otype.as_str(), parse_tag(&data)) {
+            ("tag", Ok(object)) => hash = object,
+            _ => break,
+        }
+    }
+
+    hash
+}
+
+// Returns the commits reachable from the
+// references of the repository at the given offset.
+fn reachable_commits(offset: &str) -> io::Result<HashMap<String, GraphCommit>> {
+    let mut commits = HashMap::new();
+    let mut stack = vec![];
+
+    for (name, hash) in roots(offset)? {
+        // Staged blobs are not commits.
+        if name == "index" {
+            continue;
+        }
+
+        // References may point to blobs or trees too.
+        let hash = peel(hash, offset);
+        if let Ok(commit) = parse_commit(&hash, offset) {
+            stack.push((hash, commit));
+        }
+    }
+
+    while let Some((hash, commit)) = stack.pop() {
+        if commits.contains_key(&hash) {
+            continue;
+        }
+
+        for parent in &commit.parents {
+            if !commits.contains_key(parent) {
+                stack.push((parent.clone(), parse_commit(parent, offset)?));
+            }
+        }
+
+        commits.insert(hash, commit);
+    }
+
+    Ok(commits)
+}
+
+/// Writes the commit-graph of the repository at the given
+/// offset with every commit reachable from it's references.
+pub fn write_commit_graph_with_offset(offset: &str) -> io::Result<()> {
+    let graph = CommitGraph::new(reachable_commits(offset)?)?;
+
+    let dir = format!("{offset}/objects/info");
+    fs::create_dir_all(&dir)?;
+    fs::write(format!("{dir}/commit-graph"), graph.as_bytes()?)
+}
+
+/// Writes the commit-graph of the local repository.
+pub fn write_commit_graph() -> io::Result<()> {
+    write_commit_graph_with_offset(&common_dir())
+}
+
+/// Looks up commits for history walks through the
+/// commit-graph, inflating the ones missing from it.
+pub struct History {
+    graph: CommitGraph,
+    offset: String,
+}
+
+impl History {
+    /// Creates a History for the repository at the given offset.
+    /// A missing or invalid commit-graph is ignored.
+    pub fn with_offset(offset: &str) -> Self {
+        let graph = fs::read(format!("{offset}/objects/info/commit-graph"))
+            .and_then(|data| CommitGraph::from_bytes(&data))
+            .unwrap_or_default();
+
+        Self {
+            graph,
+            offset: offset.to_string(),
+        }
+    }
+
+    /// Returns the commit with the given hash.
+    pub fn commit(&self, hash: &str) -> io::Result<GraphCommit> {
+        match self.graph.get(hash) {
+            Some(commit) => Ok(commit.clone()),
+            None => parse_commit(hash, &self.offset),
+        }
+    }
+
+    /// Returns true if a is an ancestor of b, or b itself.
+    pub fn is_ancestor(&self, a: &str, b: &str) -> io::Result<bool> {
+        let generation = self.commit(a)?.generation;
+        let mut visited = HashSet::new();
+        let mut stack = vec![b.to_string()];
+
+        while let Some(hash) = stack.pop() {
+            if hash == a {
+                return Ok(true);
+            }
+
+            if !visited.insert(hash.clone()) {
+                continue;
+            }
+
+            // Commits with a lower generation can't reach a.
+            let commit = self.commit(&hash)?;
+            if commit.generation < generation {
+                continue;
+            }
+
+            stack.extend(commit.parents);
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the best common ancestors of a and b, none
+    /// of them is an ancestor of another. Criss-cross
+    /// merges have more than one merge base.
+    pub fn merge_bases(&self, a: &str, b: &str) -> io::Result<Vec<String>> {
+        if a == b {
+            return Ok(vec![a.to_string()]);
+        }
+
+        // Commits are walked from the highest generation,
+        // then the newest, so children come before parents.
+        // Entries are queued with the flags they were pushed with,
+        // the walk ends once every queued entry is stale.
+        let mut queue = BinaryHeap::new();
+        let mut flags: HashMap<String, u8> = HashMap::new();
+        let mut results = vec![];
+        let mut nonstale = 0;
+
+        for (hash, flag) in [(a, PARENT1), (b, PARENT2)] {
+            let commit = self.commit(hash)?;
+            flags.insert(hash.to_string(), flag);
+            queue.push((commit.generation, commit.date, hash.to_string(), flag));
+            nonstale += 1;
+        }
+
+        while nonstale > 0 {
+            let (_, _, hash, pushed) = match queue.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            if pushed & STALE == 0 {
+                nonstale -= 1;
+            }
+
+            let mut flag = flags[&hash] & (PARENT1 | PARENT2 | STALE);
+            if flag & (PARENT1 | PARENT2) == PARENT1 | PARENT2 {
+                if flags[&hash] & RESULT == 0 {
+                    flags.insert(hash.clone(), flags[&hash] | RESULT);
+                    results.push(hash.clone());
+                }
+
+                // It's ancestors are common, but not the best.
+                flag |= STALE;
+            }
+
+            for parent in self.commit(&hash)?.parents {
+                let old = flags.get(&parent).copied().unwrap_or_default();
+                if old & flag == flag {
+                    continue;
+                }
+
+                let commit = self.commit(&parent)?;
+                flags.insert(parent.clone(), old | flag);
+                if flag & STALE == 0 {
+                    nonstale += 1;
+                }
+
+                queue.push((commit.generation, commit.date, parent, flag));
+            }
+        }
+
+        // Remove the results that can reach another one.
+        let mut bases = vec![];
+        for (i, base) in results.iter().enumerate() {
+            let mut redundant = false;
+            for (j, other) in results.iter().enumerate() {
+                if i != j && self.is_ancestor(base, other)? {
+                    redundant = true;
+                    break;
+                }
+            }
+
+            if !redundant {
+                bases.push(base.clone());
+            }
+        }
+
+        Ok(bases)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(n: u8) -> String {
+        format!("{n:02x}").repeat(20)
+    }
+
+    fn graph(commits: &[(u8, &[u8])]) -> CommitGraph {
+        let commits = commits
+            .iter()
+            .map(|(n, parents)| {
+                let commit = GraphCommit {
+                    tree: hash(0xee),
+                    parents: parents.iter().map(|p| hash(*p)).collect(),
+                    generation: GENERATION_INFINITY,
+                    date: 1700000000 + *n as i64,
+                };
+
+                (hash(*n), commit)
+            })
+            .collect();
+
+        CommitGraph::new(commits).unwrap()
+    }
+
+    fn history(graph: CommitGraph) -> History {
+        History {
+            graph,
+            offset: String::new(),
+        }
+    }
+
+    #[test]
+    fn generations() {
+        let graph = graph(&[(1, &[]), (2, &[1]), (3, &[1]), (4, &[2, 3]), (5, &[4])]);
+
+        assert_eq!(graph.get(&hash(1)).unwrap().generation, 1);
+        assert_eq!(graph.get(&hash(4)).unwrap().generation, 3);
+        assert_eq!(graph.get(&hash(5)).unwrap().generation, 4);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let graph = graph(&[(1, &[]), (2, &[1]), (3, &[1]), (4, &[1]), (5, &[2, 3, 4])]);
+        let bytes = graph.as_bytes().unwrap();
+        let read = CommitGraph::from_bytes(&bytes).unwrap();
+
+        assert_eq!(read.len(), 5);
+        for n in 1..=5 {
+            assert_eq!(read.get(&hash(n)), graph.get(&hash(n)));
+        }
+    }
+
+    #[test]
+    fn missing_parent() {
+        let commits = HashMap::from([(
+            hash(2),
+            GraphCommit {
+                tree: hash(0xee),
+                parents: vec![hash(1)],
+                generation: GENERATION_INFINITY,
+                date: 0,
+            },
+        )]);
+
+        assert!(CommitGraph::new(commits).is_err());
+    }
+
+    #[test]
+    fn merge_of_descendant() {
+        // A no-ff merge of 2 into 1, whichever commit the walk starts at.
+        for _ in 0..30 {
+            let graph = graph(&[(1, &[]), (2, &[1]), (3, &[1, 2])]);
+            assert_eq!(graph.get(&hash(3)).unwrap().generation, 3);
+        }
+    }
+
+    #[test]
+    fn cycle() {
+        let commits = [(1, 2), (2, 1)].map(|(n, parent)| {
+            let commit = GraphCommit {
+                tree: hash(0xee),
+                parents: vec![hash(parent)],
+                generation: GENERATION_INFINITY,
+                date: 0,
+            };
+            (hash(n), commit)
+        });
+
+        assert!(CommitGraph::new(HashMap::from(commits)).is_err());
+    }
+
+    #[test]
+    fn ancestors() {
+        let history = history(graph(&[(1, &[]), (2, &[1]), (3, &[1]), (4, &[2])]));
+
+        assert!(history.is_ancestor(&hash(1), &hash(4)).unwrap());
+        assert!(history.is_ancestor(&hash(4), &hash(4)).unwrap());
+        assert!(!history.is_ancestor(&hash(3), &hash(4)).unwrap());
+        assert!(!history.is_ancestor(&hash(4), &hash(1)).unwrap());
+    }
+
+    #[test]
+    fn merge_base() {
+        let history = history(graph(&[
+            (1, &[]),
+            (2, &[1]),
+            (3, &[2]),
+            (4, &[2]),
+            (5, &[4]),
+        ]));
+
+        assert_eq!(history.merge_bases(&hash(3), &hash(5)).unwrap(), [hash(2)]);
+        assert_eq!(history.merge_bases(&hash(2), &hash(5)).unwrap(), [hash(2)]);
+    }
+
+    #[test]
+    fn criss_cross_merge_bases() {
+        // 1 <- 2 <- 4 (merges 3) <- 6
+        //   <- 3 <- 5 (merges 2) <- 7
+        let history = history(graph(&[
+            (1, &[]),
+            (2, &[1]),
+            (3, &[1]),
+            (4, &[2, 3]),
+            (5, &[3, 2]),
+            (6, &[4]),
+            (7, &[5]),
+        ]));
+
+        let mut bases = history.merge_bases(&hash(6), &hash(7)).unwrap();
+        bases.sort();
+        assert_eq!(bases, [hash(2), hash(3)]);
+    }
+}
@@ -37,11 +37,10 @@ pub fn diff_tree(a: &str, b: &str) -> impl Iterator<Item = Diff> {
             }
             Some(data) => {
                 let (mode2, otype2, hash2) = data;
-                // If the mode or object type changed
-                // this means the file was renamed or
-                // changed from a file to a directory
-                // or viceversa.
-                if mode != mode2 || otype != otype2 {
+                // If the object type changed this
+                // means the file was changed from a
+                // file to a directory or viceversa.
+                if otype != otype2 {
                     // Remove the old entry.
                     let diff = Diff::removed(line);
                     diffs.push(diff);
@@ -50,11 +49,13 @@ pub fn diff_tree(a: &str, b: &str) -> impl Iterator<Item = Diff> {
                     let line = format!("{mode2} {otype2} {hash2} {name}\0");
                     let diff = Diff::added(line);
                     diffs.push(diff);
-                } else if hash != hash2 {
-                    // If only the hash changed this means
-                    // the file content was modified or in
-                    // the case of a directory it now
-                    // contains different files.
+                } else if hash != hash2 || mode != mode2 {
+                    // If the hash changed this means the
+                    // file content was modified or in the
+                    // case of a directory it now contains
+                    // different files. A different mode
+                    // means the executable bit was toggled
+                    // or the file became a symlink.
                     let other = format!("{mode2} {otype2} {hash2} {name}\0");
                     let diff = Diff::modified(line, other);
                     diffs.push(diff);
@@ -135,6 +136,24 @@ mod tests {
         println!("{diff:#?}");
     }
 
+    #[test]
+    fn mode_changed() {
+        let a = r#"
+100644 blob ce6a0f315497cc7b7f6b8864617c10e89e59275f    build.sh
+100644 blob e8ffadba264e83be620b1c7b19a3b20bb429b4bc    link
+"#;
+        let b = r#"
+100755 blob ce6a0f315497cc7b7f6b8864617c10e89e59275f    build.sh
+120000 blob e8ffadba264e83be620b1c7b19a3b20bb429b4bc    link
+"#;
+        let diff: Vec<_> = diff_tree(a, b).collect();
+        assert_eq!(diff.len(), 2);
+        assert!(diff.iter().all(|diff| matches!(
+            diff.tag,
+            crate::plumbing::diff::diff_type::DiffType::Modified(_)
+        )));
+    }
+
     #[test]
     #[allow(unused_variables)]
     fn multi_diff() {
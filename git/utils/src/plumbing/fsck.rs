@@ -0,0 +1,411 @@
+use crate::{
+    __get_head_commit_with_offset, common_dir, get_refs_from_with_prefix,
+    index_file::commands::__read_index, object::object_db::deflate_object, package::pack::Pack,
+    plumbing::hash_object::__hash_object, plumbing::ls_tree::hash_to_str,
+};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader},
+};
+
+// An object in the database along with the
+// (type, hash) of every object it points to.
+pub(crate) struct FsckObject {
+    pub(crate) otype: String,
+    pub(crate) links: Vec<(String, String)>,
+}
+
+fn is_hash(s: &str) -> bool {
+    s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Validates a tree object returning the objects it points to.
+// Gitlinks are not followed, they live in another repository.
+fn parse_tree(mut data: &[u8]) -> Result<Vec<(String, String)>, &'static str> {
+    let mut links = vec![];
+
+    while !data.is_empty() {
+        let sep = data.iter().position(|&b| b == b' ').ok_or("missing mode")?;
+        let otype = match &data[..sep] {
+            b"40000" => "tree",
+            b"100644" | b"100755" | b"120000" => "blob",
+            b"160000" => "commit",
+            _ => return Err("invalid mode"),
+        };
+        data = &data[sep + 1..];
+
+        let end = data
+            .iter()
+            .position(|&b| b == b'\0')
+            .ok_or("missing name")?;
+        match &data[..end] {
+            b"" | b"." | b".." => return Err("invalid name"),
+            name if name.contains(&b'/') => return Err("invalid name"),
+            _ => {}
+        }
+        data = &data[end + 1..];
+
+        if data.len() < 20 {
+            return Err("truncated hash");
+        }
+
+        if otype != "commit" {
+            links.push((otype.to_string(), hash_to_str(&data[..20])));
+        }
+        data = &data[20..];
+    }
+
+    Ok(links)
+}
+
+// Validates a commit object returning it's tree and parents.
+fn parse_commit(data: &[u8]) -> Result<Vec<(String, String)>, &'static str> {
+    let data = String::from_utf8_lossy(data);
+    let mut lines = data.lines().peekable();
+    let mut links = vec![];
+
+    match lines.next().and_then(|line| line.strip_prefix("tree ")) {
+        Some(hash) if is_hash(hash) => links.push(("tree".to_string(), hash.to_string())),
+        _ => return Err("invalid tree line"),
+    }
+
+    while let Some(hash) = lines.peek().and_then(|line| line.strip_prefix("parent ")) {
+        if !is_hash(hash) {
+            return Err("invalid parent line");
+        }
+
+        links.push(("commit".to_string(), hash.to_string()));
+        lines.next();
+    }
+
+    if !lines.next().is_some_and(|line| line.starts_with("author ")) {
+        return Err("invalid author line");
+    }
+
+    if !lines
+        .next()
+        .is_some_and(|line| line.starts_with("committer "))
+    {
+        return Err("invalid committer line");
+    }
+
+    Ok(links)
+}
+
+// Validates a tag object returning the object it points to.
+fn parse_tag(data: &[u8]) -> Result<Vec<(String, String)>, &'static str> {
+    let data = String::from_utf8_lossy(data);
+    let mut lines = data.lines();
+
+    let hash = match lines.next().and_then(|line| line.strip_prefix("object ")) {
+        Some(hash) if is_hash(hash) => hash,
+        _ => return Err("invalid object line"),
+    };
+
+    let otype = match lines.next().and_then(|line| line.strip_prefix("type ")) {
+        Some(otype @ ("blob" | "tree" | "commit" | "tag")) => otype,
+        _ => return Err("invalid type line"),
+    };
+
+    if !lines.next().is_some_and(|line| line.starts_with("tag ")) {
+        return Err("invalid tag line");
+    }
+
+    Ok(vec![(otype.to_string(), hash.to_string())])
+}
+
+// Validates the object's syntax.
+pub(crate) fn check_object(hash: &str, otype: &str, data: &[u8]) -> Result<FsckObject, String> {
+    let links = match otype {
+        "blob" => Ok(vec![]),
+        "tree" => parse_tree(data),
+        "commit" => parse_commit(data),
+        "tag" => parse_tag(data),
+        _ => Err("invalid object type"),
+    };
+
+    match links {
+        Ok(links) => Ok(FsckObject {
+            otype: otype.to_string(),
+            links,
+        }),
+        Err(msg) => Err(format!("error in {otype} {hash}: {msg}")),
+    }
+}
+
+// Inflates a loose object, checking it's header
+// and that it's content hashes to it's name.
+fn check_loose(hash: &str, path: &str) -> Result<FsckObject, String> {
+    let data = fs::read(path)
+        .and_then(|data| deflate_object(&data))
+        .map_err(|_| format!("error: {hash}: object corrupt or missing"))?;
+
+    if format!("{:x}", Sha1::digest(&data)) != hash {
+        return Err(format!("error: hash mismatch {hash}"));
+    }
+
+    let sep = data
+        .iter()
+        .position(|&b| b == b'\0')
+        .ok_or(format!("error: {hash}: invalid header"))?;
+    let header = String::from_utf8_lossy(&data[..sep]);
+    let body = &data[sep + 1..];
+
+    match header.split_once(' ') {
+        Some((otype, size)) if size.parse() == Ok(body.len()) => check_object(hash, otype, body),
+        _ => Err(format!("error: {hash}: invalid header")),
+    }
+}
+
+// Returns the (hash, path) of every loose object.
+pub(crate) fn loose_objects(offset: &str) -> io::Result<Vec<(String, String)>> {
+    let mut objects = vec![];
+
+    for dir in fs::read_dir(format!("{offset}/objects"))?.flatten() {
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !dir.file_type()?.is_dir() {
+            continue;
+        }
+
+        for file in fs::read_dir(dir.path())?.flatten() {
+            let hash = prefix.clone() + &file.file_name().to_string_lossy();
+            if is_hash(&hash) {
+                objects.push((hash, file.path().to_string_lossy().to_string()));
+            }
+        }
+    }
+
+    Ok(objects)
+}
+
+// Returns the paths to every PACK file in the database.
+pub(crate) fn pack_files(offset: &str) -> io::Result<Vec<String>> {
+    let mut packs = vec![];
+
+    if let Ok(dir) = fs::read_dir(format!("{offset}/objects/pack")) {
+        for file in dir.flatten() {
+            let path = file.path().to_string_lossy().to_string();
+            if path.ends_with(".pack") {
+                packs.push(path);
+            }
+        }
+    }
+
+    Ok(packs)
+}
+
+// Returns the hash of every object a reference or the index points to.
+pub(crate) fn roots(offset: &str) -> io::Result<Vec<(String, String)>> {
+    let mut roots = vec![];
+
+    let refs = get_refs_from_with_prefix(&format!("{offset}/refs"), &format!("{offset}/"))?;
+    for (name, hash) in refs {
+        // Symbolic refs point to another ref, which is checked itself.
+        if hash.starts_with("ref: ") {
+            continue;
+        }
+
+        roots.push((name, hash));
+    }
+
+    // Linked worktrees have their own HEAD and index.
+    let mut git_dirs = vec![offset.to_string()];
+    if let Ok(worktrees) = fs::read_dir(format!("{offset}/worktrees")) {
+        for worktree in worktrees.flatten() {
+            git_dirs.push(worktree.path().to_string_lossy().to_string());
+        }
+    }
+
+    for git_dir in git_dirs {
+        if let Ok(head) = File::open(format!("{git_dir}/HEAD")) {
+            if let Ok(hash) = __get_head_commit_with_offset(head, offset) {
+                roots.push(("HEAD".to_string(), hash));
+            }
+        }
+
+        // An ongoing merge and the commit before
+        // the last reset or rebase are kept too.
+        for name in ["MERGE_HEAD", "ORIG_HEAD"] {
+            if let Ok(data) = fs::read_to_string(format!("{git_dir}/{name}")) {
+                for hash in data.lines().filter(|hash| hash.len() == 40) {
+                    roots.push((name.to_string(), hash.to_string()));
+                }
+            }
+        }
+
+        // Staged blobs are reachable too.
+        if let Ok(file) = File::open(format!("{git_dir}/index")) {
+            for entry in __read_index(file)? {
+                if !entry.is_gitlink() {
+                    roots.push(("index".to_string(), hash_to_str(entry.get_hash())));
+                }
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Checks the object database at the given offset. Every loose
+/// and packed object is rehashed and it's syntax validated, then
+/// the objects are walked from the references. Returns a line for
+/// every corrupt, missing or dangling object found.
+pub fn fsck_with_offset(offset: &str) -> io::Result<Vec<String>> {
+    let mut errors = vec![];
+    let mut objects = HashMap::new();
+
+    for (hash, path) in loose_objects(offset)? {
+        match check_loose(&hash, &path) {
+            Ok(object) => {
+                objects.insert(hash, object);
+            }
+            Err(msg) => errors.push(msg),
+        }
+    }
+
+    for path in pack_files(offset)? {
+        let data = fs::read(&path)?;
+        let reader = BufReader::with_capacity(data.len(), data.as_slice());
+        let pack = match Pack::read_with_offset(reader, offset) {
+            Ok(pack) => pack,
+            Err(e) => {
+                errors.push(format!("error: {path}: {e}"));
+                continue;
+            }
+        };
+
+        for entry in pack.entries() {
+            let hash = __hash_object(&entry.data, &entry.otype, false, offset)?.1;
+            match check_object(&hash, &entry.otype, &entry.data) {
+                Ok(object) => {
+                    objects.insert(hash, object);
+                }
+                Err(msg) => errors.push(msg),
+            }
+        }
+    }
+
+    // Walk the objects reachable from the references.
+    let mut reachable = HashSet::new();
+    let mut stack = vec![];
+    for (name, hash) in roots(offset)? {
+        if objects.contains_key(&hash) {
+            stack.push((String::new(), hash));
+        } else {
+            errors.push(format!("error: {name}: invalid sha1 pointer {hash}"));
+        }
+    }
+
+    while let Some((otype, hash)) = stack.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+
+        match objects.get(&hash) {
+            None => errors.push(format!("missing {otype} {hash}")),
+            Some(object) => {
+                if !otype.is_empty() && otype != object.otype {
+                    errors.push(format!(
+                        "error: {hash}: expected {otype}, found {}",
+                        object.otype
+                    ));
+                }
+
+                stack.extend(object.links.iter().cloned());
+            }
+        }
+    }
+
+    // Unreachable objects no other object points to are dangling.
+    let referenced: HashSet<_> = objects
+        .values()
+        .flat_map(|object| object.links.iter().map(|(_, hash)| hash))
+        .collect();
+
+    let mut dangling: Vec<_> = objects
+        .iter()
+        .filter(|(hash, _)| !reachable.contains(*hash) && !referenced.contains(hash))
+        .map(|(hash, object)| format!("dangling {} {hash}", object.otype))
+        .collect();
+
+    errors.sort();
+    dangling.sort();
+    errors.extend(dangling);
+    Ok(errors)
+}
+
+/// Checks the integrity and connectivity of the local repository.
+pub fn fsck() -> io::Result<Vec<String>> {
+    fsck_with_offset(&common_dir())
+}
+
+pub fn fsck_from_repo(repo: &str) -> io::Result<Vec<String>> {
+    fsck_with_offset(repo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_commit() {
+        let commit = b"tree e6f3d7bb6df0b70b77be69c602b9d4bdcc5e0ef4
+parent ce6a0f315497cc7b7f6b8864617c10e89e59275f
+author pepito <pepito@fi.uba.ar> 1700000000 -0300
+committer pepito <pepito@fi.uba.ar> 1700000000 -0300
+
+msg
+";
+
+        let links = parse_commit(commit).unwrap();
+        assert_eq!(links[0].0, "tree");
+        assert_eq!(links[1].0, "commit");
+    }
+
+    #[test]
+    fn invalid_commit() {
+        let commit = b"tree e6f3d7bb6df0b70b77be69c602b9d4bdcc5e0ef4
+committer pepito <pepito@fi.uba.ar> 1700000000 -0300
+";
+
+        assert_eq!(parse_commit(commit), Err("invalid author line"));
+    }
+
+    #[test]
+    fn tree_entries() {
+        let mut tree = b"100644 a.txt\0".to_vec();
+        tree.extend_from_slice(&[0x11; 20]);
+        tree.extend_from_slice(b"160000 lib\0");
+        tree.extend_from_slice(&[0x22; 20]);
+        tree.extend_from_slice(b"40000 src\0");
+        tree.extend_from_slice(&[0x33; 20]);
+
+        let links = parse_tree(&tree).unwrap();
+        assert_eq!(
+            links,
+            vec![
+                ("blob".to_string(), "11".repeat(20)),
+                ("tree".to_string(), "33".repeat(20)),
+            ]
+        );
+
+        assert!(parse_tree(&tree[..tree.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn tag_object() {
+        let tag = b"object e6f3d7bb6df0b70b77be69c602b9d4bdcc5e0ef4
+type commit
+tag v1.0
+tagger pepito <pepito@fi.uba.ar> 1700000000 -0300
+
+msg
+";
+
+        let links = parse_tag(tag).unwrap();
+        assert_eq!(links[0].0, "commit");
+        assert!(parse_tag(b"type commit\n").is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{
+    common_dir, get_refs_from_with_prefix,
+    object::{
+        object_cache::clear_cache,
+        object_db::{get_object_with_indexes, pack_indexes},
+    },
+    package::{pack::Pack, packentry::PackEntry, packindex::PackIndex},
+    plumbing::{
+        commit_graph::write_commit_graph_with_offset,
+        fsck::{check_object, loose_objects, pack_files, roots},
+        hash_object::__hash_object,
+        ls_tree::hash_to_str,
+    },
+    write_packed_refs_with_offset,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// Unreachable objects younger than this are kept by gc,
+/// they may belong to work that is still in progress.
+pub const PRUNE_GRACE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// Number of loose objects after which an automatic gc packs the repository.
+pub const GC_AUTO: usize = 6700;
+
+// Removes a file, it not existing is not an error.
+fn remove_file(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Walks the objects reachable from the references and the index,
+// returning them as PACK entries along with their hashes.
+fn reachable_entries(
+    offset: &str,
+    indexes: &[(PackIndex, String)],
+) -> io::Result<(Vec<PackEntry>, HashSet<String>)> {
+    let mut reachable = HashSet::new();
+    let mut entries = vec![];
+    let mut stack: Vec<_> = roots(offset)?.into_iter().map(|(_, hash)| hash).collect();
+
+    while let Some(hash) = stack.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+
+        let (otype, _, data) = get_object_with_indexes(&hash, offset, indexes)?;
+        let object = check_object(&hash, &otype, &data)
+            .map_err(|msg| io::Error::new(ErrorKind::InvalidData, msg))?;
+
+        stack.extend(object.links.into_iter().map(|(_, hash)| hash));
+        entries.push(PackEntry::new(&otype, data));
+    }
+
+    Ok((entries, reachable))
+}
+
+// Writes the unreachable objects of PACK files younger than the
+// grace period as loose objects, dated as their PACK file, so
+// they outlive the PACK file until the grace period expires.
+fn unpack_unreachable(
+    offset: &str,
+    indexes: &[(PackIndex, String)],
+    reachable: &HashSet<String>,
+    grace: Duration,
+) -> io::Result<()> {
+    let now = SystemTime::now();
+
+    for (index, path) in indexes {
+        let mtime = fs::metadata(path)?.modified()?;
+        if now.duration_since(mtime).unwrap_or_default() > grace {
+            continue;
+        }
+
+        for hash in index.hashes().filter(|hash| !reachable.contains(hash)) {
+            let loose = format!("{offset}/objects/{}/{}", &hash[..2], &hash[2..]);
+            if Path::new(&loose).exists() {
+                continue;
+            }
+
+            let (otype, _, data) = get_object_with_indexes(&hash, offset, indexes)?;
+            __hash_object(&data, &otype, true, offset)?;
+            File::options()
+                .write(true)
+                .open(&loose)?
+                .set_modified(mtime)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Removes the loose objects that were packed and the
+// unreachable ones older than the grace period.
+fn prune_loose(offset: &str, packed: &HashSet<String>, grace: Duration) -> io::Result<()> {
+    let now = SystemTime::now();
+
+    for (hash, path) in loose_objects(offset)? {
+        let mtime = fs::metadata(&path)?.modified()?;
+        let expired = now.duration_since(mtime).unwrap_or_default() > grace;
+
+        if packed.contains(&hash) || expired {
+            remove_file(&path)?;
+        }
+    }
+
+    // Fan-out directories left empty are removed,
+    // removing non empty ones fails and is ignored.
+    for dir in fs::read_dir(format!("{offset}/objects"))?.flatten() {
+        if dir.file_name().len() == 2 {
+            let _ = fs::remove_dir(dir.path());
+        }
+    }
+
+    Ok(())
+}
+
+// Moves every reference to the packed-refs file.
+// Symbolic references are left loose.
+fn pack_refs(offset: &str) -> io::Result<()> {
+    let refs = get_refs_from_with_prefix(&format!("{offset}/refs"), &format!("{offset}/"))?;
+    let packed: HashMap<_, _> = refs
+        .into_iter()
+        .filter(|(_, hash)| !hash.starts_with("ref: "))
+        .collect();
+
+    write_packed_refs_with_offset(&packed, offset)?;
+    for name in packed.keys() {
+        remove_file(&format!("{offset}/{name}"))?;
+    }
+
+    Ok(())
+}
+
+/// Cleans up the object database at the given offset. Every reachable
+/// object is written to a single PACK file with it's index, replacing
+/// the previous PACK files, objects that were packed or are unreachable
+/// and older than the grace period are removed, the references are
+/// packed and the commit-graph is rewritten.
+pub fn gc_with_offset(offset: &str, grace: Duration) -> io::Result<()> {
+    let indexes = pack_indexes(offset)?;
+    let (entries, reachable) = reachable_entries(offset, &indexes)?;
+
+    if !entries.is_empty() {
+        unpack_unreachable(offset, &indexes, &reachable, grace)?;
+
+        let old_packs = pack_files(offset)?;
+        let dir = format!("{offset}/objects/pack");
+        fs::create_dir_all(&dir)?;
+
+        let (pack, index) = Pack::new(entries).as_bytes_with_index()?;
+        let name = format!("{dir}/pack-{}", hash_to_str(&pack[pack.len() - 20..]));
+
+        // The index is written last, objects
+        // are only looked up through it.
+        fs::write(format!("{name}.pack"), &pack)?;
+        fs::write(format!("{name}.idx"), &index)?;
+
+        for path in old_packs {
+            if let Some(old) = path.strip_suffix(".pack") {
+                if old != name {
+                    remove_file(&format!("{old}.idx"))?;
+                    remove_file(&path)?;
+                }
+            }
+        }
+    }
+
+    // Pruned objects must not be served from the cache.
+    prune_loose(offset, &reachable, grace)?;
+    clear_cache();
+    pack_refs(offset)?;
+    write_commit_graph_with_offset(offset)
+}
+
+/// Runs gc on the object database at the given offset if it has more
+/// loose objects than the threshold. Returns whether gc was run.
+pub fn gc_auto_with_offset(offset: &str, threshold: usize) -> io::Result<bool> {
+    if threshold == 0 || loose_objects(offset)?.len() <= threshold {
+        return Ok(false);
+    }
+
+    gc_with_offset(offset, PRUNE_GRACE)?;
+    Ok(true)
+}
+
+/// Packs the objects and references of the local repository.
+pub fn gc() -> io::Result<()> {
+    gc_with_offset(&common_dir(), PRUNE_GRACE)
+}
+
+/// Runs gc on the local repository if it has too many loose objects.
+pub fn gc_auto() -> io::Result<bool> {
+    gc_auto_with_offset(&common_dir(), GC_AUTO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_packed_refs_with_offset, object::object_db::get_object_with_offset};
+
+    fn repo(name: &str) -> String {
+        let offset = format!(
+            "{}/gc-{name}-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let _ = fs::remove_dir_all(&offset);
+        fs::create_dir_all(format!("{offset}/refs/heads")).unwrap();
+        fs::create_dir_all(format!("{offset}/objects")).unwrap();
+        fs::write(format!("{offset}/HEAD"), "ref: refs/heads/master\n").unwrap();
+        offset
+    }
+
+    fn commit(offset: &str) -> (String, String) {
+        let blob = __hash_object(b"hello world\n", "blob", true, offset)
+            .unwrap()
+            .0;
+        let mut tree = b"100644 a.txt\0".to_vec();
+        tree.extend(blob);
+        let tree = __hash_object(&tree, "tree", true, offset).unwrap().1;
+
+        let commit = format!(
+            "tree {tree}\nauthor pepito <pepito@fi.uba.ar> 1700000000 -0300\n\
+             committer pepito <pepito@fi.uba.ar> 1700000000 -0300\n\nmsg\n"
+        );
+        let commit = __hash_object(commit.as_bytes(), "commit", true, offset)
+            .unwrap()
+            .1;
+        fs::write(format!("{offset}/refs/heads/master"), format!("{commit}\n")).unwrap();
+        (commit, tree)
+    }
+
+    #[test]
+    fn packs_reachable_objects() {
+        let offset = repo("pack");
+        let (commit, tree) = commit(&offset);
+        let garbage = __hash_object(b"garbage\n", "blob", true, &offset)
+            .unwrap()
+            .1;
+
+        gc_with_offset(&offset, Duration::ZERO).unwrap();
+
+        // Everything reachable is read back from the PACK file.
+        assert!(loose_objects(&offset).unwrap().is_empty());
+        assert_eq!(pack_files(&offset).unwrap().len(), 1);
+        assert_eq!(get_object_with_offset(&tree, &offset).unwrap().0, "tree");
+        assert_eq!(
+            get_object_with_offset(&commit[..7], &offset).unwrap().0,
+            "commit"
+        );
+        assert!(get_object_with_offset(&garbage, &offset).is_err());
+
+        // References are packed.
+        let refs = get_packed_refs_with_offset(&offset);
+        assert_eq!(refs.get("refs/heads/master"), Some(&commit));
+        assert_eq!(crate::get_head_with_offset(&offset), Some(commit));
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+
+    #[test]
+    fn keeps_recent_unreachable_objects() {
+        let offset = repo("grace");
+        commit(&offset);
+        let garbage = __hash_object(b"garbage\n", "blob", true, &offset)
+            .unwrap()
+            .1;
+
+        gc_with_offset(&offset, PRUNE_GRACE).unwrap();
+        gc_with_offset(&offset, PRUNE_GRACE).unwrap();
+
+        let loose = loose_objects(&offset).unwrap();
+        assert_eq!(loose.len(), 1);
+        assert_eq!(loose[0].0, garbage);
+        assert_eq!(pack_files(&offset).unwrap().len(), 1);
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+
+    #[test]
+    fn keeps_recent_unreachable_packed_objects() {
+        let offset = repo("packed-grace");
+        let (old, _) = commit(&offset);
+        gc_with_offset(&offset, Duration::ZERO).unwrap();
+
+        // The packed commit is no longer reachable.
+        let blob = __hash_object(b"new\n", "blob", true, &offset).unwrap().0;
+        let mut tree = b"100644 b.txt\0".to_vec();
+        tree.extend(blob);
+        let tree = __hash_object(&tree, "tree", true, &offset).unwrap().1;
+        let commit = format!(
+            "tree {tree}\nauthor pepito <pepito@fi.uba.ar> 1700000000 -0300\n\
+             committer pepito <pepito@fi.uba.ar> 1700000000 -0300\n\nnew\n"
+        );
+        let new = __hash_object(commit.as_bytes(), "commit", true, &offset)
+            .unwrap()
+            .1;
+        fs::remove_file(format!("{offset}/packed-refs")).unwrap();
+        fs::write(format!("{offset}/refs/heads/master"), format!("{new}\n")).unwrap();
+
+        gc_with_offset(&offset, PRUNE_GRACE).unwrap();
+        assert_eq!(get_object_with_offset(&old, &offset).unwrap().0, "commit");
+        assert_eq!(loose_objects(&offset).unwrap().len(), 3);
+
+        gc_with_offset(&offset, Duration::ZERO).unwrap();
+        assert!(get_object_with_offset(&old, &offset).is_err());
+        assert!(loose_objects(&offset).unwrap().is_empty());
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+
+    #[test]
+    fn keeps_merge_and_orig_head() {
+        let offset = repo("heads");
+        let (commit, _) = commit(&offset);
+        fs::remove_file(format!("{offset}/refs/heads/master")).unwrap();
+        fs::write(format!("{offset}/ORIG_HEAD"), format!("{commit}\n")).unwrap();
+
+        gc_with_offset(&offset, Duration::ZERO).unwrap();
+        assert_eq!(
+            get_object_with_offset(&commit, &offset).unwrap().0,
+            "commit"
+        );
+
+        fs::remove_file(format!("{offset}/ORIG_HEAD")).unwrap();
+        fs::write(format!("{offset}/MERGE_HEAD"), format!("{commit}\n")).unwrap();
+
+        gc_with_offset(&offset, Duration::ZERO).unwrap();
+        assert_eq!(
+            get_object_with_offset(&commit, &offset).unwrap().0,
+            "commit"
+        );
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+
+    #[test]
+    fn auto_threshold() {
+        let offset = repo("auto");
+        commit(&offset);
+
+        assert!(!gc_auto_with_offset(&offset, 3).unwrap());
+        assert!(gc_auto_with_offset(&offset, 2).unwrap());
+        assert!(loose_objects(&offset).unwrap().is_empty());
+
+        fs::remove_dir_all(offset).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    io_err,
+    common_dir, io_err,
     object::object_db::{get_object_from_repo, get_object_with_offset},
 };
 use std::io::{self, BufRead, Read};
@@ -33,6 +33,9 @@ pub fn __ls_tree(mut data: &[u8]) -> io::Result<String> {
             b"40000" => ret.push_str("040000 tree "),
             b"100644" => ret.push_str("100644 blob "),
             b"100755" => ret.push_str("100755 blob "),
+            b"120000" => ret.push_str("120000 blob "),
+            // Gitlink, the hash is a commit in a submodule.
+            b"160000" => ret.push_str("160000 commit "),
             _ => {
                 return Err(io_err!("Invalid mode in tree entry"));
             }
@@ -63,7 +66,7 @@ pub fn __ls_tree(mut data: &[u8]) -> io::Result<String> {
 /// Returns a readable representation of given
 /// tree object's data, represented by it's hash.
 pub fn ls_tree(hash: &str) -> io::Result<String> {
-    ls_tree_with_offset(hash, ".git")
+    ls_tree_with_offset(hash, &common_dir())
 }
 
 pub fn ls_tree_with_offset(hash: &str, offset: &str) -> io::Result<String> {
@@ -76,6 +79,34 @@ pub fn ls_tree_from_repo(hash: &str, repo: &str) -> io::Result<String> {
     __ls_tree(&data)
 }
 
+/// Looks for the entry at the given path, relative to the tree,
+/// returning it's type and hash. An empty path is the tree itself.
+pub fn get_entry_with_offset(
+    tree: &str,
+    path: &str,
+    offset: &str,
+) -> io::Result<Option<(String, String)>> {
+    let mut entry = ("tree".to_string(), tree.to_string());
+
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        if entry.0 != "tree" {
+            return Ok(None);
+        }
+
+        let entries = ls_tree_with_offset(&entry.1, offset)?;
+        match entries
+            .lines()
+            .map(parse_ls_tree_entry)
+            .find(|(_, _, _, entry)| entry == name)
+        {
+            Some((_, otype, hash, _)) => entry = (otype.to_string(), hash.to_string()),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(entry))
+}
+
 // Parses an ls-tree line into a tuple of (mode, type, hash, name).
 pub fn parse_ls_tree_entry(data: &str) -> (&str, &str, &str, String) {
     let data = data.split_whitespace().collect::<Vec<&str>>();
@@ -101,7 +132,47 @@ mod tests {
                 "b0490c9675eac72a51abae693878d87ebae4dc23",
                 "Cargo.toml".to_string()
             ),
-            parse_ls_tree_entry(&diff)
+            parse_ls_tree_entry(diff)
+        );
+    }
+
+    #[test]
+    fn gitlink() {
+        let mut data = b"160000 lib\0".to_vec();
+        data.extend_from_slice(&[0xab; 20]);
+
+        assert_eq!(
+            format!("160000 commit {}\tlib\0\n", "ab".repeat(20)),
+            __ls_tree(&data).unwrap()
+        );
+    }
+
+    #[test]
+    fn entry_at_path() {
+        use crate::plumbing::hash_object::__hash_object;
+
+        let offset = format!(
+            "{}/ls-tree-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
         );
+        std::fs::create_dir_all(format!("{offset}/objects")).unwrap();
+
+        let (blob, blob_hex) = __hash_object(b"hello\n", "blob", true, &offset).unwrap();
+        let mut sub = b"100644 a.txt\0".to_vec();
+        sub.extend(blob);
+        let (sub, sub_hex) = __hash_object(&sub, "tree", true, &offset).unwrap();
+        let mut root = b"40000 src\0".to_vec();
+        root.extend(sub);
+        let root = __hash_object(&root, "tree", true, &offset).unwrap().1;
+
+        let entry = |path| get_entry_with_offset(&root, path, &offset).unwrap();
+        assert_eq!(entry("src/a.txt"), Some(("blob".to_string(), blob_hex)));
+        assert_eq!(entry("src"), Some(("tree".to_string(), sub_hex)));
+        assert_eq!(entry(""), Some(("tree".to_string(), root.clone())));
+        assert_eq!(entry("src/b.txt"), None);
+        assert_eq!(entry("src/a.txt/c"), None);
+
+        std::fs::remove_dir_all(offset).unwrap();
     }
 }
@@ -1,4 +1,7 @@
 pub mod commit;
+pub mod commit_graph;
 pub mod diff;
+pub mod fsck;
+pub mod gc;
 pub mod hash_object;
 pub mod ls_tree;